@@ -0,0 +1,5 @@
+fn main() {
+    // Node.js addons need extra linker setup
+    #[cfg(feature = "napi")]
+    napi_build::setup();
+}
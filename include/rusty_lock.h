@@ -0,0 +1,98 @@
+#ifndef RUSTY_LOCK_H
+#define RUSTY_LOCK_H
+
+/* Generated with cbindgen, do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The version of the plugin ABI, handed to `rustylock_plugin_init` so a
+ * plugin built against another can refuse to load
+ */
+#define RUSTY_LOCK_PLUGIN_ABI 1
+
+/**
+ * A session event as handed to C callbacks
+ */
+typedef struct RustyLockEvent {
+  /**
+   * The WTS status code, eg. 7 for WTS_SESSION_LOCK
+   */
+  uint32_t state;
+  /**
+   * The session the notification is about
+   */
+  uint32_t session_id;
+  /**
+   * Milliseconds since the UNIX epoch when the event was received
+   */
+  uint64_t timestamp_ms;
+} RustyLockEvent;
+
+/**
+ * Called on the monitor thread for every event, `event` is only valid for
+ * the duration of the call. Must not call back into `rustylock_*`
+ */
+typedef void (*RustyLockCallback)(const struct RustyLockEvent *event, void *user_data);
+
+/**
+ * A session event as handed to plugins. The strings are UTF-8, empty if
+ * unknown, and like the event only valid for the duration of the call
+ */
+typedef struct RustyLockPluginEvent {
+  /**
+   * The WTS status code, eg. 7 for WTS_SESSION_LOCK
+   */
+  uint32_t state;
+  /**
+   * The session the notification is about
+   */
+  uint32_t session_id;
+  /**
+   * Milliseconds since the UNIX epoch when the event was received
+   */
+  uint64_t timestamp_ms;
+  /**
+   * The state's name, eg. `lock`
+   */
+  const char *name;
+  const char *username;
+  const char *domain;
+  const char *client_ip;
+} RustyLockPluginEvent;
+
+/**
+ * `rustylock_plugin_init`, called once after the DLL is loaded with
+ * [`RUSTY_LOCK_PLUGIN_ABI`]. Returning anything but 0 refuses to load
+ */
+typedef int32_t (*RustyLockPluginInit)(uint32_t abi_version);
+
+/**
+ * `rustylock_plugin_on_event`, called on the monitor thread for every
+ * event so it has to return quickly
+ */
+typedef void (*RustyLockPluginOnEvent)(const struct RustyLockPluginEvent *event);
+
+/**
+ * `rustylock_plugin_shutdown`, called once before the DLL is unloaded
+ */
+typedef void (*RustyLockPluginShutdown)(void);
+
+/**
+ * Register `callback` to be called with every session event, starting the
+ * monitor thread on the first subscription. Returns 0 on success and -1 if
+ * the monitor could not be started
+ */
+int32_t rustylock_subscribe(RustyLockCallback callback, void *user_data);
+
+/**
+ * Stop the monitor thread, wait for it to exit and drop every
+ * subscription. Returns 0 on success and -1 if nothing was running or the
+ * thread could not be stopped
+ */
+int32_t rustylock_stop(void);
+
+#endif  /* RUSTY_LOCK_H */
@@ -0,0 +1,3278 @@
+//! Commands to run when events arrive, read from a TOML config such as
+//!
+//! ```toml
+//! on_lock = ["pause-music.cmd", { command = "backup.cmd", timeout = 600 }]
+//! on_unlock = "resume-music.cmd"
+//! ```
+//!
+//! Only top level keys named `on_` followed by a [`WtsState::name`] are
+//! understood. Each takes an action or an array of them, where an action is
+//! a command or an inline table with the fields of [`Action`] and the keys
+//! of one [`ActionKind`]. Commands can use the placeholders listed on
+//! [`ActionRunner`]. How an event's actions
+//! run together is chosen with a table holding them, see [`Execution`]
+//!
+//! ```toml
+//! on_logoff = { mode = "sequential", actions = ["backup.cmd", "shutdown /s"] }
+//! on_lock = { mode = "parallel", concurrency = 2, actions = ["a", "b", "c"] }
+//! ```
+use crate::builtin;
+use crate::config::{self, ConfigError, Value};
+use crate::control::{send_message, MessageButtons};
+use crate::journal::{self, Entry, Unfinished};
+#[cfg(feature = "lua")]
+use crate::lua;
+#[cfg(feature = "wasm")]
+use crate::plugin;
+use crate::pool::{Pool, DEFAULT_WORKERS};
+#[cfg(feature = "rhai")]
+use crate::script;
+use crate::wynapi::{
+    create_process, create_process_as_user, current_session_id, local_time,
+    read_credential, Job, Pipe, ProcessHandle,
+};
+use crate::{Schedule, SessionEvent, SessionScope, StateSet, WtsState};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs::OpenOptions;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tracing::{event, Level};
+
+/// Held from creating a command's pipes until we have closed our copies of
+/// their write ends, so no other command started meanwhile inherits them and
+/// keeps its output from ending
+static SPAWN_LOCK: Mutex<()> = Mutex::new(());
+
+/// A smart home device reached over HTTP on the local network, for
+/// [`ActionKind::SmartHome`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SmartDevice {
+    /// Lights or groups of them on a Philips Hue bridge, `hue` in the config
+    /// with its address, and `lights` or `groups` by number. `user` is the
+    /// name the bridge gave out when its button was pressed, which can be
+    /// `cred:target`
+    Hue {
+        bridge: String,
+        user: String,
+        lights: Vec<u32>,
+        groups: Vec<u32>,
+    },
+    /// A plug running Tasmota, `tasmota` in the config with its address.
+    /// `relay` picks one of several, counting from 1
+    Tasmota { host: String, relay: Option<u32> },
+    /// A Shelly plug or relay, `shelly` in the config with its address.
+    /// `relay` picks one of several, counting from 0
+    Shelly { host: String, relay: u32 },
+}
+
+impl core::fmt::Display for SmartDevice {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let list = |numbers: &[u32]| {
+            numbers
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Self::Hue {
+                bridge,
+                lights,
+                groups,
+                ..
+            } => {
+                write!(f, "Hue")?;
+                if !lights.is_empty() {
+                    write!(f, " lights {}", list(lights))?;
+                }
+                if !groups.is_empty() {
+                    write!(f, " groups {}", list(groups))?;
+                }
+                write!(f, " on {bridge}")
+            }
+            Self::Tasmota { host, relay: None } => write!(f, "Tasmota {host}"),
+            Self::Tasmota {
+                host,
+                relay: Some(relay),
+            } => write!(f, "Tasmota {host} relay {relay}"),
+            Self::Shelly { host, relay } => {
+                write!(f, "Shelly {host} relay {relay}")
+            }
+        }
+    }
+}
+
+/// What an action does, a command or one of the built in actions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionKind {
+    /// Run through `cmd /C` after the placeholders are expanded, `command`
+    /// in the config
+    Command(String),
+    /// Kill every process in the event's session whose exe has one of these
+    /// names, ignoring case and with or without `.exe`. `kill` and `grace`
+    /// in the config. With a grace period their windows are first asked to
+    /// close and given that long, which only reaches windows on our desktop
+    KillProcesses {
+        names: Vec<String>,
+        grace: Option<Duration>,
+    },
+    /// Start these services through the service control manager and wait
+    /// for them to run, `start_service` in the config. Names are the
+    /// short service names, such as `Spooler`
+    StartServices(Vec<String>),
+    /// Stop these services and wait for them to stop, `stop_service` in the
+    /// config
+    StopServices(Vec<String>),
+    /// Power the displays off after this long, unless unlocked by then,
+    /// `displays_off` in seconds in the config. This has to run in the
+    /// session the displays belong to
+    DisplaysOff(Duration),
+    /// Empty the clipboard, `clear_clipboard = true` in the config. With
+    /// `only_secrets` it is only emptied if it holds text that looks like a
+    /// password or key. Clipboard history is left alone
+    ClearClipboard { only_secrets: bool },
+    /// Hang up the dial up and VPN connections with these phonebook entry
+    /// names, or every one if empty. `hang_up_vpn` in the config, `true`
+    /// for every one. `rasdial /disconnect` is tried when RAS fails
+    HangUpVpn(Vec<String>),
+    /// Dial these phonebook entries with `rasdial` and their saved
+    /// credentials, or if empty the ones the last [`HangUpVpn`] dropped.
+    /// `dial_vpn` in the config, `true` to redial
+    ///
+    /// [`HangUpVpn`]: ActionKind::HangUpVpn
+    DialVpn(Vec<String>),
+    /// Unmap these network drives, such as `S:`, and remember their shares.
+    /// `unmap_drives` in the config. Mapped drives belong to the logon, so
+    /// this has to run as the user
+    UnmapDrives(Vec<String>),
+    /// Map these drives back to the shares [`UnmapDrives`] took them from
+    /// using saved credentials, or every one it took if empty. `map_drives`
+    /// in the config, `true` for every one
+    ///
+    /// [`UnmapDrives`]: ActionKind::UnmapDrives
+    MapDrives(Vec<String>),
+    /// Eject every removable disk, such as USB sticks and drives, as Safely
+    /// Remove Hardware would. `eject_removable = true` in the config. A disk
+    /// with files open on it refuses, which is logged with the culprit
+    EjectRemovableStorage,
+    /// Take a photo with the first webcam and save it as a bitmap in this
+    /// directory, with a text file of the event's details beside it.
+    /// `webcam_snapshot` in the config, which can use the placeholders
+    WebcamSnapshot(PathBuf),
+    /// Save a PNG of the desktop in `dir`, deleting the PNGs there older
+    /// than `keep`. `screenshot` in the config, which can use the
+    /// placeholders, and `keep_days`. Run it on lock to see what was on
+    /// screen when the user walked away, the desktop is then captured every
+    /// few seconds so the one from just before the lock can be saved
+    Screenshot {
+        dir: PathBuf,
+        keep: Option<Duration>,
+    },
+    /// Ask the KeePass and KeePassXC instances in the event's session to
+    /// lock their databases. `lock_password_managers = true` in the config
+    LockPasswordManagers,
+    /// Remove the keys held by ssh-agent and the passphrases cached by
+    /// gpg-agent. `clear_agent_keys = true` in the config
+    ClearAgentKeys,
+    /// Pause these Docker containers, or every running one if empty.
+    /// `pause_containers` in the config, `true` for every one
+    PauseContainers(Vec<String>),
+    /// Resume these Docker containers, or the ones [`PauseContainers`]
+    /// paused if empty. `unpause_containers` in the config, `true` for
+    /// those
+    ///
+    /// [`PauseContainers`]: ActionKind::PauseContainers
+    UnpauseContainers(Vec<String>),
+    /// Terminate these WSL distributions, or shut WSL down if empty.
+    /// `shutdown_wsl` in the config, `true` for all of it
+    ShutdownWsl(Vec<String>),
+    /// Set the Slack status of the user `token` belongs to, to away or
+    /// back to available. `slack_status = "away"` or `"available"` in the
+    /// config with `token`, a user token with `users:write` and
+    /// `users.profile:write`, which can be `cred:target`
+    SlackStatus { away: bool, token: String },
+    /// Set the Teams presence of the user `token` belongs to, to away or
+    /// back to what Teams works out. `teams_presence = "away"` or
+    /// `"available"` in the config with `token`, a Microsoft Graph access
+    /// token with `Presence.ReadWrite`, which can be `cred:target`
+    TeamsPresence { away: bool, token: String },
+    /// Turn a smart home device on or off, `hue`, `tasmota` or `shelly` in
+    /// the config with `power = "on"` or `"off"`
+    SmartHome { device: SmartDevice, on: bool },
+    /// Run this Rhai script, `script` in the config, which can use the
+    /// placeholders. It sees them in the `event` map, can run commands with
+    /// `exec` and keeps values between runs in `store`. What it evaluates to
+    /// is its output, and throwing fails the action
+    #[cfg(feature = "rhai")]
+    Script(PathBuf),
+    /// Run this Lua script, `lua` in the config, which can use the
+    /// placeholders. It has the same `event`, `exec` and `store` as a Rhai
+    /// script, though `store` only keeps booleans, numbers and strings. What
+    /// it returns is its output, and raising an error fails the action
+    #[cfg(feature = "lua")]
+    Lua(PathBuf),
+    /// Run this WebAssembly plugin's `on_event`, `plugin` in the config,
+    /// which can use the placeholders, with `allow` listing its
+    /// [`Capabilities`](crate::Capabilities)
+    #[cfg(feature = "wasm")]
+    Plugin {
+        path: PathBuf,
+        capabilities: crate::Capabilities,
+    },
+}
+
+impl core::fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Command(command) => write!(f, "{command}"),
+            Self::KillProcesses { names, .. } => {
+                write!(f, "kill {}", names.join(", "))
+            }
+            Self::StartServices(names) => {
+                write!(f, "start service {}", names.join(", "))
+            }
+            Self::StopServices(names) => {
+                write!(f, "stop service {}", names.join(", "))
+            }
+            Self::DisplaysOff(delay) => {
+                write!(f, "turn the displays off after {delay:?}")
+            }
+            Self::ClearClipboard { .. } => write!(f, "clear the clipboard"),
+            Self::HangUpVpn(names) if names.is_empty() => {
+                write!(f, "hang up every VPN")
+            }
+            Self::HangUpVpn(names) => write!(f, "hang up {}", names.join(", ")),
+            Self::DialVpn(names) if names.is_empty() => {
+                write!(f, "redial the VPNs hung up")
+            }
+            Self::DialVpn(names) => write!(f, "dial {}", names.join(", ")),
+            Self::UnmapDrives(drives) => {
+                write!(f, "unmap {}", drives.join(", "))
+            }
+            Self::MapDrives(drives) if drives.is_empty() => {
+                write!(f, "map back the drives unmapped")
+            }
+            Self::MapDrives(drives) => write!(f, "map {}", drives.join(", ")),
+            Self::EjectRemovableStorage => write!(f, "eject removable storage"),
+            Self::WebcamSnapshot(dir) => {
+                write!(f, "save a webcam photo in {}", dir.display())
+            }
+            Self::Screenshot { dir, .. } => {
+                write!(f, "save a screenshot in {}", dir.display())
+            }
+            Self::LockPasswordManagers => write!(f, "lock password managers"),
+            Self::ClearAgentKeys => write!(f, "clear ssh and gpg agent keys"),
+            Self::PauseContainers(names) if names.is_empty() => {
+                write!(f, "pause running containers")
+            }
+            Self::PauseContainers(names) => {
+                write!(f, "pause containers {}", names.join(", "))
+            }
+            Self::UnpauseContainers(names) if names.is_empty() => {
+                write!(f, "unpause paused containers")
+            }
+            Self::UnpauseContainers(names) => {
+                write!(f, "unpause containers {}", names.join(", "))
+            }
+            Self::ShutdownWsl(distros) if distros.is_empty() => {
+                write!(f, "shut down WSL")
+            }
+            Self::ShutdownWsl(distros) => {
+                write!(f, "terminate WSL {}", distros.join(", "))
+            }
+            Self::SlackStatus { away, .. } => {
+                write!(f, "set the Slack status to {}", status_name(*away))
+            }
+            Self::TeamsPresence { away, .. } => {
+                write!(f, "set the Teams presence to {}", status_name(*away))
+            }
+            Self::SmartHome { device, on } => {
+                let power = if *on { "on" } else { "off" };
+                write!(f, "turn {power} {device}")
+            }
+            #[cfg(feature = "rhai")]
+            Self::Script(path) => write!(f, "run {}", path.display()),
+            #[cfg(feature = "lua")]
+            Self::Lua(path) => write!(f, "run {}", path.display()),
+            #[cfg(feature = "wasm")]
+            Self::Plugin { path, .. } => {
+                write!(f, "run plugin {}", path.display())
+            }
+        }
+    }
+}
+
+/// One thing to do for an event. Only `retry`, `rate_limit`, `name` and
+/// `after` apply to the built in actions, the rest are for commands
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Action {
+    pub kind: ActionKind,
+    /// Kill the command and everything it started once it has run this
+    /// long, `timeout` in seconds in the config. `None` waits forever
+    pub timeout: Option<Duration>,
+    /// Run the command again when it fails or times out, `retry` in the
+    /// config. `None` runs it once
+    pub retry: Option<Retry>,
+    /// Where the command starts, `cwd` in the config. `None` keeps ours
+    pub cwd: Option<PathBuf>,
+    /// Extra environment variables, the `env` table in the config. Values
+    /// can use the placeholders and win over inherited ones of the same name.
+    /// `cred:target`, or `{cred:target}` in a value or the command, is the
+    /// secret of that Credential Manager entry, read as the command starts
+    pub env: Vec<(String, String)>,
+    /// Start from our environment, true by default. Without it the command
+    /// only sees `env` and the `RUSTY_LOCK_` variables, so `SystemRoot` and
+    /// `PATH` need passing if it relies on them
+    pub inherit_env: bool,
+    /// Skip the action once it has run this often lately, `rate_limit` in
+    /// the config. A run counts once however many attempts it takes
+    pub rate_limit: Option<RateLimit>,
+    /// What other actions for the same event call this one in `after`,
+    /// `name` in the config. A named command's stdout is kept for them, and
+    /// tags what it prints in the log
+    pub name: Option<String>,
+    /// Only run once these named actions, earlier in the event's list, have
+    /// succeeded, `after` in the config. Skipped if one fails. Each one's
+    /// trimmed output is seen as `{output_<name>}` and
+    /// `RUSTY_LOCK_OUTPUT_<NAME>`, with anything but letters and digits in
+    /// the name as `_`
+    pub after: Vec<String>,
+    /// A file to append what the command prints to, `log` in the config.
+    /// It goes to our log either way
+    pub log: Option<OutputLog>,
+    /// Run in order once the action has succeeded, `on_success` in the
+    /// config, after any retries. Follow-ups also see `{exit_code}`
+    pub on_success: Vec<Action>,
+    /// Run in order once the action has failed or timed out, `on_failure`
+    /// in the config, after any retries
+    pub on_failure: Vec<Action>,
+    /// Run in order when the command exits with the code, instead of
+    /// `on_success` or `on_failure`. The `on_exit` table in the config
+    pub on_exit: Vec<(i32, Vec<Action>)>,
+}
+
+impl Action {
+    /// A command
+    pub fn new(command: impl Into<String>) -> Self {
+        ActionKind::Command(command.into()).into()
+    }
+
+    /// Kill the processes named `names` after asking them to close if there
+    /// is a `grace` period
+    pub fn kill_processes(
+        names: impl IntoIterator<Item = impl Into<String>>,
+        grace: Option<Duration>,
+    ) -> Self {
+        ActionKind::KillProcesses {
+            names: names.into_iter().map(Into::into).collect(),
+            grace,
+        }
+        .into()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    pub fn cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    pub fn env(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.env.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn inherit_env(mut self, inherit_env: bool) -> Self {
+        self.inherit_env = inherit_env;
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn after(mut self, name: impl Into<String>) -> Self {
+        self.after.push(name.into());
+        self
+    }
+
+    pub fn log(mut self, log: OutputLog) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    pub fn on_success(mut self, action: impl Into<Action>) -> Self {
+        self.on_success.push(action.into());
+        self
+    }
+
+    pub fn on_failure(mut self, action: impl Into<Action>) -> Self {
+        self.on_failure.push(action.into());
+        self
+    }
+
+    pub fn on_exit(mut self, code: i32, action: impl Into<Action>) -> Self {
+        match self.on_exit.iter_mut().find(|(c, _)| *c == code) {
+            Some((_, actions)) => actions.push(action.into()),
+            None => self.on_exit.push((code, vec![action.into()])),
+        }
+        self
+    }
+
+    /// What the action does, commands with their placeholders filled in
+    fn description(&self, variables: &[(&str, String)]) -> String {
+        match &self.kind {
+            ActionKind::Command(command) => expand(command, variables),
+            kind => kind.to_string(),
+        }
+    }
+
+    /// Do the action to completion for an event in `session_id`, retrying
+    /// as configured and then following up, its output if it succeeded. Only
+    /// named commands have any. Commands start as the user logged on to the
+    /// session if `as_user`
+    fn execute(
+        &self,
+        variables: &[(&str, String)],
+        session_id: u32,
+        as_user: bool,
+    ) -> Option<String> {
+        let description = self.description(variables);
+        let attempt = || {
+            let succeeded = match &self.kind {
+                ActionKind::Command(command) => {
+                    return self.run_command(
+                        command,
+                        variables,
+                        as_user.then_some(session_id),
+                    )
+                }
+                ActionKind::KillProcesses { names, grace } => {
+                    builtin::kill_processes(names, *grace, session_id)
+                }
+                ActionKind::StartServices(names) => {
+                    builtin::set_services(names, true)
+                }
+                ActionKind::StopServices(names) => {
+                    builtin::set_services(names, false)
+                }
+                ActionKind::DisplaysOff(delay) => builtin::displays_off(*delay),
+                ActionKind::ClearClipboard { only_secrets } => {
+                    builtin::clear_clipboard(*only_secrets)
+                }
+                ActionKind::HangUpVpn(names) => builtin::hang_up_vpn(names),
+                ActionKind::DialVpn(names) => builtin::dial_vpn(names),
+                ActionKind::UnmapDrives(drives) => {
+                    builtin::unmap_drives(drives)
+                }
+                ActionKind::MapDrives(drives) => builtin::map_drives(drives),
+                ActionKind::EjectRemovableStorage => {
+                    builtin::eject_removable_storage()
+                }
+                ActionKind::WebcamSnapshot(dir) => {
+                    let dir = expand(&dir.to_string_lossy(), variables);
+                    builtin::webcam_snapshot(Path::new(&dir), variables)
+                }
+                ActionKind::Screenshot { dir, keep } => {
+                    let dir = expand(&dir.to_string_lossy(), variables);
+                    builtin::screenshot(Path::new(&dir), *keep, variables)
+                }
+                ActionKind::LockPasswordManagers => {
+                    builtin::lock_password_managers(session_id)
+                }
+                ActionKind::ClearAgentKeys => builtin::clear_agent_keys(),
+                ActionKind::PauseContainers(names) => {
+                    builtin::pause_containers(names)
+                }
+                ActionKind::UnpauseContainers(names) => {
+                    builtin::unpause_containers(names)
+                }
+                ActionKind::ShutdownWsl(distros) => {
+                    builtin::shutdown_wsl(distros)
+                }
+                ActionKind::SlackStatus { away, token } => secret(token)
+                    .is_some_and(|token| builtin::slack_status(*away, &token)),
+                ActionKind::TeamsPresence { away, token } => secret(token)
+                    .is_some_and(|token| {
+                        builtin::teams_presence(*away, &token)
+                    }),
+                ActionKind::SmartHome { device, on } => {
+                    builtin::switch_device(device, *on)
+                }
+                #[cfg(feature = "rhai")]
+                ActionKind::Script(path) => {
+                    let path = expand(&path.to_string_lossy(), variables);
+                    return script::run(Path::new(&path), variables)
+                        .ok_or(None);
+                }
+                #[cfg(feature = "lua")]
+                ActionKind::Lua(path) => {
+                    let path = expand(&path.to_string_lossy(), variables);
+                    return lua::run(Path::new(&path), variables).ok_or(None);
+                }
+                #[cfg(feature = "wasm")]
+                ActionKind::Plugin { path, capabilities } => {
+                    let path = expand(&path.to_string_lossy(), variables);
+                    return plugin::run(
+                        Path::new(&path),
+                        *capabilities,
+                        variables,
+                    )
+                    .ok_or(None);
+                }
+            };
+            succeeded.then(String::new).ok_or(None)
+        };
+        let outcome = match &self.retry {
+            None => attempt(),
+            Some(retry) => {
+                let mut outcome = attempt();
+                for number in 1..retry.attempts {
+                    if outcome.is_ok() {
+                        break;
+                    }
+                    let delay = retry.delay(number);
+                    event!(
+                        Level::WARN,
+                        "{description} failed attempt {number} of {}, \
+                         retrying in {delay:?}",
+                        retry.attempts
+                    );
+                    thread::sleep(delay);
+                    outcome = attempt();
+                }
+                outcome
+            }
+        };
+        self.follow_up(&outcome, variables, session_id, as_user);
+        outcome.ok()
+    }
+
+    /// Run the follow-ups for how the action went, `outcome` being its
+    /// output or the exit code it failed with, if it got as far as exiting
+    fn follow_up(
+        &self,
+        outcome: &Result<String, Option<i32>>,
+        variables: &[(&str, String)],
+        session_id: u32,
+        as_user: bool,
+    ) {
+        let code = match outcome {
+            Ok(_) => Some(0),
+            Err(code) => *code,
+        };
+        let on_exit =
+            code.and_then(|code| self.on_exit.iter().find(|(c, _)| *c == code));
+        let follow_ups = match (on_exit, outcome) {
+            (Some((_, actions)), _) => actions,
+            (None, Ok(_)) => &self.on_success,
+            (None, Err(_)) => &self.on_failure,
+        };
+        if follow_ups.is_empty() {
+            return;
+        }
+        let mut variables = variables.to_vec();
+        let code = code.map(|code| code.to_string()).unwrap_or_default();
+        variables.push(("exit_code", code));
+        for follow_up in follow_ups {
+            event!(
+                Level::INFO,
+                "Following up {} with {}",
+                self.description(&variables),
+                follow_up.description(&variables)
+            );
+            follow_up.execute(&variables, session_id, as_user);
+        }
+    }
+
+    /// Log the follow-ups a dry run of the action would start as it succeeds,
+    /// and what would follow it up otherwise
+    fn log_follow_ups(&self, variables: &[(&str, String)]) {
+        let description = self.description(variables);
+        let mut variables = variables.to_vec();
+        variables.push(("exit_code", "0".into()));
+        let on_success = self
+            .on_exit
+            .iter()
+            .find(|(code, _)| *code == 0)
+            .map_or(&self.on_success, |(_, actions)| actions);
+        for follow_up in on_success {
+            event!(
+                Level::INFO,
+                "Dry run, would follow up {description} with {}",
+                follow_up.description(&variables)
+            );
+            follow_up.log_follow_ups(&variables);
+        }
+        variables.pop();
+        let on_exit = self.on_exit.iter().filter(|(code, _)| *code != 0);
+        for (code, follow_ups) in on_exit {
+            for follow_up in follow_ups {
+                event!(
+                    Level::INFO,
+                    "Dry run, had {description} exited with {code} would \
+                     follow it up with {}",
+                    follow_up.description(&variables)
+                );
+            }
+        }
+        for follow_up in &self.on_failure {
+            event!(
+                Level::INFO,
+                "Dry run, had {description} failed would follow it up with {}",
+                follow_up.description(&variables)
+            );
+        }
+    }
+
+    /// Run the command once, its output if it exited with 0 or else the
+    /// code it exited with, if it did. `command` is as configured, the logs
+    /// show it without secrets
+    fn run_command(
+        &self,
+        command: &str,
+        variables: &[(&str, String)],
+        session: Option<u32>,
+    ) -> Result<String, Option<i32>> {
+        let shown = expand(command, variables);
+        // A job takes the processes the command starts along with it
+        let job = self.timeout.and_then(|_| Job::new().ok());
+        let (process, [stdout, _]) =
+            match self.spawn(command, variables, session, job.as_ref()) {
+                Ok(started) => started,
+                Err(err) => {
+                    event!(Level::ERROR, "Failed to start {shown} {err}");
+                    return Err(None);
+                }
+            };
+        event!(Level::INFO, "Started {shown}, pid {}", process.id());
+
+        // INFINITE is u32::MAX, so one less at most
+        let timeout_ms = self.timeout.map(|timeout| {
+            timeout.as_millis().min(u128::from(u32::MAX - 1)) as u32
+        });
+        let status = match process.wait(timeout_ms) {
+            Ok(Some(code)) => Ok(code as i32),
+            Ok(None) => {
+                let timeout = self.timeout.unwrap_or_default();
+                event!(
+                    Level::ERROR,
+                    "{shown} timed out after {timeout:?}, killing it"
+                );
+                let _ = match &job {
+                    Some(job) => job.terminate(1),
+                    None => process.kill(),
+                };
+                let _ = process.wait(None);
+                return Err(None);
+            }
+            Err(err) => Err(std::io::Error::other(err)),
+        };
+        let code = status.as_ref().ok().copied();
+        if !log_exit(&shown, status) {
+            return Err(code);
+        }
+        if self.name.is_none() {
+            return Ok(String::new());
+        }
+        // Anything it left running that still holds stdout delays this
+        let output = stdout.join().unwrap_or_default();
+        Ok(String::from_utf8_lossy(&output).trim_end().to_string())
+    }
+
+    /// Start `cmd /C command` with our environment settings, in `job` if
+    /// given, and threads capturing its stdout and stderr, see [`capture`].
+    /// Only a named action's stdout is kept. Secrets are filled in here,
+    /// and every value escaped, see [`expand_command`]
+    fn spawn(
+        &self,
+        command: &str,
+        variables: &[(&str, String)],
+        session: Option<u32>,
+        job: Option<&Job>,
+    ) -> std::io::Result<(ProcessHandle, [JoinHandle<Vec<u8>>; 2])> {
+        let command = &expand_command(command, variables)?;
+        let mut env: Vec<(String, String)> = variables
+            .iter()
+            .map(|(name, value)| {
+                (format!("RUSTY_LOCK_{}", name.to_uppercase()), value.clone())
+            })
+            .collect();
+        for (name, value) in &self.env {
+            // A value can be nothing but a reference to a secret
+            let value = match value.strip_prefix("cred:") {
+                Some(target) => read_credential(target).map_err(|err| {
+                    std::io::Error::other(format!("{value} {err}"))
+                })?,
+                None => expand_secrets(value, variables)?,
+            };
+            env.push((name.clone(), value));
+        }
+
+        let tag = Tag {
+            action: self.name.clone().unwrap_or_else(|| self.kind.to_string()),
+            event_id: variables
+                .iter()
+                .find(|(name, _)| *name == "event_id")
+                .map(|(_, id)| id.clone())
+                .unwrap_or_default(),
+            log: self.log.as_ref().map(|log| {
+                let path = expand(&log.path.to_string_lossy(), variables);
+                (PathBuf::from(path), log.max_size)
+            }),
+        };
+        let keep = self.name.is_some();
+
+        start_cmd(
+            command,
+            session,
+            self.cwd.as_deref(),
+            &env,
+            self.inherit_env,
+            job,
+            (tag, keep),
+        )
+    }
+}
+
+/// Start `cmd /C command`, passed to cmd as is, in `session` or else ours,
+/// with threads capturing its stdout and stderr, see [`capture`]. Its
+/// stdout is kept if the `tag`'s `keep` is set
+fn start_cmd(
+    command: &str,
+    session: Option<u32>,
+    cwd: Option<&Path>,
+    env: &[(String, String)],
+    inherit_env: bool,
+    job: Option<&Job>,
+    (tag, keep): (Tag, bool),
+) -> std::io::Result<(ProcessHandle, [JoinHandle<Vec<u8>>; 2])> {
+    let spawning = SPAWN_LOCK.lock().unwrap();
+    let pipes = Pipe::new().and_then(|stdout| Ok((stdout, Pipe::new()?)));
+    let (stdout, stderr) = pipes.map_err(std::io::Error::other)?;
+    let command_line = format!("cmd /C {command}");
+    let output = Some((&stdout, &stderr));
+    let process = match session {
+        Some(session) => create_process_as_user(
+            session,
+            &command_line,
+            cwd,
+            env,
+            inherit_env,
+            output,
+            job,
+        ),
+        None => {
+            create_process(&command_line, cwd, env, inherit_env, output, job)
+        }
+    }
+    .map_err(std::io::Error::other)?;
+    let (stdout, stderr) = (stdout.into_reader(), stderr.into_reader());
+    drop(spawning);
+    let output = [
+        capture(stdout, Stream::Stdout, tag.clone(), keep),
+        capture(stderr, Stream::Stderr, tag, false),
+    ];
+    Ok((process, output))
+}
+
+/// Run `cmd /C command` to completion for a script's `exec`, started the
+/// same way as a command action. Returns its exit code and trimmed stdout,
+/// what it prints is logged tagged with `caller`
+#[cfg(any(feature = "rhai", feature = "lua", feature = "wasm"))]
+pub(crate) fn exec(
+    caller: &str,
+    command: &str,
+) -> std::io::Result<(i32, String)> {
+    let tag = Tag {
+        action: caller.to_string(),
+        event_id: String::new(),
+        log: None,
+    };
+    let (process, [stdout, _]) =
+        start_cmd(command, None, None, &[], true, None, (tag, true))?;
+    let code = process.wait(None).map_err(std::io::Error::other)?;
+    let output = stdout.join().unwrap_or_default();
+    Ok((
+        code.unwrap_or_default() as i32,
+        String::from_utf8_lossy(&output).trim_end().to_string(),
+    ))
+}
+
+/// A file [`Action::log`] appends a command's output to, `log` in the config
+/// as the path or a table with `max_size` in bytes. The path can use the
+/// placeholders
+///
+/// ```toml
+/// on_lock = { command = "backup.cmd", log = { path = 'C:\Logs\backup.log', max_size = 65536 } }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputLog {
+    pub path: PathBuf,
+    /// Once the file would grow past this it is moved to `<path>.1`,
+    /// replacing the one before, and a new one started
+    pub max_size: u64,
+}
+
+impl OutputLog {
+    /// A log at `path` of at most 1 MiB
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Which of a command's outputs a line came from
+#[derive(Debug, Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// What the lines of one command's output are logged with
+#[derive(Debug, Clone)]
+struct Tag {
+    /// The action's name, or what it does if it has none
+    action: String,
+    event_id: String,
+    /// The expanded path of its [`OutputLog`] and the size limit
+    log: Option<(PathBuf, u64)>,
+}
+
+/// Log what a command prints on `stream` a line at a time as it comes,
+/// stdout as INFO and stderr as WARN, and append it to the action's own log
+/// if it has one. Returns it all if `keep`, once the command and anything
+/// it left running close the stream
+fn capture(
+    stream: impl Read + Send + 'static,
+    which: Stream,
+    tag: Tag,
+    keep: bool,
+) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let Tag {
+            action,
+            event_id,
+            mut log,
+        } = tag;
+        let mut kept = Vec::new();
+        for line in BufReader::new(stream).split(b'\n') {
+            let Ok(line) = line else {
+                break;
+            };
+            if keep {
+                kept.extend_from_slice(&line);
+                kept.push(b'\n');
+            }
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches('\r');
+            match which {
+                Stream::Stdout => {
+                    event!(Level::INFO, action, event_id, "{line}")
+                }
+                Stream::Stderr => {
+                    event!(Level::WARN, action, event_id, "{line}")
+                }
+            }
+            if let Some((path, max_size)) = &log {
+                let stream = match which {
+                    Stream::Stdout => "stdout",
+                    Stream::Stderr => "stderr",
+                };
+                let line = format!("{} {event_id} {stream} {line}\r\n", now());
+                // Logged once rather than for every line
+                if let Err(err) = append_log(path, *max_size, &line) {
+                    event!(Level::ERROR, "Writing {}, {err}", path.display());
+                    log = None;
+                }
+            }
+        }
+        kept
+    })
+}
+
+/// Append `line` to the file at `path`, first moving it to `<path>.1` if
+/// it would grow past `max_size`
+fn append_log(path: &Path, max_size: u64, line: &str) -> std::io::Result<()> {
+    // Commands running together can share a file
+    static WRITING: Mutex<()> = Mutex::new(());
+    let _writing = WRITING.lock().unwrap();
+    let size = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+    if size > 0 && size + line.len() as u64 > max_size {
+        let mut old = path.as_os_str().to_owned();
+        old.push(".1");
+        std::fs::rename(path, old)?;
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+/// The local time for a line of an [`OutputLog`]
+fn now() -> String {
+    let time = local_time();
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+        time.wYear,
+        time.wMonth,
+        time.wDay,
+        time.wHour,
+        time.wMinute,
+        time.wSecond
+    )
+}
+
+/// How often and how far apart to rerun a failed action, the fields of the
+/// `retry` table in the config with the delays in seconds
+///
+/// ```toml
+/// on_lock = { command = "notify.cmd", retry = { attempts = 5, delay = 2, jitter = true } }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// How many times to run the command at most, including the first
+    pub attempts: u32,
+    /// The wait before the second attempt, doubled for each one after
+    pub delay: Duration,
+    /// The longest a wait can grow to
+    pub max_delay: Option<Duration>,
+    /// Wait a random time between half and all of each delay, so machines
+    /// locking together don't retry in step
+    pub jitter: bool,
+}
+
+impl Default for Retry {
+    /// 3 attempts a second apart then 2 seconds, without jitter
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            delay: Duration::from_secs(1),
+            max_delay: None,
+            jitter: false,
+        }
+    }
+}
+
+impl Retry {
+    /// The wait after failed attempt number `attempt`, counting from 1
+    fn delay(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(31);
+        let mut delay = self.delay.saturating_mul(1 << doublings);
+        if let Some(max_delay) = self.max_delay {
+            delay = delay.min(max_delay);
+        }
+        if self.jitter {
+            // Good enough randomness without a dependency, each RandomState
+            // is seeded differently
+            let random = RandomState::new().build_hasher().finish();
+            let fraction = (random % 1000) as u32;
+            delay = delay / 2 + delay / 2 * fraction / 1000;
+        }
+        delay
+    }
+}
+
+/// At most `max` runs in any `per`, the ones over it are skipped. The
+/// `rate_limit` table in the config with `per` in seconds, for an action or
+/// at the top level for every action together
+///
+/// ```toml
+/// rate_limit = { max = 100, per = 60 }
+/// on_remote_connect = { command = "notify.cmd", rate_limit = { max = 5, per = 60 } }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    pub max: u32,
+    pub per: Duration,
+    /// When the runs within the last `per` started, shared by clones as
+    /// the runner clones its actions for each event
+    runs: Arc<Mutex<VecDeque<Instant>>>,
+}
+
+impl RateLimit {
+    pub fn new(max: u32, per: Duration) -> Self {
+        Self {
+            max,
+            per,
+            runs: Arc::default(),
+        }
+    }
+
+    /// Count a run, false if it would go over the limit
+    fn allow(&self) -> bool {
+        let now = Instant::now();
+        let mut runs = self.runs.lock().unwrap();
+        while runs
+            .front()
+            .is_some_and(|&start| now.duration_since(start) >= self.per)
+        {
+            runs.pop_front();
+        }
+        if runs.len() >= self.max as usize {
+            return false;
+        }
+        runs.push_back(now);
+        true
+    }
+}
+
+// The runs so far aren't part of a limit's configuration
+impl PartialEq for RateLimit {
+    fn eq(&self, other: &Self) -> bool {
+        self.max == other.max && self.per == other.per
+    }
+}
+
+impl Eq for RateLimit {}
+
+impl core::fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "over the limit of {} every {:?}", self.max, self.per)
+    }
+}
+
+impl From<ActionKind> for Action {
+    fn from(kind: ActionKind) -> Self {
+        Self {
+            kind,
+            timeout: None,
+            retry: None,
+            cwd: None,
+            env: Vec::new(),
+            inherit_env: true,
+            rate_limit: None,
+            name: None,
+            after: Vec::new(),
+            log: None,
+            on_success: Vec::new(),
+            on_failure: Vec::new(),
+            on_exit: Vec::new(),
+        }
+    }
+}
+
+impl From<String> for Action {
+    fn from(command: String) -> Self {
+        Self::new(command)
+    }
+}
+
+impl From<&str> for Action {
+    fn from(command: &str) -> Self {
+        Self::new(command)
+    }
+}
+
+/// Log how a command ended, true if it succeeded
+fn log_exit(command: &str, status: std::io::Result<i32>) -> bool {
+    match status {
+        Ok(0) => true,
+        Ok(code) => {
+            event!(Level::ERROR, "{command} failed, exit code {code}");
+            false
+        }
+        Err(err) => {
+            event!(Level::ERROR, "Failed to wait for {command} {err}");
+            false
+        }
+    }
+}
+
+/// How the actions for one event run, `mode` and `concurrency` in the
+/// config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Execution {
+    /// All at once, or at most `limit` at a time taking the next in order as
+    /// each finishes
+    Parallel { limit: Option<NonZeroUsize> },
+    /// One after the other in order, stopping at the first that fails or
+    /// times out
+    Sequential,
+}
+
+impl Default for Execution {
+    fn default() -> Self {
+        Self::Parallel { limit: None }
+    }
+}
+
+/// What happens when one of an event's actions fails or times out,
+/// `on_failure` in the config. Without one sequential actions abort and
+/// parallel ones continue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnFailure {
+    /// Start none of the actions not yet started
+    Abort,
+    /// Carry on with the rest
+    Continue,
+    /// Start none of the actions not yet started and run this instead, once
+    /// however many fail
+    Fallback(Box<Action>),
+    /// Carry on with the rest and tell the user of the event's session which
+    /// one failed in a message box
+    Notify,
+}
+
+/// The actions to run for each state, on a pool of worker threads so the
+/// message loop keeps going.
+///
+/// `{event}`, `{session_id}`, `{username}`, `{domain}`, `{timestamp}` and
+/// `{client_ip}` in a command are replaced with the event's details, empty
+/// if unknown, and `{event_id}` with a number telling its actions apart in
+/// the log, each escaped for `cmd`. Each command also sees them as
+/// `RUSTY_LOCK_EVENT`, `RUSTY_LOCK_SESSION_ID` and so on. The timestamp is
+/// in seconds since the Unix epoch. What commands print is logged tagged
+/// with the action and event id, see [`Action::log`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActionRunner {
+    actions: HashMap<WtsState, Vec<Action>>,
+    execution: HashMap<WtsState, Execution>,
+    scheduled: HashMap<Schedule, Vec<Action>>,
+    scheduled_execution: HashMap<Schedule, Execution>,
+    on_failure: HashMap<WtsState, OnFailure>,
+    scheduled_on_failure: HashMap<Schedule, OnFailure>,
+    debounce: Option<Duration>,
+    rate_limit: Option<RateLimit>,
+    workers: Option<NonZeroUsize>,
+    journal: Option<PathBuf>,
+    run_initial_state: Option<bool>,
+    scope: Option<SessionScope>,
+    dry_run: bool,
+    profiles: BTreeMap<String, ActionRunner>,
+    active: ActiveProfile,
+    pending: Pending,
+    pool: Pool,
+}
+
+/// The profile whose actions run too, shared by clones so it can be switched
+/// while the monitor holds one
+#[derive(Debug, Clone, Default)]
+struct ActiveProfile(Arc<Mutex<Option<String>>>);
+
+impl PartialEq for ActiveProfile {
+    fn eq(&self, other: &Self) -> bool {
+        // Locking the one mutex twice would deadlock
+        Arc::ptr_eq(&self.0, &other.0)
+            || *self.0.lock().unwrap() == *other.0.lock().unwrap()
+    }
+}
+
+impl Eq for ActiveProfile {}
+
+/// The events [`ActionRunner::debounce`] is holding back, by state and
+/// session, with the token of the latest so a repeat replaces the one before
+#[derive(Debug, Clone, Default)]
+struct Pending(Arc<Mutex<Held>>);
+
+#[derive(Debug, Default)]
+struct Held {
+    /// The last token handed out
+    token: u64,
+    tokens: HashMap<(WtsState, u32), u64>,
+}
+
+impl Pending {
+    /// Hold back `key`, returns the token to release it with
+    fn hold(&self, key: (WtsState, u32)) -> u64 {
+        let mut held = self.0.lock().unwrap();
+        held.token += 1;
+        let token = held.token;
+        held.tokens.insert(key, token);
+        token
+    }
+
+    /// Drop `key` if it is being held back, true if it was
+    fn cancel(&self, key: (WtsState, u32)) -> bool {
+        self.0.lock().unwrap().tokens.remove(&key).is_some()
+    }
+
+    /// Stop holding back `key`, true if it is still held with `token`
+    fn release(&self, key: (WtsState, u32), token: u64) -> bool {
+        let tokens = &mut self.0.lock().unwrap().tokens;
+        if tokens.get(&key) != Some(&token) {
+            return false;
+        }
+        tokens.remove(&key);
+        true
+    }
+}
+
+// What is pending at the moment isn't part of a runner's configuration
+impl PartialEq for Pending {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Pending {}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new(DEFAULT_WORKERS)
+    }
+}
+
+// Set by `workers`, which is compared instead
+impl PartialEq for Pool {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Pool {}
+
+impl ActionRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also run `action` when `state` arrives, after any already added
+    pub fn add(mut self, state: WtsState, action: impl Into<Action>) -> Self {
+        self.actions.entry(state).or_default().push(action.into());
+        self
+    }
+
+    /// Run the actions for `state` this way, they run in parallel otherwise
+    pub fn execution(mut self, state: WtsState, execution: Execution) -> Self {
+        self.execution.insert(state, execution);
+        self
+    }
+
+    /// Also run `action` each time `schedule` comes round, after any already
+    /// added. The monitor has to be given the schedule too, see
+    /// [`ActionRunner::schedules`]
+    pub fn at(mut self, schedule: Schedule, action: impl Into<Action>) -> Self {
+        self.scheduled
+            .entry(schedule)
+            .or_default()
+            .push(action.into());
+        self
+    }
+
+    /// Run the actions at `schedule` this way, they run in parallel
+    /// otherwise
+    pub fn schedule_execution(
+        mut self,
+        schedule: Schedule,
+        execution: Execution,
+    ) -> Self {
+        self.scheduled_execution.insert(schedule, execution);
+        self
+    }
+
+    /// What to do when one of the actions for `state` fails
+    pub fn on_failure(
+        mut self,
+        state: WtsState,
+        on_failure: OnFailure,
+    ) -> Self {
+        self.on_failure.insert(state, on_failure);
+        self
+    }
+
+    /// What to do when one of the actions at `schedule` fails
+    pub fn schedule_on_failure(
+        mut self,
+        schedule: Schedule,
+        on_failure: OnFailure,
+    ) -> Self {
+        self.scheduled_on_failure.insert(schedule, on_failure);
+        self
+    }
+
+    /// Hold back the actions for a state that can be undone, such as Lock,
+    /// for `window`. If the opposite state arrives for the same session in
+    /// that time, such as an Unlock straight after a fat fingered Win+L,
+    /// neither one's actions run
+    pub fn debounce(mut self, window: Duration) -> Self {
+        self.debounce = Some(window);
+        self
+    }
+
+    /// Skip any action once this many have run lately, across every event
+    /// and schedule, on top of each action's own limit
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Run the actions on at most `workers` threads, 16 unless set. A burst
+    /// of events beyond that, such as every session of a terminal server
+    /// locking at once, waits for one to be free rather than starting
+    /// another thread and process for each
+    pub fn workers(mut self, workers: NonZeroUsize) -> Self {
+        self.workers = Some(workers);
+        self.pool = Pool::new(workers);
+        self
+    }
+
+    /// Keep a file in `dir` for each event whose actions haven't all
+    /// finished, so that if the monitor or machine dies first
+    /// [`ActionRunner::resume`] runs the rest when it next starts. An action
+    /// cut short as it finished runs again
+    pub fn journal(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.journal = Some(dir.into());
+        self
+    }
+
+    /// Also run the actions for the [`SessionEvent::initial`] event a monitor
+    /// hands out at startup, such as the Unlock actions when started on an
+    /// unlocked desktop. Off by default, as nothing happened
+    pub fn run_initial_state(mut self, run_initial_state: bool) -> Self {
+        self.run_initial_state = Some(run_initial_state);
+        self
+    }
+
+    /// Ask for the monitor to watch every session on the machine, or only its
+    /// own, see [`ActionRunner::scope`]
+    pub fn all_sessions(mut self, all_sessions: bool) -> Self {
+        self.scope = Some(SessionScope::from_all_sessions(all_sessions));
+        self
+    }
+
+    /// Only log what each action would do, with its placeholders filled in,
+    /// and the follow-ups it would start, for trying a config out.
+    /// Debouncing and rate limits still apply
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Add a profile called `name`, whose actions run after these ones for
+    /// the same event while it is active, see
+    /// [`ActionRunner::switch_profile`]. Its execution and on_failure win
+    /// over ours, the rest of it is ignored
+    pub fn profile(
+        mut self,
+        name: impl Into<String>,
+        profile: ActionRunner,
+    ) -> Self {
+        self.profiles.insert(name.into(), profile);
+        self
+    }
+
+    /// Make the profile called `name` the active one, or none, for this
+    /// runner and its clones. False, leaving it as it was, if there's no
+    /// such profile
+    pub fn switch_profile(&self, name: Option<&str>) -> bool {
+        if name.is_some_and(|name| !self.profiles.contains_key(name)) {
+            return false;
+        }
+        let mut active = self.active.0.lock().unwrap();
+        if active.as_deref() != name {
+            event!(Level::INFO, "Profile {}", name.unwrap_or("none"));
+            *active = name.map(str::to_string);
+        }
+        true
+    }
+
+    /// The name of the active profile
+    pub fn active_profile(&self) -> Option<String> {
+        self.active.0.lock().unwrap().clone()
+    }
+
+    /// The names of the profiles, in order
+    pub fn profiles(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    /// Read a config file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Where the configs are looked for when none is given, in the order
+    /// they are layered: the machine's `%ProgramData%\rusty-lock\config.toml`
+    /// then the user's `%APPDATA%\rusty-lock\config.toml`
+    pub fn config_paths() -> Vec<PathBuf> {
+        ["ProgramData", "APPDATA"]
+            .into_iter()
+            .filter_map(std::env::var_os)
+            .map(|dir| {
+                PathBuf::from(dir).join("rusty-lock").join("config.toml")
+            })
+            .collect()
+    }
+
+    /// Read each of `paths` that exists and layer them in order, see
+    /// [`ActionRunner::layer`]. None existing is an empty runner
+    pub fn load_layered(paths: &[PathBuf]) -> Result<Self, ConfigError> {
+        let mut runner = Self::new();
+        for path in paths {
+            let in_file = |error| ConfigError::File {
+                path: path.clone(),
+                error: Box::new(error),
+            };
+            let layer = match Self::load(path) {
+                Ok(layer) => layer,
+                Err(ConfigError::Io(err))
+                    if err.kind() == std::io::ErrorKind::NotFound =>
+                {
+                    continue
+                }
+                Err(err) => return Err(in_file(err)),
+            };
+            runner = runner.layer(layer);
+            runner
+                .check_after()
+                .map_err(|message| in_file(ConfigError::Layer(message)))?;
+        }
+        Ok(runner)
+    }
+
+    /// Put `over` on top of this. Its actions for each state, schedule and
+    /// profile run after ours, while what it sets of debounce, rate_limit,
+    /// workers, journal, each execution and on_failure, and the active
+    /// profile wins
+    pub fn layer(mut self, over: ActionRunner) -> Self {
+        let active = over.active_profile().or_else(|| self.active_profile());
+        for (state, actions) in over.actions {
+            self.actions.entry(state).or_default().extend(actions);
+        }
+        self.execution.extend(over.execution);
+        self.on_failure.extend(over.on_failure);
+        for (schedule, actions) in over.scheduled {
+            self.scheduled.entry(schedule).or_default().extend(actions);
+        }
+        self.scheduled_execution.extend(over.scheduled_execution);
+        self.scheduled_on_failure.extend(over.scheduled_on_failure);
+        self.debounce = over.debounce.or(self.debounce);
+        self.rate_limit = over.rate_limit.or(self.rate_limit);
+        self.journal = over.journal.or(self.journal);
+        self.run_initial_state =
+            over.run_initial_state.or(self.run_initial_state);
+        self.scope = over.scope.or(self.scope);
+        if over.workers.is_some() {
+            self.workers = over.workers;
+            self.pool = over.pool;
+        }
+        for (name, profile) in over.profiles {
+            let layered = match self.profiles.remove(&name) {
+                Some(under) => under.layer(profile),
+                None => profile,
+            };
+            self.profiles.insert(name, layered);
+        }
+        self.switch_profile(active.as_deref());
+        self
+    }
+
+    /// The states that have actions in any profile, for
+    /// [`SessionMonitor::on`](crate::SessionMonitor::on)
+    pub fn states(&self) -> StateSet {
+        let runners = || Some(self).into_iter().chain(self.profiles.values());
+        let with_actions = runners().flat_map(|runner| runner.actions.keys());
+        let mut states: StateSet = with_actions.clone().copied().collect();
+        // A debounced state is cancelled by its opposite, which may have no
+        // actions of its own
+        if self.debounce.is_some() {
+            for state in with_actions.filter_map(|&s| opposite(s)) {
+                states.insert(state);
+            }
+        }
+        if runners().any(|runner| !runner.scheduled.is_empty()) {
+            states.insert(WtsState::Scheduled);
+        }
+        states
+    }
+
+    /// The scope the config asks for with `all_sessions`, if it does, for
+    /// [`SessionMonitorBuilder::scope`](crate::SessionMonitorBuilder::scope)
+    pub fn scope(&self) -> Option<SessionScope> {
+        self.scope
+    }
+
+    /// The schedules that have actions in any profile, for
+    /// [`SessionMonitorBuilder::schedule`](crate::SessionMonitorBuilder::schedule)
+    pub fn schedules(&self) -> impl Iterator<Item = &Schedule> {
+        let profiles = self.profiles.values();
+        let schedules: HashSet<&Schedule> = self
+            .scheduled
+            .keys()
+            .chain(profiles.flat_map(|profile| profile.scheduled.keys()))
+            .collect();
+        schedules.into_iter()
+    }
+
+    /// The actions run for `state`, before any of the active profile's
+    pub fn actions(&self, state: WtsState) -> &[Action] {
+        self.actions.get(&state).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether a Lock in any profile saves a screenshot, for which the
+    /// desktop is captured while unlocked. Not for a dry run
+    fn screenshots_on_lock(&self) -> bool {
+        !self.dry_run
+            && Some(self)
+                .into_iter()
+                .chain(self.profiles.values())
+                .flat_map(|runner| runner.actions(WtsState::Lock))
+                .any(|action| {
+                    matches!(action.kind, ActionKind::Screenshot { .. })
+                })
+    }
+
+    /// Swap in `new`, keeping the events held back by debouncing so an
+    /// opposite state still cancels them, and the workers unless their
+    /// number changed. A profile switched to stays active while `new` has it
+    pub(crate) fn replace(&mut self, new: Self) {
+        builtin::keep_frames(new.screenshots_on_lock());
+        let profile = self
+            .active_profile()
+            .filter(|name| new.profiles.contains_key(name))
+            .or_else(|| new.active_profile());
+        let (pending, active) = (self.pending.clone(), self.active.clone());
+        // The old workers finish what they have queued
+        let pool = if new.pool.size() == self.pool.size() {
+            self.pool.clone()
+        } else {
+            new.pool
+        };
+        *self = Self {
+            pending,
+            active,
+            pool,
+            ..new
+        };
+        self.switch_profile(profile.as_deref());
+    }
+
+    /// What changed from this runner to `new`, a line each, for logging a
+    /// reload
+    pub(crate) fn changes(&self, new: &Self) -> Vec<String> {
+        let mut changes = Vec::new();
+        let mut states: Vec<WtsState> = [self, new]
+            .iter()
+            .flat_map(|runner| {
+                runner
+                    .actions
+                    .keys()
+                    .chain(runner.execution.keys())
+                    .chain(runner.on_failure.keys())
+            })
+            .copied()
+            .collect();
+        states.sort_by_key(|&state| usize::from(state));
+        states.dedup();
+        for state in states {
+            let key = format!("on_{}", state.name());
+            list_changes(
+                &key,
+                self.actions(state),
+                new.actions(state),
+                &mut changes,
+            );
+            if self.execution.get(&state) != new.execution.get(&state) {
+                let execution = new.execution.get(&state).copied();
+                changes.push(format!(
+                    "{key} execution is now {:?}",
+                    execution.unwrap_or_default()
+                ));
+            }
+            if self.on_failure.get(&state) != new.on_failure.get(&state) {
+                changes.push(format!("{key} on_failure changed"));
+            }
+        }
+        let mut schedules: Vec<&Schedule> = [self, new]
+            .iter()
+            .flat_map(|runner| {
+                runner
+                    .scheduled
+                    .keys()
+                    .chain(runner.scheduled_execution.keys())
+                    .chain(runner.scheduled_on_failure.keys())
+            })
+            .collect();
+        schedules.sort_by_key(|schedule| schedule.to_string());
+        schedules.dedup();
+        for schedule in schedules {
+            let key = format!("schedule {schedule}");
+            list_changes(
+                &key,
+                self.scheduled.get(schedule).map_or(&[], Vec::as_slice),
+                new.scheduled.get(schedule).map_or(&[], Vec::as_slice),
+                &mut changes,
+            );
+            let (old_execution, execution) = (
+                self.scheduled_execution.get(schedule),
+                new.scheduled_execution.get(schedule),
+            );
+            if old_execution != execution {
+                changes.push(format!(
+                    "{key} execution is now {:?}",
+                    execution.copied().unwrap_or_default()
+                ));
+            }
+            if self.scheduled_on_failure.get(schedule)
+                != new.scheduled_on_failure.get(schedule)
+            {
+                changes.push(format!("{key} on_failure changed"));
+            }
+        }
+        if self.debounce != new.debounce {
+            changes.push(format!("debounce is now {:?}", new.debounce));
+        }
+        if self.rate_limit != new.rate_limit {
+            match &new.rate_limit {
+                Some(rate_limit) => {
+                    changes.push(format!("rate_limit is now {rate_limit}"))
+                }
+                None => changes.push("rate_limit removed".into()),
+            }
+        }
+        if self.journal != new.journal {
+            match &new.journal {
+                Some(dir) => {
+                    changes.push(format!("journal is now {}", dir.display()))
+                }
+                None => changes.push("journal removed".into()),
+            }
+        }
+        if self.workers != new.workers {
+            changes.push(format!("workers is now {}", new.pool.size()));
+        }
+        if self.scope != new.scope {
+            changes.push(
+                "all_sessions changed, restart the monitor for it to apply"
+                    .into(),
+            );
+        }
+        if self.run_initial_state != new.run_initial_state {
+            changes.push(format!(
+                "run_initial_state is now {}",
+                new.run_initial_state.unwrap_or_default()
+            ));
+        }
+        let names: BTreeSet<&String> =
+            self.profiles.keys().chain(new.profiles.keys()).collect();
+        for name in names {
+            match (self.profiles.get(name), new.profiles.get(name)) {
+                (Some(old), Some(profile)) => changes.extend(
+                    old.changes(profile)
+                        .into_iter()
+                        .map(|change| format!("profile {name} {change}")),
+                ),
+                (None, _) => changes.push(format!("profile {name} added")),
+                (_, None) => changes.push(format!("profile {name} removed")),
+            }
+        }
+        changes
+    }
+
+    /// Start the actions for `event`'s state, or its schedule for a
+    /// Scheduled event, failures are logged. The state at startup is skipped
+    /// unless [`ActionRunner::run_initial_state`] is set
+    pub fn run(&self, event: &SessionEvent) {
+        if event.initial && self.run_initial_state != Some(true) {
+            event!(
+                Level::DEBUG,
+                "Not running the actions for {} at startup",
+                event.state.name()
+            );
+            return;
+        }
+        let schedule = event.schedule.as_ref();
+        let (actions, execution, on_failure) =
+            self.with_profile(event.state, schedule);
+        let session_id = event.session_id;
+        let debounce = self.debounce.zip(opposite(event.state));
+        if let Some((_, opposite)) = debounce {
+            if self.pending.cancel((opposite, session_id)) {
+                event!(
+                    Level::INFO,
+                    "{} straight after {}, skipping the actions for both",
+                    event.state.name(),
+                    opposite.name()
+                );
+                return;
+            }
+        }
+        let actions: Arc<[Action]> = actions.into();
+        if actions.is_empty() {
+            return;
+        }
+        if let Err(err) = check_after(&actions) {
+            event!(
+                Level::ERROR,
+                "Not running the actions for {}, {err}",
+                event.state.name()
+            );
+            return;
+        }
+        let run = self.begin(
+            (event.state, schedule),
+            variables(event),
+            &actions,
+            on_failure,
+            HashMap::new(),
+        );
+        let Some((window, _)) = debounce else {
+            start(actions, run, execution, &self.pool);
+            return;
+        };
+        let key = (event.state, session_id);
+        let token = self.pending.hold(key);
+        let pending = self.pending.clone();
+        let pool = self.pool.clone();
+        self.pool.execute_after(window, move || {
+            if pending.release(key, token) {
+                start(actions, run, execution, &pool);
+            }
+        });
+    }
+
+    /// Run the actions the journal says were cut short, see
+    /// [`ActionRunner::journal`], with the details of the events they were
+    /// for. Each is found in the config by what it does, those no longer in
+    /// it are skipped. For calling once before the monitor runs, it also
+    /// starts capturing the desktop if a Lock saves a screenshot
+    pub fn resume(&self) {
+        builtin::keep_frames(self.screenshots_on_lock());
+        let Some(dir) = &self.journal else {
+            return;
+        };
+        if self.dry_run {
+            event!(
+                Level::INFO,
+                "Dry run, leaving the journal in {}",
+                dir.display()
+            );
+            return;
+        }
+        for unfinished in journal::unfinished(dir) {
+            self.resume_run(unfinished);
+        }
+    }
+
+    /// Start what is left of `unfinished` and drop it from the journal
+    fn resume_run(&self, unfinished: Unfinished) {
+        let schedule = unfinished.schedule.as_ref();
+        let (mut config, execution, on_failure) =
+            self.with_profile(unfinished.state, schedule);
+        let mut actions = Vec::new();
+        for (description, _) in
+            unfinished.actions.iter().filter(|(_, done)| !done)
+        {
+            let found = config
+                .iter()
+                .position(|action| action.kind.to_string() == *description);
+            match found {
+                Some(i) => actions.push(config.remove(i)),
+                None => event!(
+                    Level::WARN,
+                    "Not resuming {description}, it's no longer in the config"
+                ),
+            }
+        }
+        let event = unfinished.variable("event");
+        let finished = unfinished.outputs.keys().collect();
+        if let Err(err) = check_after_from(finished, &actions) {
+            event!(Level::ERROR, "Not resuming the actions for {event}, {err}");
+            actions.clear();
+        }
+        if !actions.is_empty() {
+            event!(
+                Level::INFO,
+                "Resuming {} action(s) for {event} at {}",
+                actions.len(),
+                unfinished.variable("timestamp")
+            );
+            let variables = [
+                "event",
+                "session_id",
+                "username",
+                "domain",
+                "timestamp",
+                "client_ip",
+            ]
+            .map(|name| (name, unfinished.variable(name)));
+            // Journaled again before this one goes
+            let run = self.begin(
+                (unfinished.state, schedule),
+                variables,
+                &actions,
+                on_failure,
+                unfinished.outputs.clone(),
+            );
+            start(actions.into(), run, execution, &self.pool);
+        }
+        if let Err(err) = std::fs::remove_file(&unfinished.path) {
+            let path = unfinished.path.display();
+            event!(Level::ERROR, "Removing {path}, {err}");
+        }
+    }
+
+    /// The actions for `state` or `schedule` with the active profile's after
+    /// them, and how to run them
+    fn with_profile(
+        &self,
+        state: WtsState,
+        schedule: Option<&Schedule>,
+    ) -> (Vec<Action>, Execution, OnFailure) {
+        let (actions, mut execution, mut on_failure) =
+            self.for_event(state, schedule);
+        let mut actions = actions.to_vec();
+        let profile = self
+            .active_profile()
+            .and_then(|name| self.profiles.get(&name));
+        if let Some(profile) = profile {
+            let (more, profile_execution, profile_on_failure) =
+                profile.for_event(state, schedule);
+            actions.extend_from_slice(more);
+            execution = profile_execution.or(execution);
+            on_failure = profile_on_failure.or(on_failure);
+        }
+        let execution = execution.copied().unwrap_or_default();
+        let on_failure = on_failure.cloned().unwrap_or(match execution {
+            Execution::Sequential => OnFailure::Abort,
+            Execution::Parallel { .. } => OnFailure::Continue,
+        });
+        (actions, execution, on_failure)
+    }
+
+    /// The actions for `state`, or `schedule` for a Scheduled event, and how
+    /// to run them, leaving out the profiles
+    fn for_event(
+        &self,
+        state: WtsState,
+        schedule: Option<&Schedule>,
+    ) -> (&[Action], Option<&Execution>, Option<&OnFailure>) {
+        match schedule {
+            Some(schedule) => (
+                self.scheduled.get(schedule).map_or(&[][..], Vec::as_slice),
+                self.scheduled_execution.get(schedule),
+                self.scheduled_on_failure.get(schedule),
+            ),
+            None => (
+                self.actions(state),
+                self.execution.get(&state),
+                self.on_failure.get(&state),
+            ),
+        }
+    }
+
+    /// What `actions` for `state`, or `schedule`, run with, journaled unless
+    /// this is a dry run, with the `outputs` of named ones already finished
+    fn begin(
+        &self,
+        (state, schedule): (WtsState, Option<&Schedule>),
+        variables: [(&'static str, String); 6],
+        actions: &[Action],
+        on_failure: OnFailure,
+        outputs: HashMap<String, Option<String>>,
+    ) -> Arc<Run> {
+        let journal = self.journal.as_ref().filter(|_| !self.dry_run);
+        let journal = journal.and_then(|dir| {
+            let descriptions: Vec<String> = actions
+                .iter()
+                .map(|action| action.kind.to_string())
+                .collect();
+            Entry::create(dir, state, schedule, &variables, &descriptions)
+                .map_err(|err| {
+                    event!(
+                        Level::ERROR,
+                        "Journaling in {}, {err}",
+                        dir.display()
+                    )
+                })
+                .ok()
+        });
+        let session_id = variables[1].1.parse().unwrap_or_default();
+        Arc::new(Run {
+            variables,
+            session_id,
+            // A service in session 0 starts commands on the user's desktop
+            // instead, where they would otherwise run unseen
+            as_user: session_id != 0 && matches!(current_session_id(), Ok(0)),
+            rate_limit: self.rate_limit.clone(),
+            dry_run: self.dry_run,
+            on_failure,
+            aborted: AtomicBool::new(false),
+            outputs: Mutex::new(outputs),
+            finished: Condvar::new(),
+            journal,
+            id: EVENT_IDS.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+
+    /// Apply one `key = value` of the config
+    fn entry(mut self, key: &str, value: Value) -> Result<Self, String> {
+        if key == "debounce" {
+            let Value::Integer(secs) = value else {
+                return Err(format!("debounce can't be {}", value.kind()));
+            };
+            self = self.debounce(seconds("debounce", secs)?);
+            return Ok(self);
+        }
+        if key == "rate_limit" {
+            self = self.rate_limit(parse_rate_limit(value)?);
+            return Ok(self);
+        }
+        if key == "journal" {
+            let Value::String(dir) = value else {
+                return Err(format!("journal can't be {}", value.kind()));
+            };
+            self = self.journal(dir);
+            return Ok(self);
+        }
+        if key == "all_sessions" {
+            let Value::Boolean(all_sessions) = value else {
+                return Err(format!("all_sessions can't be {}", value.kind()));
+            };
+            self = self.all_sessions(all_sessions);
+            return Ok(self);
+        }
+        if key == "run_initial_state" {
+            let Value::Boolean(run) = value else {
+                return Err(format!(
+                    "run_initial_state can't be {}",
+                    value.kind()
+                ));
+            };
+            self = self.run_initial_state(run);
+            return Ok(self);
+        }
+        if key == "workers" {
+            let workers = match value {
+                Value::Integer(workers) => {
+                    usize::try_from(workers).ok().and_then(NonZeroUsize::new)
+                }
+                _ => return Err(format!("workers can't be {}", value.kind())),
+            };
+            let Some(workers) = workers else {
+                return Err("workers has to be at least 1".into());
+            };
+            self = self.workers(workers);
+            return Ok(self);
+        }
+        if key == "schedule" {
+            let tables = match value {
+                Value::Array(values) => values,
+                value => vec![value],
+            };
+            for table in tables {
+                let (schedule, fields) = parse_schedule(table)?;
+                let ActionList {
+                    execution,
+                    on_failure,
+                    actions,
+                } = parse_actions(Value::Table(fields))?;
+                if let Some(execution) = execution {
+                    self = self.schedule_execution(schedule.clone(), execution);
+                }
+                if let Some(on_failure) = on_failure {
+                    self =
+                        self.schedule_on_failure(schedule.clone(), on_failure);
+                }
+                for action in actions {
+                    let action = parse_action(action)?;
+                    self = self.at(schedule.clone(), action);
+                }
+            }
+            return Ok(self);
+        }
+        let state = key
+            .strip_prefix("on_")
+            .and_then(|name| name.parse().ok())
+            // Scheduled actions go under schedule, with their cron
+            .filter(|&state| state != WtsState::Scheduled)
+            .ok_or_else(|| format!("unknown key {key}"))?;
+        let ActionList {
+            execution,
+            on_failure,
+            actions,
+        } = parse_actions(value)?;
+        if let Some(execution) = execution {
+            self = self.execution(state, execution);
+        }
+        if let Some(on_failure) = on_failure {
+            self = self.on_failure(state, on_failure);
+        }
+        for action in actions {
+            self = self.add(state, parse_action(action)?);
+        }
+        Ok(self)
+    }
+
+    /// That each `after` names an earlier action for the same event, counting
+    /// those of each profile after ours
+    fn check_after(&self) -> Result<(), String> {
+        let lists = self.actions.values().chain(self.scheduled.values());
+        for actions in lists {
+            check_after(actions)?;
+        }
+        for profile in self.profiles.values() {
+            for (state, actions) in &profile.actions {
+                check_after(&[self.actions(*state), actions].concat())?;
+            }
+            for (schedule, actions) in &profile.scheduled {
+                let ours = self.scheduled.get(schedule);
+                let ours = ours.map_or(&[][..], Vec::as_slice);
+                check_after(&[ours, actions].concat())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The actions removed from and added to `key`, and whether the rest were
+/// reordered, for [`ActionRunner::changes`]
+fn list_changes(
+    key: &str,
+    old: &[Action],
+    new: &[Action],
+    changes: &mut Vec<String>,
+) {
+    let removed: Vec<&Action> =
+        old.iter().filter(|action| !new.contains(action)).collect();
+    let added: Vec<&Action> =
+        new.iter().filter(|action| !old.contains(action)).collect();
+    for action in &removed {
+        changes.push(format!("{key} removed {}", action.kind));
+    }
+    for action in &added {
+        changes.push(format!("{key} added {}", action.kind));
+    }
+    if removed.is_empty() && added.is_empty() && old != new {
+        changes.push(format!("{key} reordered"));
+    }
+}
+
+/// The state that undoes `state`, for [`ActionRunner::debounce`]
+fn opposite(state: WtsState) -> Option<WtsState> {
+    match state {
+        WtsState::Lock => Some(WtsState::Unlock),
+        WtsState::Unlock => Some(WtsState::Lock),
+        WtsState::ConsoleConnect => Some(WtsState::ConsoleDisconnect),
+        WtsState::ConsoleDisconnect => Some(WtsState::ConsoleConnect),
+        WtsState::RemoteConnect => Some(WtsState::RemoteDisconnnect),
+        WtsState::RemoteDisconnnect => Some(WtsState::RemoteConnect),
+        WtsState::ScreensaverStart => Some(WtsState::ScreensaverStop),
+        WtsState::ScreensaverStop => Some(WtsState::ScreensaverStart),
+        WtsState::LidClosed => Some(WtsState::LidOpened),
+        WtsState::LidOpened => Some(WtsState::LidClosed),
+        _ => None,
+    }
+}
+
+/// The id of the next event whose actions run, see [`Run::id`]
+static EVENT_IDS: AtomicU64 = AtomicU64::new(1);
+
+/// What the actions for one event run with
+struct Run {
+    variables: [(&'static str, String); 6],
+    session_id: u32,
+    as_user: bool,
+    /// The runner's limit across every action
+    rate_limit: Option<RateLimit>,
+    dry_run: bool,
+    on_failure: OnFailure,
+    /// Set once a failure stops the rest starting
+    aborted: AtomicBool,
+    /// The output of each named action that has finished, `None` if it
+    /// failed or never ran
+    outputs: Mutex<HashMap<String, Option<String>>>,
+    finished: Condvar,
+    /// Where the actions that finished are noted, removed with the run
+    journal: Option<Entry>,
+    /// Tells this event's actions apart in the log, `{event_id}`
+    id: u64,
+}
+
+impl Run {
+    /// Run `action` once those it runs after have succeeded, true unless it
+    /// failed itself
+    fn execute(&self, action: &Action) -> bool {
+        let Some(outputs) = self.wait_for(action) else {
+            event!(
+                Level::WARN,
+                "Skipping {}, an action it runs after failed",
+                action.description(&self.variables)
+            );
+            self.finish(action, None);
+            return true;
+        };
+        let mut variables = self.placeholders();
+        variables.extend(
+            outputs
+                .iter()
+                .map(|(name, output)| (name.as_str(), output.clone())),
+        );
+        let output = self.attempt(action, &variables);
+        let succeeded = output.is_some();
+        self.finish(action, output);
+        succeeded
+    }
+
+    /// The event's placeholders with its id
+    fn placeholders(&self) -> Vec<(&str, String)> {
+        let id = ("event_id", self.id.to_string());
+        self.variables.iter().cloned().chain(Some(id)).collect()
+    }
+
+    /// Wait for the actions `action` runs after to finish, their outputs by
+    /// placeholder name or `None` if one failed
+    fn wait_for(&self, action: &Action) -> Option<Vec<(String, String)>> {
+        let mut outputs = self.outputs.lock().unwrap();
+        while !action.after.iter().all(|name| outputs.contains_key(name)) {
+            outputs = self.finished.wait(outputs).unwrap();
+        }
+        action
+            .after
+            .iter()
+            .map(|name| Some((output_variable(name), outputs[name].clone()?)))
+            .collect()
+    }
+
+    /// Note in the journal that the action at `index` finished
+    fn record(&self, index: usize, action: &Action) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        let Some(name) = &action.name else {
+            return journal.done(index, None);
+        };
+        let output = self.outputs.lock().unwrap().get(name).cloned().flatten();
+        journal.done(index, Some((name, output.as_deref())));
+    }
+
+    /// Hand how `action` went to those that run after it
+    fn finish(&self, action: &Action, output: Option<String>) {
+        if let Some(name) = &action.name {
+            self.outputs.lock().unwrap().insert(name.clone(), output);
+            self.finished.notify_all();
+        }
+    }
+
+    /// Run `action` unless a rate limit says not to, or only log it for a
+    /// dry run, its output if it succeeded
+    fn attempt(
+        &self,
+        action: &Action,
+        variables: &[(&str, String)],
+    ) -> Option<String> {
+        let description = action.description(variables);
+        if let Some(rate_limit) = &self.rate_limit {
+            if !rate_limit.allow() {
+                event!(
+                    Level::WARN,
+                    "Skipping {description}, actions are {rate_limit}"
+                );
+                return None;
+            }
+        }
+        if let Some(rate_limit) = &action.rate_limit {
+            if !rate_limit.allow() {
+                event!(Level::WARN, "Skipping {description}, {rate_limit}");
+                return None;
+            }
+        }
+        if self.dry_run {
+            let target = match (&action.kind, self.as_user) {
+                (ActionKind::Command(_), true) => {
+                    format!(" as the user of session {}", self.session_id)
+                }
+                _ => String::new(),
+            };
+            event!(Level::INFO, "Dry run, would {description}{target}");
+            action.log_follow_ups(variables);
+            if let OnFailure::Fallback(fallback) = &self.on_failure {
+                event!(
+                    Level::INFO,
+                    "Dry run, had {description} failed would run {} instead",
+                    fallback.description(variables)
+                );
+            }
+            return Some(String::new());
+        }
+        action.execute(variables, self.session_id, self.as_user)
+    }
+
+    /// Deal with `action` having failed the way `on_failure` says, true if
+    /// no more actions should start
+    fn fail(&self, action: &Action) -> bool {
+        let description = action.description(&self.variables);
+        let fallback = match &self.on_failure {
+            OnFailure::Continue => return false,
+            OnFailure::Notify => {
+                // Errors are logged by the wrapper
+                let _ = send_message(
+                    self.session_id,
+                    "rusty-lock",
+                    &format!("{description} failed"),
+                    MessageButtons::Ok,
+                    None,
+                    false,
+                );
+                return false;
+            }
+            OnFailure::Abort => None,
+            OnFailure::Fallback(fallback) => Some(fallback),
+        };
+        // Parallel actions can fail together, the fallback only runs once
+        if !self.aborted.swap(true, Ordering::Relaxed) {
+            if let Some(fallback) = fallback {
+                event!(
+                    Level::WARN,
+                    "Running {} as {description} failed",
+                    fallback.description(&self.variables)
+                );
+                self.attempt(fallback, &self.placeholders());
+            }
+        }
+        true
+    }
+}
+
+/// Start `actions` on `pool`, the way `execution` says. An action waiting for
+/// an earlier one can't hold up the pool, workers take a run's actions in
+/// order so the earlier one is already running
+fn start(
+    actions: Arc<[Action]>,
+    run: Arc<Run>,
+    execution: Execution,
+    pool: &Pool,
+) {
+    match execution {
+        Execution::Sequential => {
+            pool.execute(move || {
+                for (done, action) in actions.iter().enumerate() {
+                    let succeeded = run.execute(action);
+                    run.record(done, action);
+                    if !succeeded && run.fail(action) {
+                        let skipped = actions.len() - done - 1;
+                        if skipped > 0 {
+                            event!(
+                                Level::WARN,
+                                "Skipping the {skipped} action(s) after \
+                                 {}",
+                                action.kind
+                            );
+                        }
+                        break;
+                    }
+                }
+            });
+        }
+        Execution::Parallel { limit } => {
+            // Each worker takes the next action not yet started
+            let next = Arc::new(AtomicUsize::new(0));
+            let workers = limit
+                .map_or(actions.len(), |limit| limit.get().min(actions.len()));
+            for _ in 0..workers {
+                let actions = actions.clone();
+                let run = run.clone();
+                let next = next.clone();
+                pool.execute(move || {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(action) = actions.get(index) else {
+                            break;
+                        };
+                        // Later ones taken already may be waiting for it
+                        if run.aborted.load(Ordering::Relaxed) {
+                            run.finish(action, None);
+                        } else if !run.execute(action) {
+                            run.fail(action);
+                        }
+                        run.record(index, action);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Check each action only runs after ones named before it, so they always
+/// start in an order that can't wait forever
+fn check_after(actions: &[Action]) -> Result<(), String> {
+    check_after_from(HashSet::new(), actions)
+}
+
+/// Like [`check_after`] with the actions named `names` already finished
+fn check_after_from<'a>(
+    mut names: HashSet<&'a String>,
+    actions: &'a [Action],
+) -> Result<(), String> {
+    for action in actions {
+        if let Some(name) = action.after.iter().find(|&n| !names.contains(n)) {
+            return Err(format!("no earlier action is named {name} for after"));
+        }
+        if let Some(name) = &action.name {
+            if !names.insert(name) {
+                return Err(format!("two actions are named {name}"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The placeholder for the output of the action named `name`
+fn output_variable(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("output_{name}")
+}
+
+/// The placeholder names and their values for `event`
+pub(crate) fn variables(event: &SessionEvent) -> [(&'static str, String); 6] {
+    let (user_name, domain) = match (&event.user, &event.info) {
+        (Some(user), _) => (user.user_name.clone(), user.domain.clone()),
+        (None, Some(info)) => (info.user_name.clone(), info.domain.clone()),
+        (None, None) => Default::default(),
+    };
+    let timestamp = event
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let client_ip = event
+        .info
+        .as_ref()
+        .and_then(|info| info.client_address)
+        .map(|address| address.to_string())
+        .unwrap_or_default();
+    [
+        ("event", event.state.name().to_string()),
+        ("session_id", event.session_id.to_string()),
+        ("username", user_name),
+        ("domain", domain),
+        ("timestamp", timestamp.to_string()),
+        ("client_ip", client_ip),
+    ]
+}
+
+/// Replace each `{name}` in `command` in one pass, so values are never
+/// expanded themselves. Anything else in braces is left alone
+fn expand(command: &str, variables: &[(&str, String)]) -> String {
+    substitute(command, |name, _| {
+        let (_, value) = variables.iter().find(|(n, _)| *n == name)?;
+        Some(value.clone())
+    })
+}
+
+/// Like [`expand`], also replacing each `{cred:target}` with the secret of
+/// that Credential Manager entry. Only for what is run, never what is logged
+fn expand_secrets(
+    command: &str,
+    variables: &[(&str, String)],
+) -> std::io::Result<String> {
+    expand_with(command, variables, |value, _| value)
+}
+
+/// Like [`expand_secrets`] for the line handed to `cmd /C`, with each value
+/// escaped so it is only ever text in the command. Otherwise a user named
+/// `a&evil.exe` would run `evil.exe` as us
+fn expand_command(
+    command: &str,
+    variables: &[(&str, String)],
+) -> std::io::Result<String> {
+    expand_with(command, variables, |value, before| {
+        escape_cmd(&value, quoted(before))
+    })
+}
+
+/// Expand `command` with `escape` given each value and what comes before it
+fn expand_with(
+    command: &str,
+    variables: &[(&str, String)],
+    mut escape: impl FnMut(String, &str) -> String,
+) -> std::io::Result<String> {
+    let mut err = None;
+    let expanded = substitute(command, |name, before| {
+        let value = match name.strip_prefix("cred:") {
+            Some(target) => read_credential(target)
+                .map_err(|e| err.get_or_insert(format!("{name} {e}")))
+                .ok()?,
+            None => {
+                let (_, value) = variables.iter().find(|(n, _)| *n == name)?;
+                value.clone()
+            }
+        };
+        Some(escape(value, before))
+    });
+    match err {
+        Some(err) => Err(std::io::Error::other(err)),
+        None => Ok(expanded),
+    }
+}
+
+/// Whether the end of `line` is inside double quotes for `cmd`
+fn quoted(line: &str) -> bool {
+    let mut quoted = false;
+    let mut caret = false;
+    for c in line.chars() {
+        // Only outside quotes does a caret escape what follows
+        if c == '"' && !caret {
+            quoted = !quoted;
+        }
+        caret = c == '^' && !caret && !quoted;
+    }
+    quoted
+}
+
+/// `value` as literal text for `cmd`, `quoted` if it goes inside double
+/// quotes. Outside them `cmd`'s special characters are escaped with `^`.
+/// Inside them they are already literal, but for `%` and `"`, which step
+/// out of the quotes to be escaped. A line break would end the command so
+/// becomes a space
+fn escape_cmd(value: &str, quoted: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\r' | '\n' => escaped.push(' '),
+            '%' | '"' if quoted => {
+                escaped.push_str("\"^");
+                escaped.push(c);
+                escaped.push('"');
+            }
+            '^' | '&' | '|' | '<' | '>' | '(' | ')' | '%' | '"' if !quoted => {
+                escaped.push('^');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Replace each `{name}` in `command` that `value` knows in one pass,
+/// `value` is also given what has been expanded so far
+fn substitute(
+    command: &str,
+    mut value: impl FnMut(&str, &str) -> Option<String>,
+) -> String {
+    let mut expanded = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest
+            .find('}')
+            .and_then(|end| Some((value(&rest[1..end], &expanded)?, end)));
+        match value {
+            Some((value, end)) => {
+                expanded.push_str(&value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                expanded.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+impl core::str::FromStr for ActionRunner {
+    type Err = ConfigError;
+
+    fn from_str(config: &str) -> Result<Self, Self::Err> {
+        let mut runner = Self::new();
+        let mut active = None;
+        for mut entry in config::parse(config)? {
+            let line = entry.line;
+            let error = |message| ConfigError::Parse { line, message };
+            // After interpolating so a variable can hold an encrypted value
+            entry.value = entry
+                .value
+                .interpolate()
+                .and_then(Value::decrypt)
+                .map_err(error)?;
+            if entry.key == "profile" {
+                let Value::String(name) = entry.value else {
+                    return Err(error(format!(
+                        "profile can't be {}",
+                        entry.value.kind()
+                    )));
+                };
+                active = Some((name, entry.line));
+                continue;
+            }
+            // Everything under a [profile.name] header
+            let profile = entry
+                .key
+                .strip_prefix("profile.")
+                .and_then(|key| key.split_once('.'));
+            runner = match profile {
+                Some((
+                    _,
+                    key @ ("debounce" | "rate_limit" | "workers" | "journal"
+                    | "run_initial_state" | "all_sessions"),
+                )) => return Err(error(format!("{key} can't be per profile"))),
+                Some((name, key)) => {
+                    let profile = runner
+                        .profiles
+                        .remove(name)
+                        .unwrap_or_default()
+                        .entry(key, entry.value)
+                        .map_err(error)?;
+                    runner.profile(name, profile)
+                }
+                None => runner.entry(&entry.key, entry.value).map_err(error)?,
+            };
+            runner.check_after().map_err(error)?;
+        }
+        if let Some((name, line)) = active {
+            if !runner.switch_profile(Some(&name)) {
+                return Err(ConfigError::Parse {
+                    line,
+                    message: format!("there's no [profile.{name}]"),
+                });
+            }
+        }
+        Ok(runner)
+    }
+}
+
+/// A schedule from a table holding its `cron` expression, and the rest of
+/// the table, which is its actions
+fn parse_schedule(
+    value: Value,
+) -> Result<(Schedule, Vec<(String, Value)>), String> {
+    let Value::Table(mut fields) = value else {
+        return Err(format!(
+            "expected a table with cron, found {}",
+            value.kind()
+        ));
+    };
+    let cron = fields
+        .iter()
+        .position(|(key, _)| key == "cron")
+        .ok_or("a schedule needs cron")?;
+    match fields.remove(cron).1 {
+        Value::String(cron) => {
+            let schedule = cron.parse().map_err(|()| {
+                format!("{cron} is not a cron expression such as 0 19 * * 1-5")
+            })?;
+            Ok((schedule, fields))
+        }
+        value => Err(format!("cron can't be {}", value.kind())),
+    }
+}
+
+/// The actions for a config key, still to be parsed
+struct ActionList {
+    execution: Option<Execution>,
+    on_failure: Option<OnFailure>,
+    actions: Vec<Value>,
+}
+
+impl ActionList {
+    fn new(actions: Vec<Value>) -> Self {
+        Self {
+            execution: None,
+            on_failure: None,
+            actions,
+        }
+    }
+}
+
+/// The actions for a key, with how they run and what happens when one
+/// fails if given as a table holding `actions`
+fn parse_actions(value: Value) -> Result<ActionList, String> {
+    let fields = match value {
+        Value::Array(values) => return Ok(ActionList::new(values)),
+        Value::Table(fields)
+            if fields.iter().any(|(key, _)| key == "actions") =>
+        {
+            fields
+        }
+        value => return Ok(ActionList::new(vec![value])),
+    };
+    let mut sequential = false;
+    let mut limit = None;
+    let mut on_failure = None;
+    let mut actions = Vec::new();
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("mode", Value::String(mode)) => {
+                sequential = match mode.as_str() {
+                    "sequential" => true,
+                    "parallel" => false,
+                    _ => return Err(format!("unknown mode {mode}")),
+                }
+            }
+            ("concurrency", Value::Integer(count)) => {
+                let count = usize::try_from(count)
+                    .ok()
+                    .and_then(NonZeroUsize::new)
+                    .ok_or_else(|| format!("concurrency can't be {count}"))?;
+                limit = Some(count);
+            }
+            ("on_failure", value) => {
+                on_failure = Some(parse_on_failure(value)?)
+            }
+            ("actions", Value::Array(values)) => actions = values,
+            ("actions", value) => actions = vec![value],
+            ("mode" | "concurrency", value) => {
+                return Err(format!("{key} can't be {}", value.kind()))
+            }
+            _ => return Err(format!("unknown field {key}")),
+        }
+    }
+    let execution = match (sequential, limit) {
+        (true, Some(_)) => {
+            return Err("concurrency only applies to parallel mode".into())
+        }
+        (true, None) => Execution::Sequential,
+        (false, limit) => Execution::Parallel { limit },
+    };
+    Ok(ActionList {
+        execution: Some(execution),
+        on_failure,
+        actions,
+    })
+}
+
+/// `abort`, `continue`, `notify` or a table with a `fallback` action
+fn parse_on_failure(value: Value) -> Result<OnFailure, String> {
+    match value {
+        Value::String(policy) => match policy.as_str() {
+            "abort" => Ok(OnFailure::Abort),
+            "continue" => Ok(OnFailure::Continue),
+            "notify" => Ok(OnFailure::Notify),
+            _ => Err(format!("unknown on_failure {policy}")),
+        },
+        Value::Table(mut fields) => {
+            let (key, fallback) = match fields.pop() {
+                Some(field) if fields.is_empty() => field,
+                _ => return Err("on_failure needs only a fallback".into()),
+            };
+            if key != "fallback" {
+                return Err(format!("unknown field {key}"));
+            }
+            let fallback = parse_action(fallback)?;
+            if !fallback.after.is_empty() {
+                return Err("a fallback can't run after other actions".into());
+            }
+            Ok(OnFailure::Fallback(Box::new(fallback)))
+        }
+        value => Err(format!("on_failure can't be {}", value.kind())),
+    }
+}
+
+/// An action from a command or an inline table of its fields, which has
+/// `command` or one of the built in actions' keys
+fn parse_action(value: Value) -> Result<Action, String> {
+    let fields = match value {
+        Value::String(command) => return Ok(Action::new(command)),
+        Value::Table(fields) => fields,
+        value => {
+            return Err(format!(
+                "expected a command or a table, found {}",
+                value.kind()
+            ))
+        }
+    };
+    let mut kind = None;
+    let mut set_kind = |new| match kind.replace(new) {
+        Some(_) => Err("an action can only do one thing"),
+        None => Ok(()),
+    };
+    let mut grace = None;
+    let mut token = None;
+    let mut power = None;
+    let mut lights = None;
+    let mut groups = None;
+    let mut relay = None;
+    let mut user = None;
+    let mut only_secrets = None;
+    let mut keep = None;
+    let mut allow = None;
+    let mut timeout = None;
+    let mut retry = None;
+    let mut rate_limit = None;
+    let mut cwd = None;
+    let mut env = Vec::new();
+    let mut inherit_env = true;
+    let mut name = None;
+    let mut after = Vec::new();
+    let mut log = None;
+    let mut on_success = Vec::new();
+    let mut on_failure = Vec::new();
+    let mut on_exit = Vec::new();
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("command", Value::String(value)) => {
+                set_kind(ActionKind::Command(value))?
+            }
+            ("kill", value) => set_kind(ActionKind::KillProcesses {
+                names: strings(&key, value)?,
+                grace: None,
+            })?,
+            ("start_service", value) => {
+                set_kind(ActionKind::StartServices(strings(&key, value)?))?
+            }
+            ("stop_service", value) => {
+                set_kind(ActionKind::StopServices(strings(&key, value)?))?
+            }
+            ("displays_off", Value::Integer(secs)) => {
+                set_kind(ActionKind::DisplaysOff(seconds(&key, secs)?))?
+            }
+            ("clear_clipboard", Value::Boolean(true)) => {
+                set_kind(ActionKind::ClearClipboard {
+                    only_secrets: false,
+                })?
+            }
+            ("hang_up_vpn", value) => {
+                set_kind(ActionKind::HangUpVpn(names_or_all(&key, value)?))?
+            }
+            ("dial_vpn", value) => {
+                set_kind(ActionKind::DialVpn(names_or_all(&key, value)?))?
+            }
+            ("unmap_drives", value) => {
+                set_kind(ActionKind::UnmapDrives(strings(&key, value)?))?
+            }
+            ("map_drives", value) => {
+                set_kind(ActionKind::MapDrives(names_or_all(&key, value)?))?
+            }
+            ("pause_containers", value) => set_kind(
+                ActionKind::PauseContainers(names_or_all(&key, value)?),
+            )?,
+            ("unpause_containers", value) => set_kind(
+                ActionKind::UnpauseContainers(names_or_all(&key, value)?),
+            )?,
+            ("shutdown_wsl", value) => {
+                set_kind(ActionKind::ShutdownWsl(names_or_all(&key, value)?))?
+            }
+            ("slack_status", Value::String(status)) => {
+                set_kind(ActionKind::SlackStatus {
+                    away: parse_status(&key, &status)?,
+                    token: String::new(),
+                })?
+            }
+            ("teams_presence", Value::String(status)) => {
+                set_kind(ActionKind::TeamsPresence {
+                    away: parse_status(&key, &status)?,
+                    token: String::new(),
+                })?
+            }
+            ("token", Value::String(value)) => token = Some(value),
+            ("hue", Value::String(bridge)) => {
+                set_kind(ActionKind::SmartHome {
+                    device: SmartDevice::Hue {
+                        bridge,
+                        user: String::new(),
+                        lights: Vec::new(),
+                        groups: Vec::new(),
+                    },
+                    on: false,
+                })?
+            }
+            ("tasmota", Value::String(host)) => {
+                set_kind(ActionKind::SmartHome {
+                    device: SmartDevice::Tasmota { host, relay: None },
+                    on: false,
+                })?
+            }
+            ("shelly", Value::String(host)) => {
+                set_kind(ActionKind::SmartHome {
+                    device: SmartDevice::Shelly { host, relay: 0 },
+                    on: false,
+                })?
+            }
+            ("power", Value::String(value)) => {
+                power = Some(match value.as_str() {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("power can't be {value}")),
+                })
+            }
+            ("lights", value) => lights = Some(numbers(&key, value)?),
+            ("groups", value) => groups = Some(numbers(&key, value)?),
+            ("relay", Value::Integer(number)) => {
+                relay = Some(
+                    u32::try_from(number)
+                        .map_err(|_| format!("relay can't be {number}"))?,
+                )
+            }
+            ("user", Value::String(value)) => user = Some(value),
+            ("webcam_snapshot", Value::String(dir)) => {
+                set_kind(ActionKind::WebcamSnapshot(PathBuf::from(dir)))?
+            }
+            #[cfg(feature = "rhai")]
+            ("script", Value::String(path)) => {
+                set_kind(ActionKind::Script(PathBuf::from(path)))?
+            }
+            #[cfg(feature = "rhai")]
+            ("script", value) => {
+                return Err(format!("{key} can't be {}", value.kind()))
+            }
+            #[cfg(not(feature = "rhai"))]
+            ("script", _) => return Err("script needs the rhai feature".into()),
+            #[cfg(feature = "lua")]
+            ("lua", Value::String(path)) => {
+                set_kind(ActionKind::Lua(PathBuf::from(path)))?
+            }
+            #[cfg(feature = "lua")]
+            ("lua", value) => {
+                return Err(format!("{key} can't be {}", value.kind()))
+            }
+            #[cfg(not(feature = "lua"))]
+            ("lua", _) => return Err("lua needs the lua feature".into()),
+            #[cfg(feature = "wasm")]
+            ("plugin", Value::String(path)) => set_kind(ActionKind::Plugin {
+                path: PathBuf::from(path),
+                capabilities: Default::default(),
+            })?,
+            #[cfg(feature = "wasm")]
+            ("plugin", value) => {
+                return Err(format!("{key} can't be {}", value.kind()))
+            }
+            #[cfg(not(feature = "wasm"))]
+            ("plugin", _) => return Err("plugin needs the wasm feature".into()),
+            ("allow", value) => allow = Some(strings(&key, value)?),
+            ("screenshot", Value::String(dir)) => {
+                set_kind(ActionKind::Screenshot {
+                    dir: PathBuf::from(dir),
+                    keep: None,
+                })?
+            }
+            ("eject_removable", Value::Boolean(true)) => {
+                set_kind(ActionKind::EjectRemovableStorage)?
+            }
+            ("lock_password_managers", Value::Boolean(true)) => {
+                set_kind(ActionKind::LockPasswordManagers)?
+            }
+            ("clear_agent_keys", Value::Boolean(true)) => {
+                set_kind(ActionKind::ClearAgentKeys)?
+            }
+            (
+                "clear_clipboard"
+                | "eject_removable"
+                | "lock_password_managers"
+                | "clear_agent_keys",
+                Value::Boolean(false),
+            ) => return Err(format!("{key} can only be true")),
+            ("only_secrets", Value::Boolean(value)) => {
+                only_secrets = Some(value)
+            }
+            ("keep_days", Value::Integer(days)) => {
+                let secs = days
+                    .checked_mul(24 * 60 * 60)
+                    .filter(|&secs| secs > 0)
+                    .ok_or_else(|| format!("{key} can't be {days}"))?;
+                keep = Some(seconds(&key, secs)?);
+            }
+            ("grace", Value::Integer(secs)) => {
+                grace = Some(seconds(&key, secs)?);
+            }
+            ("timeout", Value::Integer(secs)) => {
+                timeout = Some(seconds(&key, secs)?);
+            }
+            ("retry", value) => retry = Some(parse_retry(value)?),
+            ("rate_limit", value) => {
+                rate_limit = Some(parse_rate_limit(value)?)
+            }
+            ("cwd", Value::String(value)) => cwd = Some(PathBuf::from(value)),
+            ("env", Value::Table(fields)) => {
+                for (name, value) in fields {
+                    match value {
+                        Value::String(value) => env.push((name, value)),
+                        value => {
+                            return Err(format!(
+                                "env {name} can't be {}",
+                                value.kind()
+                            ))
+                        }
+                    }
+                }
+            }
+            ("inherit_env", Value::Boolean(value)) => inherit_env = value,
+            ("name", Value::String(value)) => name = Some(value),
+            ("after", value) => after = strings(&key, value)?,
+            ("log", value) => log = Some(parse_log(value)?),
+            ("on_success", value) => on_success = parse_follow_ups(value)?,
+            ("on_failure", value) if is_list_policy(&value) => {
+                return Err(
+                    "an action's own on_failure is actions to follow it up \
+                     with, abort, continue, notify and fallback belong on the \
+                     table holding the actions"
+                        .into(),
+                )
+            }
+            ("on_failure", value) => on_failure = parse_follow_ups(value)?,
+            ("on_exit", Value::Table(fields)) => {
+                for (code, value) in fields {
+                    let Ok(code) = code.parse() else {
+                        return Err(format!(
+                            "on_exit {code} isn't an exit code"
+                        ));
+                    };
+                    on_exit.push((code, parse_follow_ups(value)?));
+                }
+            }
+            (
+                "command"
+                | "displays_off"
+                | "clear_clipboard"
+                | "eject_removable"
+                | "webcam_snapshot"
+                | "screenshot"
+                | "lock_password_managers"
+                | "clear_agent_keys"
+                | "only_secrets"
+                | "keep_days"
+                | "grace"
+                | "timeout"
+                | "cwd"
+                | "env"
+                | "inherit_env"
+                | "name"
+                | "on_exit"
+                | "slack_status"
+                | "teams_presence"
+                | "token"
+                | "hue"
+                | "tasmota"
+                | "shelly"
+                | "power"
+                | "relay"
+                | "user",
+                value,
+            ) => return Err(format!("{key} can't be {}", value.kind())),
+            _ => return Err(format!("unknown action field {key}")),
+        }
+    }
+    let mut kind =
+        kind.ok_or("an action needs a command or a built in action")?;
+    // Options of one built in action, each is taken by its own
+    match &mut kind {
+        ActionKind::KillProcesses { grace: slot, .. } => *slot = grace.take(),
+        ActionKind::ClearClipboard { only_secrets: slot } => {
+            *slot = only_secrets.take().unwrap_or_default()
+        }
+        ActionKind::Screenshot { keep: slot, .. } => *slot = keep.take(),
+        ActionKind::SlackStatus { token: slot, .. }
+        | ActionKind::TeamsPresence { token: slot, .. } => {
+            *slot = token.take().ok_or("a status needs a token")?
+        }
+        ActionKind::SmartHome { device, on } => {
+            *on = power.take().ok_or("a device needs power on or off")?;
+            match device {
+                SmartDevice::Hue {
+                    user: user_slot,
+                    lights: lights_slot,
+                    groups: groups_slot,
+                    ..
+                } => {
+                    *user_slot = user.take().ok_or("hue needs a user")?;
+                    *lights_slot = lights.take().unwrap_or_default();
+                    *groups_slot = groups.take().unwrap_or_default();
+                    if lights_slot.is_empty() && groups_slot.is_empty() {
+                        return Err("hue needs lights or groups".into());
+                    }
+                }
+                SmartDevice::Tasmota { relay: slot, .. } => {
+                    *slot = relay.take()
+                }
+                SmartDevice::Shelly { relay: slot, .. } => {
+                    *slot = relay.take().unwrap_or_default()
+                }
+            }
+        }
+        #[cfg(feature = "wasm")]
+        ActionKind::Plugin { capabilities, .. } => {
+            for name in allow.take().unwrap_or_default() {
+                match name.as_str() {
+                    "exec" => capabilities.exec = true,
+                    "http" => capabilities.http = true,
+                    "fs" => capabilities.fs = true,
+                    _ => return Err(format!("unknown capability {name}")),
+                }
+            }
+        }
+        _ => {}
+    }
+    if grace.is_some() {
+        return Err("grace only applies to kill".into());
+    }
+    if token.is_some() {
+        return Err(
+            "token only applies to slack_status and teams_presence".into()
+        );
+    }
+    if power.is_some() {
+        return Err("power only applies to hue, tasmota and shelly".into());
+    }
+    if lights.is_some() || groups.is_some() || user.is_some() {
+        return Err("lights, groups and user only apply to hue".into());
+    }
+    if relay.is_some() {
+        return Err("relay only applies to tasmota and shelly".into());
+    }
+    if only_secrets.is_some() {
+        return Err("only_secrets only applies to clear_clipboard".into());
+    }
+    if keep.is_some() {
+        return Err("keep_days only applies to screenshot".into());
+    }
+    if allow.is_some() {
+        return Err("allow only applies to plugin".into());
+    }
+    let for_commands = timeout.is_some()
+        || cwd.is_some()
+        || !env.is_empty()
+        || !inherit_env
+        || log.is_some()
+        || !on_exit.is_empty();
+    if for_commands && !matches!(kind, ActionKind::Command(_)) {
+        return Err("timeout, cwd, env, inherit_env, log and on_exit only \
+                    apply to commands"
+            .into());
+    }
+    Ok(Action {
+        kind,
+        timeout,
+        retry,
+        cwd,
+        env,
+        inherit_env,
+        rate_limit,
+        name,
+        after,
+        log,
+        on_success,
+        on_failure,
+        on_exit,
+    })
+}
+
+/// Whether `value` is an `on_failure` for a list of actions, which is easy
+/// to put on a single action by mistake
+fn is_list_policy(value: &Value) -> bool {
+    match value {
+        Value::String(policy) => {
+            matches!(policy.as_str(), "abort" | "continue" | "notify")
+        }
+        Value::Table(fields) => fields.iter().any(|(key, _)| key == "fallback"),
+        _ => false,
+    }
+}
+
+/// The actions to follow another up with, one or an array of them
+fn parse_follow_ups(value: Value) -> Result<Vec<Action>, String> {
+    let values = match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+    let follow_ups = values
+        .into_iter()
+        .map(parse_action)
+        .collect::<Result<Vec<_>, _>>()?;
+    if follow_ups.iter().any(|action| !action.after.is_empty()) {
+        return Err("a follow-up can't run after other actions".into());
+    }
+    Ok(follow_ups)
+}
+
+/// `away` or `available` for `key`, true for away
+fn parse_status(key: &str, status: &str) -> Result<bool, String> {
+    match status {
+        "away" => Ok(true),
+        "available" => Ok(false),
+        _ => Err(format!("{key} can't be {status}, only away or available")),
+    }
+}
+
+/// How [`parse_status`] reads `away`
+fn status_name(away: bool) -> &'static str {
+    if away {
+        "away"
+    } else {
+        "available"
+    }
+}
+
+/// `value`, or the secret of the Credential Manager entry it names as
+/// `cred:target`. Failures are logged by the wrapper
+pub(crate) fn secret(value: &str) -> Option<String> {
+    match value.strip_prefix("cred:") {
+        Some(target) => read_credential(target).ok(),
+        None => Some(value.to_string()),
+    }
+}
+
+/// An output log from its path or a table of `path` and `max_size`
+fn parse_log(value: Value) -> Result<OutputLog, String> {
+    let fields = match value {
+        Value::String(path) => return Ok(OutputLog::new(path)),
+        Value::Table(fields) => fields,
+        value => return Err(format!("log can't be {}", value.kind())),
+    };
+    let mut path = None;
+    let mut max_size = None;
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("path", Value::String(value)) => path = Some(value),
+            ("max_size", Value::Integer(bytes)) => {
+                max_size = Some(
+                    u64::try_from(bytes)
+                        .ok()
+                        .filter(|&bytes| bytes > 0)
+                        .ok_or_else(|| format!("max_size can't be {bytes}"))?,
+                );
+            }
+            ("path" | "max_size", value) => {
+                return Err(format!("{key} can't be {}", value.kind()))
+            }
+            _ => return Err(format!("unknown log field {key}")),
+        }
+    }
+    let mut log = OutputLog::new(path.ok_or("log needs a path")?);
+    if let Some(max_size) = max_size {
+        log.max_size = max_size;
+    }
+    Ok(log)
+}
+
+/// A retry policy from `true` for the defaults or a table of its fields
+fn parse_retry(value: Value) -> Result<Retry, String> {
+    let fields = match value {
+        Value::Boolean(true) => return Ok(Retry::default()),
+        Value::Table(fields) => fields,
+        value => {
+            return Err(format!(
+                "retry can't be {}, expected true or a table",
+                value.kind()
+            ))
+        }
+    };
+    let mut retry = Retry::default();
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("attempts", Value::Integer(attempts)) => {
+                retry.attempts = u32::try_from(attempts)
+                    .ok()
+                    .filter(|&attempts| attempts > 0)
+                    .ok_or_else(|| format!("attempts can't be {attempts}"))?;
+            }
+            ("delay", Value::Integer(secs)) => {
+                retry.delay = seconds(&key, secs)?;
+            }
+            ("max_delay", Value::Integer(secs)) => {
+                retry.max_delay = Some(seconds(&key, secs)?);
+            }
+            ("jitter", Value::Boolean(jitter)) => retry.jitter = jitter,
+            ("attempts" | "delay" | "max_delay" | "jitter", value) => {
+                return Err(format!("{key} can't be {}", value.kind()))
+            }
+            _ => return Err(format!("unknown retry field {key}")),
+        }
+    }
+    Ok(retry)
+}
+
+/// A rate limit from a table of `max` and `per`
+fn parse_rate_limit(value: Value) -> Result<RateLimit, String> {
+    let Value::Table(fields) = value else {
+        return Err(format!("rate_limit can't be {}", value.kind()));
+    };
+    let mut max = None;
+    let mut per = None;
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("max", Value::Integer(count)) => {
+                max = Some(
+                    u32::try_from(count)
+                        .ok()
+                        .filter(|&count| count > 0)
+                        .ok_or_else(|| format!("max can't be {count}"))?,
+                );
+            }
+            ("per", Value::Integer(secs)) => per = Some(seconds(&key, secs)?),
+            ("max" | "per", value) => {
+                return Err(format!("{key} can't be {}", value.kind()))
+            }
+            _ => return Err(format!("unknown rate_limit field {key}")),
+        }
+    }
+    match (max, per) {
+        (Some(max), Some(per)) => Ok(RateLimit::new(max, per)),
+        _ => Err("rate_limit needs max and per".into()),
+    }
+}
+
+/// `true` for an empty list meaning all, or a string or an array of them
+fn names_or_all(key: &str, value: Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::Boolean(true) => Ok(Vec::new()),
+        value => strings(key, value),
+    }
+}
+
+/// A number or an array of them from the config
+fn numbers(key: &str, value: Value) -> Result<Vec<u32>, String> {
+    let values = match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::Integer(number) => u32::try_from(number)
+                .map_err(|_| format!("{key} can't have {number}")),
+            value => Err(format!("{key} can't have {}", value.kind())),
+        })
+        .collect()
+}
+
+/// A string or an array of them from the config
+fn strings(key: &str, value: Value) -> Result<Vec<String>, String> {
+    let values = match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| match value {
+            Value::String(value) => Ok(value),
+            value => Err(format!("{key} can't hold {}", value.kind())),
+        })
+        .collect()
+}
+
+/// A duration in whole seconds from the config
+fn seconds(key: &str, secs: i64) -> Result<Duration, String> {
+    u64::try_from(secs)
+        .map(Duration::from_secs)
+        .map_err(|_| format!("{key} can't be {secs}"))
+}
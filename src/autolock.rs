@@ -0,0 +1,75 @@
+//! Lock the workstation once the user has been idle for too long
+use crate::control::lock_workstation;
+use crate::wynapi::{foreground_process_path, fullscreen_app_running};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{event, Level};
+
+/// When [`SessionMonitorBuilder::auto_lock`] should lock the workstation
+///
+/// [`SessionMonitorBuilder::auto_lock`]: crate::SessionMonitorBuilder::auto_lock
+#[derive(Debug, Clone)]
+pub struct AutoLock {
+    after: Duration,
+    exempt_fullscreen: bool,
+    exempt_processes: Vec<String>,
+    /// Set once we have locked, until there is input again
+    fired: bool,
+}
+
+impl AutoLock {
+    /// Lock after `after` without any input
+    pub fn after(after: Duration) -> Self {
+        Self {
+            after,
+            exempt_fullscreen: false,
+            exempt_processes: Vec::new(),
+            fired: false,
+        }
+    }
+
+    /// Don't lock while a full screen app, game or presentation is running
+    pub fn exempt_fullscreen(mut self, exempt: bool) -> Self {
+        self.exempt_fullscreen = exempt;
+        self
+    }
+
+    /// Don't lock while this exe has the foreground window, matched on the
+    /// file name ignoring case, eg. `vlc.exe`
+    pub fn exempt_process(mut self, name: impl Into<String>) -> Self {
+        self.exempt_processes.push(name.into());
+        self
+    }
+
+    /// Feed a fresh idle time, locks at most once per idle period
+    pub(crate) fn check(&mut self, idle: Duration) {
+        if idle < self.after {
+            self.fired = false;
+            return;
+        }
+        if self.fired || self.exempt() {
+            return;
+        }
+        event!(Level::INFO, "Idle for {idle:?}, locking the workstation");
+        self.fired = lock_workstation().is_ok();
+    }
+
+    fn exempt(&self) -> bool {
+        if self.exempt_fullscreen && fullscreen_app_running() {
+            return true;
+        }
+        if self.exempt_processes.is_empty() {
+            return false;
+        }
+        let Some(path) = foreground_process_path() else {
+            return false;
+        };
+        let name = Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.exempt_processes
+            .iter()
+            .any(|exempt| exempt.eq_ignore_ascii_case(&name))
+    }
+}
@@ -0,0 +1,146 @@
+//! Where a [`SessionMonitor`](crate::SessionMonitor) gets its events from.
+//! WTS notifications arrive on the window directly, the other backends run
+//! on threads of their own and hand events over through an [`Inbox`]
+use crate::eventlog::EventLogSubscription;
+use crate::guard::{MessageWindow, Registration};
+use crate::polling::PollingSource;
+use crate::sens::SensSubscription;
+use crate::wmi::WmiSubscription;
+use crate::wynapi::*;
+use crate::SessionEvent;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The source of session notifications, chosen when the monitor is built
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Backend {
+    /// WTSRegisterSessionNotification on the monitor's window
+    #[default]
+    Wts,
+    /// Lock and unlock from the Security event log, IDs 4800 and 4801.
+    /// Needs to run as an administrator with "Audit Other Logon/Logoff
+    /// Events" enabled, but works without an interactive session and the
+    /// events carry the time they were logged
+    EventLog,
+    /// The System Event Notification Service, for machines where WTS
+    /// registration is blocked. Gives Lock, Unlock and the screensaver for
+    /// our own session and Logon and Logoff for every session in scope
+    Sens,
+    /// A WMI notification query for Win32_SessionChangeEvent, for services
+    /// and scripted deployments where a window of ours won't get WTS
+    /// notifications. Gives the same states as [`Backend::Wts`]
+    Wmi,
+    /// Enumerate the sessions every `poll_interval` and diff them into
+    /// Logon, Logoff, Lock and Unlock, for Wine and stripped down editions
+    /// where nothing else works. Changes that come and go between two polls
+    /// are missed
+    Polling,
+}
+
+impl Backend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Wts => "wts",
+            Self::EventLog => "eventlog",
+            Self::Sens => "sens",
+            Self::Wmi => "wmi",
+            Self::Polling => "polling",
+        }
+    }
+}
+
+impl core::str::FromStr for Backend {
+    type Err = ();
+
+    /// Parse a name returned by [`Backend::name`], so the backend can be
+    /// picked at runtime from a flag or config file
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "wts" => Ok(Self::Wts),
+            "eventlog" => Ok(Self::EventLog),
+            "sens" => Ok(Self::Sens),
+            "wmi" => Ok(Self::Wmi),
+            "polling" => Ok(Self::Polling),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Events queued by a backend thread for the monitor's thread, which is
+/// woken up with WM_BACKEND
+#[derive(Clone)]
+pub(crate) struct Inbox {
+    events: Arc<Mutex<VecDeque<SessionEvent>>>,
+    /// The HWND as an integer, so the inbox can be sent to other threads
+    window: usize,
+}
+
+impl Inbox {
+    pub fn new(window: &MessageWindow) -> Self {
+        Self {
+            events: Arc::default(),
+            window: window.handle() as usize,
+        }
+    }
+
+    /// Queue `event` and wake the monitor, from any thread
+    pub fn push(&self, event: SessionEvent) {
+        self.events.lock().unwrap().push_back(event);
+        let _ = post_message_a(self.window as HWND, WM_BACKEND);
+    }
+
+    pub fn drain(&self) -> Vec<SessionEvent> {
+        self.events.lock().unwrap().drain(..).collect()
+    }
+}
+
+/// A running backend, stopped on drop. Must be dropped before the window
+// The variants other than Wts are only held to be dropped
+#[allow(dead_code)]
+pub(crate) enum Source {
+    Wts(Registration),
+    EventLog(EventLogSubscription),
+    Sens(SensSubscription),
+    Wmi(WmiSubscription),
+    Polling(PollingSource),
+}
+
+impl Source {
+    pub fn start(
+        backend: Backend,
+        window: &MessageWindow,
+        scope: SessionScope,
+        inbox: &Inbox,
+        poll_interval: Duration,
+    ) -> Result<Self, Error> {
+        Ok(match backend {
+            Backend::Wts => Self::Wts(Registration::register(window, scope)?),
+            Backend::EventLog => Self::EventLog(EventLogSubscription::start(
+                scope,
+                inbox.clone(),
+            )?),
+            Backend::Sens => {
+                Self::Sens(SensSubscription::start(scope, inbox.clone())?)
+            }
+            Backend::Wmi => {
+                Self::Wmi(WmiSubscription::start(scope, inbox.clone())?)
+            }
+            Backend::Polling => Self::Polling(PollingSource::start(
+                scope,
+                inbox.clone(),
+                poll_interval,
+            )?),
+        })
+    }
+
+    /// Register for WTS notifications again, the other backends are left
+    /// alone
+    pub fn renew(&self) -> Result<(), Error> {
+        match self {
+            Self::Wts(registration) => registration.renew(),
+            _ => Ok(()),
+        }
+    }
+}
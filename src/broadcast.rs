@@ -0,0 +1,25 @@
+//! Fan out session events over a [`tokio::sync::broadcast`] channel, enabled
+//! with the `broadcast` feature
+use crate::spawn::spawn_pump;
+use crate::{Error, MonitorHandle, SessionEvent, SessionMonitorBuilder};
+use tokio::sync::broadcast;
+
+impl SessionMonitorBuilder {
+    /// Spawn a monitor thread that sends every event on a broadcast channel
+    /// holding up to `capacity` events. Call `subscribe` on the sender for
+    /// each task, receivers that fall behind see `RecvError::Lagged` instead
+    /// of slowing the monitor down. Events sent while nobody is subscribed
+    /// are dropped
+    pub fn spawn_broadcast(
+        self,
+        capacity: usize,
+    ) -> Result<(broadcast::Sender<SessionEvent>, MonitorHandle), Error> {
+        let (tx, _) = broadcast::channel(capacity);
+        let sender = tx.clone();
+        let handle = spawn_pump(self, move |event| {
+            let _ = sender.send(event);
+            true
+        })?;
+        Ok((tx, handle))
+    }
+}
@@ -0,0 +1,848 @@
+//! The built in actions, done natively instead of through a command
+use crate::actions::{secret, SmartDevice};
+use crate::png;
+use crate::session::query_lock_state;
+use crate::webcam;
+use crate::wynapi::{
+    close_windows, current_session_id, eject_device, http_request,
+    input_desktop_is_switchable, monitors_off, process_list, process_path,
+    process_session_id, ras_connections, ras_hang_up, removable_disks,
+    screen_capture, wnet_add_connection, wnet_cancel_connection,
+    wnet_get_connection, Clipboard, Error, ProcessHandle, Screenshot, Service,
+    WtsState, SERVICE_RUNNING, SERVICE_STOPPED,
+};
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{event, Level};
+
+/// How long a service gets to finish starting or stopping
+const SERVICE_WAIT: Duration = Duration::from_secs(30);
+/// How often a starting or stopping service is checked on
+const SERVICE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How often [`keep_frames`] captures the desktop
+const FRAME_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The VPN connections [`hang_up_vpn`] dropped, for [`dial_vpn`] to bring
+/// back. Each Lock adds to them
+static HUNG_UP: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// The drives the last [`unmap_drives`] removed and the shares they were
+/// mapped to, for [`map_drives`] to put back
+static UNMAPPED: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// The containers [`pause_containers`] paused, for [`unpause_containers`]
+/// to resume
+static PAUSED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// The desktop as [`keep_frames`] last captured it and when, for
+/// [`screenshot`] on Lock
+static FRAME: Mutex<Option<(Instant, Screenshot)>> = Mutex::new(None);
+
+/// Set while the thread [`keep_frames`] started should keep capturing
+static KEEPER: Mutex<Option<Arc<AtomicBool>>> = Mutex::new(None);
+
+/// Kill the processes in `session_id` named any of `names`, asking their
+/// windows to close first if there is a `grace` period. True if none are
+/// left running
+pub(crate) fn kill_processes(
+    names: &[String],
+    grace: Option<Duration>,
+    session_id: u32,
+) -> bool {
+    let matches = |exe_name: &str| {
+        names.iter().any(|name| {
+            exe_name.eq_ignore_ascii_case(name)
+                || exe_name.eq_ignore_ascii_case(&format!("{name}.exe"))
+        })
+    };
+    let processes: Vec<_> = match process_list() {
+        Ok(processes) => processes,
+        Err(_) => return false,
+    }
+    .into_iter()
+    .filter(|process| matches(&process.exe_name))
+    .filter(|process| process_session_id(process.id) == Some(session_id))
+    .filter_map(|process| {
+        let handle = ProcessHandle::open(process.id).ok()?;
+        Some((process.exe_name, handle))
+    })
+    .collect();
+
+    if let Some(grace) = grace.filter(|_| !processes.is_empty()) {
+        let ids: Vec<u32> =
+            processes.iter().map(|(_, handle)| handle.id()).collect();
+        close_windows(&ids);
+        let deadline = Instant::now() + grace;
+        for (_, handle) in &processes {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let _ = handle.wait(Some(left.as_millis() as u32));
+        }
+    }
+
+    let mut killed_all = true;
+    for (exe_name, handle) in processes {
+        match handle.wait(Some(0)) {
+            Ok(Some(_)) => {
+                event!(Level::INFO, "{exe_name} {} closed", handle.id())
+            }
+            _ => match handle.kill() {
+                Ok(()) => {
+                    event!(Level::INFO, "Killed {exe_name} {}", handle.id())
+                }
+                Err(_) => killed_all = false,
+            },
+        }
+    }
+    killed_all
+}
+
+/// Start the services `names`, or stop them if not `running`, waiting for
+/// each to get there. True if they all did, already being there counts
+pub(crate) fn set_services(names: &[String], running: bool) -> bool {
+    let mut all_done = true;
+    for name in names {
+        if let Err(err) = set_service(name, running) {
+            let verb = if running { "start" } else { "stop" };
+            event!(Level::ERROR, "Failed to {verb} service {name} {err}");
+            all_done = false;
+        }
+    }
+    all_done
+}
+
+fn set_service(name: &str, running: bool) -> Result<(), String> {
+    let service = Service::open(name).map_err(|err| err.to_string())?;
+    let (requested, target) = if running {
+        (service.start(), SERVICE_RUNNING)
+    } else {
+        (service.stop(), SERVICE_STOPPED)
+    };
+    match requested {
+        Ok(())
+        | Err(Error::ERROR_SERVICE_ALREADY_RUNNING)
+        | Err(Error::ERROR_SERVICE_NOT_ACTIVE) => {}
+        Err(err) => return Err(err.to_string()),
+    }
+    let deadline = Instant::now() + SERVICE_WAIT;
+    loop {
+        if service.state().map_err(|err| err.to_string())? == target {
+            event!(Level::INFO, "Service {name} is {}", state_name(target));
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "it isn't {} after {SERVICE_WAIT:?}",
+                state_name(target)
+            ));
+        }
+        thread::sleep(SERVICE_POLL_INTERVAL);
+    }
+}
+
+fn state_name(state: u32) -> &'static str {
+    match state {
+        SERVICE_RUNNING => "running",
+        _ => "stopped",
+    }
+}
+
+/// Turn the displays off once `delay` has passed, unless the workstation
+/// was unlocked in the meantime. True if they were turned off or didn't
+/// need to be
+pub(crate) fn displays_off(delay: Duration) -> bool {
+    thread::sleep(delay);
+    // Only the lock screen can't be switched to, so this means unlocked
+    if input_desktop_is_switchable() {
+        event!(Level::INFO, "Unlocked again, leaving the displays on");
+        return true;
+    }
+    monitors_off().is_ok()
+}
+
+/// Empty the clipboard, or only if it holds text that looks like a secret
+/// if `only_secrets`. True unless it couldn't be emptied
+pub(crate) fn clear_clipboard(only_secrets: bool) -> bool {
+    let Ok(clipboard) = Clipboard::open() else {
+        return false;
+    };
+    if only_secrets && !clipboard.text().is_some_and(|t| looks_like_secret(&t))
+    {
+        return true;
+    }
+    let emptied = clipboard.empty().is_ok();
+    if emptied {
+        event!(Level::INFO, "Cleared the clipboard");
+    }
+    emptied
+}
+
+/// A guess at whether copied text is a password, key or token: one word of
+/// 8 to 256 characters mixing at least three of lower case, upper case,
+/// digits and symbols, or a long run of hex or base64 such as an API key
+fn looks_like_secret(text: &str) -> bool {
+    let text = text.trim();
+    let length = text.chars().count();
+    if !(8..=256).contains(&length) || text.contains(char::is_whitespace) {
+        return false;
+    }
+    let classes = [
+        text.contains(|c: char| c.is_lowercase()),
+        text.contains(|c: char| c.is_uppercase()),
+        text.contains(|c: char| c.is_ascii_digit()),
+        text.contains(|c: char| !c.is_alphanumeric()),
+    ];
+    let key_like = length >= 20
+        && text.contains(|c: char| c.is_ascii_digit())
+        && text.chars().all(|c| {
+            c.is_ascii_alphanumeric()
+                || matches!(c, '+' | '/' | '=' | '-' | '_')
+        });
+    classes.iter().filter(|&&class| class).count() >= 3 || key_like
+}
+
+/// The password managers [`lock_password_managers`] knows and the argument
+/// that asks a running one to lock its databases
+const PASSWORD_MANAGERS: [(&str, &str); 2] =
+    [("KeePass.exe", "--lock-all"), ("KeePassXC.exe", "--lock")];
+
+/// Ask the KeePass and KeePassXC instances running in `session_id` to lock
+/// their databases, by starting their exe again with its lock argument,
+/// which hands it to the running one. True if they all took it
+pub(crate) fn lock_password_managers(session_id: u32) -> bool {
+    let Ok(processes) = process_list() else {
+        return false;
+    };
+    let mut locked_all = true;
+    for (exe_name, lock) in PASSWORD_MANAGERS {
+        let mut paths: Vec<String> = processes
+            .iter()
+            .filter(|process| process.exe_name.eq_ignore_ascii_case(exe_name))
+            .filter(|process| {
+                process_session_id(process.id) == Some(session_id)
+            })
+            .filter_map(|process| process_path(process.id))
+            .collect();
+        paths.sort();
+        paths.dedup();
+        for path in paths {
+            match Command::new(&path).arg(lock).status() {
+                Ok(status) if status.success() => {
+                    event!(Level::INFO, "Asked {exe_name} to lock")
+                }
+                Ok(status) => {
+                    event!(Level::ERROR, "{path} {lock} failed, {status}");
+                    locked_all = false;
+                }
+                Err(err) => {
+                    event!(Level::ERROR, "Failed to start {path} {err}");
+                    locked_all = false;
+                }
+            }
+        }
+    }
+    locked_all
+}
+
+/// Remove every key from ssh-agent with `ssh-add -D` and make gpg-agent
+/// forget cached passphrases by reloading it. A tool that isn't installed
+/// is skipped. True if the rest succeeded
+pub(crate) fn clear_agent_keys() -> bool {
+    let ssh = run_if_installed("ssh-add", &["-D"]);
+    let gpg = run_if_installed("gpg-connect-agent", &["reloadagent", "/bye"]);
+    ssh && gpg
+}
+
+/// Run a tool found on the path, true if it succeeded or isn't there
+fn run_if_installed(program: &str, args: &[&str]) -> bool {
+    match Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => {
+            event!(Level::INFO, "Ran {program} {}", args.join(" "));
+            true
+        }
+        Ok(output) => {
+            event!(
+                Level::ERROR,
+                "{program} {} failed, {} {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            false
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            event!(Level::INFO, "{program} isn't installed, skipping it");
+            true
+        }
+        Err(err) => {
+            event!(Level::ERROR, "Failed to start {program} {err}");
+            false
+        }
+    }
+}
+
+/// Hang up the dial up and VPN connections named `names`, or every one if
+/// empty, falling back to `rasdial /disconnect`. True if they all went
+pub(crate) fn hang_up_vpn(names: &[String]) -> bool {
+    let wanted = |name: &str| {
+        names.is_empty() || names.iter().any(|n| n.eq_ignore_ascii_case(name))
+    };
+    let connections = match ras_connections() {
+        Ok(connections) => connections,
+        // Without the RAS API only named connections can be hung up
+        Err(_) => {
+            let hung_up = names
+                .iter()
+                .filter(|name| rasdial(&[name, "/disconnect"]))
+                .count();
+            return !names.is_empty() && hung_up == names.len();
+        }
+    };
+    let mut hung_up = HUNG_UP.lock().unwrap();
+    let mut all_gone = true;
+    for connection in connections.iter().filter(|c| wanted(&c.name)) {
+        if ras_hang_up(connection).is_ok()
+            || rasdial(&[&connection.name, "/disconnect"])
+        {
+            event!(Level::INFO, "Hung up {}", connection.name);
+            if !hung_up.contains(&connection.name) {
+                hung_up.push(connection.name.clone());
+            }
+        } else {
+            all_gone = false;
+        }
+    }
+    all_gone
+}
+
+/// Dial the connections named `names` with their saved credentials, or the
+/// ones [`hang_up_vpn`] dropped if empty. True if all connected
+pub(crate) fn dial_vpn(names: &[String]) -> bool {
+    let names = match names {
+        [] => core::mem::take(&mut *HUNG_UP.lock().unwrap()),
+        names => names.to_vec(),
+    };
+    let dialled = names.iter().filter(|name| rasdial(&[name])).count();
+    dialled == names.len()
+}
+
+/// Run rasdial, true if it succeeded
+fn rasdial(args: &[&str]) -> bool {
+    match Command::new("rasdial").args(args).output() {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => {
+            event!(
+                Level::ERROR,
+                "rasdial {} failed, {} {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+            false
+        }
+        Err(err) => {
+            event!(Level::ERROR, "Failed to start rasdial {err}");
+            false
+        }
+    }
+}
+
+/// Unmap the network drives `drives` such as `S:`, remembering their
+/// shares. True if none are left mapped
+pub(crate) fn unmap_drives(drives: &[String]) -> bool {
+    let mut unmapped = UNMAPPED.lock().unwrap();
+    let mut all_gone = true;
+    for drive in drives {
+        // A drive that isn't mapped has nothing to do
+        let Ok(remote) = wnet_get_connection(drive) else {
+            continue;
+        };
+        match wnet_cancel_connection(drive) {
+            Ok(()) => {
+                event!(Level::INFO, "Unmapped {drive} from {remote}");
+                unmapped.retain(|(existing, _)| {
+                    !existing.eq_ignore_ascii_case(drive)
+                });
+                unmapped.push((drive.clone(), remote));
+            }
+            Err(_) => all_gone = false,
+        }
+    }
+    all_gone
+}
+
+/// Map back the drives `drives` unmapped by [`unmap_drives`], or all of
+/// them if empty. True if they all were
+pub(crate) fn map_drives(drives: &[String]) -> bool {
+    let mut unmapped = UNMAPPED.lock().unwrap();
+    let mut all_mapped = true;
+    unmapped.retain(|(drive, remote)| {
+        if !drives.is_empty()
+            && !drives.iter().any(|d| d.eq_ignore_ascii_case(drive))
+        {
+            return true;
+        }
+        match wnet_add_connection(drive, remote) {
+            Ok(()) => {
+                event!(Level::INFO, "Mapped {drive} to {remote}");
+                false
+            }
+            Err(_) => {
+                all_mapped = false;
+                true
+            }
+        }
+    });
+    all_mapped
+}
+
+/// Eject every removable disk, such as USB sticks. True if they all were
+pub(crate) fn eject_removable_storage() -> bool {
+    let Ok(disks) = removable_disks() else {
+        return false;
+    };
+    let ejected = disks
+        .iter()
+        .filter(|disk| {
+            let ejected = eject_device(disk).is_ok();
+            if ejected {
+                event!(Level::INFO, "Ejected {}", disk.name);
+            }
+            ejected
+        })
+        .count();
+    ejected == disks.len()
+}
+
+/// Save a webcam photo in `dir` with a text file of the event's details
+/// beside it, both named after the time, event and session. True if saved
+pub(crate) fn webcam_snapshot(
+    dir: &Path,
+    variables: &[(&str, String)],
+) -> bool {
+    let stem = file_stem(variables);
+    let Ok(frame) = webcam::capture() else {
+        return false;
+    };
+    let details: String = variables
+        .iter()
+        .map(|(name, value)| format!("{name}={value}\n"))
+        .collect();
+    let saved = std::fs::create_dir_all(dir)
+        .and_then(|()| {
+            std::fs::write(dir.join(format!("{stem}.bmp")), frame.to_bitmap())
+        })
+        .and_then(|()| {
+            std::fs::write(dir.join(format!("{stem}.txt")), details)
+        });
+    match saved {
+        Ok(()) => {
+            event!(Level::INFO, "Saved {stem}.bmp in {}", dir.display());
+            true
+        }
+        Err(err) => {
+            event!(Level::ERROR, "Failed to save {stem} {err}");
+            false
+        }
+    }
+}
+
+/// Capture the desktop every [`FRAME_INTERVAL`] while our session is
+/// unlocked, or stop if `keep` isn't set. By the time a Lock arrives
+/// Windows has often switched to the lock screen, which can't be captured,
+/// so [`screenshot`] saves the last of these instead
+pub(crate) fn keep_frames(keep: bool) {
+    let mut keeper = KEEPER.lock().unwrap();
+    match (keep, keeper.take()) {
+        (true, Some(running)) => *keeper = Some(running),
+        (true, None) => {
+            event!(
+                Level::INFO,
+                "Capturing the desktop for screenshots on lock"
+            );
+            let running = Arc::new(AtomicBool::new(true));
+            *keeper = Some(running.clone());
+            thread::spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    let state =
+                        current_session_id().ok().and_then(query_lock_state);
+                    if state == Some(WtsState::Unlock) {
+                        if let Ok(screen) = screen_capture() {
+                            let frame = (Instant::now(), screen);
+                            *FRAME.lock().unwrap() = Some(frame);
+                        }
+                    }
+                    thread::sleep(FRAME_INTERVAL);
+                }
+            });
+        }
+        (false, Some(running)) => {
+            event!(Level::INFO, "No longer capturing the desktop");
+            running.store(false, Ordering::Relaxed);
+            *FRAME.lock().unwrap() = None;
+        }
+        (false, None) => {}
+    }
+}
+
+/// Save a PNG of the desktop in `dir`, named after the time, event and
+/// session, then delete the PNGs there older than `keep`. For a Lock it is
+/// the desktop just before, from [`keep_frames`], if it has one from the
+/// last couple of captures. True if saved
+pub(crate) fn screenshot(
+    dir: &Path,
+    keep: Option<Duration>,
+    variables: &[(&str, String)],
+) -> bool {
+    let lock = variables.iter().any(|(name, value)| {
+        *name == "event" && value == WtsState::Lock.name()
+    });
+    let frame = FRAME
+        .lock()
+        .unwrap()
+        .as_ref()
+        .filter(|(captured, _)| {
+            lock && captured.elapsed() <= FRAME_INTERVAL * 2
+        })
+        .map(|(_, screen)| screen.clone());
+    let screen = match frame {
+        Some(screen) => screen,
+        None => match screen_capture() {
+            Ok(screen) => screen,
+            Err(_) => return false,
+        },
+    };
+    let name = format!("{}.png", file_stem(variables));
+    let png = png::encode(screen.width, screen.height, &screen.pixels);
+    let saved = std::fs::create_dir_all(dir)
+        .and_then(|()| std::fs::write(dir.join(&name), png));
+    if let Err(err) = saved {
+        event!(Level::ERROR, "Failed to save {name} {err}");
+        return false;
+    }
+    event!(Level::INFO, "Saved {name} in {}", dir.display());
+    if let Some(keep) = keep {
+        delete_old_pngs(dir, keep);
+    }
+    true
+}
+
+/// Delete the PNGs in `dir` last modified more than `keep` ago
+fn delete_old_pngs(dir: &Path, keep: Duration) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            event!(Level::ERROR, "Failed to list {} {err}", dir.display());
+            return;
+        }
+    };
+    let now = SystemTime::now();
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let is_png = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+        let expired = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| {
+                now.duration_since(modified).is_ok_and(|age| age > keep)
+            });
+        if is_png && expired {
+            match std::fs::remove_file(&path) {
+                Ok(()) => event!(Level::INFO, "Deleted {}", path.display()),
+                Err(err) => event!(
+                    Level::ERROR,
+                    "Failed to delete {} {err}",
+                    path.display()
+                ),
+            }
+        }
+    }
+}
+
+/// A file name for an event's capture, eg. `1700000000-lock-session1`
+fn file_stem(variables: &[(&str, String)]) -> String {
+    let value = |name| {
+        variables
+            .iter()
+            .find(|(variable, _)| *variable == name)
+            .map_or("", |(_, value)| value.as_str())
+    };
+    format!(
+        "{}-{}-session{}",
+        value("timestamp"),
+        value("event"),
+        value("session_id")
+    )
+}
+
+/// Pause the Docker containers `names`, or every running one if empty,
+/// remembering them. True if they all were
+pub(crate) fn pause_containers(names: &[String]) -> bool {
+    let names = match names {
+        [] => match docker(&[
+            "ps",
+            "--filter",
+            "status=running",
+            "--format",
+            "{{.Names}}",
+        ]) {
+            Some(running) => running.lines().map(String::from).collect(),
+            None => return false,
+        },
+        names => names.to_vec(),
+    };
+    let mut paused = PAUSED.lock().unwrap();
+    let mut all_paused = true;
+    for name in names {
+        if docker(&["pause", &name]).is_some() {
+            event!(Level::INFO, "Paused container {name}");
+            if !paused.contains(&name) {
+                paused.push(name);
+            }
+        } else {
+            all_paused = false;
+        }
+    }
+    all_paused
+}
+
+/// Resume the Docker containers `names`, or the ones [`pause_containers`]
+/// paused if empty. True if they all were
+pub(crate) fn unpause_containers(names: &[String]) -> bool {
+    let mut paused = PAUSED.lock().unwrap();
+    let names = match names {
+        [] => core::mem::take(&mut *paused),
+        names => names.to_vec(),
+    };
+    let mut all_resumed = true;
+    for name in names {
+        if docker(&["unpause", &name]).is_some() {
+            event!(Level::INFO, "Unpaused container {name}");
+            paused.retain(|existing| *existing != name);
+        } else {
+            all_resumed = false;
+        }
+    }
+    all_resumed
+}
+
+/// Run the docker CLI, its output if it succeeded
+fn docker(args: &[&str]) -> Option<String> {
+    match Command::new("docker").args(args).output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(output) => {
+            event!(
+                Level::ERROR,
+                "docker {} failed, {} {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            None
+        }
+        Err(err) => {
+            event!(Level::ERROR, "Failed to start docker {err}");
+            None
+        }
+    }
+}
+
+/// Terminate the WSL distributions `distros`, or shut the whole WSL VM
+/// down if empty. They start again the next time they're used. True if
+/// they all stopped
+pub(crate) fn shutdown_wsl(distros: &[String]) -> bool {
+    if distros.is_empty() {
+        return wsl(&["--shutdown"]);
+    }
+    let stopped = distros
+        .iter()
+        .filter(|distro| wsl(&["--terminate", distro]))
+        .count();
+    stopped == distros.len()
+}
+
+/// Run wsl.exe, true if it succeeded
+fn wsl(args: &[&str]) -> bool {
+    // Otherwise it writes UTF-16
+    let output = Command::new("wsl").args(args).env("WSL_UTF8", "1").output();
+    match output {
+        Ok(output) if output.status.success() => {
+            event!(Level::INFO, "Ran wsl {}", args.join(" "));
+            true
+        }
+        Ok(output) => {
+            event!(
+                Level::ERROR,
+                "wsl {} failed, {} {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+            false
+        }
+        Err(err) => {
+            event!(Level::ERROR, "Failed to start wsl {err}");
+            false
+        }
+    }
+}
+
+/// Set the Slack status of the user `token` belongs to, away with a lock
+/// and their presence away, or cleared with their presence back to
+/// automatic. True if Slack took both
+pub(crate) fn slack_status(away: bool, token: &str) -> bool {
+    let (presence, profile) = if away {
+        ("away", r#"{"status_text":"Away","status_emoji":":lock:"}"#)
+    } else {
+        ("auto", r#"{"status_text":"","status_emoji":""}"#)
+    };
+    let presence = format!(r#"{{"presence":"{presence}"}}"#);
+    let profile = format!(r#"{{"profile":{profile}}}"#);
+    let presence_set = slack("users.setPresence", token, &presence);
+    let profile_set = slack("users.profile.set", token, &profile);
+    presence_set && profile_set
+}
+
+/// Call the Slack Web API `method` with the JSON `body`, true if it
+/// answered ok
+fn slack(method: &str, token: &str, body: &str) -> bool {
+    let url = format!("https://slack.com/api/{method}");
+    let authorization = format!("Bearer {token}");
+    let headers = [
+        ("Authorization", authorization.as_str()),
+        ("Content-Type", "application/json; charset=utf-8"),
+    ];
+    // Failures are logged by the wrapper
+    let Ok((status, response)) =
+        http_request("POST", &url, &headers, body.as_bytes())
+    else {
+        return false;
+    };
+    let response = String::from_utf8_lossy(&response);
+    // Slack answers most failures with a 200 saying why
+    if status == 200 && response.contains(r#""ok":true"#) {
+        return true;
+    }
+    event!(Level::ERROR, "Slack {method} failed, {status} {response}");
+    false
+}
+
+/// Set the Teams presence of the user a Microsoft Graph `token` belongs to,
+/// to Away, or clear that so Teams works it out again. True if Graph took it
+pub(crate) fn teams_presence(away: bool, token: &str) -> bool {
+    let (method, body) = if away {
+        (
+            "setUserPreferredPresence",
+            r#"{"availability":"Away","activity":"Away"}"#,
+        )
+    } else {
+        ("clearUserPreferredPresence", "")
+    };
+    let url = format!("https://graph.microsoft.com/v1.0/me/presence/{method}");
+    let authorization = format!("Bearer {token}");
+    let headers = [
+        ("Authorization", authorization.as_str()),
+        ("Content-Type", "application/json"),
+    ];
+    // Failures are logged by the wrapper
+    let Ok((status, response)) =
+        http_request("POST", &url, &headers, body.as_bytes())
+    else {
+        return false;
+    };
+    if (200..300).contains(&status) {
+        return true;
+    }
+    let response = String::from_utf8_lossy(&response);
+    event!(Level::ERROR, "Teams {method} failed, {status} {response}");
+    false
+}
+
+/// Turn `device` on or off. True if it, or for Hue every light and group,
+/// did
+pub(crate) fn switch_device(device: &SmartDevice, on: bool) -> bool {
+    let power = if on { "on" } else { "off" };
+    let switched = match device {
+        SmartDevice::Hue {
+            bridge,
+            user,
+            lights,
+            groups,
+        } => {
+            let Some(user) = secret(user) else {
+                return false;
+            };
+            let body = format!(r#"{{"on":{on}}}"#);
+            let lights =
+                lights.iter().map(|light| format!("lights/{light}/state"));
+            let groups =
+                groups.iter().map(|group| format!("groups/{group}/action"));
+            let paths: Vec<String> = lights.chain(groups).collect();
+            let switched = paths.iter().filter(|path| {
+                let url = format!("http://{bridge}/api/{user}/{path}");
+                hue(&url, &body)
+            });
+            switched.count() == paths.len()
+        }
+        SmartDevice::Tasmota { host, relay } => {
+            let relay =
+                relay.map(|relay| relay.to_string()).unwrap_or_default();
+            let url = format!("http://{host}/cm?cmnd=Power{relay}%20{power}");
+            get(&url)
+        }
+        SmartDevice::Shelly { host, relay } => {
+            let url =
+                format!("http://{host}/rpc/Switch.Set?id={relay}&on={on}");
+            match http_request("GET", &url, &[], &[]) {
+                Ok((200, _)) => true,
+                // Gen 1 devices only have the older API
+                Ok((404, _)) => {
+                    get(&format!("http://{host}/relay/{relay}?turn={power}"))
+                }
+                Ok((status, _)) => {
+                    event!(Level::ERROR, "Shelly {host} answered {status}");
+                    false
+                }
+                Err(_) => false,
+            }
+        }
+    };
+    if switched {
+        event!(Level::INFO, "Turned {power} {device}");
+    }
+    switched
+}
+
+/// PUT `body` to a Hue bridge's `url`, true unless it answered with an
+/// error. The bridge answers 200 either way
+fn hue(url: &str, body: &str) -> bool {
+    // Failures are logged by the wrapper
+    let Ok((status, response)) = http_request("PUT", url, &[], body.as_bytes())
+    else {
+        return false;
+    };
+    let response = String::from_utf8_lossy(&response);
+    if status == 200 && !response.contains(r#""error""#) {
+        return true;
+    }
+    event!(Level::ERROR, "Hue bridge failed, {status} {response}");
+    false
+}
+
+/// GET `url`, true if it answered 200
+fn get(url: &str) -> bool {
+    // Failures are logged by the wrapper
+    match http_request("GET", url, &[], &[]) {
+        Ok((200, _)) => true,
+        Ok((status, _)) => {
+            event!(Level::ERROR, "{url} answered {status}");
+            false
+        }
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,69 @@
+//! Fan out every session event to any number of independent subscribers
+use crate::spawn::spawn_pump;
+use crate::{
+    Error, MonitorHandle, SessionEvent, SessionMonitorBuilder, StateSet,
+};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// A subscriber and the states it asked for
+type Subscriber = (StateSet, Sender<SessionEvent>);
+
+/// Hands a copy of each published event to every subscriber. Cheap to clone
+/// and can be shared with other threads, subscribers that hang up are
+/// dropped on the next publish
+#[derive(Debug, Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive every event published from now on
+    pub fn subscribe(&self) -> Receiver<SessionEvent> {
+        self.subscribe_to(StateSet::ALL)
+    }
+
+    /// Receive only the events in `states` published from now on, the rest
+    /// are never cloned or sent to this subscriber
+    pub fn subscribe_to(
+        &self,
+        states: impl Into<StateSet>,
+    ) -> Receiver<SessionEvent> {
+        let (tx, rx) = channel();
+        self.subscribers.lock().unwrap().push((states.into(), tx));
+        rx
+    }
+
+    /// Send `event` to every subscriber still listening
+    pub fn publish(&self, event: SessionEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|(states, subscriber)| {
+                !states.contains(event.state)
+                    || subscriber.send(event.clone()).is_ok()
+            });
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().unwrap().len()
+    }
+}
+
+impl SessionMonitorBuilder {
+    /// Spawn a monitor thread that publishes every event onto the returned
+    /// bus, subscribers can be added at any time
+    pub fn spawn_bus(self) -> Result<(EventBus, MonitorHandle), Error> {
+        let bus = EventBus::new();
+        let publisher = bus.clone();
+        let handle = spawn_pump(self, move |event| {
+            publisher.publish(event);
+            true
+        })?;
+        Ok((bus, handle))
+    }
+}
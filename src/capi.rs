@@ -0,0 +1,99 @@
+//! C ABI for consuming session events from other languages, enabled with the
+//! `capi` feature. The header is generated into `include/rusty_lock.h` with
+//! `cbindgen --config cbindgen.toml --output include/rusty_lock.h`
+use crate::spawn::spawn_pump;
+use crate::{MonitorHandle, SessionEvent, SessionMonitorBuilder};
+use core::ffi::c_void;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// A session event as handed to C callbacks
+#[repr(C)]
+pub struct RustyLockEvent {
+    /// The WTS status code, eg. 7 for WTS_SESSION_LOCK
+    pub state: u32,
+    /// The session the notification is about
+    pub session_id: u32,
+    /// Milliseconds since the UNIX epoch when the event was received
+    pub timestamp_ms: u64,
+}
+
+impl From<&SessionEvent> for RustyLockEvent {
+    fn from(event: &SessionEvent) -> Self {
+        Self {
+            state: usize::from(event.state) as u32,
+            session_id: event.session_id,
+            timestamp_ms: event
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+        }
+    }
+}
+
+/// Called on the monitor thread for every event, `event` is only valid for
+/// the duration of the call. Must not call back into `rustylock_*`
+pub type RustyLockCallback =
+    extern "C" fn(event: *const RustyLockEvent, user_data: *mut c_void);
+
+struct Subscriber {
+    callback: RustyLockCallback,
+    user_data: *mut c_void,
+}
+
+// The caller promises `user_data` may be used from the monitor thread
+unsafe impl Send for Subscriber {}
+
+static MONITOR: Mutex<Option<MonitorHandle>> = Mutex::new(None);
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+/// Register `callback` to be called with every session event, starting the
+/// monitor thread on the first subscription. Returns 0 on success and -1 if
+/// the monitor could not be started
+#[no_mangle]
+pub extern "C" fn rustylock_subscribe(
+    callback: RustyLockCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    SUBSCRIBERS.lock().unwrap().push(Subscriber {
+        callback,
+        user_data,
+    });
+
+    let mut monitor = MONITOR.lock().unwrap();
+    if monitor.is_some() {
+        return 0;
+    }
+    match spawn_pump(SessionMonitorBuilder::new(), |event| {
+        let event = RustyLockEvent::from(&event);
+        for subscriber in SUBSCRIBERS.lock().unwrap().iter() {
+            (subscriber.callback)(&event, subscriber.user_data);
+        }
+        true
+    }) {
+        Ok(handle) => {
+            *monitor = Some(handle);
+            0
+        }
+        Err(_) => {
+            SUBSCRIBERS.lock().unwrap().clear();
+            -1
+        }
+    }
+}
+
+/// Stop the monitor thread, wait for it to exit and drop every
+/// subscription. Returns 0 on success and -1 if nothing was running or the
+/// thread could not be stopped
+#[no_mangle]
+pub extern "C" fn rustylock_stop() -> i32 {
+    let Some(handle) = MONITOR.lock().unwrap().take() else {
+        return -1;
+    };
+    let res = match handle.stop() {
+        Ok(()) => handle.join().map_or(-1, |_| 0),
+        Err(_) => -1,
+    };
+    SUBSCRIBERS.lock().unwrap().clear();
+    res
+}
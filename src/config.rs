@@ -0,0 +1,428 @@
+//! Just enough of TOML for the action config: `key = value` lines whose
+//! values are strings, integers, booleans, arrays and inline tables, under
+//! `[table]` headers or none. Strings can take values from the environment
+//! and be encrypted
+use crate::wynapi::{protect_data, unprotect_data, Error};
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::str::Chars;
+
+/// What a string encrypted with [`encrypt_value`] starts with
+const ENCRYPTED: &str = "dpapi:";
+
+/// Encrypt `value` with DPAPI for a config, where it is decrypted as the
+/// config loads. Only the user who encrypted it can load it, or with
+/// `machine` anyone on this machine, such as a LocalSystem service
+pub fn encrypt_value(value: &str, machine: bool) -> Result<String, Error> {
+    Ok(format!("{ENCRYPTED}{}", protect_data(value, machine)?))
+}
+
+/// Why a config couldn't be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    /// The config isn't the TOML we understand, `line` counts from 1
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// The config parses but doesn't fit with the one it is layered over,
+    /// such as by naming an action again
+    Layer(String),
+    /// What went wrong with one of several configs
+    File {
+        path: PathBuf,
+        error: Box<ConfigError>,
+    },
+}
+
+impl core::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Parse { line, message } => {
+                write!(f, "line {line}: {message}")
+            }
+            Self::Layer(message) => write!(f, "{message}"),
+            Self::File { path, error } => {
+                write!(f, "{}: {error}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// A parsed value, tables keep their keys in the order written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// This with each `${VAR}` in its strings replaced with that
+    /// environment variable, or `${VAR:-fallback}` with the fallback if it is
+    /// unset or empty. `$${` is a literal `${`
+    pub fn interpolate(self) -> Result<Self, String> {
+        self.map_strings(&interpolate)
+    }
+
+    /// This with every `dpapi:` string in it decrypted
+    pub fn decrypt(self) -> Result<Self, String> {
+        self.map_strings(&|string| match string.strip_prefix(ENCRYPTED) {
+            Some(base64) => unprotect_data(base64)
+                .map_err(|err| format!("can't decrypt a dpapi: value, {err}")),
+            None => Ok(string),
+        })
+    }
+
+    /// This with `f` applied to every string in it, however deep
+    fn map_strings(
+        self,
+        f: &impl Fn(String) -> Result<String, String>,
+    ) -> Result<Self, String> {
+        Ok(match self {
+            Self::String(string) => Self::String(f(string)?),
+            Self::Array(values) => Self::Array(
+                values
+                    .into_iter()
+                    .map(|value| value.map_strings(f))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Self::Table(fields) => Self::Table(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, value.map_strings(f)?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            value => value,
+        })
+    }
+
+    /// What the value is, for error messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::String(_) => "a string",
+            Self::Integer(_) => "an integer",
+            Self::Boolean(_) => "a boolean",
+            Self::Array(_) => "an array",
+            Self::Table(_) => "a table",
+        }
+    }
+}
+
+/// One `key = value` and the line it started on. Under a header the key is
+/// dotted, `on_lock` after `[profile.work]` is `profile.work.on_lock`
+pub(crate) struct Entry {
+    pub line: usize,
+    pub key: String,
+    pub value: Value,
+}
+
+/// Parse every top level entry of `config`
+pub(crate) fn parse(config: &str) -> Result<Vec<Entry>, ConfigError> {
+    let mut parser = Parser {
+        chars: config.chars().peekable(),
+        line: 1,
+        table: None,
+    };
+    let mut entries = Vec::new();
+    while let Some(entry) = parser.entry()? {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Fill in the `${VAR}` and `${VAR:-fallback}` in `string`, see
+/// [`Value::interpolate`]
+fn interpolate(string: String) -> Result<String, String> {
+    if !string.contains("${") {
+        return Ok(string);
+    }
+    let mut interpolated = String::with_capacity(string.len());
+    let mut rest = string.as_str();
+    while let Some(start) = rest.find('$') {
+        interpolated.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            interpolated.push_str("${");
+            rest = after;
+            continue;
+        }
+        let Some(inner) = rest.strip_prefix("${") else {
+            interpolated.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let end = inner
+            .find('}')
+            .ok_or_else(|| format!("no }} after ${{ in {string}"))?;
+        let (name, fallback) = match inner[..end].split_once(":-") {
+            Some((name, fallback)) => (name, Some(fallback)),
+            None => (&inner[..end], None),
+        };
+        let value = std::env::var(name).ok().filter(|value| !value.is_empty());
+        match (value, fallback) {
+            (Some(value), _) => interpolated.push_str(&value),
+            (None, Some(fallback)) => interpolated.push_str(fallback),
+            (None, None) => {
+                return Err(format!(
+                    "{name} isn't set, ${{{name}:-fallback}} gives a default"
+                ))
+            }
+        }
+        rest = &inner[end + 1..];
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    /// The dotted name of the last `[table]` header
+    table: Option<String>,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> ConfigError {
+        ConfigError::Parse {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    /// Skip spaces and comments, and newlines too if `newlines`
+    fn skip(&mut self, newlines: bool) {
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {}
+                '\n' if newlines => {}
+                '#' => {
+                    while self.chars.peek().is_some_and(|&c| c != '\n') {
+                        self.next();
+                    }
+                    continue;
+                }
+                _ => return,
+            }
+            self.next();
+        }
+    }
+
+    /// The next `key = value` line, `None` at the end
+    fn entry(&mut self) -> Result<Option<Entry>, ConfigError> {
+        self.skip(true);
+        while self.chars.peek() == Some(&'[') {
+            self.header()?;
+            self.skip(true);
+        }
+        if self.chars.peek().is_none() {
+            return Ok(None);
+        }
+        let line = self.line;
+        let (key, value) = self.key_value()?;
+        let key = match &self.table {
+            Some(table) => format!("{table}.{key}"),
+            None => key,
+        };
+        self.skip(false);
+        match self.next() {
+            None | Some('\n') => Ok(Some(Entry { line, key, value })),
+            Some(c) => Err(self.error(format!("unexpected {c:?} after value"))),
+        }
+    }
+
+    /// A `[table]` header line, its keys can be dotted
+    fn header(&mut self) -> Result<(), ConfigError> {
+        self.next();
+        if self.chars.peek() == Some(&'[') {
+            return Err(self.error("arrays of tables aren't supported"));
+        }
+        let mut keys = Vec::new();
+        loop {
+            self.skip(false);
+            keys.push(self.key()?);
+            self.skip(false);
+            match self.next() {
+                Some('.') => {}
+                Some(']') => break,
+                _ => return Err(self.error("expected . or ] in header")),
+            }
+        }
+        self.skip(false);
+        match self.next() {
+            None | Some('\n') => {
+                self.table = Some(keys.join("."));
+                Ok(())
+            }
+            Some(c) => {
+                Err(self.error(format!("unexpected {c:?} after header")))
+            }
+        }
+    }
+
+    fn key_value(&mut self) -> Result<(String, Value), ConfigError> {
+        let key = self.key()?;
+        self.skip(false);
+        if self.next() != Some('=') {
+            return Err(self.error(format!("expected = after {key}")));
+        }
+        self.skip(false);
+        Ok((key, self.value()?))
+    }
+
+    /// A bare key or a quoted one
+    fn key(&mut self) -> Result<String, ConfigError> {
+        if matches!(self.chars.peek(), Some('"' | '\'')) {
+            return self.string();
+        }
+        let mut key = String::new();
+        while let Some(&c) = self
+            .chars
+            .peek()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+        {
+            key.push(c);
+            self.next();
+        }
+        if key.is_empty() {
+            return Err(self.error("expected a key"));
+        }
+        Ok(key)
+    }
+
+    fn value(&mut self) -> Result<Value, ConfigError> {
+        match self.chars.peek() {
+            Some('"' | '\'') => Ok(Value::String(self.string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.table(),
+            Some(c) if c.is_ascii_digit() || *c == '-' || *c == '+' => {
+                self.integer()
+            }
+            Some('t' | 'f') => self.boolean(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    /// An array, which can span lines and end with a comma
+    fn array(&mut self) -> Result<Value, ConfigError> {
+        self.next();
+        let mut values = Vec::new();
+        loop {
+            self.skip(true);
+            if self.chars.peek() == Some(&']') {
+                self.next();
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip(true);
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err(self.error("expected , or ] in array")),
+            }
+        }
+    }
+
+    /// An inline table, `{ key = value, ... }` on one line
+    fn table(&mut self) -> Result<Value, ConfigError> {
+        self.next();
+        let mut fields = Vec::new();
+        self.skip(false);
+        if self.chars.peek() == Some(&'}') {
+            self.next();
+            return Ok(Value::Table(fields));
+        }
+        loop {
+            self.skip(false);
+            let (key, value) = self.key_value()?;
+            if fields.iter().any(|(existing, _)| *existing == key) {
+                return Err(self.error(format!("{key} is set twice")));
+            }
+            fields.push((key, value));
+            self.skip(false);
+            match self.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Value::Table(fields)),
+                _ => return Err(self.error("expected , or } in table")),
+            }
+        }
+    }
+
+    /// A decimal integer, underscores between digits are allowed
+    fn integer(&mut self) -> Result<Value, ConfigError> {
+        let mut digits = String::new();
+        while let Some(&c) = self
+            .chars
+            .peek()
+            .filter(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '_'))
+        {
+            if c != '_' {
+                digits.push(c);
+            }
+            self.next();
+        }
+        digits
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| self.error(format!("{digits} is not an integer")))
+    }
+
+    fn boolean(&mut self) -> Result<Value, ConfigError> {
+        let mut word = String::new();
+        while let Some(&c) =
+            self.chars.peek().filter(|c| c.is_ascii_alphabetic())
+        {
+            word.push(c);
+            self.next();
+        }
+        match word.as_str() {
+            "true" => Ok(Value::Boolean(true)),
+            "false" => Ok(Value::Boolean(false)),
+            _ => Err(self.error(format!("expected a value, found {word}"))),
+        }
+    }
+
+    /// A basic `"..."` string with escapes or a literal `'...'` one
+    fn string(&mut self) -> Result<String, ConfigError> {
+        let quote = match self.next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => return Err(self.error("expected a string")),
+        };
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some(c) if c == quote => return Ok(string),
+                Some('\\') if quote == '"' => string.push(match self.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some(c @ ('"' | '\\')) => c,
+                    _ => return Err(self.error("unsupported escape")),
+                }),
+                None | Some('\n') => {
+                    return Err(self.error("unterminated string"))
+                }
+                Some(c) => string.push(c),
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Acting on sessions rather than observing them
+use crate::wynapi::*;
+use crate::WtsServer;
+use std::time::Duration;
+
+/// Lock the workstation, like pressing Win+L. Only works from a process
+/// running on the interactive desktop, the lock happens asynchronously and
+/// arrives as a [`WtsState::Lock`] event like any other
+pub fn lock_workstation() -> Result<(), Error> {
+    lock_work_station()
+}
+
+/// Disconnect a session on the local machine, leaving its programs running
+/// for the user to reconnect to. With `wait` this returns once the session
+/// is disconnected
+pub fn disconnect_session(session_id: u32, wait: bool) -> Result<(), Error> {
+    WtsServer::local().disconnect_session(session_id, wait)
+}
+
+/// Log off a session on the local machine, ending its programs. With `wait`
+/// this returns once the session is logged off
+pub fn logoff_session(session_id: u32, wait: bool) -> Result<(), Error> {
+    WtsServer::local().logoff_session(session_id, wait)
+}
+
+impl WtsServer {
+    /// Like [`disconnect_session`] for a session on this server
+    pub fn disconnect_session(
+        &self,
+        session_id: u32,
+        wait: bool,
+    ) -> Result<(), Error> {
+        wts_disconnect_session(self.handle(), session_id, wait)
+    }
+
+    /// Like [`logoff_session`] for a session on this server
+    pub fn logoff_session(
+        &self,
+        session_id: u32,
+        wait: bool,
+    ) -> Result<(), Error> {
+        wts_logoff_session(self.handle(), session_id, wait)
+    }
+}
+
+/// The buttons shown by [`send_message`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageButtons {
+    #[default]
+    Ok,
+    OkCancel,
+    YesNo,
+}
+
+/// What the user did with a message box shown by [`send_message`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MessageResponse {
+    Ok,
+    Cancel,
+    Yes,
+    No,
+    /// Nobody answered before the timeout
+    Timeout,
+    /// The message was sent without waiting for an answer
+    Async,
+    Unknown(u32),
+}
+
+impl From<u32> for MessageResponse {
+    fn from(id: u32) -> Self {
+        match id {
+            1 => Self::Ok,
+            2 => Self::Cancel,
+            6 => Self::Yes,
+            7 => Self::No,
+            32000 => Self::Timeout,
+            32001 => Self::Async,
+            _ => Self::Unknown(id),
+        }
+    }
+}
+
+/// Show a message box on the desktop of a session on the local machine, eg.
+/// from a service in session 0. With `wait` this blocks until the user
+/// answers or `timeout` passes, which never happens for `None`
+pub fn send_message(
+    session_id: u32,
+    title: &str,
+    message: &str,
+    buttons: MessageButtons,
+    timeout: Option<Duration>,
+    wait: bool,
+) -> Result<MessageResponse, Error> {
+    WtsServer::local()
+        .send_message(session_id, title, message, buttons, timeout, wait)
+}
+
+impl WtsServer {
+    /// Like [`send_message`] for a session on this server
+    pub fn send_message(
+        &self,
+        session_id: u32,
+        title: &str,
+        message: &str,
+        buttons: MessageButtons,
+        timeout: Option<Duration>,
+        wait: bool,
+    ) -> Result<MessageResponse, Error> {
+        let buttons = match buttons {
+            MessageButtons::Ok => MB_OK,
+            MessageButtons::OkCancel => MB_OKCANCEL,
+            MessageButtons::YesNo => MB_YESNO,
+        };
+        let timeout = timeout.map_or(0, |timeout| timeout.as_secs() as u32);
+        let response = wts_send_message_w(
+            self.handle(),
+            session_id,
+            title,
+            message,
+            buttons | MB_ICONINFORMATION,
+            timeout,
+            wait,
+        )?;
+        Ok(response.into())
+    }
+}
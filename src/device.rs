@@ -0,0 +1,57 @@
+//! USB devices seen through WM_DEVICECHANGE
+use core::str::FromStr;
+
+/// A USB device that was plugged in or removed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsbDevice {
+    pub vid: u16,
+    pub pid: u16,
+    /// The device interface path, eg.
+    /// `\\?\USB#VID_1050&PID_0407#5&1a2b3c&0&1#{a5dcbf10-...}`
+    pub path: String,
+}
+
+impl UsbDevice {
+    /// Pull the VID and PID out of an interface path, `None` if either is
+    /// missing
+    pub(crate) fn from_path(path: String) -> Option<Self> {
+        let upper = path.to_ascii_uppercase();
+        let id = |key: &str| {
+            let start = upper.find(key)? + key.len();
+            u16::from_str_radix(upper.get(start..start + 4)?, 16).ok()
+        };
+        Some(Self {
+            vid: id("VID_")?,
+            pid: id("PID_")?,
+            path,
+        })
+    }
+
+    pub fn id(&self) -> UsbId {
+        UsbId {
+            vid: self.vid,
+            pid: self.pid,
+        }
+    }
+}
+
+/// A USB vendor and product ID pair, parses from `VID:PID` in hex such as
+/// `1050:0407`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UsbId {
+    pub vid: u16,
+    pub pid: u16,
+}
+
+impl FromStr for UsbId {
+    type Err = ();
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let (vid, pid) = id.split_once(':').ok_or(())?;
+        Ok(Self {
+            vid: u16::from_str_radix(vid, 16).map_err(|_| ())?,
+            pid: u16::from_str_radix(pid, 16).map_err(|_| ())?,
+        })
+    }
+}
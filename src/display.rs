@@ -0,0 +1,64 @@
+//! Monitors being plugged in and out and resolution changes, told apart from
+//! the WM_DISPLAYCHANGE broadcast by counting the monitors
+use crate::wynapi::{monitor_count, screen_size, WtsState};
+
+/// The display layout after a MonitorAdded, MonitorRemoved or
+/// ResolutionChanged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisplayConfig {
+    /// How many monitors make up the desktop
+    pub monitors: u32,
+    /// The resolution of the primary monitor
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_pixel: u32,
+}
+
+#[derive(Debug)]
+pub(crate) struct DisplayTracker {
+    monitors: u32,
+    size: (u32, u32),
+}
+
+impl DisplayTracker {
+    /// Start from the layout at the moment
+    pub fn new() -> Self {
+        Self {
+            monitors: monitor_count(),
+            size: screen_size(),
+        }
+    }
+
+    /// Feed a WM_DISPLAYCHANGE, returns the state it amounts to if anything
+    /// changed. A change in the number of monitors wins over the
+    /// resolution change that usually comes with it
+    pub fn on_change(
+        &mut self,
+        width: u32,
+        height: u32,
+        bits_per_pixel: u32,
+    ) -> Option<(WtsState, DisplayConfig)> {
+        let monitors = monitor_count();
+        let state = if monitors > self.monitors {
+            WtsState::MonitorAdded
+        } else if monitors < self.monitors {
+            WtsState::MonitorRemoved
+        } else if (width, height) != self.size {
+            WtsState::ResolutionChanged
+        } else {
+            return None;
+        };
+        self.monitors = monitors;
+        self.size = (width, height);
+        Some((
+            state,
+            DisplayConfig {
+                monitors,
+                width,
+                height,
+                bits_per_pixel,
+            },
+        ))
+    }
+}
@@ -0,0 +1,89 @@
+//! The events handed to consumers of the library
+use crate::device::UsbDevice;
+use crate::display::DisplayConfig;
+use crate::hotkey::Hotkey;
+use crate::logon::LogonType;
+use crate::network::NetworkInterface;
+use crate::power::Sleep;
+use crate::schedule::Schedule;
+use crate::session::{SessionInfo, SessionUser, Shadow};
+use crate::wynapi::WtsState;
+use std::time::SystemTime;
+
+/// A single session notification received by a
+/// [`SessionMonitor`](crate::SessionMonitor)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionEvent {
+    pub state: WtsState,
+    /// The session the notification is about, taken from lParam. With
+    /// [`SessionScope::AllSessions`](crate::SessionScope::AllSessions) this
+    /// can differ from the session the monitor runs in
+    pub session_id: u32,
+    /// When the notification was pulled off the message queue
+    pub timestamp: SystemTime,
+    /// Who the session belongs to, when enabled with
+    /// [`SessionMonitorBuilder::session_info`](crate::SessionMonitorBuilder::session_info)
+    /// and the session could still be queried. Always filled in for
+    /// RemoteConnect and RemoteDisconnect so the client's name, address and
+    /// protocol are known
+    pub info: Option<SessionInfo>,
+    /// Set for the event describing the state at startup, see
+    /// [`SessionMonitorBuilder::initial_state`](crate::SessionMonitorBuilder::initial_state),
+    /// and for a change the [`watchdog`](crate::SessionMonitorBuilder::watchdog)
+    /// found was missed
+    pub synthetic: bool,
+    /// Set for the synthetic event describing the state at startup, which
+    /// is not a change that happened
+    pub initial: bool,
+    /// The session on the other side of a SwitchedAway or SwitchedBack, if
+    /// it could be told
+    pub other_session_id: Option<u32>,
+    /// The device a DeviceArrived or DeviceRemoved is about
+    pub device: Option<UsbDevice>,
+    /// Who started shadowing the session on a RemoteControl, `None` when
+    /// the shadowing ended
+    pub shadow: Option<Shadow>,
+    /// How the user logged on, for Logon and Logoff, or
+    /// [`LogonType::Reconnect`] for a ConsoleConnect or RemoteConnect to a
+    /// session that was already logged on to
+    pub logon_type: Option<LogonType>,
+    /// Who the session belongs to, resolved once per session with
+    /// [`SessionScope::AllSessions`](crate::SessionScope::AllSessions).
+    /// `None` when nobody is logged on to it
+    pub user: Option<SessionUser>,
+    /// Whether a Resume came back from sleep or hibernation and how long
+    /// the machine was asleep
+    pub sleep: Option<Sleep>,
+    /// The interface a NetworkConnected or NetworkDisconnected is about
+    pub network: Option<NetworkInterface>,
+    /// The layout after a MonitorAdded, MonitorRemoved or ResolutionChanged
+    pub display: Option<DisplayConfig>,
+    /// The hotkey that injected the event
+    pub hotkey: Option<Hotkey>,
+    /// The schedule a Scheduled event is for
+    pub schedule: Option<Schedule>,
+}
+
+impl SessionEvent {
+    pub(crate) fn new(state: WtsState, session_id: u32) -> Self {
+        Self {
+            state,
+            session_id,
+            timestamp: SystemTime::now(),
+            info: None,
+            synthetic: false,
+            initial: false,
+            other_session_id: None,
+            device: None,
+            shadow: None,
+            logon_type: None,
+            user: None,
+            sleep: None,
+            network: None,
+            display: None,
+            hotkey: None,
+            schedule: None,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! The Security event log as a source of lock and unlock events
+use crate::backend::Inbox;
+use crate::wynapi::*;
+use crate::SessionEvent;
+use core::ffi::{c_int, c_void};
+use std::time::{Duration, SystemTime};
+use tracing::{event, Level};
+
+const CHANNEL: &str = "Security";
+const QUERY: &str = "*[System[(EventID=4800 or EventID=4801)]]";
+/// The values rendered for each event, in this order
+const PATHS: [&str; 3] = [
+    "Event/System/EventID",
+    "Event/System/TimeCreated/@SystemTime",
+    "Event/EventData/Data[@Name='SessionId']",
+];
+/// 100ns intervals between 1601, where FILETIME starts, and 1970
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// Handed to the subscription callback
+struct Context {
+    inbox: Inbox,
+    render: EVT_HANDLE,
+    /// Only report this session, for [`SessionScope::ThisSession`]
+    session_id: Option<u32>,
+}
+
+/// An EvtSubscribe subscription, closed on drop
+pub(crate) struct EventLogSubscription {
+    subscription: EVT_HANDLE,
+    context: *mut Context,
+}
+
+impl EventLogSubscription {
+    pub fn start(scope: SessionScope, inbox: Inbox) -> Result<Self, Error> {
+        let render = evt_create_render_context(&PATHS)?;
+        let session_id = match scope {
+            SessionScope::ThisSession => Some(current_session_id()?),
+            SessionScope::AllSessions => None,
+        };
+        let context = Box::into_raw(Box::new(Context {
+            inbox,
+            render,
+            session_id,
+        }));
+        match evt_subscribe(CHANNEL, QUERY, context.cast(), Some(callback)) {
+            Ok(subscription) => Ok(Self {
+                subscription,
+                context,
+            }),
+            Err(err) => {
+                let context = unsafe { Box::from_raw(context) };
+                let _ = evt_close(context.render);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Drop for EventLogSubscription {
+    fn drop(&mut self) {
+        let _ = evt_close(self.subscription);
+        // No callback can be running once the subscription is closed
+        let context = unsafe { Box::from_raw(self.context) };
+        let _ = evt_close(context.render);
+    }
+}
+
+unsafe extern "system" fn callback(
+    action: c_int,
+    context: *mut c_void,
+    event: EVT_HANDLE,
+) -> DWORD {
+    let context = &*(context as *const Context);
+    if action != EVT_SUBSCRIBE_ACTION_DELIVER {
+        event!(
+            Level::ERROR,
+            "EvtSubscribe callback error {}",
+            event as usize
+        );
+        return 0;
+    }
+    let Ok(values) = evt_render_values(context.render, event) else {
+        return 0;
+    };
+    let (Some(Some(id)), Some(time), Some(Some(session_id))) =
+        (values.first(), values.get(1), values.get(2))
+    else {
+        return 0;
+    };
+    let session_id = *session_id as u32;
+    if context.session_id.is_some_and(|ours| ours != session_id) {
+        return 0;
+    }
+    let state = match *id as u16 {
+        4800 => WtsState::Lock,
+        4801 => WtsState::Unlock,
+        _ => return 0,
+    };
+    let mut session_event = SessionEvent::new(state, session_id);
+    if let Some(time) = time {
+        let since_epoch = time.saturating_sub(FILETIME_UNIX_EPOCH) * 100;
+        session_event.timestamp =
+            SystemTime::UNIX_EPOCH + Duration::from_nanos(since_epoch);
+    }
+    context.inbox.push(session_event);
+    0
+}
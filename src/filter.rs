@@ -0,0 +1,117 @@
+//! A cheap set of [`WtsState`]s for subscribers to say which kinds of event
+//! they care about
+use crate::wynapi::WtsState;
+use std::ops::{BitOr, BitOrAssign};
+
+/// A bitmask of [`WtsState`]s, one bit per code. Unknown codes past the
+/// known ranges share the top bit of their half
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StateSet(u64);
+
+impl StateSet {
+    /// Every state, including ones Windows may add later
+    pub const ALL: Self = Self(u64::MAX);
+    pub const EMPTY: Self = Self(0);
+
+    pub fn all() -> Self {
+        Self::ALL
+    }
+
+    pub fn empty() -> Self {
+        Self::EMPTY
+    }
+
+    /// Just Lock and Unlock, what most consumers want
+    pub fn lock_unlock() -> Self {
+        Self::from_iter([WtsState::Lock, WtsState::Unlock])
+    }
+
+    pub fn contains(&self, state: WtsState) -> bool {
+        self.0 & Self::bit(state) != 0
+    }
+
+    /// Whether any state is in both sets
+    pub fn intersects(&self, other: impl Into<StateSet>) -> bool {
+        self.0 & other.into().0 != 0
+    }
+
+    pub fn insert(&mut self, state: WtsState) {
+        self.0 |= Self::bit(state);
+    }
+
+    pub fn remove(&mut self, state: WtsState) {
+        self.0 &= !Self::bit(state);
+    }
+
+    /// Add `state` to the set, for chaining
+    pub fn with(mut self, state: WtsState) -> Self {
+        self.insert(state);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// WTS codes take the low 32 bits and our own codes from 0x100 the rest
+    fn bit(state: WtsState) -> u64 {
+        match usize::from(state) {
+            code @ 0..=0xFF => 1 << code.min(31),
+            code => 1 << (32 + (code - 0x100).min(31)),
+        }
+    }
+}
+
+impl Default for StateSet {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl From<WtsState> for StateSet {
+    fn from(state: WtsState) -> Self {
+        Self::EMPTY.with(state)
+    }
+}
+
+impl FromIterator<WtsState> for StateSet {
+    fn from_iter<I: IntoIterator<Item = WtsState>>(states: I) -> Self {
+        states.into_iter().fold(Self::EMPTY, Self::with)
+    }
+}
+
+impl<const N: usize> From<[WtsState; N]> for StateSet {
+    fn from(states: [WtsState; N]) -> Self {
+        Self::from_iter(states)
+    }
+}
+
+impl BitOr for StateSet {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl BitOr<WtsState> for StateSet {
+    type Output = Self;
+
+    fn bitor(self, state: WtsState) -> Self {
+        self.with(state)
+    }
+}
+
+impl BitOr for WtsState {
+    type Output = StateSet;
+
+    fn bitor(self, other: Self) -> StateSet {
+        StateSet::from(self).with(other)
+    }
+}
+
+impl BitOrAssign<WtsState> for StateSet {
+    fn bitor_assign(&mut self, state: WtsState) {
+        self.insert(state);
+    }
+}
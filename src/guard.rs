@@ -0,0 +1,237 @@
+//! RAII owners for the window and the notification registrations, so they
+//! are released even if the message loop panics or returns early
+use crate::hotkey::Hotkey;
+use crate::stop::StopHandle;
+use crate::wynapi::*;
+use core::marker::PhantomData;
+use std::ffi::CString;
+use std::time::Duration;
+
+/// A message only window, or a hidden top level one when broadcasts are
+/// needed, destroyed on drop along with the handler its window procedure
+/// dispatches to and its window class.
+///
+/// Messages for a window can only be pumped on the thread that created it,
+/// so this is neither `Send` nor `Sync`. Use [`MessageWindow::stop_handle`]
+/// to control it from other threads
+pub struct MessageWindow {
+    handle: HWND,
+    handler: *mut WindowHandler,
+    class_name: CString,
+    thread_id: DWORD,
+    /// Keeps the type !Send and !Sync whatever HWND is defined as
+    _thread_bound: PhantomData<*const ()>,
+}
+
+impl MessageWindow {
+    pub(crate) fn create(
+        class_name: CString,
+        handler: WindowHandler,
+        top_level: bool,
+    ) -> Result<Self, Error> {
+        let handle = create_window_ex_a(&class_name, top_level)?;
+        let handler = Box::into_raw(Box::new(handler));
+        // The handler is only freed in drop, after the window is gone
+        unsafe { set_window_handler(handle, handler) };
+        Ok(Self {
+            handle,
+            handler,
+            class_name,
+            thread_id: get_current_thread_id(),
+            _thread_bound: PhantomData,
+        })
+    }
+
+    /// The raw HWND, only valid for use on the thread that created the
+    /// window and while this value is alive
+    pub fn handle(&self) -> HWND {
+        self.handle
+    }
+
+    /// A `Send` handle that ends the message loop of the thread that owns
+    /// this window
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle::new(self.thread_id)
+    }
+}
+
+impl Drop for MessageWindow {
+    fn drop(&mut self) {
+        let _ = destroy_window(self.handle);
+        // Nothing can call the window procedure for this window any more
+        drop(unsafe { Box::from_raw(self.handler) });
+        let _ = unregister_class_a(&self.class_name);
+    }
+}
+
+/// A WTS session notification registration, unregistered on drop. Must be
+/// dropped before the [`MessageWindow`] it was registered for
+pub(crate) struct Registration {
+    handle: HWND,
+    scope: SessionScope,
+}
+
+impl Registration {
+    pub fn register(
+        window: &MessageWindow,
+        scope: SessionScope,
+    ) -> Result<Self, Error> {
+        wts_register_session_notification(window.handle(), scope)?;
+        Ok(Self {
+            handle: window.handle(),
+            scope,
+        })
+    }
+
+    /// Unregister and register again, for when notifications have stopped
+    /// arriving such as after Terminal Services restarted
+    pub fn renew(&self) -> Result<(), Error> {
+        let _ = wts_unregister_session_notification(self.handle);
+        wts_register_session_notification(self.handle, self.scope)
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _ = wts_unregister_session_notification(self.handle);
+    }
+}
+
+/// A power notification registration, unregistered on drop. Must be dropped
+/// before the [`MessageWindow`] it was registered for
+pub(crate) enum PowerRegistration {
+    SuspendResume(HPOWERNOTIFY),
+    Setting(HPOWERNOTIFY),
+}
+
+impl PowerRegistration {
+    /// WM_POWERBROADCAST suspend and resume notifications
+    pub fn suspend_resume(window: &MessageWindow) -> Result<Self, Error> {
+        let handle = register_suspend_resume_notification(window.handle())?;
+        Ok(Self::SuspendResume(handle))
+    }
+
+    /// PBT_POWERSETTINGCHANGE notifications for `setting`, Windows sends the
+    /// current value straight away
+    pub fn setting(
+        window: &MessageWindow,
+        setting: &GUID,
+    ) -> Result<Self, Error> {
+        let handle =
+            register_power_setting_notification(window.handle(), setting)?;
+        Ok(Self::Setting(handle))
+    }
+}
+
+impl Drop for PowerRegistration {
+    fn drop(&mut self) {
+        let _ = match *self {
+            Self::SuspendResume(handle) => {
+                unregister_suspend_resume_notification(handle)
+            }
+            Self::Setting(handle) => {
+                unregister_power_setting_notification(handle)
+            }
+        };
+    }
+}
+
+/// Tells Windows why the session should not end yet, the reason is shown to
+/// the user until this is dropped
+pub struct ShutdownBlock<'a> {
+    handle: HWND,
+    _window: PhantomData<&'a MessageWindow>,
+}
+
+impl<'a> ShutdownBlock<'a> {
+    pub(crate) fn create(
+        window: &'a MessageWindow,
+        reason: &str,
+    ) -> Result<Self, Error> {
+        shutdown_block_reason_create(window.handle(), reason)?;
+        Ok(Self {
+            handle: window.handle(),
+            _window: PhantomData,
+        })
+    }
+}
+
+impl Drop for ShutdownBlock<'_> {
+    fn drop(&mut self) {
+        let _ = shutdown_block_reason_destroy(self.handle);
+    }
+}
+
+/// A timer posting WM_TIMER to a [`MessageWindow`], killed on drop. Must be
+/// dropped before the window
+pub(crate) struct Timer {
+    handle: HWND,
+    id: usize,
+}
+
+impl Timer {
+    pub fn start(
+        window: &MessageWindow,
+        id: usize,
+        interval: Duration,
+    ) -> Result<Self, Error> {
+        let interval_ms = interval.as_millis().clamp(1, u32::MAX as u128);
+        set_timer(window.handle(), id, interval_ms as u32)?;
+        Ok(Self {
+            handle: window.handle(),
+            id,
+        })
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let _ = kill_timer(self.handle, self.id);
+    }
+}
+
+/// A global hotkey posting WM_HOTKEY to a [`MessageWindow`], unregistered
+/// on drop. Must be dropped before the window
+pub(crate) struct HotkeyRegistration {
+    handle: HWND,
+    id: usize,
+}
+
+impl HotkeyRegistration {
+    pub fn register(
+        window: &MessageWindow,
+        id: usize,
+        hotkey: Hotkey,
+    ) -> Result<Self, Error> {
+        register_hot_key(window.handle(), id, hotkey.modifiers, hotkey.key)?;
+        Ok(Self {
+            handle: window.handle(),
+            id,
+        })
+    }
+}
+
+impl Drop for HotkeyRegistration {
+    fn drop(&mut self) {
+        let _ = unregister_hot_key(self.handle, self.id);
+    }
+}
+
+/// A USB device notification registration, unregistered on drop. Must be
+/// dropped before the [`MessageWindow`] it was registered for
+pub(crate) struct DeviceRegistration {
+    handle: HDEVNOTIFY,
+}
+
+impl DeviceRegistration {
+    pub fn register(window: &MessageWindow) -> Result<Self, Error> {
+        let handle = register_device_notification_w(window.handle())?;
+        Ok(Self { handle })
+    }
+}
+
+impl Drop for DeviceRegistration {
+    fn drop(&mut self) {
+        let _ = unregister_device_notification(self.handle);
+    }
+}
@@ -0,0 +1,87 @@
+//! Global hotkeys that inject an event of the caller's choosing, such as a
+//! panic lock
+use crate::wynapi::WtsState;
+use core::str::FromStr;
+
+const MOD_ALT: u32 = 0x1;
+const MOD_CONTROL: u32 = 0x2;
+const MOD_SHIFT: u32 = 0x4;
+const MOD_WIN: u32 = 0x8;
+
+/// A key combination for RegisterHotKey, parses from names joined with `+`
+/// such as `ctrl+alt+l`, `win+shift+f12` or `ctrl+pause`, ignoring case
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Hotkey {
+    /// MOD_ALT, MOD_CONTROL, MOD_SHIFT and MOD_WIN
+    pub modifiers: u32,
+    /// The virtual key code
+    pub key: u32,
+}
+
+impl FromStr for Hotkey {
+    type Err = ();
+
+    /// Needs exactly one key that isn't a modifier
+    fn from_str(combo: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = 0;
+        let mut key = None;
+        for part in combo.split('+').map(str::trim) {
+            match part.to_ascii_lowercase().as_str() {
+                "alt" => modifiers |= MOD_ALT,
+                "ctrl" | "control" => modifiers |= MOD_CONTROL,
+                "shift" => modifiers |= MOD_SHIFT,
+                "win" => modifiers |= MOD_WIN,
+                name if key.is_none() => key = Some(virtual_key(name)?),
+                _ => return Err(()),
+            }
+        }
+        Ok(Self {
+            modifiers,
+            key: key.ok_or(())?,
+        })
+    }
+}
+
+/// The virtual key code for a key name, letters and digits are their ASCII
+/// upper case codes
+fn virtual_key(name: &str) -> Result<u32, ()> {
+    const VK_F1: u32 = 0x70;
+
+    match name {
+        "pause" => Ok(0x13),
+        "escape" | "esc" => Ok(0x1B),
+        "space" => Ok(0x20),
+        "end" => Ok(0x23),
+        "home" => Ok(0x24),
+        "insert" => Ok(0x2D),
+        "delete" => Ok(0x2E),
+        "scrolllock" => Ok(0x91),
+        _ => match name.as_bytes() {
+            [c @ (b'a'..=b'z' | b'0'..=b'9')] => {
+                Ok(c.to_ascii_uppercase() as u32)
+            }
+            [b'f', number @ ..] => {
+                let number: u32 = std::str::from_utf8(number)
+                    .map_err(|_| ())?
+                    .parse()
+                    .map_err(|_| ())?;
+                match number {
+                    1..=24 => Ok(VK_F1 + number - 1),
+                    _ => Err(()),
+                }
+            }
+            _ => Err(()),
+        },
+    }
+}
+
+/// What pressing a hotkey registered with the builder does
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HotkeyBinding {
+    pub hotkey: Hotkey,
+    /// The state of the event handed out
+    pub state: WtsState,
+    /// Lock the workstation first
+    pub lock: bool,
+}
@@ -0,0 +1,35 @@
+//! How long it has been since the user last gave any input
+use crate::wynapi::{get_last_input_info, Error};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The idle time of our session right now. Only covers the session this
+/// process runs in, from a service in session 0 it never sees any input
+pub fn idle_time() -> Result<Duration, Error> {
+    get_last_input_info().map(|ms| Duration::from_millis(ms.into()))
+}
+
+/// The idle time as of the last poll by a [`SessionMonitor`]'s timer, cheap
+/// to clone and read from any thread
+///
+/// [`SessionMonitor`]: crate::SessionMonitor
+#[derive(Debug, Clone, Default)]
+pub struct IdleTracker {
+    idle_ms: Arc<AtomicU64>,
+}
+
+impl IdleTracker {
+    /// Zero until the first poll
+    pub fn idle(&self) -> Duration {
+        Duration::from_millis(self.idle_ms.load(Ordering::Relaxed))
+    }
+
+    /// Poll GetLastInputInfo and remember the result
+    pub(crate) fn update(&self) -> Option<Duration> {
+        let idle = idle_time().ok()?;
+        self.idle_ms
+            .store(idle.as_millis() as u64, Ordering::Relaxed);
+        Some(idle)
+    }
+}
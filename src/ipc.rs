@@ -0,0 +1,44 @@
+//! Requests to a running monitor over a named pipe, such as switching the
+//! active profile with `rusty-lock profile work`
+use crate::wynapi::{current_session_id, Error, NamedPipe};
+use std::thread;
+use tracing::{event, Level};
+
+/// The pipe a monitor serves requests on, followed by `-` and the session
+/// it runs in so each user's monitor on a terminal server has its own
+pub const PIPE_NAME: &str = r"\\.\pipe\rusty-lock";
+
+/// [`PIPE_NAME`] for the session we run in
+pub fn pipe_name() -> Result<String, Error> {
+    Ok(format!("{PIPE_NAME}-{}", current_session_id()?))
+}
+
+/// Answer each request sent to [`pipe_name`] with `handler`, one at a time
+/// on a thread of its own. Only clients on this machine running as the same
+/// user, LocalSystem or an administrator can connect. Fails if another
+/// process in the session already serves them
+pub fn serve_requests(
+    handler: impl Fn(&str) -> String + Send + 'static,
+) -> Result<(), Error> {
+    let pipe = NamedPipe::create(&pipe_name()?)?;
+    thread::spawn(move || {
+        while pipe.connect().is_ok() {
+            // Failures are logged, the client sees the pipe close
+            if let Ok(request) = pipe.read() {
+                let request = String::from_utf8_lossy(&request);
+                event!(Level::DEBUG, "Request {request}");
+                let _ = pipe.write(handler(&request).as_bytes());
+            }
+            pipe.disconnect();
+        }
+    });
+    Ok(())
+}
+
+/// Send `request` to the monitor serving [`pipe_name`], the one in our
+/// session, and wait for its response
+pub fn send_request(request: &str) -> Result<String, Error> {
+    let pipe = NamedPipe::open(&pipe_name()?)?;
+    pipe.write(request.as_bytes())?;
+    Ok(String::from_utf8_lossy(&pipe.read()?).into_owned())
+}
@@ -0,0 +1,222 @@
+//! A journal on disk of the actions each event has yet to finish, so those
+//! cut short by the monitor or the machine dying run when it next starts
+use crate::{Schedule, WtsState};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{event, Level};
+
+/// Told apart from anything else in the directory by this
+const EXTENSION: &str = "journal";
+
+/// One run's file in the journal, lines of what it is for and which of its
+/// actions finished. Removed on drop, once every action finished or was
+/// given up on
+pub(crate) struct Entry {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Entry {
+    /// Start a file in `dir` for a run of `actions`, by their descriptions,
+    /// for `state` or `schedule` with the event's `variables`
+    pub fn create(
+        dir: &Path,
+        state: WtsState,
+        schedule: Option<&Schedule>,
+        variables: &[(&str, String)],
+        actions: &[String],
+    ) -> io::Result<Self> {
+        // Unique and in the order the events arrived
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis());
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let pid = std::process::id();
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{millis:020}-{pid}-{count}.{EXTENSION}"));
+        let mut lines = format!("state {}\n", state.name());
+        if let Some(schedule) = schedule {
+            lines += &format!("schedule {schedule}\n");
+        }
+        for (name, value) in variables {
+            lines += &format!("variable {name} {}\n", escape(value));
+        }
+        for action in actions {
+            lines += &format!("action {}\n", escape(action));
+        }
+        let mut file = File::create(&path)?;
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Note the action at `index` finished, with its name and output if it
+    /// has one, `None` if it failed. Failures are logged
+    pub fn done(&self, index: usize, named: Option<(&str, Option<&str>)>) {
+        let mut lines = format!("done {index}\n");
+        match named {
+            Some((name, Some(output))) => {
+                lines += &format!("output {name} {}\n", escape(output))
+            }
+            Some((name, None)) => lines += &format!("failed {name}\n"),
+            None => (),
+        }
+        let mut file = self.file.lock().unwrap();
+        let written = file
+            .write_all(lines.as_bytes())
+            .and_then(|()| file.sync_data());
+        if let Err(err) = written {
+            event!(Level::ERROR, "Writing {}, {err}", self.path.display());
+        }
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            event!(Level::ERROR, "Removing {}, {err}", self.path.display());
+        }
+    }
+}
+
+/// A run the journal says didn't finish
+pub(crate) struct Unfinished {
+    pub path: PathBuf,
+    pub state: WtsState,
+    pub schedule: Option<Schedule>,
+    variables: Vec<(String, String)>,
+    /// Each action's description and whether it finished
+    pub actions: Vec<(String, bool)>,
+    /// The output of each named action that finished, `None` if it failed
+    pub outputs: HashMap<String, Option<String>>,
+}
+
+impl Unfinished {
+    /// Read the file at `path`
+    fn read(path: PathBuf) -> Result<Self, String> {
+        let lines =
+            std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let mut state = None;
+        let mut schedule = None;
+        let mut variables = Vec::new();
+        let mut actions = Vec::new();
+        let mut outputs = HashMap::new();
+        // A line cut short while being written is left out
+        let complete = lines.rfind('\n').map_or("", |end| &lines[..end]);
+        for line in complete.lines() {
+            let (key, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "state" => {
+                    let parsed = rest.parse().map_err(|()| "unknown state")?;
+                    state = Some(parsed);
+                }
+                "schedule" => {
+                    let parsed = rest.parse().map_err(|()| "bad schedule")?;
+                    schedule = Some(parsed);
+                }
+                "variable" => {
+                    let (name, value) =
+                        rest.split_once(' ').unwrap_or((rest, ""));
+                    variables.push((name.to_string(), unescape(value)));
+                }
+                "action" => actions.push((unescape(rest), false)),
+                "done" => {
+                    let index: usize =
+                        rest.parse().map_err(|_| "bad action index")?;
+                    let (_, done) =
+                        actions.get_mut(index).ok_or("bad action index")?;
+                    *done = true;
+                }
+                "output" => {
+                    let (name, output) =
+                        rest.split_once(' ').unwrap_or((rest, ""));
+                    outputs.insert(name.to_string(), Some(unescape(output)));
+                }
+                "failed" => {
+                    outputs.insert(rest.to_string(), None);
+                }
+                _ => return Err(format!("unknown line {line}")),
+            }
+        }
+        Ok(Self {
+            path,
+            state: state.ok_or("no state")?,
+            schedule,
+            variables,
+            actions,
+            outputs,
+        })
+    }
+
+    /// The event's value of the placeholder `name`, empty if unknown
+    pub fn variable(&self, name: &str) -> String {
+        self.variables
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// The runs left unfinished in `dir`, in the order their events arrived.
+/// Files that can't be read are logged and left alone
+pub(crate) fn unfinished(dir: &Path) -> Vec<Unfinished> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Vec::new(),
+        Err(err) => {
+            event!(Level::ERROR, "Reading {}, {err}", dir.display());
+            return Vec::new();
+        }
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == EXTENSION))
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let shown = path.display().to_string();
+            Unfinished::read(path)
+                .map_err(|err| event!(Level::ERROR, "Ignoring {shown}, {err}"))
+                .ok()
+        })
+        .collect()
+}
+
+/// Keep `value` on one line
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Undo [`escape`]
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
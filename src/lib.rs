@@ -0,0 +1,106 @@
+//! Library for detecting WTS SESSION events such as lock screens.
+//!
+//! [`SessionMonitor`] creates a message only window, registers it for
+//! session notifications and hands back each [`SessionEvent`] as it arrives
+//! so other programs can embed lock/unlock detection directly
+mod actions;
+mod autolock;
+mod backend;
+#[cfg(feature = "broadcast")]
+mod broadcast;
+mod builtin;
+mod bus;
+#[cfg(feature = "capi")]
+pub mod capi;
+mod config;
+mod control;
+mod device;
+mod display;
+mod event;
+mod eventlog;
+mod filter;
+mod guard;
+mod hotkey;
+mod idle;
+mod ipc;
+mod journal;
+mod logon;
+#[cfg(feature = "lua")]
+mod lua;
+mod monitor;
+mod native;
+mod network;
+#[cfg(feature = "napi")]
+mod node;
+#[cfg(feature = "wasm")]
+mod plugin;
+mod png;
+mod polling;
+mod pool;
+mod power;
+mod presence;
+#[cfg(feature = "pyo3")]
+mod python;
+mod router;
+mod schedule;
+#[cfg(feature = "rhai")]
+mod script;
+mod sens;
+mod server;
+mod session;
+mod spawn;
+mod stop;
+#[cfg(feature = "tokio")]
+mod stream;
+mod switch;
+mod watch;
+mod webcam;
+mod wmi;
+mod wynapi;
+
+pub use actions::{
+    Action, ActionKind, ActionRunner, Execution, OnFailure, OutputLog,
+    RateLimit, Retry, SmartDevice,
+};
+pub use autolock::AutoLock;
+pub use backend::Backend;
+pub use bus::EventBus;
+pub use config::{encrypt_value, ConfigError};
+pub use control::{
+    disconnect_session, lock_workstation, logoff_session, send_message,
+    MessageButtons, MessageResponse,
+};
+pub use device::{UsbDevice, UsbId};
+pub use display::DisplayConfig;
+pub use event::SessionEvent;
+pub use filter::StateSet;
+pub use guard::{MessageWindow, ShutdownBlock};
+pub use hotkey::Hotkey;
+pub use idle::{idle_time, IdleTracker};
+pub use ipc::{pipe_name, send_request, serve_requests, PIPE_NAME};
+pub use logon::LogonType;
+pub use monitor::{Events, SessionMonitor, SessionMonitorBuilder};
+pub use native::{
+    NativePlugins, RustyLockPluginEvent, RustyLockPluginInit,
+    RustyLockPluginOnEvent, RustyLockPluginShutdown, RUSTY_LOCK_PLUGIN_ABI,
+};
+pub use network::NetworkInterface;
+#[cfg(feature = "wasm")]
+pub use plugin::Capabilities;
+pub use power::{Sleep, SleepState};
+pub use presence::Presence;
+pub use router::SessionRouter;
+pub use schedule::Schedule;
+pub use server::WtsServer;
+pub use session::{
+    enumerate_sessions, query_lock_state, query_session_info, Protocol,
+    Session, SessionInfo, SessionUser, Shadow,
+};
+pub use spawn::{spawn_monitor, MonitorHandle};
+pub use stop::{ConsoleCtrl, StopHandle};
+#[cfg(feature = "tokio")]
+pub use stream::SessionStream;
+pub use watch::ConfigWatcher;
+pub use wynapi::{
+    is_terminal_server, ConnectState, Error, SessionScope, WtsState,
+};
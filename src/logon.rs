@@ -0,0 +1,61 @@
+//! How a session was logged on to, from LSA with the WTS protocol to fall
+//! back on
+use crate::session::{Protocol, SessionInfo};
+use crate::wynapi::lsa_logon_sessions;
+
+/// How the user of a session logged on, attached to Logon and Logoff events
+/// and to reconnects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum LogonType {
+    /// At the console
+    Interactive,
+    /// Over RDP
+    RemoteInteractive,
+    /// At the console with cached credentials, no domain controller was
+    /// reached
+    CachedInteractive,
+    /// Over RDP with cached credentials
+    CachedRemoteInteractive,
+    /// A ConsoleConnect or RemoteConnect to a session somebody was logged
+    /// on to and disconnected from
+    Reconnect,
+    /// Any other SECURITY_LOGON_TYPE
+    Other(u32),
+}
+
+impl From<u32> for LogonType {
+    fn from(logon_type: u32) -> Self {
+        match logon_type {
+            2 => Self::Interactive,
+            10 => Self::RemoteInteractive,
+            11 => Self::CachedInteractive,
+            12 => Self::CachedRemoteInteractive,
+            _ => Self::Other(logon_type),
+        }
+    }
+}
+
+impl LogonType {
+    /// Ask LSA for the interactive logon of the user in `info` in
+    /// `session_id`, or go by the protocol if LSA doesn't know it. Window
+    /// Manager and Font Driver Host logons share the session, matching the
+    /// user skips them
+    pub(crate) fn query(session_id: u32, info: &SessionInfo) -> Option<Self> {
+        let logon = lsa_logon_sessions().ok().and_then(|sessions| {
+            sessions.into_iter().find(|logon| {
+                logon.session_id == session_id
+                    && matches!(logon.logon_type, 2 | 10 | 11 | 12)
+                    && logon.user_name.eq_ignore_ascii_case(&info.user_name)
+                    && logon.domain.eq_ignore_ascii_case(&info.domain)
+            })
+        });
+        match (logon, info.protocol) {
+            (Some(logon), _) => Some(logon.logon_type.into()),
+            (None, Protocol::Console) => Some(Self::Interactive),
+            (None, Protocol::Rdp) => Some(Self::RemoteInteractive),
+            (None, _) => None,
+        }
+    }
+}
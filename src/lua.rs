@@ -0,0 +1,132 @@
+//! Lua scripts run as actions, the same as the Rhai ones for those with Lua
+//! already written for other tools
+use crate::actions;
+use mlua::{Lua, MultiValue, Table, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{event, Level};
+
+/// What scripts keep in `store` between runs, until we exit. Each run has
+/// a Lua of its own so only plain values can outlive it
+static STORE: Mutex<BTreeMap<String, Stored>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone)]
+enum Stored {
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+}
+
+/// Run the script at `path` with the event's placeholders in the `event`
+/// table, its output if it didn't raise an error: what it returns, empty
+/// for nothing
+pub(crate) fn run(path: &Path, variables: &[(&str, String)]) -> Option<String> {
+    let lua = Lua::new();
+    let result = globals(&lua, variables).and_then(|()| {
+        lua.load(path)
+            .eval::<Value>()
+            .and_then(|value| match value {
+                Value::Nil => Ok(String::new()),
+                value => value.to_string(),
+            })
+    });
+    match result {
+        Ok(output) => Some(output),
+        Err(err) => {
+            event!(Level::ERROR, "{} failed {err}", path.display());
+            None
+        }
+    }
+}
+
+/// Set up `event`, `store` and `exec`, and send `print` to the log
+fn globals(lua: &Lua, variables: &[(&str, String)]) -> mlua::Result<()> {
+    let globals = lua.globals();
+
+    let event = lua.create_table()?;
+    for (name, value) in variables {
+        // The numbers are more use as numbers
+        match value.parse::<i64>() {
+            Ok(number) if matches!(*name, "session_id" | "timestamp") => {
+                event.set(*name, number)?
+            }
+            _ => event.set(*name, value.as_str())?,
+        }
+    }
+    globals.set("event", event)?;
+
+    // An empty table whose fields are looked up in and stored to STORE
+    let store = lua.create_table()?;
+    let metatable = lua.create_table()?;
+    metatable.set(
+        "__index",
+        lua.create_function(|lua, (_, key): (Table, String)| {
+            let stored = STORE.lock().unwrap().get(&key).cloned();
+            Ok(match stored {
+                None => Value::Nil,
+                Some(Stored::Boolean(value)) => Value::Boolean(value),
+                Some(Stored::Integer(value)) => Value::Integer(value),
+                Some(Stored::Number(value)) => Value::Number(value),
+                Some(Stored::String(value)) => {
+                    Value::String(lua.create_string(value)?)
+                }
+            })
+        })?,
+    )?;
+    metatable.set(
+        "__newindex",
+        lua.create_function(|_, (_, key, value): (Table, String, Value)| {
+            let stored = match value {
+                Value::Nil => {
+                    STORE.lock().unwrap().remove(&key);
+                    return Ok(());
+                }
+                Value::Boolean(value) => Stored::Boolean(value),
+                Value::Integer(value) => Stored::Integer(value),
+                Value::Number(value) => Stored::Number(value),
+                Value::String(value) => Stored::String(value.to_string_lossy()),
+                value => {
+                    return Err(mlua::Error::runtime(format!(
+                        "store can't hold a {}",
+                        value.type_name()
+                    )))
+                }
+            };
+            STORE.lock().unwrap().insert(key, stored);
+            Ok(())
+        })?,
+    )?;
+    store.set_metatable(Some(metatable))?;
+    globals.set("store", store)?;
+
+    globals.set("exec", lua.create_function(exec)?)?;
+    globals.set(
+        "print",
+        lua.create_function(|_, values: MultiValue| {
+            let text = values
+                .iter()
+                .map(Value::to_string)
+                .collect::<mlua::Result<Vec<_>>>()?;
+            event!(Level::INFO, "{}", text.join("\t"));
+            Ok(())
+        })?,
+    )
+}
+
+/// `exec(command)` for scripts, runs `cmd /C command` to completion and
+/// returns its exit `code` and `output`, -1 if it couldn't start
+fn exec(lua: &Lua, command: String) -> mlua::Result<Table> {
+    let (code, output) = match actions::exec("lua", &command) {
+        Ok(result) => result,
+        Err(err) => {
+            event!(Level::ERROR, "Failed to start {command} {err}");
+            (-1, String::new())
+        }
+    };
+    let result = lua.create_table()?;
+    result.set("code", code)?;
+    result.set("output", output)?;
+    Ok(result)
+}
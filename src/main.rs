@@ -1,29 +1,367 @@
 //! This program creates a window in order to register for
 //! WTS SESSION events such as lock screens. Once we see an event
 //! We can run our arbitrary code
-mod wynapi;
-use wynapi::*;
+use clap::{Args, Parser, Subcommand};
+use rusty_lock::{
+    ActionRunner, AutoLock, Backend, ConfigWatcher, ConsoleCtrl, Hotkey,
+    NativePlugins, SessionEvent, SessionMonitor, SessionMonitorBuilder,
+    SessionScope, StateSet, UsbId, WtsState,
+};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    // Without a subcommand the monitor flags can be given directly, eg.
+    // `rusty-lock --on-lock "nircmd.exe mutesysvolume 1"`
+    #[command(flatten)]
+    monitor: MonitorArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Watch for session events, the default
+    Monitor(Box<MonitorArgs>),
+    /// Lock the workstation
+    Lock,
+    /// Switch the running monitor to a profile from its config, or print
+    /// the active one without a name
+    Profile {
+        /// The profile to switch to
+        name: Option<String>,
+        /// Switch to no profile, running only the actions outside them
+        #[arg(long, conflicts_with = "name")]
+        clear: bool,
+    },
+    /// Help with writing a config
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Encrypt a value with DPAPI and print it as dpapi:..., to use in a
+    /// config in place of the value
+    Encrypt {
+        /// The value, read from stdin if not given so it stays out of the
+        /// shell's history
+        value: Option<String>,
+        /// Let anyone on this machine decrypt it, such as the service
+        /// running as LocalSystem, rather than only us
+        #[arg(long)]
+        machine: bool,
+    },
+}
+
+#[derive(Args, Default)]
+struct MonitorArgs {
+    /// Watch every session on the machine instead of only our own, the
+    /// default on a Remote Desktop Session Host. Wins over `all_sessions` in
+    /// the config
+    #[arg(long, env = "RUSTY_LOCK_ALL_SESSIONS")]
+    all_sessions: bool,
+    /// Lock the workstation after this many seconds without input
+    #[arg(long, value_name = "SECS")]
+    auto_lock_after: Option<u64>,
+    /// Don't auto lock while a full screen app or presentation is running
+    #[arg(long, requires = "auto_lock_after")]
+    exempt_fullscreen: bool,
+    /// Don't auto lock while this exe is in the foreground, can be repeated
+    #[arg(long, value_name = "EXE", requires = "auto_lock_after")]
+    exempt_process: Vec<String>,
+    /// Lock the workstation when this USB device is removed, as a hex
+    /// VID:PID such as 1050:0407. Can be repeated
+    #[arg(long, value_name = "VID:PID", value_parser = parse_usb_id)]
+    lock_on_removal: Vec<UsbId>,
+    /// Lock the workstation and run the lock actions when this key
+    /// combination is pressed, such as ctrl+alt+l
+    #[arg(long, value_name = "KEYS", value_parser = parse_hotkey)]
+    panic_lock: Option<Hotkey>,
+    /// A TOML file of commands to run per event, such as
+    /// on_lock = ["pause-music.cmd"]. Changes to it are applied as it is
+    /// saved. Without one %ProgramData%\rusty-lock\config.toml is used with
+    /// %APPDATA%\rusty-lock\config.toml layered on top, if they exist
+    #[arg(
+        long,
+        env = "RUSTY_LOCK_CONFIG",
+        value_name = "FILE",
+        value_parser = parse_config
+    )]
+    config: Option<Config>,
+    /// Run this command on Lock, after any from the config. Can be repeated
+    #[arg(long, value_name = "COMMAND")]
+    on_lock: Vec<String>,
+    /// Run this command on Unlock, after any from the config. Can be
+    /// repeated
+    #[arg(long, value_name = "COMMAND")]
+    on_unlock: Vec<String>,
+    /// Start with this profile from the config active instead of the one it
+    /// names, `rusty-lock profile` switches it later
+    #[arg(long, env = "RUSTY_LOCK_PROFILE", value_name = "NAME")]
+    profile: Option<String>,
+    /// Log what each action would do instead of doing it
+    #[arg(long)]
+    dry_run: bool,
+    /// Load every DLL in this directory as a plugin and hand it each event
+    #[arg(long, env = "RUSTY_LOCK_PLUGINS", value_name = "DIR")]
+    plugins: Option<PathBuf>,
+    /// Where session events come from: wts, eventlog, sens, wmi or polling
+    #[arg(long, default_value = "wts", value_parser = parse_backend)]
+    backend: Backend,
+}
+
+/// The config file, or the layered ones, and what was in it when we
+/// started
+#[derive(Clone)]
+struct Config {
+    paths: Vec<PathBuf>,
+    layered: bool,
+    actions: ActionRunner,
+}
+
+fn parse_usb_id(id: &str) -> Result<UsbId, String> {
+    id.parse()
+        .map_err(|_| format!("{id} is not a hex VID:PID such as 1050:0407"))
+}
+
+fn parse_hotkey(combo: &str) -> Result<Hotkey, String> {
+    combo.parse().map_err(|_| {
+        format!("{combo} is not a key combination such as ctrl+alt+l")
+    })
+}
+
+fn parse_config(path: &str) -> Result<Config, String> {
+    let actions =
+        ActionRunner::load(path).map_err(|err| format!("{path}: {err}"))?;
+    Ok(Config {
+        paths: vec![path.into()],
+        layered: false,
+        actions,
+    })
+}
+
+/// The machine's and the user's configs layered, if either exists
+fn discover_config() -> Option<Config> {
+    let paths = ActionRunner::config_paths();
+    if !paths.iter().any(|path| path.exists()) {
+        return None;
+    }
+    match ActionRunner::load_layered(&paths) {
+        Ok(actions) => Some(Config {
+            paths,
+            layered: true,
+            actions,
+        }),
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_backend(name: &str) -> Result<Backend, String> {
+    name.parse().map_err(|_| {
+        format!("{name} is not one of wts, eventlog, sens, wmi or polling")
+    })
+}
 
 fn main() {
     // Enable logging
     tracing_subscriber::fmt().init();
 
-    // Create a window for the events to be sent to
-    let handle = create_window_ex_a().unwrap();
+    let cli = Cli::parse();
+    let command = cli
+        .command
+        .unwrap_or_else(|| Command::Monitor(Box::new(cli.monitor)));
+    match command {
+        Command::Monitor(args) => monitor(*args),
+        Command::Lock => rusty_lock::lock_workstation().unwrap(),
+        Command::Profile { name, clear } => profile(name, clear),
+        Command::Config(ConfigCommand::Encrypt { value, machine }) => {
+            encrypt(value, machine)
+        }
+    }
+}
 
-    // Register the window to recieve the events
-    wts_register_session_notification(handle);
+/// Print `value`, or stdin's first line, encrypted for a config
+fn encrypt(value: Option<String>, machine: bool) {
+    let value = value.unwrap_or_else(|| {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).unwrap();
+        line.trim_end_matches(['\r', '\n']).to_string()
+    });
+    match rusty_lock::encrypt_value(&value, machine) {
+        Ok(encrypted) => println!("{encrypted}"),
+        Err(err) => {
+            eprintln!("Couldn't encrypt it, {err}");
+            std::process::exit(1);
+        }
+    }
+}
 
-    // Handle session notifcation events
-    while let Some(msg) = get_message_a(handle) {
-        match msg {
-            WtsState::Lock => {
-                println!("User lock happened... execute your code here")
+/// Ask the running monitor to switch profile, see [`answer`]
+fn profile(name: Option<String>, clear: bool) {
+    let request = match (name, clear) {
+        (Some(name), _) => format!("profile {name}"),
+        (None, true) => "profile --clear".to_string(),
+        (None, false) => "profile".to_string(),
+    };
+    match rusty_lock::send_request(&request) {
+        Ok(response) => match response.strip_prefix("error: ") {
+            Some(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
             }
-            _ => {}
+            None => println!("{response}"),
+        },
+        Err(err) => {
+            eprintln!("Couldn't reach a running monitor, {err}");
+            std::process::exit(1);
         }
     }
+}
 
-    // Cleanup when we are done
-    wts_unregister_session_notification(handle);
+/// The monitor's response to a request from [`profile`]
+fn answer(actions: &ActionRunner, request: &str) -> String {
+    match request.strip_prefix("profile") {
+        Some("") => actions.active_profile().unwrap_or_else(|| "none".into()),
+        Some(" --clear") => {
+            actions.switch_profile(None);
+            "Switched to no profile".into()
+        }
+        Some(name) if name.starts_with(' ') => {
+            let name = name.trim_start();
+            if actions.switch_profile(Some(name)) {
+                return format!("Switched to {name}");
+            }
+            let profiles: Vec<&str> = actions.profiles().collect();
+            if profiles.is_empty() {
+                return "error: the config has no profiles".into();
+            }
+            format!(
+                "error: there's no profile {name}, only {}",
+                profiles.join(", ")
+            )
+        }
+        _ => format!("error: unknown request {request}"),
+    }
+}
+
+fn monitor(args: MonitorArgs) {
+    // Applied again each time the config is reloaded
+    let (on_lock, on_unlock, dry_run) =
+        (args.on_lock, args.on_unlock, args.dry_run);
+    let prepare = move |actions: ActionRunner| {
+        let lock = on_lock.iter().map(|c| (WtsState::Lock, c));
+        let unlock = on_unlock.iter().map(|c| (WtsState::Unlock, c));
+        lock.chain(unlock)
+            .fold(actions, |actions, (state, command)| {
+                actions.add(state, command.clone())
+            })
+            .dry_run(dry_run)
+    };
+    let (actions, watcher) = match args.config.or_else(discover_config) {
+        Some(config) => {
+            let Config {
+                mut paths,
+                layered,
+                actions,
+            } = config;
+            let watcher = if layered {
+                ConfigWatcher::start_layered(paths, actions, prepare)
+            } else {
+                ConfigWatcher::start(paths.remove(0), actions, prepare)
+            };
+            let watcher = watcher.unwrap();
+            (watcher.runner(), Some(watcher))
+        }
+        None => (prepare(ActionRunner::default()), None),
+    };
+    if let Some(name) = args.profile {
+        if !actions.switch_profile(Some(&name)) {
+            eprintln!("The config has no profile {name}");
+            std::process::exit(1);
+        }
+    }
+    // What the last run of the monitor didn't get to finish
+    actions.resume();
+    // Create a window and register it to recieve the events
+    let scope = if args.all_sessions {
+        SessionScope::AllSessions
+    } else {
+        actions.scope().unwrap_or_else(SessionScope::detect)
+    };
+    let mut builder = SessionMonitor::builder()
+        .scope(scope)
+        .session_info(true)
+        .initial_state(true)
+        .backend(args.backend);
+    if let Some(secs) = args.auto_lock_after {
+        let policy = args.exempt_process.into_iter().fold(
+            AutoLock::after(Duration::from_secs(secs))
+                .exempt_fullscreen(args.exempt_fullscreen),
+            AutoLock::exempt_process,
+        );
+        builder = builder.auto_lock(policy);
+    }
+    builder = args
+        .lock_on_removal
+        .into_iter()
+        .fold(builder, |builder, id| builder.lock_on_removal(id));
+    if let Some(hotkey) = args.panic_lock {
+        builder = builder.panic_lock(hotkey);
+    }
+    builder = actions
+        .schedules()
+        .cloned()
+        .fold(builder, SessionMonitorBuilder::schedule);
+    // Declared first so it is dropped after the monitor has cleaned up
+    let _console;
+    let mut monitor = builder.build().unwrap();
+    _console = ConsoleCtrl::install(monitor.stop_handle()).unwrap();
+
+    // Handle session notifcation events
+    monitor.on(StateSet::ALL, |event| {
+        println!(
+            "{} in session {} for user {}",
+            event.state.name(),
+            event.session_id,
+            user(event)
+        )
+    });
+    let (control, initial) = (watcher.clone(), actions.clone());
+    // Logged if it fails, such as with another monitor running
+    let _ = rusty_lock::serve_requests(move |request| {
+        // The active profile is shared by every copy of the runner
+        let actions = control
+            .as_ref()
+            .map_or_else(|| initial.clone(), ConfigWatcher::runner);
+        answer(&actions, request)
+    });
+    if let Some(watcher) = watcher {
+        // A reload can add actions for any state
+        monitor.on(StateSet::ALL, move |event| watcher.run(event));
+    } else {
+        monitor.on(actions.states(), move |event| actions.run(event));
+    }
+    if let Some(dir) = args.plugins {
+        let plugins = NativePlugins::load(dir).unwrap();
+        monitor.on(StateSet::ALL, move |event| plugins.on_event(event));
+    }
+    monitor.run();
+}
+
+/// The user the event's session belongs to, `DOMAIN\user` if known
+fn user(event: &SessionEvent) -> String {
+    match (&event.user, &event.info) {
+        (Some(user), _) => user.to_string(),
+        (None, Some(info)) if !info.domain.is_empty() => {
+            format!("{}\\{}", info.domain, info.user_name)
+        }
+        (None, Some(info)) => info.user_name.clone(),
+        (None, None) => String::new(),
+    }
 }
@@ -0,0 +1,1192 @@
+//! The public entry point of the library, wraps the window creation,
+//! registration and message loop from [`crate::wynapi`]
+use crate::autolock::AutoLock;
+use crate::backend::{Backend, Inbox, Source};
+use crate::bus::EventBus;
+use crate::control::lock_workstation;
+use crate::device::{UsbDevice, UsbId};
+use crate::display::DisplayTracker;
+use crate::event::SessionEvent;
+use crate::filter::StateSet;
+use crate::guard::{
+    DeviceRegistration, HotkeyRegistration, MessageWindow, PowerRegistration,
+    ShutdownBlock, Timer,
+};
+use crate::hotkey::{Hotkey, HotkeyBinding};
+use crate::idle::IdleTracker;
+use crate::logon::LogonType;
+use crate::network::NetworkListener;
+use crate::power::Sleep;
+use crate::presence::{Presence, PresenceTracker};
+use crate::schedule::{Schedule, ScheduleTracker};
+use crate::session::{
+    enumerate_sessions, find_shadow, query_lock_state, query_session_info,
+    query_session_user, SessionInfo, SessionUser,
+};
+use crate::stop::StopHandle;
+use crate::switch::SwitchTracker;
+use crate::wynapi::*;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::CString;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tracing::{event, Level};
+
+/// The WM_TIMER ID for polling the screensaver
+const SCREENSAVER_TIMER: usize = 1;
+/// The WM_TIMER ID for polling the idle time
+const IDLE_TIMER: usize = 2;
+/// The WM_TIMER ID for polling the input desktop
+const DESKTOP_TIMER: usize = 3;
+/// The WM_TIMER ID for checking that no lock state change was missed
+const WATCHDOG_TIMER: usize = 4;
+/// The WM_TIMER ID for checking whether a schedule is due
+const SCHEDULE_TIMER: usize = 5;
+/// How long after a panic lock the real Lock is expected, it is swallowed
+/// as the lock handlers already ran for the synthetic one
+const PANIC_LOCK_WINDOW: Duration = Duration::from_secs(5);
+
+/// A closure registered to run when a given [`WtsState`] arrives
+type Callback = Box<dyn FnMut(&SessionEvent)>;
+
+/// Owns a message only window registered for WTS session notifications.
+/// Several monitors can exist in one process, even with the same class name,
+/// but a [`StopHandle`] ends the message loop of every monitor on its thread
+pub struct SessionMonitor {
+    // Fields drop in order, the registrations have to go before the window
+    source: Source,
+    _power: Vec<PowerRegistration>,
+    _timers: Vec<Timer>,
+    _devices: Option<DeviceRegistration>,
+    _hotkeys: Vec<HotkeyRegistration>,
+    _network: Option<NetworkListener>,
+    window: MessageWindow,
+    /// Filled by the window procedure while messages are dispatched
+    queue: Rc<RefCell<VecDeque<WindowEvent>>>,
+    /// Filled by backends other than [`Backend::Wts`]
+    inbox: Inbox,
+    /// Synthetic events such as the startup lock state and presence
+    /// changes, handed out before any further notification
+    pending: RefCell<VecDeque<SessionEvent>>,
+    /// Shared with the window procedure for SessionEnding
+    callbacks: Rc<RefCell<Vec<(StateSet, Callback)>>>,
+    bus: EventBus,
+    scope: SessionScope,
+    /// Reported for machine wide events such as [`WtsState::Suspend`]
+    session_id: u32,
+    states: StateSet,
+    log_messages: bool,
+    session_info: bool,
+    shutdown_reason: String,
+    /// Whether the screensaver was running at the last poll
+    screensaver: Cell<bool>,
+    idle: IdleTracker,
+    /// Whether the input desktop was a secure one at the last poll
+    secure_desktop: Cell<bool>,
+    presence: RefCell<Option<PresenceTracker>>,
+    auto_lock: RefCell<Option<AutoLock>>,
+    switches: RefCell<Option<SwitchTracker>>,
+    displays: RefCell<Option<DisplayTracker>>,
+    schedules: RefCell<Option<ScheduleTracker>>,
+    /// Removing any of these devices locks the workstation
+    lock_on_removal: Vec<UsbId>,
+    /// Indexed by the ID each hotkey was registered with
+    hotkeys: Vec<HotkeyBinding>,
+    /// The client of each remote session as of its RemoteConnect, as
+    /// WTS may have forgotten it by the time of RemoteDisconnect
+    remote_clients: RefCell<HashMap<u32, SessionInfo>>,
+    /// How each session was logged on to, LSA forgets by Logoff
+    logon_types: RefCell<HashMap<u32, LogonType>>,
+    /// The sessions disconnected from since their Logon, or already when
+    /// the monitor started, a connect to one of these is a reconnect
+    disconnected: RefCell<HashSet<u32>>,
+    /// Who each session belongs to, resolved on its first event with
+    /// [`SessionScope::AllSessions`] and forgotten on Logoff
+    users: RefCell<HashMap<u32, SessionUser>>,
+    /// Whether to re-register on resume and when the watchdog finds a
+    /// missed change
+    watchdog: bool,
+    /// The last Lock or Unlock of our session, for the watchdog
+    lock_state: Cell<Option<WtsState>>,
+    /// When a panic lock handed out its Lock, the real one is swallowed
+    panic_locked: Cell<Option<Instant>>,
+}
+
+impl SessionMonitor {
+    /// Create the window for the events to be sent to and register it to
+    /// recieve the events, using the default [`SessionMonitorBuilder`]
+    pub fn new() -> Result<Self, Error> {
+        Self::builder().build()
+    }
+
+    /// Like [`SessionMonitor::new`] but for the sessions in `scope`, use
+    /// [`SessionScope::AllSessions`] to see every user on a terminal server
+    pub fn with_scope(scope: SessionScope) -> Result<Self, Error> {
+        Self::builder().scope(scope).build()
+    }
+
+    /// Configure the monitor before the window is created
+    pub fn builder() -> SessionMonitorBuilder {
+        SessionMonitorBuilder::new()
+    }
+
+    /// Which sessions this monitor receives notifications for
+    pub fn scope(&self) -> SessionScope {
+        self.scope
+    }
+
+    /// Block until the next session notification arrives, returns `None`
+    /// once the message loop has ended
+    pub fn next_event(&self) -> Option<SessionEvent> {
+        loop {
+            let pending = self.pending.borrow_mut().pop_front();
+            if pending.is_some() {
+                return pending;
+            }
+
+            // Drain what the window procedure has seen before pumping again
+            let queued = self.queue.borrow_mut().pop_front();
+            let (state, session_id) = match queued {
+                Some(WindowEvent::SessionChange { state, session_id }) => {
+                    (state, session_id)
+                }
+                Some(WindowEvent::Machine(state)) => (state, self.session_id),
+                Some(WindowEvent::Timer(id)) => match self.poll(id) {
+                    Some(state) => (state, self.session_id),
+                    None => continue,
+                },
+                Some(WindowEvent::Backend) => {
+                    for event in self.inbox.drain() {
+                        self.observe(event.state, event.session_id);
+                        if self.wants(event.state)
+                            && !self.panic_locked(event.state, event.session_id)
+                        {
+                            let event = self.enrich(event);
+                            self.pending.borrow_mut().push_back(event);
+                        }
+                    }
+                    continue;
+                }
+                Some(WindowEvent::Device { state, path }) => {
+                    match self.device(state, path) {
+                        Some(event) => return Some(event),
+                        None => continue,
+                    }
+                }
+                Some(WindowEvent::Hotkey(id)) => match self.hotkey(id) {
+                    Some(event) => return Some(event),
+                    None => continue,
+                },
+                Some(WindowEvent::Display {
+                    width,
+                    height,
+                    bits_per_pixel,
+                }) => match self.display(width, height, bits_per_pixel) {
+                    Some(event) => return Some(event),
+                    None => continue,
+                },
+                None => match get_message_a(self.log_messages) {
+                    Ok(Message::Dispatched) => continue,
+                    Ok(Message::Quit) => return None,
+                    Err(err) => {
+                        event!(Level::ERROR, "Message loop ended {}", err);
+                        return None;
+                    }
+                },
+            };
+
+            self.observe(state, session_id);
+            if self.wants(state) && !self.panic_locked(state, session_id) {
+                return Some(self.event(state, session_id));
+            }
+        }
+    }
+
+    /// Feed a notification to the trackers that derive events from others,
+    /// whether or not it is wanted itself
+    fn observe(&self, state: WtsState, session_id: u32) {
+        if session_id == self.session_id {
+            self.track_presence(|presence| presence.on_state(state));
+            if matches!(state, WtsState::Lock | WtsState::Unlock) {
+                self.lock_state.set(Some(state));
+            }
+        }
+        self.track_switches(state, session_id);
+        match state {
+            WtsState::ConsoleDisconnect | WtsState::RemoteDisconnnect => {
+                self.disconnected.borrow_mut().insert(session_id);
+            }
+            WtsState::Logon | WtsState::Logoff => {
+                self.disconnected.borrow_mut().remove(&session_id);
+            }
+            _ => {}
+        }
+        // Registrations don't always survive a hibernate
+        if self.watchdog && state == WtsState::Resume {
+            self.renew("resumed");
+        }
+    }
+
+    /// Register for session notifications again and queue Reregistered
+    fn renew(&self, reason: &str) {
+        event!(Level::WARN, "Re-registering for notifications, {reason}");
+        if self.source.renew().is_ok() && self.wants(WtsState::Reregistered) {
+            let event = self.event(WtsState::Reregistered, self.session_id);
+            self.pending.borrow_mut().push_back(event);
+        }
+    }
+
+    /// Probe the lock state of our session, a change we weren't told about
+    /// means notifications stopped. Re-register and hand out the missed
+    /// state as a synthetic event
+    fn check_registration(&self) {
+        let Some(probed) = query_lock_state(self.session_id) else {
+            return;
+        };
+        match self.lock_state.get() {
+            Some(seen) if seen != probed => {
+                self.renew(&format!("missed {}", probed.name()));
+                self.observe(probed, self.session_id);
+                if self.wants(probed) {
+                    let mut event = self.event(probed, self.session_id);
+                    event.synthetic = true;
+                    self.pending.borrow_mut().push_back(event);
+                }
+            }
+            Some(_) => {}
+            None => self.lock_state.set(Some(probed)),
+        }
+    }
+
+    /// Where the user is at the moment, needs
+    /// [`SessionMonitorBuilder::away_after`]
+    pub fn presence(&self) -> Option<Presence> {
+        self.presence
+            .borrow()
+            .as_ref()
+            .map(PresenceTracker::current)
+    }
+
+    /// Run `feed` on the presence tracker and queue an event if the presence
+    /// changed
+    fn track_presence(
+        &self,
+        feed: impl FnOnce(&mut PresenceTracker) -> Option<Presence>,
+    ) {
+        let changed = self.presence.borrow_mut().as_mut().and_then(feed);
+        if let Some(presence) = changed {
+            let state = presence.state();
+            if self.wants(state) {
+                let event = self.event(state, self.session_id);
+                self.pending.borrow_mut().push_back(event);
+            }
+        }
+    }
+
+    /// Build the event for a USB device change, enforcing the lock on
+    /// removal rule whether or not anybody wants the event
+    fn device(&self, state: WtsState, path: String) -> Option<SessionEvent> {
+        let device = UsbDevice::from_path(path)?;
+        if state == WtsState::DeviceRemoved
+            && self.lock_on_removal.contains(&device.id())
+        {
+            event!(Level::INFO, "{:?} removed, locking", device.id());
+            let _ = lock_workstation();
+        }
+        self.wants(state).then(|| {
+            let mut event = self.event(state, self.session_id);
+            event.device = Some(device);
+            event
+        })
+    }
+
+    /// Run what the hotkey registered as `id` is bound to. The event is
+    /// synthetic and not fed to the trackers, it only stands in for the
+    /// state so its handlers run straight away
+    fn hotkey(&self, id: usize) -> Option<SessionEvent> {
+        let binding = self.hotkeys.get(id)?;
+        if binding.lock {
+            event!(Level::INFO, "Panic lock hotkey pressed, locking");
+            if lock_workstation().is_ok() && self.wants(binding.state) {
+                self.panic_locked.set(Some(Instant::now()));
+            }
+        }
+        self.wants(binding.state).then(|| {
+            let mut event = self.event(binding.state, self.session_id);
+            event.synthetic = true;
+            event.hotkey = Some(binding.hotkey);
+            event
+        })
+    }
+
+    /// Whether this is the real Lock following a panic lock's, which is
+    /// swallowed so the lock handlers don't run twice
+    fn panic_locked(&self, state: WtsState, session_id: u32) -> bool {
+        if state != WtsState::Lock || session_id != self.session_id {
+            return false;
+        }
+        let locked = self.panic_locked.take();
+        let swallowed =
+            locked.is_some_and(|at| at.elapsed() <= PANIC_LOCK_WINDOW);
+        if swallowed {
+            event!(Level::DEBUG, "Lock already handed out by the panic lock");
+        }
+        swallowed
+    }
+
+    /// Work out what a WM_DISPLAYCHANGE amounts to, `None` if nothing
+    /// wanted changed
+    fn display(
+        &self,
+        width: u32,
+        height: u32,
+        bits_per_pixel: u32,
+    ) -> Option<SessionEvent> {
+        let (state, config) = self.displays.borrow_mut().as_mut()?.on_change(
+            width,
+            height,
+            bits_per_pixel,
+        )?;
+        self.wants(state).then(|| {
+            let mut event = self.event(state, self.session_id);
+            event.display = Some(config);
+            event
+        })
+    }
+
+    /// Queue SwitchedAway and SwitchedBack for the switches `state`
+    /// completes
+    fn track_switches(&self, state: WtsState, session_id: u32) {
+        let switches = match self.switches.borrow_mut().as_mut() {
+            Some(tracker) => tracker.on_state(state, session_id),
+            None => return,
+        };
+        for switch in switches {
+            if self.wants(switch.state) {
+                let mut event = self.event(switch.state, switch.session_id);
+                event.other_session_id = switch.other_session_id;
+                self.pending.borrow_mut().push_back(event);
+            }
+        }
+    }
+
+    /// The message only window the notifications are delivered to
+    pub fn window(&self) -> &MessageWindow {
+        &self.window
+    }
+
+    /// The idle time as of the last poll, needs
+    /// [`SessionMonitorBuilder::idle_tracking`]
+    pub fn idle_time(&self) -> Duration {
+        self.idle.idle()
+    }
+
+    /// A handle to read the idle time from other threads, updated while this
+    /// monitor pumps messages
+    pub fn idle_tracker(&self) -> IdleTracker {
+        self.idle.clone()
+    }
+
+    /// Hold off a pending shutdown or logoff with `reason` shown to the user
+    /// until the returned guard is dropped. [`run`](Self::run) does this by
+    /// itself while handling ShutdownPending and LogoffPending, which needs
+    /// one of them to be in the monitor's states
+    pub fn block_shutdown(
+        &self,
+        reason: &str,
+    ) -> Result<ShutdownBlock<'_>, Error> {
+        ShutdownBlock::create(&self.window, reason)
+    }
+
+    /// A handle that ends this monitor's message loop from another thread
+    pub fn stop_handle(&self) -> StopHandle {
+        self.window.stop_handle()
+    }
+
+    /// Iterate over session notifications until the message loop ends
+    pub fn events(&self) -> Events<'_> {
+        Events { monitor: self }
+    }
+
+    /// Register a callback for a state or a [`StateSet`] of them, several
+    /// callbacks can be registered for the same state and they run in the
+    /// order they were added. Other states never invoke the callback
+    pub fn on<F>(
+        &mut self,
+        states: impl Into<StateSet>,
+        callback: F,
+    ) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.callbacks
+            .borrow_mut()
+            .push((states.into(), Box::new(callback)));
+        self
+    }
+
+    pub fn on_console_connect<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::ConsoleConnect, callback)
+    }
+
+    pub fn on_console_disconnect<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::ConsoleDisconnect, callback)
+    }
+
+    pub fn on_remote_connect<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::RemoteConnect, callback)
+    }
+
+    pub fn on_remote_disconnect<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::RemoteDisconnnect, callback)
+    }
+
+    pub fn on_logon<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::Logon, callback)
+    }
+
+    pub fn on_logoff<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::Logoff, callback)
+    }
+
+    pub fn on_lock<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::Lock, callback)
+    }
+
+    pub fn on_unlock<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::Unlock, callback)
+    }
+
+    pub fn on_remote_control<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::RemoteControl, callback)
+    }
+
+    pub fn on_suspend<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::Suspend, callback)
+    }
+
+    pub fn on_resume<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::Resume, callback)
+    }
+
+    pub fn on_display_off<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::DisplayOff, callback)
+    }
+
+    pub fn on_display_on<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::DisplayOn, callback)
+    }
+
+    pub fn on_lid_closed<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::LidClosed, callback)
+    }
+
+    pub fn on_battery_power<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::BatteryPower, callback)
+    }
+
+    pub fn on_shutdown_pending<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::ShutdownPending, callback)
+    }
+
+    pub fn on_logoff_pending<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::LogoffPending, callback)
+    }
+
+    /// Runs from inside the window procedure, the last chance to clean up
+    /// before Windows ends the process
+    pub fn on_session_ending<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(&SessionEvent) + 'static,
+    {
+        self.on(WtsState::SessionEnding, callback)
+    }
+
+    /// The bus [`run`](Self::run) publishes every event to, for consumers
+    /// that would rather read from a channel than register a callback
+    pub fn bus(&self) -> &EventBus {
+        &self.bus
+    }
+
+    /// Pump session notifications, invoking the registered callbacks for
+    /// each one and publishing it to the [`bus`](Self::bus) until the message
+    /// loop ends
+    pub fn run(&mut self) {
+        while let Some(event) = self.next_event() {
+            self.dispatch(&event);
+        }
+    }
+
+    fn event(&self, state: WtsState, session_id: u32) -> SessionEvent {
+        self.enrich(SessionEvent::new(state, session_id))
+    }
+
+    /// Attach whatever the builder asked for that `event` lacks, the client
+    /// details of remote connects and disconnects, who is shadowing, how
+    /// sessions were logged on to, who they belong to and what a resume came
+    /// back from
+    fn enrich(&self, mut event: SessionEvent) -> SessionEvent {
+        let remote = matches!(
+            event.state,
+            WtsState::RemoteConnect | WtsState::RemoteDisconnnect
+        );
+        if (self.session_info || remote) && event.info.is_none() {
+            let connected = match event.state {
+                WtsState::RemoteDisconnnect => {
+                    self.remote_clients.borrow_mut().remove(&event.session_id)
+                }
+                _ => None,
+            };
+            event.info =
+                connected.or_else(|| query_session_info(event.session_id).ok());
+        }
+        if event.logon_type.is_none() {
+            event.logon_type = self.logon_type(&event);
+        }
+        if event.state == WtsState::RemoteControl && event.shadow.is_none() {
+            event.shadow = find_shadow(event.session_id);
+        }
+        if event.state == WtsState::Resume && event.sleep.is_none() {
+            event.sleep = Some(Sleep::query());
+        }
+        if self.scope == SessionScope::AllSessions && event.user.is_none() {
+            event.user = self.user(&event);
+        }
+        if event.state == WtsState::RemoteConnect {
+            if let Some(info) = &event.info {
+                self.remote_clients
+                    .borrow_mut()
+                    .insert(event.session_id, info.clone());
+            }
+        }
+        event
+    }
+
+    /// Who the session of `event` belongs to, from the cache unless it is a
+    /// Logon. A Logoff still carries the user but drops them from the cache
+    /// so the next user of the session id is resolved afresh
+    fn user(&self, event: &SessionEvent) -> Option<SessionUser> {
+        let id = event.session_id;
+        let mut users = self.users.borrow_mut();
+        match event.state {
+            WtsState::Logoff => {
+                return users.remove(&id).or_else(|| query_session_user(id))
+            }
+            WtsState::Logon => {
+                users.remove(&id);
+            }
+            _ => {}
+        }
+        if let Some(user) = users.get(&id) {
+            return Some(user.clone());
+        }
+        let user = event
+            .info
+            .as_ref()
+            .filter(|info| !info.user_name.is_empty())
+            .map(|info| SessionUser {
+                user_name: info.user_name.clone(),
+                domain: info.domain.clone(),
+            })
+            .or_else(|| query_session_user(id))?;
+        users.insert(id, user.clone());
+        Some(user)
+    }
+
+    /// How the session of a Logon, Logoff or reconnect was logged on to
+    fn logon_type(&self, event: &SessionEvent) -> Option<LogonType> {
+        let id = event.session_id;
+        let info = || {
+            event
+                .info
+                .clone()
+                .or_else(|| query_session_info(id).ok())
+                .filter(|info| !info.user_name.is_empty())
+        };
+        match event.state {
+            WtsState::Logon => {
+                let logon_type = LogonType::query(id, &info()?)?;
+                self.logon_types.borrow_mut().insert(id, logon_type);
+                Some(logon_type)
+            }
+            WtsState::Logoff => self
+                .logon_types
+                .borrow_mut()
+                .remove(&id)
+                .or_else(|| LogonType::query(id, &info()?)),
+            WtsState::ConsoleConnect | WtsState::RemoteConnect => self
+                .disconnected
+                .borrow_mut()
+                .remove(&id)
+                .then_some(LogonType::Reconnect),
+            _ => None,
+        }
+    }
+
+    /// Check whatever timer `id` polls for, returns the state on a change
+    fn poll(&self, id: usize) -> Option<WtsState> {
+        match id {
+            SCREENSAVER_TIMER => {
+                let running = screensaver_running().ok()?;
+                (self.screensaver.replace(running) != running).then_some(
+                    match running {
+                        true => WtsState::ScreensaverStart,
+                        false => WtsState::ScreensaverStop,
+                    },
+                )
+            }
+            DESKTOP_TIMER => {
+                let secure = !input_desktop_is_switchable();
+                (self.secure_desktop.replace(secure) != secure).then_some(
+                    match secure {
+                        true => WtsState::SecureDesktopEnter,
+                        false => WtsState::SecureDesktopLeave,
+                    },
+                )
+            }
+            IDLE_TIMER => {
+                let idle = self.idle.update()?;
+                if let Some(auto_lock) = self.auto_lock.borrow_mut().as_mut() {
+                    auto_lock.check(idle);
+                }
+                self.track_presence(|presence| presence.on_idle(idle));
+                None
+            }
+            WATCHDOG_TIMER => {
+                self.check_registration();
+                None
+            }
+            SCHEDULE_TIMER => {
+                let due = self.schedules.borrow_mut().as_mut()?.on_tick();
+                for schedule in due {
+                    event!(Level::INFO, "Schedule {schedule} is due");
+                    let mut event =
+                        self.event(WtsState::Scheduled, self.session_id);
+                    event.synthetic = true;
+                    event.schedule = Some(schedule);
+                    self.pending.borrow_mut().push_back(event);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn wants(&self, state: WtsState) -> bool {
+        self.states.contains(state)
+    }
+
+    fn dispatch(&mut self, event: &SessionEvent) {
+        // Keep the session alive until every handler has finished
+        let _block = match event.state {
+            WtsState::ShutdownPending | WtsState::LogoffPending => {
+                ShutdownBlock::create(&self.window, &self.shutdown_reason).ok()
+            }
+            _ => None,
+        };
+        run_callbacks(&self.callbacks, event);
+        self.bus.publish(event.clone());
+    }
+}
+
+/// Run the callbacks registered for `event`'s state, unless the window
+/// procedure was re-entered from inside one of them
+fn run_callbacks(
+    callbacks: &RefCell<Vec<(StateSet, Callback)>>,
+    event: &SessionEvent,
+) {
+    let Ok(mut callbacks) = callbacks.try_borrow_mut() else {
+        event!(Level::WARN, "{} during a callback", event.state.name());
+        return;
+    };
+    callbacks
+        .iter_mut()
+        .filter(|(states, _)| states.contains(event.state))
+        .for_each(|(_, callback)| callback(event));
+}
+
+/// Hand out SessionEnding and end the message loop, so the monitor is
+/// dropped and unregisters if Windows waits long enough
+fn end_session(
+    callbacks: &RefCell<Vec<(StateSet, Callback)>>,
+    bus: &EventBus,
+    states: StateSet,
+) {
+    if states.contains(WtsState::SessionEnding) {
+        let session_id = current_session_id().unwrap_or_default();
+        let event = SessionEvent::new(WtsState::SessionEnding, session_id);
+        run_callbacks(callbacks, &event);
+        bus.publish(event);
+    }
+    let _ = post_thread_message_a(get_current_thread_id(), WM_QUIT);
+}
+
+/// Options for a [`SessionMonitor`] that have to be chosen before its window
+/// is created
+#[derive(Debug, Clone)]
+pub struct SessionMonitorBuilder {
+    scope: SessionScope,
+    class_name: String,
+    states: StateSet,
+    log_messages: bool,
+    session_info: bool,
+    initial_state: bool,
+    shutdown_reason: String,
+    poll_interval: Duration,
+    idle_tracking: bool,
+    away_after: Option<Duration>,
+    auto_lock: Option<AutoLock>,
+    lock_on_removal: Vec<UsbId>,
+    hotkeys: Vec<HotkeyBinding>,
+    schedules: Vec<Schedule>,
+    backend: Backend,
+    watchdog: Option<Duration>,
+}
+
+impl Default for SessionMonitorBuilder {
+    fn default() -> Self {
+        Self {
+            scope: SessionScope::default(),
+            class_name: "rustylock".into(),
+            states: StateSet::ALL,
+            log_messages: true,
+            session_info: false,
+            initial_state: false,
+            shutdown_reason: "Running session end handlers".into(),
+            poll_interval: Duration::from_secs(1),
+            idle_tracking: false,
+            away_after: None,
+            auto_lock: None,
+            lock_on_removal: Vec::new(),
+            hotkeys: Vec::new(),
+            schedules: Vec::new(),
+            backend: Backend::default(),
+            watchdog: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+impl SessionMonitorBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Which sessions to receive notifications for
+    pub fn scope(mut self, scope: SessionScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// The window class registered for the message window
+    pub fn class_name(mut self, class_name: impl Into<String>) -> Self {
+        self.class_name = class_name.into();
+        self
+    }
+
+    /// Only receive these kinds of events, all kinds are received by default.
+    /// Takes anything that converts to a [`StateSet`]
+    pub fn states(mut self, states: impl Into<StateSet>) -> Self {
+        self.states = states.into();
+        self
+    }
+
+    /// Log every raw message pulled off the queue
+    pub fn log_messages(mut self, log_messages: bool) -> Self {
+        self.log_messages = log_messages;
+        self
+    }
+
+    /// Query [`SessionInfo`](crate::SessionInfo) for each event and attach it
+    /// as [`SessionEvent::info`]
+    pub fn session_info(mut self, session_info: bool) -> Self {
+        self.session_info = session_info;
+        self
+    }
+
+    /// Probe whether our session is locked after registering and hand that
+    /// out as a [`SessionEvent::synthetic`] Lock or Unlock first, so a
+    /// monitor started on a locked workstation doesn't see Unlock first
+    pub fn initial_state(mut self, initial_state: bool) -> Self {
+        self.initial_state = initial_state;
+        self
+    }
+
+    /// The reason Windows shows while [`SessionMonitor::run`] holds off a
+    /// shutdown or logoff to finish the handlers
+    pub fn shutdown_reason(mut self, reason: impl Into<String>) -> Self {
+        self.shutdown_reason = reason.into();
+        self
+    }
+
+    /// How often to check the states Windows doesn't notify us about, such
+    /// as the screensaver, and the sessions for [`Backend::Polling`]
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Poll GetLastInputInfo every `poll_interval` so
+    /// [`SessionMonitor::idle_time`] is kept up to date
+    pub fn idle_tracking(mut self, idle_tracking: bool) -> Self {
+        self.idle_tracking = idle_tracking;
+        self
+    }
+
+    /// Track the [`Presence`] of the user in our session, who counts as away
+    /// once idle for `threshold` or while the screensaver runs. Transitions
+    /// arrive as Present, Away and Locked events
+    pub fn away_after(mut self, threshold: Duration) -> Self {
+        self.away_after = Some(threshold);
+        self
+    }
+
+    /// Lock the workstation once the user has been idle for as long as
+    /// `policy` says, checked every `poll_interval`
+    pub fn auto_lock(mut self, policy: AutoLock) -> Self {
+        self.auto_lock = Some(policy);
+        self
+    }
+
+    /// Lock the workstation when the USB device `id` is removed, eg. a
+    /// security key. Can be called for several devices
+    pub fn lock_on_removal(mut self, id: UsbId) -> Self {
+        self.lock_on_removal.push(id);
+        self
+    }
+
+    /// Hand out an event of `state` whenever `hotkey` is pressed anywhere in
+    /// the session, [`WtsState::Hotkey`] for one that stands for nothing
+    /// else. Fails to build if another program has the hotkey
+    pub fn hotkey(mut self, hotkey: Hotkey, state: WtsState) -> Self {
+        self.hotkeys.push(HotkeyBinding {
+            hotkey,
+            state,
+            lock: false,
+        });
+        self
+    }
+
+    /// Lock the workstation when `hotkey` is pressed and hand out a Lock
+    /// straight away so the lock handlers don't wait for the notification.
+    /// The notification that follows is swallowed so they run once
+    pub fn panic_lock(mut self, hotkey: Hotkey) -> Self {
+        self.hotkeys.push(HotkeyBinding {
+            hotkey,
+            state: WtsState::Lock,
+            lock: true,
+        });
+        self
+    }
+
+    /// Hand out a Scheduled event at the start of each minute `schedule`
+    /// matches in local time, checked every `poll_interval`. Can be called
+    /// for several schedules
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.schedules.push(schedule);
+        self
+    }
+
+    /// Where the session notifications come from, WTS by default
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// How often to check that WTS notifications still arrive, by comparing
+    /// the lock state of our session with the last one we were told about.
+    /// On a mismatch, and after every resume, the window is registered again
+    /// and a Reregistered event is sent. Every 30 seconds by default, `None`
+    /// turns it off
+    pub fn watchdog(mut self, interval: Option<Duration>) -> Self {
+        self.watchdog = interval;
+        self
+    }
+
+    /// Create the window and register it to recieve the events
+    pub fn build(self) -> Result<SessionMonitor, Error> {
+        let class_name = CString::new(self.class_name).map_err(|_| {
+            event!(Level::ERROR, "Window class name contains a nul byte");
+            Error::INVALID_PARAMETER
+        })?;
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        let callbacks = Rc::new(RefCell::new(Vec::new()));
+        let bus = EventBus::new();
+        let handler = {
+            let queue = queue.clone();
+            let callbacks = callbacks.clone();
+            let bus = bus.clone();
+            let states = self.states;
+            Box::new(move |event| match event {
+                // The process can be ended as soon as the window procedure
+                // returns, so this can't wait in the queue
+                WindowEvent::Machine(WtsState::SessionEnding) => {
+                    end_session(&callbacks, &bus, states)
+                }
+                event => queue.borrow_mut().push_back(event),
+            })
+        };
+        // Session end and display notifications are only sent to top level
+        // windows
+        let displays = self.states.intersects([
+            WtsState::MonitorAdded,
+            WtsState::MonitorRemoved,
+            WtsState::ResolutionChanged,
+        ]);
+        let top_level = displays
+            || self.states.intersects([
+                WtsState::ShutdownPending,
+                WtsState::LogoffPending,
+                WtsState::SessionEnding,
+            ]);
+        let window = MessageWindow::create(class_name, handler, top_level)?;
+        let inbox = Inbox::new(&window);
+        let source = Source::start(
+            self.backend,
+            &window,
+            self.scope,
+            &inbox,
+            self.poll_interval,
+        )?;
+        // Only ask for power notifications somebody wants
+        let mut power = Vec::new();
+        if self.states.intersects(WtsState::Suspend | WtsState::Resume) {
+            power.push(PowerRegistration::suspend_resume(&window)?);
+        }
+        if self.states.intersects([
+            WtsState::DisplayOff,
+            WtsState::DisplayOn,
+            WtsState::DisplayDimmed,
+        ]) {
+            power.push(PowerRegistration::setting(
+                &window,
+                &GUID_CONSOLE_DISPLAY_STATE,
+            )?);
+        }
+        if self
+            .states
+            .intersects(WtsState::LidClosed | WtsState::LidOpened)
+        {
+            power.push(PowerRegistration::setting(
+                &window,
+                &GUID_LIDSWITCH_STATE_CHANGE,
+            )?);
+        }
+        if self.states.intersects([
+            WtsState::AcPower,
+            WtsState::BatteryPower,
+            WtsState::UpsPower,
+        ]) {
+            power.push(PowerRegistration::setting(
+                &window,
+                &GUID_ACDC_POWER_SOURCE,
+            )?);
+        }
+        let devices = (!self.lock_on_removal.is_empty()
+            || self
+                .states
+                .intersects(WtsState::DeviceArrived | WtsState::DeviceRemoved))
+        .then(|| DeviceRegistration::register(&window))
+        .transpose()?;
+        let session_id = current_session_id().unwrap_or_default();
+        let network = self
+            .states
+            .intersects(
+                WtsState::NetworkConnected | WtsState::NetworkDisconnected,
+            )
+            .then(|| NetworkListener::start(session_id, inbox.clone()))
+            .transpose()?;
+        let hotkeys = self
+            .hotkeys
+            .iter()
+            .enumerate()
+            .map(|(id, binding)| {
+                HotkeyRegistration::register(&window, id, binding.hotkey)
+            })
+            .collect::<Result<_, _>>()?;
+        // There is no notification for the screensaver so it is polled
+        let mut timers = Vec::new();
+        let presence = self.away_after.is_some();
+        if presence
+            || self.states.intersects(
+                WtsState::ScreensaverStart | WtsState::ScreensaverStop,
+            )
+        {
+            timers.push(Timer::start(
+                &window,
+                SCREENSAVER_TIMER,
+                self.poll_interval,
+            )?);
+        }
+        if self.states.intersects(
+            WtsState::SecureDesktopEnter | WtsState::SecureDesktopLeave,
+        ) {
+            timers.push(Timer::start(
+                &window,
+                DESKTOP_TIMER,
+                self.poll_interval,
+            )?);
+        }
+        let idle = IdleTracker::default();
+        if self.idle_tracking || presence || self.auto_lock.is_some() {
+            idle.update();
+            timers.push(Timer::start(&window, IDLE_TIMER, self.poll_interval)?);
+        }
+        let watchdog = self.watchdog.filter(|_| self.backend == Backend::Wts);
+        if let Some(interval) = watchdog {
+            timers.push(Timer::start(&window, WATCHDOG_TIMER, interval)?);
+        }
+        let schedules = (!self.schedules.is_empty()
+            && self.states.contains(WtsState::Scheduled))
+        .then(|| ScheduleTracker::new(self.schedules));
+        if schedules.is_some() {
+            timers.push(Timer::start(
+                &window,
+                SCHEDULE_TIMER,
+                self.poll_interval,
+            )?);
+        }
+
+        let mut monitor = SessionMonitor {
+            source,
+            _power: power,
+            _timers: timers,
+            _devices: devices,
+            _network: network,
+            _hotkeys: hotkeys,
+            window,
+            queue,
+            inbox,
+            pending: RefCell::new(VecDeque::new()),
+            callbacks,
+            bus,
+            scope: self.scope,
+            session_id,
+            states: self.states,
+            log_messages: self.log_messages,
+            session_info: self.session_info,
+            shutdown_reason: self.shutdown_reason,
+            screensaver: Cell::new(screensaver_running().unwrap_or(false)),
+            idle,
+            secure_desktop: Cell::new(!input_desktop_is_switchable()),
+            presence: RefCell::new(None),
+            auto_lock: RefCell::new(self.auto_lock),
+            lock_on_removal: self.lock_on_removal,
+            hotkeys: self.hotkeys,
+            remote_clients: RefCell::default(),
+            logon_types: RefCell::default(),
+            disconnected: RefCell::new(
+                enumerate_sessions()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|session| {
+                        session.state == ConnectState::Disconnected
+                    })
+                    .map(|session| session.id)
+                    .collect(),
+            ),
+            users: RefCell::default(),
+            watchdog: watchdog.is_some(),
+            lock_state: Cell::new(
+                watchdog
+                    .and_then(|_| query_lock_state(current_session_id().ok()?)),
+            ),
+            panic_locked: Cell::new(None),
+            switches: RefCell::new(
+                self.states
+                    .intersects(WtsState::SwitchedAway | WtsState::SwitchedBack)
+                    .then(SwitchTracker::default),
+            ),
+            displays: RefCell::new(displays.then(DisplayTracker::new)),
+            schedules: RefCell::new(schedules),
+        };
+
+        // Probe after registering so a change in between is not missed
+        if self.initial_state {
+            let initial = current_session_id().ok().and_then(|session_id| {
+                let state = query_lock_state(session_id)?;
+                monitor
+                    .wants(state)
+                    .then(|| monitor.event(state, session_id))
+            });
+            if let Some(mut event) = initial {
+                event.synthetic = true;
+                event.initial = true;
+                monitor.pending.get_mut().push_back(event);
+            }
+        }
+        if let Some(threshold) = self.away_after {
+            let locked = query_lock_state(monitor.session_id);
+            *monitor.presence.get_mut() = Some(PresenceTracker::new(
+                threshold,
+                locked == Some(WtsState::Lock),
+                monitor.screensaver.get(),
+            ));
+        }
+        Ok(monitor)
+    }
+}
+
+/// Iterator over the events of a [`SessionMonitor`], ends on WM_QUIT
+pub struct Events<'a> {
+    monitor: &'a SessionMonitor,
+}
+
+impl Iterator for Events<'_> {
+    type Item = SessionEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.monitor.next_event()
+    }
+}
@@ -0,0 +1,178 @@
+//! Plugins loaded from DLLs at runtime, for extensions that need to be fast
+//! or lean on the Windows API. The types are in `include/rusty_lock.h`
+use crate::actions::variables;
+use crate::wynapi::{Library, LPVOID};
+use crate::SessionEvent;
+use core::ffi::{c_char, CStr};
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tracing::{event, Level};
+
+/// The version of the plugin ABI, handed to `rustylock_plugin_init` so a
+/// plugin built against another can refuse to load
+pub const RUSTY_LOCK_PLUGIN_ABI: u32 = 1;
+
+/// A session event as handed to plugins. The strings are UTF-8, empty if
+/// unknown, and like the event only valid for the duration of the call
+#[repr(C)]
+pub struct RustyLockPluginEvent {
+    /// The WTS status code, eg. 7 for WTS_SESSION_LOCK
+    pub state: u32,
+    /// The session the notification is about
+    pub session_id: u32,
+    /// Milliseconds since the UNIX epoch when the event was received
+    pub timestamp_ms: u64,
+    /// The state's name, eg. `lock`
+    pub name: *const c_char,
+    pub username: *const c_char,
+    pub domain: *const c_char,
+    pub client_ip: *const c_char,
+}
+
+/// `rustylock_plugin_init`, called once after the DLL is loaded with
+/// [`RUSTY_LOCK_PLUGIN_ABI`]. Returning anything but 0 refuses to load
+pub type RustyLockPluginInit = unsafe extern "C" fn(abi_version: u32) -> i32;
+
+/// `rustylock_plugin_on_event`, called on the monitor thread for every
+/// event so it has to return quickly
+pub type RustyLockPluginOnEvent =
+    unsafe extern "C" fn(event: *const RustyLockPluginEvent);
+
+/// `rustylock_plugin_shutdown`, called once before the DLL is unloaded
+pub type RustyLockPluginShutdown = unsafe extern "C" fn();
+
+struct Plugin {
+    path: PathBuf,
+    on_event: RustyLockPluginOnEvent,
+    shutdown: RustyLockPluginShutdown,
+    /// Dropped last, the functions point into it
+    _library: Library,
+}
+
+impl Plugin {
+    /// Load the DLL at `path` and initialise it, `None` if it has no
+    /// plugin exports or refused
+    fn load(path: PathBuf) -> Option<Self> {
+        let library = Library::load(&path).ok()?;
+        let symbol = |name: &CStr| {
+            let address = library.symbol(name);
+            if address.is_none() {
+                event!(
+                    Level::ERROR,
+                    "{} doesn't export {}",
+                    path.display(),
+                    name.to_string_lossy()
+                );
+            }
+            address
+        };
+        let init = symbol(c"rustylock_plugin_init")?;
+        let on_event = symbol(c"rustylock_plugin_on_event")?;
+        let shutdown = symbol(c"rustylock_plugin_shutdown")?;
+        // The plugin ABI gives the exports these signatures
+        let (init, on_event, shutdown) = unsafe {
+            (
+                core::mem::transmute::<LPVOID, RustyLockPluginInit>(init),
+                core::mem::transmute::<LPVOID, RustyLockPluginOnEvent>(
+                    on_event,
+                ),
+                core::mem::transmute::<LPVOID, RustyLockPluginShutdown>(
+                    shutdown,
+                ),
+            )
+        };
+        let res = unsafe { init(RUSTY_LOCK_PLUGIN_ABI) };
+        if res != 0 {
+            event!(Level::WARN, "{} refused to load, {res}", path.display());
+            return None;
+        }
+        event!(Level::INFO, "Loaded plugin {}", path.display());
+        Some(Self {
+            path,
+            on_event,
+            shutdown,
+            _library: library,
+        })
+    }
+}
+
+/// The DLL plugins in a directory, each exporting `rustylock_plugin_init`,
+/// `rustylock_plugin_on_event` and `rustylock_plugin_shutdown`. They are
+/// shut down and unloaded on drop
+pub struct NativePlugins {
+    plugins: Vec<Plugin>,
+}
+
+impl NativePlugins {
+    /// Load every `.dll` in `dir` in name order. Ones that fail to load,
+    /// miss an export or refuse are logged and skipped
+    pub fn load(dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.extension().is_some_and(|extension| {
+                    extension.eq_ignore_ascii_case("dll")
+                })
+            })
+            .collect();
+        paths.sort();
+        Ok(Self {
+            plugins: paths.into_iter().filter_map(Plugin::load).collect(),
+        })
+    }
+
+    /// How many plugins loaded
+    pub fn len(&self) -> usize {
+        self.plugins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// Hand `event` to each plugin in turn, for
+    /// [`SessionMonitor::on`](crate::SessionMonitor::on)
+    pub fn on_event(&self, event: &SessionEvent) {
+        let variables = variables(event);
+        // Nothing we fill in has a NUL, but a user name can't be trusted
+        let c_string = |name: &str| {
+            let value = variables
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default();
+            CString::new(value).unwrap_or_default()
+        };
+        let (name, username, domain, client_ip) = (
+            c_string("event"),
+            c_string("username"),
+            c_string("domain"),
+            c_string("client_ip"),
+        );
+        let plugin_event = RustyLockPluginEvent {
+            state: usize::from(event.state) as u32,
+            session_id: event.session_id,
+            timestamp_ms: event
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+            name: name.as_ptr(),
+            username: username.as_ptr(),
+            domain: domain.as_ptr(),
+            client_ip: client_ip.as_ptr(),
+        };
+        for plugin in &self.plugins {
+            unsafe { (plugin.on_event)(&plugin_event) };
+        }
+    }
+}
+
+impl Drop for NativePlugins {
+    fn drop(&mut self) {
+        for plugin in self.plugins.iter().rev() {
+            event!(Level::INFO, "Unloading plugin {}", plugin.path.display());
+            unsafe { (plugin.shutdown)() };
+        }
+    }
+}
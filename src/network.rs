@@ -0,0 +1,120 @@
+//! Network interfaces connecting and disconnecting, from IP interface change
+//! notifications
+use crate::backend::Inbox;
+use crate::wynapi::*;
+use crate::SessionEvent;
+use core::ffi::{c_int, c_void};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// The network interface a NetworkConnected or NetworkDisconnected is about
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NetworkInterface {
+    pub index: u32,
+    /// The name shown in Network Connections, eg. `Wi-Fi` or `Ethernet 2`
+    pub name: String,
+    /// The adapter, eg. `Intel(R) Wi-Fi 6 AX201 160MHz`
+    pub description: String,
+    /// The wireless network, `None` for wired and virtual interfaces
+    pub ssid: Option<String>,
+}
+
+impl NetworkInterface {
+    fn from_entry(entry: &IfEntry) -> Self {
+        Self {
+            index: entry.index,
+            name: entry.alias.clone(),
+            description: entry.description.clone(),
+            ssid: (entry.if_type == IF_TYPE_IEEE80211)
+                .then(|| wlan_current_ssid(&entry.guid))
+                .flatten(),
+        }
+    }
+}
+
+/// Handed to the change callback
+struct Context {
+    inbox: Inbox,
+    /// Reported as the session of every event, they are machine wide
+    session_id: u32,
+    /// Each connected interface as of its NetworkConnected, as the SSID
+    /// can't be read any more by the time it disconnects
+    connected: Mutex<HashMap<u32, NetworkInterface>>,
+}
+
+/// A NotifyIpInterfaceChange registration, cancelled on drop
+pub(crate) struct NetworkListener {
+    handle: HANDLE,
+    context: *mut Context,
+}
+
+impl NetworkListener {
+    pub fn start(session_id: u32, inbox: Inbox) -> Result<Self, Error> {
+        // Start from what is connected now so only changes are reported
+        let connected = get_if_table2()?
+            .iter()
+            .filter(|entry| entry.connected)
+            .map(|entry| (entry.index, NetworkInterface::from_entry(entry)))
+            .collect();
+        let context = Box::into_raw(Box::new(Context {
+            inbox,
+            session_id,
+            connected: Mutex::new(connected),
+        }));
+        match notify_ip_interface_change(context.cast(), Some(callback)) {
+            Ok(handle) => Ok(Self { handle, context }),
+            Err(err) => {
+                drop(unsafe { Box::from_raw(context) });
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Drop for NetworkListener {
+    fn drop(&mut self) {
+        let _ = cancel_mib_change_notify2(self.handle);
+        // No callback can be running once the notification is cancelled
+        drop(unsafe { Box::from_raw(self.context) });
+    }
+}
+
+/// Called for IPv4 and IPv6 separately, so one change usually arrives
+/// twice. Only a change of the connected state is reported
+unsafe extern "system" fn callback(
+    context: *mut c_void,
+    row: *const MIB_IPINTERFACE_ROW,
+    _notification_type: c_int,
+) {
+    let context = &*(context as *const Context);
+    let Some(row) = row.as_ref() else {
+        return;
+    };
+    let index = row.InterfaceIndex;
+    // A deleted interface can't be looked up, it is no longer connected
+    let entry = get_if_entry2(index)
+        .ok()
+        .flatten()
+        .filter(|entry| entry.connected);
+
+    let mut connected = context.connected.lock().unwrap();
+    let (state, interface) = match (connected.remove(&index), entry) {
+        (None, Some(entry)) => {
+            let interface = NetworkInterface::from_entry(&entry);
+            connected.insert(index, interface.clone());
+            (WtsState::NetworkConnected, interface)
+        }
+        (Some(interface), None) => (WtsState::NetworkDisconnected, interface),
+        (Some(interface), Some(_)) => {
+            connected.insert(index, interface);
+            return;
+        }
+        (None, None) => return,
+    };
+    drop(connected);
+
+    let mut event = SessionEvent::new(state, context.session_id);
+    event.network = Some(interface);
+    context.inbox.push(event);
+}
@@ -0,0 +1,117 @@
+//! Node.js bindings, enabled with the `napi` feature
+use crate::spawn::spawn_pump;
+use crate::{
+    MonitorHandle, SessionEvent, SessionMonitorBuilder, SessionScope, WtsState,
+};
+use napi::threadsafe_function::{
+    ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use napi::{Error, Result, Status};
+use napi_derive::napi;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+/// A session event, `state` is a name such as `"lock"`
+#[napi(object, js_name = "SessionEvent")]
+#[derive(Clone)]
+pub struct JsSessionEvent {
+    pub state: String,
+    pub session_id: u32,
+    /// Milliseconds since the UNIX epoch, as taken by `new Date(timestamp)`
+    pub timestamp: f64,
+}
+
+impl From<&SessionEvent> for JsSessionEvent {
+    fn from(event: &SessionEvent) -> Self {
+        Self {
+            state: event.state.name().into(),
+            session_id: event.session_id,
+            timestamp: event
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |since| since.as_millis() as f64),
+        }
+    }
+}
+
+type Listener =
+    ThreadsafeFunction<JsSessionEvent, (), JsSessionEvent, Status, false>;
+
+/// EventEmitter style monitor, `monitor.on('lock', cb)` then
+/// `monitor.start()`
+#[napi(js_name = "Monitor")]
+pub struct JsMonitor {
+    scope: SessionScope,
+    listeners: Arc<Mutex<Vec<(WtsState, Listener)>>>,
+    handle: Option<MonitorHandle>,
+}
+
+#[napi]
+impl JsMonitor {
+    /// `allSessions` receives events for every session on the machine
+    #[napi(constructor)]
+    pub fn new(all_sessions: Option<bool>) -> Self {
+        Self {
+            scope: SessionScope::from_all_sessions(
+                all_sessions.unwrap_or(false),
+            ),
+            listeners: Arc::default(),
+            handle: None,
+        }
+    }
+
+    /// Call `callback(event)` for every event with the named state
+    #[napi]
+    pub fn on(&mut self, state: String, callback: Listener) -> Result<()> {
+        let state = state.parse().map_err(|()| {
+            Error::new(
+                Status::InvalidArg,
+                format!("{state} is not a valid state"),
+            )
+        })?;
+        self.listeners.lock().unwrap().push((state, callback));
+        Ok(())
+    }
+
+    /// Start the monitor thread, listeners are called on the JS thread
+    #[napi]
+    pub fn start(&mut self) -> Result<()> {
+        if self.handle.is_some() {
+            return Ok(());
+        }
+        let listeners = self.listeners.clone();
+        let builder = SessionMonitorBuilder::new().scope(self.scope);
+        let handle = spawn_pump(builder, move |event| {
+            let js_event = JsSessionEvent::from(&event);
+            listeners
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(state, _)| *state == event.state)
+                .for_each(|(_, listener)| {
+                    listener.call(
+                        js_event.clone(),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                });
+            true
+        })
+        .map_err(|err| Error::from_reason(err.to_string()))?;
+        self.handle = Some(handle);
+        Ok(())
+    }
+
+    /// Stop the monitor thread and remove every listener so the process can
+    /// exit
+    #[napi]
+    pub fn stop(&mut self) -> Result<()> {
+        if let Some(handle) = self.handle.take() {
+            handle
+                .stop()
+                .map_err(|err| Error::from_reason(err.to_string()))?;
+            let _ = handle.join();
+        }
+        self.listeners.lock().unwrap().clear();
+        Ok(())
+    }
+}
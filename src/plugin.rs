@@ -0,0 +1,345 @@
+//! WebAssembly plugins run as actions, sandboxed so that beyond logging they
+//! can only do what their capabilities allow.
+//!
+//! A plugin exports its `memory`, `alloc(len) -> ptr` for the host to put
+//! the event in and `on_event(ptr, len) -> i32`, which is handed the event's
+//! placeholders as a JSON object and returns 0 on success. It can import
+//! these from `rusty_lock`, each returning -1 when refused or failing:
+//!
+//! - `log(ptr, len)`
+//! - `exec(ptr, len) -> i32`, runs `cmd /C command` and returns its exit
+//!   code. Needs `exec`
+//! - `http_request(method_ptr, method_len, url_ptr, url_len, body_ptr,
+//!   body_len) -> i32`, returns the status code. Needs `http`
+//! - `read_file(path_ptr, path_len, buf_ptr, buf_len) -> i32`, copies as
+//!   much as fits and returns the file's size. Needs `fs`
+//! - `write_file(path_ptr, path_len, data_ptr, data_len) -> i32`, returns 0.
+//!   Needs `fs`
+//!
+//! File paths are relative to a directory beside the plugin named after it,
+//! `notes.wasm` gets `notes`, and can't leave it
+use crate::actions;
+use crate::wynapi::http_request;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tracing::{event, Level};
+use wasmtime::{
+    Caller, Config, Engine, Linker, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+/// Instructions a plugin gets per event before it is stopped, for one stuck
+/// in a loop
+const FUEL: u64 = 10_000_000_000;
+/// The most memory a plugin can grow to
+const MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+/// What a plugin may do beyond logging, the names in `allow` in the config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Run commands, `exec`
+    pub exec: bool,
+    /// Make HTTP and HTTPS requests, `http`
+    pub http: bool,
+    /// Read and write files in its own directory, `fs`
+    pub fs: bool,
+}
+
+/// What the host functions of one plugin run see
+struct Host {
+    capabilities: Capabilities,
+    /// The plugin's file, for the logs
+    plugin: String,
+    /// Where its files go
+    dir: PathBuf,
+    limits: StoreLimits,
+}
+
+/// A plugin compiled with its imports, made once rather than per event.
+/// Only the [`Store`] is made per event
+struct Compiled {
+    /// When the file was modified, to compile it again when it changes
+    modified: SystemTime,
+    linker: Linker<Host>,
+    module: Module,
+}
+
+/// The plugins compiled so far by path
+static COMPILED: Mutex<BTreeMap<PathBuf, Compiled>> =
+    Mutex::new(BTreeMap::new());
+
+/// Run the plugin at `path` for an event with these placeholders, `Some`
+/// if `on_event` returned 0
+pub(crate) fn run(
+    path: &Path,
+    capabilities: Capabilities,
+    variables: &[(&str, String)],
+) -> Option<String> {
+    match call(path, capabilities, variables) {
+        Ok(0) => Some(String::new()),
+        Ok(code) => {
+            event!(Level::ERROR, "{} returned {code}", path.display());
+            None
+        }
+        Err(err) => {
+            event!(Level::ERROR, "{} failed {err:#}", path.display());
+            None
+        }
+    }
+}
+
+fn call(
+    path: &Path,
+    capabilities: Capabilities,
+    variables: &[(&str, String)],
+) -> wasmtime::Result<i32> {
+    let (linker, module) = compiled(path)?;
+
+    let host = Host {
+        capabilities,
+        plugin: path.display().to_string(),
+        dir: path.with_extension(""),
+        limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build(),
+    };
+    let mut store = Store::new(linker.engine(), host);
+    store.limiter(|host| &mut host.limits);
+    store.set_fuel(FUEL)?;
+    let instance = linker.instantiate(&mut store, &module)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or_else(|| wasmtime::Error::msg("memory isn't exported"))?;
+    let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+    let on_event =
+        instance.get_typed_func::<(i32, i32), i32>(&mut store, "on_event")?;
+
+    let json = event_json(variables);
+    let len = json.len() as i32;
+    let ptr = alloc.call(&mut store, len)?;
+    memory.write(&mut store, ptr as usize, json.as_bytes())?;
+    on_event.call(&mut store, (ptr, len))
+}
+
+/// The plugin at `path` compiled, from [`COMPILED`] unless it changed
+fn compiled(path: &Path) -> wasmtime::Result<(Linker<Host>, Module)> {
+    let modified = std::fs::metadata(path)?.modified()?;
+    let mut compiled = COMPILED.lock().unwrap();
+    if let Some(plugin) = compiled.get(path) {
+        if plugin.modified == modified {
+            return Ok((plugin.linker.clone(), plugin.module.clone()));
+        }
+    }
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::from_file(&engine, path)?;
+    let mut linker = Linker::new(&engine);
+    imports(&mut linker)?;
+    let plugin = Compiled {
+        modified,
+        linker: linker.clone(),
+        module: module.clone(),
+    };
+    compiled.insert(path.to_path_buf(), plugin);
+    Ok((linker, module))
+}
+
+/// The functions a plugin can import from `rusty_lock`
+fn imports(linker: &mut Linker<Host>) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        "rusty_lock",
+        "log",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            if let Some(text) = string(&mut caller, ptr, len) {
+                event!(Level::INFO, "{} {text}", caller.data().plugin);
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "rusty_lock",
+        "exec",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let allowed = caller.data().capabilities.exec;
+            if !check(&caller, "exec", allowed) {
+                return -1;
+            }
+            let Some(command) = string(&mut caller, ptr, len) else {
+                return -1;
+            };
+            let plugin = caller.data().plugin.clone();
+            match actions::exec(&plugin, &command) {
+                Ok((code, _)) => code,
+                Err(err) => {
+                    event!(Level::ERROR, "Failed to start {command} {err}");
+                    -1
+                }
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "rusty_lock",
+        "http_request",
+        |mut caller: Caller<'_, Host>,
+         method_ptr: i32,
+         method_len: i32,
+         url_ptr: i32,
+         url_len: i32,
+         body_ptr: i32,
+         body_len: i32| {
+            let allowed = caller.data().capabilities.http;
+            if !check(&caller, "http", allowed) {
+                return -1;
+            }
+            let method = string(&mut caller, method_ptr, method_len);
+            let url = string(&mut caller, url_ptr, url_len);
+            let body = bytes(&mut caller, body_ptr, body_len);
+            let (Some(method), Some(url), Some(body)) = (method, url, body)
+            else {
+                return -1;
+            };
+            http_request(&method, &url, &[], &body)
+                .map_or(-1, |(status, _)| status as i32)
+        },
+    )?;
+    linker.func_wrap(
+        "rusty_lock",
+        "read_file",
+        |mut caller: Caller<'_, Host>,
+         path_ptr: i32,
+         path_len: i32,
+         buf_ptr: i32,
+         buf_len: i32| {
+            let Some(path) = file(&mut caller, path_ptr, path_len) else {
+                return -1;
+            };
+            let data = match std::fs::read(&path) {
+                Ok(data) => data,
+                Err(err) => {
+                    event!(
+                        Level::WARN,
+                        "Failed to read {} {err}",
+                        path.display()
+                    );
+                    return -1;
+                }
+            };
+            let Some(memory) =
+                caller.get_export("memory").and_then(|e| e.into_memory())
+            else {
+                return -1;
+            };
+            let fits = data.len().min(buf_len.max(0) as usize);
+            match memory.write(&mut caller, buf_ptr as usize, &data[..fits]) {
+                Ok(()) => data.len() as i32,
+                Err(_) => -1,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "rusty_lock",
+        "write_file",
+        |mut caller: Caller<'_, Host>,
+         path_ptr: i32,
+         path_len: i32,
+         data_ptr: i32,
+         data_len: i32| {
+            let Some(path) = file(&mut caller, path_ptr, path_len) else {
+                return -1;
+            };
+            let Some(data) = bytes(&mut caller, data_ptr, data_len) else {
+                return -1;
+            };
+            let written = std::fs::create_dir_all(&caller.data().dir)
+                .and_then(|()| std::fs::write(&path, data));
+            match written {
+                Ok(()) => 0,
+                Err(err) => {
+                    event!(
+                        Level::WARN,
+                        "Failed to write {} {err}",
+                        path.display()
+                    );
+                    -1
+                }
+            }
+        },
+    )?;
+    Ok(())
+}
+
+/// Log a plugin trying something it isn't allowed to, true if it is
+fn check(caller: &Caller<'_, Host>, capability: &str, allowed: bool) -> bool {
+    if !allowed {
+        event!(
+            Level::WARN,
+            "{} needs {capability} in allow to do that",
+            caller.data().plugin
+        );
+    }
+    allowed
+}
+
+/// The file a plugin named, if it may use files and the path stays in its
+/// directory
+fn file(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> Option<PathBuf> {
+    let allowed = caller.data().capabilities.fs;
+    if !check(caller, "fs", allowed) {
+        return None;
+    }
+    let path = string(caller, ptr, len)?;
+    let inside = Path::new(&path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !inside {
+        event!(
+            Level::WARN,
+            "{} can't use {path}, outside its directory",
+            caller.data().plugin
+        );
+        return None;
+    }
+    Some(caller.data().dir.join(path))
+}
+
+/// A copy of `len` bytes at `ptr` in the plugin's memory, `None` if that
+/// is out of bounds
+fn bytes(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let memory = caller.get_export("memory")?.into_memory()?;
+    let start = usize::try_from(ptr).ok()?;
+    let end = start.checked_add(usize::try_from(len).ok()?)?;
+    memory.data(&caller).get(start..end).map(<[u8]>::to_vec)
+}
+
+fn string(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> Option<String> {
+    String::from_utf8(bytes(caller, ptr, len)?).ok()
+}
+
+/// The placeholders as a JSON object, the numbers as numbers
+fn event_json(variables: &[(&str, String)]) -> String {
+    let fields: Vec<String> = variables
+        .iter()
+        .map(|(name, value)| match value.parse::<u64>() {
+            Ok(number) if matches!(*name, "session_id" | "timestamp") => {
+                format!("\"{name}\":{number}")
+            }
+            _ => format!("\"{}\":\"{}\"", escape(name), escape(value)),
+        })
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
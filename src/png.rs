@@ -0,0 +1,217 @@
+//! Just enough of PNG to save screenshots: 8 bit RGB images, each row
+//! filtered against the pixel to its left and compressed with the fixed
+//! Huffman codes of deflate, which flat areas of a desktop shrink well with
+
+/// Shortest and longest matches deflate can refer back to
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How far back deflate can refer
+const WINDOW: usize = 32 * 1024;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59,
+    67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5,
+    5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513,
+    769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10,
+    11, 11, 12, 12, 13, 13,
+];
+
+/// The contents of a .png file for an image whose pixels are blue, green,
+/// red and an unused byte, top row first
+pub(crate) fn encode(width: u32, height: u32, bgrx: &[u8]) -> Vec<u8> {
+    let stride = width as usize * 4;
+    let mut raw =
+        Vec::with_capacity((width as usize * 3 + 1) * height as usize);
+    for row in bgrx.chunks_exact(stride).take(height as usize) {
+        // Filter type Sub, each byte less the one a pixel to its left
+        raw.push(1);
+        let mut left = [0u8; 3];
+        for pixel in row.chunks_exact(4) {
+            let rgb = [pixel[2], pixel[1], pixel[0]];
+            for channel in 0..3 {
+                raw.push(rgb[channel].wrapping_sub(left[channel]));
+            }
+            left = rgb;
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Append a chunk, its length, type, data and CRC
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 == 1 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    !data.iter().fold(!0u32, |crc, &byte| {
+        table[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // The most bytes that can be summed before b could overflow
+    for block in data.chunks(5552) {
+        for &byte in block {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// `data` as a zlib stream of one fixed Huffman deflate block
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut bits = Bits::default();
+    // Deflate with a 32K window, no dictionary and the default level
+    bits.out.extend_from_slice(&[0x78, 0x01]);
+    // The final block, with fixed codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut recent = vec![usize::MAX; 1 << HASH_BITS];
+    let hash = |at: usize| {
+        let key = u32::from_le_bytes([data[at], data[at + 1], data[at + 2], 0]);
+        (key.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    };
+    let mut at = 0;
+    while at < data.len() {
+        let mut length = 0;
+        let mut distance = 0;
+        if at + MIN_MATCH <= data.len() {
+            let key = hash(at);
+            let candidate = recent[key];
+            recent[key] = at;
+            if candidate != usize::MAX && at - candidate <= WINDOW {
+                let longest = (data.len() - at).min(MAX_MATCH);
+                while length < longest
+                    && data[candidate + length] == data[at + length]
+                {
+                    length += 1;
+                }
+                distance = at - candidate;
+            }
+        }
+        if length >= MIN_MATCH {
+            bits.length(length);
+            bits.distance(distance);
+            for skipped in at + 1..(at + length).min(data.len() - MIN_MATCH + 1)
+            {
+                recent[hash(skipped)] = skipped;
+            }
+            at += length;
+        } else {
+            bits.literal(data[at] as u16);
+            at += 1;
+        }
+    }
+    bits.literal(256);
+
+    let mut out = bits.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Deflate's bit stream, which fills each byte from its lowest bit
+#[derive(Default)]
+struct Bits {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl Bits {
+    fn write(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// A Huffman code, which goes in from its highest bit
+    fn code(&mut self, code: u32, count: u32) {
+        self.write(code.reverse_bits() >> (32 - count), count);
+    }
+
+    /// A literal byte or, at 256 and up, the end of the block or a length
+    fn literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn length(&mut self, length: usize) {
+        let index = LENGTH_BASES
+            .iter()
+            .rposition(|&base| base as usize <= length)
+            .unwrap_or_default();
+        self.literal(257 + index as u16);
+        let extra = length as u32 - LENGTH_BASES[index] as u32;
+        self.write(extra, LENGTH_EXTRA[index] as u32);
+    }
+
+    fn distance(&mut self, distance: usize) {
+        let index = DISTANCE_BASES
+            .iter()
+            .rposition(|&base| base as usize <= distance)
+            .unwrap_or_default();
+        self.code(index as u32, 5);
+        let extra = distance as u32 - DISTANCE_BASES[index] as u32;
+        self.write(extra, DISTANCE_EXTRA[index] as u32);
+    }
+
+    /// The bytes written, padding the last one with zeros
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
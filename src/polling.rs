@@ -0,0 +1,103 @@
+//! A fallback source of events that polls the sessions on the machine and
+//! diffs them, for when WTS notifications can't be registered for
+use crate::backend::Inbox;
+use crate::session::{enumerate_sessions, query_lock_state};
+use crate::wynapi::*;
+use crate::SessionEvent;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// The lock state of every session we can see, keyed by session ID
+type Snapshot = HashMap<u32, Option<WtsState>>;
+
+/// A thread polling the sessions every interval, stopped and joined on drop
+pub(crate) struct PollingSource {
+    /// Dropping it wakes the thread up to exit
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PollingSource {
+    pub fn start(
+        scope: SessionScope,
+        inbox: Inbox,
+        interval: Duration,
+    ) -> Result<Self, Error> {
+        let session_id = match scope {
+            SessionScope::ThisSession => Some(current_session_id()?),
+            SessionScope::AllSessions => None,
+        };
+        // Fail now rather than on the thread if the sessions can't be read
+        let mut previous = snapshot(session_id)?;
+        let (stop, stopped) = channel();
+
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) =
+                stopped.recv_timeout(interval)
+            {
+                let Ok(current) = snapshot(session_id) else {
+                    continue;
+                };
+                for (state, id) in diff(&previous, &current) {
+                    inbox.push(SessionEvent::new(state, id));
+                }
+                previous = current;
+            }
+        });
+
+        Ok(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for PollingSource {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The sessions in scope, listeners are left out as nobody logs on to them
+fn snapshot(session_id: Option<u32>) -> Result<Snapshot, Error> {
+    Ok(enumerate_sessions()?
+        .into_iter()
+        .filter(|session| session.state != ConnectState::Listen)
+        .filter(|session| session_id.is_none_or(|ours| ours == session.id))
+        .map(|session| (session.id, query_lock_state(session.id)))
+        .collect())
+}
+
+/// Logon for sessions that appeared, Logoff for ones that went away and
+/// Lock or Unlock for the ones whose lock state changed
+fn diff(previous: &Snapshot, current: &Snapshot) -> Vec<(WtsState, u32)> {
+    let mut events = Vec::new();
+    for (&id, &lock) in current {
+        match previous.get(&id) {
+            None => {
+                events.push((WtsState::Logon, id));
+                if lock == Some(WtsState::Lock) {
+                    events.push((WtsState::Lock, id));
+                }
+            }
+            Some(&before) if before != lock => {
+                if let Some(state) = lock {
+                    events.push((state, id));
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    events.extend(
+        previous
+            .keys()
+            .filter(|id| !current.contains_key(id))
+            .map(|&id| (WtsState::Logoff, id)),
+    );
+    events
+}
@@ -0,0 +1,149 @@
+//! The threads actions run on, a fixed number so that a burst of events,
+//! such as every session of a terminal server locking at once, queues up
+//! rather than starting a thread and a process each
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{event, Level};
+
+/// How many actions run at once unless the config says otherwise
+pub(crate) const DEFAULT_WORKERS: NonZeroUsize = match NonZeroUsize::new(16) {
+    Some(workers) => workers,
+    None => unreachable!(),
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Up to `size` worker threads, started as the jobs need them. Clones share
+/// them, once the last is dropped they finish what is queued and exit
+#[derive(Clone)]
+pub(crate) struct Pool(Arc<Handle>);
+
+struct Handle {
+    size: NonZeroUsize,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: VecDeque<Job>,
+    /// Jobs not to start until their instant
+    delayed: Vec<(Instant, Job)>,
+    workers: usize,
+    idle: usize,
+    closed: bool,
+}
+
+impl Pool {
+    pub fn new(size: NonZeroUsize) -> Self {
+        Self(Arc::new(Handle {
+            size,
+            shared: Arc::new(Shared {
+                queue: Mutex::default(),
+                ready: Condvar::new(),
+            }),
+        }))
+    }
+
+    pub fn size(&self) -> NonZeroUsize {
+        self.0.size
+    }
+
+    /// Run `job` on a worker once one is free
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        self.queue(|queue| queue.jobs.push_back(Box::new(job)));
+    }
+
+    /// Run `job` on a worker once `delay` has passed and one is free
+    pub fn execute_after(
+        &self,
+        delay: Duration,
+        job: impl FnOnce() + Send + 'static,
+    ) {
+        let due = Instant::now() + delay;
+        self.queue(|queue| queue.delayed.push((due, Box::new(job))));
+    }
+
+    fn queue(&self, add: impl FnOnce(&mut Queue)) {
+        let shared = &self.0.shared;
+        let mut queue = shared.queue.lock().unwrap();
+        add(&mut queue);
+        if queue.idle == 0 {
+            if queue.workers < self.0.size.get() {
+                queue.workers += 1;
+                let shared = shared.clone();
+                thread::spawn(move || work(&shared));
+            } else {
+                event!(
+                    Level::DEBUG,
+                    "{} action(s) waiting for one of {} workers",
+                    queue.jobs.len(),
+                    self.0.size
+                );
+            }
+        }
+        shared.ready.notify_one();
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.ready.notify_all();
+    }
+}
+
+impl core::fmt::Debug for Pool {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Pool").field("size", &self.0.size).finish()
+    }
+}
+
+/// A worker, running jobs as they come due until the pool is closed and
+/// there are none left
+fn work(shared: &Shared) {
+    let mut queue = shared.queue.lock().unwrap();
+    loop {
+        let now = Instant::now();
+        let mut i = 0;
+        while i < queue.delayed.len() {
+            if queue.delayed[i].0 <= now {
+                let (_, job) = queue.delayed.swap_remove(i);
+                queue.jobs.push_back(job);
+            } else {
+                i += 1;
+            }
+        }
+        if let Some(job) = queue.jobs.pop_front() {
+            drop(queue);
+            // Keep the worker for the next job if an action panics
+            if catch_unwind(AssertUnwindSafe(job)).is_err() {
+                event!(Level::ERROR, "An action panicked");
+            }
+            queue = shared.queue.lock().unwrap();
+            continue;
+        }
+        if queue.closed && queue.delayed.is_empty() {
+            queue.workers -= 1;
+            return;
+        }
+        let due = queue.delayed.iter().map(|(due, _)| *due).min();
+        queue.idle += 1;
+        queue = match due {
+            Some(due) => {
+                let timeout = due.saturating_duration_since(now);
+                shared.ready.wait_timeout(queue, timeout).unwrap().0
+            }
+            None => shared.ready.wait(queue).unwrap(),
+        };
+        queue.idle -= 1;
+    }
+}
@@ -0,0 +1,67 @@
+//! What kind of sleep a Resume came back from and for how long, the power
+//! broadcast itself doesn't say
+use crate::wynapi::{evt_query_latest, last_sleep_and_wake};
+use std::time::Duration;
+
+const CHANNEL: &str = "System";
+/// Kernel-Power logs 42 as the machine enters sleep or hibernation
+const QUERY: &str =
+    "*[System[Provider[@Name='Microsoft-Windows-Kernel-Power'] and \
+     EventID=42]]";
+/// The values rendered for the event, in this order
+const PATHS: [&str; 2] = [
+    "Event/EventData/Data[@Name='EffectiveState']",
+    "Event/EventData/Data[@Name='TargetState']",
+];
+
+/// The SYSTEM_POWER_STATE a Resume came back from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum SleepState {
+    /// S1 to S3, memory stayed powered
+    Sleep,
+    /// S4, memory was written to disk and the machine powered off
+    Hibernate,
+    /// Any other SYSTEM_POWER_STATE
+    Other(u32),
+}
+
+impl From<u32> for SleepState {
+    fn from(state: u32) -> Self {
+        match state {
+            2..=4 => Self::Sleep,
+            5 => Self::Hibernate,
+            _ => Self::Other(state),
+        }
+    }
+}
+
+/// What the machine did before a Resume, attached to Resume events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sleep {
+    /// Read from the System event log, `None` if it couldn't be
+    pub state: Option<SleepState>,
+    /// How long the machine was asleep, `None` if it couldn't be told
+    pub duration: Option<Duration>,
+}
+
+impl Sleep {
+    /// Look up the sleep the machine just woke from. The state that took
+    /// effect wins over the one asked for, as a hybrid sleep can end up
+    /// hibernating
+    pub(crate) fn query() -> Self {
+        let state = evt_query_latest(CHANNEL, QUERY, &PATHS)
+            .ok()
+            .flatten()
+            .and_then(|values| values.into_iter().flatten().next())
+            .map(|state| SleepState::from(state as u32));
+        // Interrupt time keeps counting while asleep, in 100ns units
+        let duration = last_sleep_and_wake().ok().and_then(|(sleep, wake)| {
+            let asleep = wake.checked_sub(sleep)?;
+            (sleep != 0).then(|| Duration::from_nanos(asleep * 100))
+        });
+        Self { state, duration }
+    }
+}
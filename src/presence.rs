@@ -0,0 +1,90 @@
+//! Whether the user is at the machine, worked out from the lock state, the
+//! idle time and the screensaver
+use crate::wynapi::WtsState;
+use std::time::Duration;
+
+/// Where the user is as far as we can tell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Presence {
+    Present,
+    /// Idle for longer than the threshold or the screensaver is running,
+    /// but not locked yet
+    Away,
+    Locked,
+}
+
+impl Presence {
+    /// The [`WtsState`] a transition into this presence is emitted as
+    pub fn state(&self) -> WtsState {
+        match self {
+            Self::Present => WtsState::Present,
+            Self::Away => WtsState::Away,
+            Self::Locked => WtsState::Locked,
+        }
+    }
+}
+
+/// The inputs to the presence state machine, fed by the monitor
+#[derive(Debug)]
+pub(crate) struct PresenceTracker {
+    current: Presence,
+    threshold: Duration,
+    locked: bool,
+    screensaver: bool,
+    idle: bool,
+}
+
+impl PresenceTracker {
+    pub fn new(threshold: Duration, locked: bool, screensaver: bool) -> Self {
+        let mut tracker = Self {
+            current: Presence::Present,
+            threshold,
+            locked,
+            screensaver,
+            idle: false,
+        };
+        tracker.current = tracker.presence();
+        tracker
+    }
+
+    pub fn current(&self) -> Presence {
+        self.current
+    }
+
+    /// Feed an event for our session, returns the new presence on a change
+    pub fn on_state(&mut self, state: WtsState) -> Option<Presence> {
+        match state {
+            WtsState::Lock => self.locked = true,
+            WtsState::Unlock => self.locked = false,
+            WtsState::ScreensaverStart => self.screensaver = true,
+            WtsState::ScreensaverStop => self.screensaver = false,
+            _ => return None,
+        }
+        self.transition()
+    }
+
+    /// Feed a fresh idle time, returns the new presence on a change
+    pub fn on_idle(&mut self, idle: Duration) -> Option<Presence> {
+        self.idle = idle >= self.threshold;
+        self.transition()
+    }
+
+    fn presence(&self) -> Presence {
+        if self.locked {
+            Presence::Locked
+        } else if self.screensaver || self.idle {
+            Presence::Away
+        } else {
+            Presence::Present
+        }
+    }
+
+    fn transition(&mut self) -> Option<Presence> {
+        let presence = self.presence();
+        (presence != self.current).then(|| {
+            self.current = presence;
+            presence
+        })
+    }
+}
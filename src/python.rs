@@ -0,0 +1,162 @@
+//! Python bindings, enabled with the `pyo3` feature. Build the cdylib and
+//! rename it to `rusty_lock.pyd` to `import rusty_lock`
+use crate::{
+    MonitorHandle, SessionEvent, SessionMonitorBuilder, SessionScope,
+    StopHandle, WtsState,
+};
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use std::sync::mpsc::Receiver;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/// A session event, `state` is a name such as `"lock"`
+#[pyclass(name = "Event", frozen, get_all)]
+struct PyEvent {
+    state: &'static str,
+    session_id: u32,
+    /// Seconds since the UNIX epoch
+    timestamp: f64,
+}
+
+impl From<&SessionEvent> for PyEvent {
+    fn from(event: &SessionEvent) -> Self {
+        Self {
+            state: event.state.name(),
+            session_id: event.session_id,
+            timestamp: event
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |since| since.as_secs_f64()),
+        }
+    }
+}
+
+/// Blocking iterator over session events, returned by `events()`
+#[pyclass(name = "Events")]
+struct PyEvents {
+    rx: Mutex<Receiver<SessionEvent>>,
+    handle: MonitorHandle,
+}
+
+impl PyEvents {
+    fn spawn(scope: SessionScope) -> PyResult<Self> {
+        let (rx, handle) = SessionMonitorBuilder::new()
+            .scope(scope)
+            .spawn()
+            .map_err(|err| PyOSError::new_err(err.to_string()))?;
+        Ok(Self {
+            rx: Mutex::new(rx),
+            handle,
+        })
+    }
+
+    fn next_event(&self, py: Python<'_>) -> Option<SessionEvent> {
+        // Let other Python threads run while we wait on the monitor thread
+        let rx = &self.rx;
+        py.detach(|| rx.lock().unwrap().recv().ok())
+    }
+}
+
+#[pymethods]
+impl PyEvents {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> Option<PyEvent> {
+        self.next_event(py).as_ref().map(PyEvent::from)
+    }
+}
+
+impl Drop for PyEvents {
+    fn drop(&mut self) {
+        let _ = self.handle.stop();
+    }
+}
+
+/// Runs callbacks registered per state with `on`
+#[pyclass(name = "Monitor")]
+struct PyMonitor {
+    scope: SessionScope,
+    callbacks: Mutex<Vec<(WtsState, Py<PyAny>)>>,
+    stop: Mutex<Option<StopHandle>>,
+}
+
+#[pymethods]
+impl PyMonitor {
+    /// `all_sessions` receives events for every session on the machine
+    #[new]
+    #[pyo3(signature = (all_sessions = false))]
+    fn new(all_sessions: bool) -> Self {
+        Self {
+            scope: SessionScope::from_all_sessions(all_sessions),
+            callbacks: Mutex::new(Vec::new()),
+            stop: Mutex::new(None),
+        }
+    }
+
+    /// Call `callback(event)` for every event with the named state
+    fn on(&self, state: &str, callback: Py<PyAny>) -> PyResult<()> {
+        let state = state.parse().map_err(|()| {
+            PyValueError::new_err(format!("{state} is not a valid state"))
+        })?;
+        self.callbacks.lock().unwrap().push((state, callback));
+        Ok(())
+    }
+
+    /// Block running callbacks until `stop()` is called from another thread
+    /// or a callback raises
+    fn run(&self, py: Python<'_>) -> PyResult<()> {
+        let events = PyEvents::spawn(self.scope)?;
+        *self.stop.lock().unwrap() = Some(events.handle.stop_handle());
+
+        let res = loop {
+            let Some(event) = events.next_event(py) else {
+                break Ok(());
+            };
+            // Copy the callbacks out so they are free to call `on`
+            let callbacks: Vec<_> = self
+                .callbacks
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(state, _)| *state == event.state)
+                .map(|(_, callback)| callback.clone_ref(py))
+                .collect();
+            if let Err(err) = callbacks.iter().try_for_each(|callback| {
+                callback.call1(py, (PyEvent::from(&event),)).map(drop)
+            }) {
+                break Err(err);
+            }
+        };
+
+        // Dropping `events` stops the monitor thread
+        *self.stop.lock().unwrap() = None;
+        res
+    }
+
+    /// End a running `run()`
+    fn stop(&self) -> PyResult<()> {
+        match self.stop.lock().unwrap().as_ref() {
+            Some(stop) => stop
+                .stop()
+                .map_err(|err| PyOSError::new_err(err.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Iterate over session events as they arrive, `all_sessions` receives
+/// events for every session on the machine
+#[pyfunction]
+#[pyo3(signature = (all_sessions = false))]
+fn events(all_sessions: bool) -> PyResult<PyEvents> {
+    PyEvents::spawn(SessionScope::from_all_sessions(all_sessions))
+}
+
+#[pymodule]
+mod rusty_lock {
+    #[pymodule_export]
+    use super::{events, PyEvent, PyEvents, PyMonitor};
+}
@@ -0,0 +1,105 @@
+//! Hand each session's events only to the subscribers of that session or its
+//! user, so a terminal server with many users needs no single flat stream
+use crate::spawn::spawn_pump;
+use crate::{
+    Error, MonitorHandle, SessionEvent, SessionMonitorBuilder, SessionScope,
+};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Subscribers keyed by session id and by lowercased user name
+#[derive(Debug, Default)]
+struct Routes {
+    sessions: HashMap<u32, Vec<Sender<SessionEvent>>>,
+    users: HashMap<String, Vec<Sender<SessionEvent>>>,
+}
+
+/// Routes each published event to the subscribers of its session id and of
+/// its [`SessionEvent::user`], looked up rather than offered to every
+/// subscriber. Cheap to clone and can be shared with other threads,
+/// subscribers that hang up are dropped when their next event is routed
+#[derive(Debug, Clone, Default)]
+pub struct SessionRouter {
+    routes: Arc<Mutex<Routes>>,
+}
+
+impl SessionRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive the events of session `session_id` published from now on
+    pub fn subscribe_session(&self, session_id: u32) -> Receiver<SessionEvent> {
+        let (tx, rx) = channel();
+        let mut routes = self.routes.lock().unwrap();
+        routes.sessions.entry(session_id).or_default().push(tx);
+        rx
+    }
+
+    /// Receive the events of every session `user` is logged on to, by user
+    /// name or as `DOMAIN\user`, ignoring case. Needs
+    /// [`SessionScope::AllSessions`] for events to carry their user
+    pub fn subscribe_user(&self, user: &str) -> Receiver<SessionEvent> {
+        let (tx, rx) = channel();
+        let mut routes = self.routes.lock().unwrap();
+        routes
+            .users
+            .entry(user.to_lowercase())
+            .or_default()
+            .push(tx);
+        rx
+    }
+
+    /// Send `event` to the subscribers of its session and its user
+    pub fn publish(&self, event: SessionEvent) {
+        let mut routes = self.routes.lock().unwrap();
+        let Routes { sessions, users } = &mut *routes;
+
+        send(sessions, &event.session_id, &event);
+        if let Some(user) = &event.user {
+            send(users, &user.user_name.to_lowercase(), &event);
+            if !user.domain.is_empty() {
+                send(users, &user.to_string().to_lowercase(), &event);
+            }
+        }
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        let routes = self.routes.lock().unwrap();
+        routes.sessions.values().map(Vec::len).sum::<usize>()
+            + routes.users.values().map(Vec::len).sum::<usize>()
+    }
+}
+
+/// Send `event` to everyone subscribed under `key`, dropping the key once
+/// they have all hung up
+fn send<K: Eq + std::hash::Hash>(
+    routes: &mut HashMap<K, Vec<Sender<SessionEvent>>>,
+    key: &K,
+    event: &SessionEvent,
+) {
+    let Some(subscribers) = routes.get_mut(key) else {
+        return;
+    };
+    subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    if subscribers.is_empty() {
+        routes.remove(key);
+    }
+}
+
+impl SessionMonitorBuilder {
+    /// Spawn a monitor thread for [`SessionScope::AllSessions`] that routes
+    /// every event through the returned router, subscribers can be added at
+    /// any time
+    pub fn spawn_router(self) -> Result<(SessionRouter, MonitorHandle), Error> {
+        let router = SessionRouter::new();
+        let publisher = router.clone();
+        let handle =
+            spawn_pump(self.scope(SessionScope::AllSessions), move |event| {
+                publisher.publish(event);
+                true
+            })?;
+        Ok((router, handle))
+    }
+}
@@ -0,0 +1,142 @@
+//! Cron like schedules for time driven events, checked against the local
+//! time on the monitor's own timer
+use crate::wynapi::{local_time, SYSTEMTIME};
+use core::str::FromStr;
+
+/// When a Scheduled event fires, parsed from the five fields of a cron
+/// expression: minute, hour, day of month, month and day of week, such as
+/// `0 19 * * 1-5` for 19:00 on weekdays. Each field takes `*`, numbers,
+/// ranges like `1-5`, steps like `*/15` or `8-18/2` and lists of those
+/// joined with commas. Sunday is 0 or 7. As in cron, when both days are
+/// restricted either one matching is enough
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Schedule {
+    /// The expression it was parsed from
+    pub expression: String,
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// Whether the day of month or day of week field was `*`
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    /// Whether the minute `time` falls in is one of ours
+    pub(crate) fn matches(&self, time: &SYSTEMTIME) -> bool {
+        let day = self.days & (1 << time.wDay) != 0;
+        let weekday = self.weekdays & (1 << time.wDayOfWeek) != 0;
+        let day = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        self.minutes & (1 << time.wMinute) != 0
+            && self.hours & (1 << time.wHour) != 0
+            && self.months & (1 << time.wMonth) != 0
+            && day
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = ();
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(());
+        };
+        // 7 is Sunday too
+        let weekday_bits = field(weekdays, 0, 7)?;
+        Ok(Self {
+            expression: fields.join(" "),
+            minutes: field(minutes, 0, 59)?,
+            hours: field(hours, 0, 23)? as u32,
+            days: field(days, 1, 31)? as u32,
+            months: field(months, 1, 12)? as u16,
+            weekdays: ((weekday_bits | weekday_bits >> 7) & 0x7F) as u8,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+impl core::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+/// The bits of the values a cron field allows, between `min` and `max`
+fn field(field: &str, min: u32, max: u32) -> Result<u64, ()> {
+    let number = |n: &str| match n.parse() {
+        Ok(n) if (min..=max).contains(&n) => Ok(n),
+        _ => Err(()),
+    };
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().map_err(|_| ())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(());
+        }
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // A step from a single value runs to the end, as in cron
+                None if step > 1 => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(());
+        }
+        for value in (start..=end).step_by(step) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// Works out which schedules are due as the minutes go by
+#[derive(Debug)]
+pub(crate) struct ScheduleTracker {
+    schedules: Vec<Schedule>,
+    /// The minute last checked, as year, month, day, hour and minute
+    last: (u16, u16, u16, u16, u16),
+}
+
+impl ScheduleTracker {
+    /// Starts from the current minute, so a schedule for it doesn't fire
+    pub fn new(schedules: Vec<Schedule>) -> Self {
+        Self {
+            schedules,
+            last: minute(&local_time()),
+        }
+    }
+
+    /// Check the clock, returns the schedules due if a new minute started.
+    /// Minutes skipped over while asleep or by a clock change are not made
+    /// up for
+    pub fn on_tick(&mut self) -> Vec<Schedule> {
+        let now = local_time();
+        if minute(&now) == self.last {
+            return Vec::new();
+        }
+        self.last = minute(&now);
+        self.schedules
+            .iter()
+            .filter(|schedule| schedule.matches(&now))
+            .cloned()
+            .collect()
+    }
+}
+
+fn minute(time: &SYSTEMTIME) -> (u16, u16, u16, u16, u16) {
+    (time.wYear, time.wMonth, time.wDay, time.wHour, time.wMinute)
+}
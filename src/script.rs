@@ -0,0 +1,85 @@
+//! Rhai scripts run as actions, for logic too involved for the config
+use crate::actions;
+use rhai::{Dynamic, Engine, ImmutableString, Map, Scope, INT};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{event, Level};
+
+/// What scripts keep between runs, until we exit
+static STORE: Mutex<BTreeMap<String, Dynamic>> = Mutex::new(BTreeMap::new());
+
+/// The `store` scripts see, indexed by key. Missing keys read as `()`
+#[derive(Debug, Clone, Copy)]
+struct Store;
+
+impl Store {
+    fn get(&mut self, key: ImmutableString) -> Dynamic {
+        let store = STORE.lock().unwrap();
+        store.get(key.as_str()).cloned().unwrap_or(Dynamic::UNIT)
+    }
+
+    fn set(&mut self, key: ImmutableString, value: Dynamic) {
+        STORE.lock().unwrap().insert(key.to_string(), value);
+    }
+
+    fn remove(&mut self, key: ImmutableString) -> Dynamic {
+        let removed = STORE.lock().unwrap().remove(key.as_str());
+        removed.unwrap_or(Dynamic::UNIT)
+    }
+}
+
+/// Run the script at `path` with the event's placeholders as `event`, its
+/// output if it didn't throw: what it evaluates to, empty for `()`
+pub(crate) fn run(path: &Path, variables: &[(&str, String)]) -> Option<String> {
+    let mut engine = Engine::new();
+    engine
+        .register_type_with_name::<Store>("Store")
+        .register_indexer_get_set(Store::get, Store::set)
+        .register_fn("remove", Store::remove)
+        .register_fn("exec", exec)
+        .on_print(|text| event!(Level::INFO, "{text}"))
+        .on_debug(|text, _, position| {
+            event!(Level::DEBUG, "{position:?} {text}")
+        });
+
+    let mut event = Map::new();
+    for (name, value) in variables {
+        // The numbers are more use as numbers
+        let value = match value.parse::<INT>() {
+            Ok(number) if matches!(*name, "session_id" | "timestamp") => {
+                number.into()
+            }
+            _ => value.clone().into(),
+        };
+        event.insert((*name).into(), value);
+    }
+    let mut scope = Scope::new();
+    scope.push_constant("event", event);
+    scope.push("store", Store);
+
+    match engine.eval_file_with_scope::<Dynamic>(&mut scope, path.into()) {
+        Ok(value) if value.is_unit() => Some(String::new()),
+        Ok(value) => Some(value.to_string()),
+        Err(err) => {
+            event!(Level::ERROR, "{} failed {err}", path.display());
+            None
+        }
+    }
+}
+
+/// `exec(command)` for scripts, runs `cmd /C command` to completion and
+/// returns its exit `code` and `output`, -1 if it couldn't start
+fn exec(command: &str) -> Map {
+    let (code, output) = match actions::exec("script", command) {
+        Ok((code, output)) => (code as INT, output),
+        Err(err) => {
+            event!(Level::ERROR, "Failed to start {command} {err}");
+            (-1, String::new())
+        }
+    };
+    let mut result = Map::new();
+    result.insert("code".into(), code.into());
+    result.insert("output".into(), output.into());
+    result
+}
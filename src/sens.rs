@@ -0,0 +1,433 @@
+//! The System Event Notification Service as a source of events, through
+//! COM+ event subscriptions to ISensLogon and ISensLogon2
+#![allow(non_snake_case)]
+use crate::backend::Inbox;
+use crate::wynapi::*;
+use crate::SessionEvent;
+use core::ffi::{c_int, c_void};
+use core::ptr::null_mut;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tracing::{event, Level};
+
+const CLSID_CEVENTSYSTEM: GUID = GUID {
+    Data1: 0x4e14fba2,
+    Data2: 0x2e22,
+    Data3: 0x11d1,
+    Data4: [0x99, 0x64, 0x00, 0xc0, 0x4f, 0xbb, 0xb3, 0x45],
+};
+const IID_IEVENTSYSTEM: GUID = GUID {
+    Data1: 0x4e14fb9f,
+    Data2: 0x2e22,
+    Data3: 0x11d1,
+    Data4: [0x99, 0x64, 0x00, 0xc0, 0x4f, 0xbb, 0xb3, 0x45],
+};
+const CLSID_CEVENTSUBSCRIPTION: GUID = GUID {
+    Data1: 0x7542e960,
+    Data2: 0x79c7,
+    Data3: 0x11d1,
+    Data4: [0x88, 0xf9, 0x00, 0x80, 0xc7, 0xd7, 0x71, 0xbf],
+};
+const IID_IEVENTSUBSCRIPTION: GUID = GUID {
+    Data1: 0x4a6b0e15,
+    Data2: 0x2e38,
+    Data3: 0x11d1,
+    Data4: [0x99, 0x65, 0x00, 0xc0, 0x4f, 0xbb, 0xb3, 0x45],
+};
+const SENSGUID_EVENTCLASS_LOGON: GUID = GUID {
+    Data1: 0xd5978630,
+    Data2: 0x5b9f,
+    Data3: 0x11d1,
+    Data4: [0x8d, 0xd2, 0x00, 0xaa, 0x00, 0x4a, 0xbd, 0x5e],
+};
+const SENSGUID_EVENTCLASS_LOGON2: GUID = GUID {
+    Data1: 0xd5978650,
+    Data2: 0x5b9f,
+    Data3: 0x11d1,
+    Data4: [0x8d, 0xd2, 0x00, 0xaa, 0x00, 0x4a, 0xbd, 0x5e],
+};
+const IID_ISENSLOGON: GUID = GUID {
+    Data1: 0xd597bab3,
+    Data2: 0x5b9f,
+    Data3: 0x11d1,
+    Data4: [0x8d, 0xd2, 0x00, 0xaa, 0x00, 0x4a, 0xbd, 0x5e],
+};
+const IID_ISENSLOGON2: GUID = GUID {
+    Data1: 0xd597bab4,
+    Data2: 0x5b9f,
+    Data3: 0x11d1,
+    Data4: [0x8d, 0xd2, 0x00, 0xaa, 0x00, 0x4a, 0xbd, 0x5e],
+};
+const PROGID_EVENTSUBSCRIPTION: &str = "EventSystem.EventSubscription";
+
+type Put = unsafe extern "system" fn(this: LPVOID, value: BSTR) -> HRESULT;
+
+#[repr(C)]
+struct IEventSystemVtbl {
+    base: IDispatchVtbl,
+    Query: usize,
+    Store: unsafe extern "system" fn(
+        this: LPVOID,
+        ProgID: BSTR,
+        pInterface: LPVOID,
+    ) -> HRESULT,
+    Remove: unsafe extern "system" fn(
+        this: LPVOID,
+        progID: BSTR,
+        queryCriteria: BSTR,
+        errorIndex: *mut c_int,
+    ) -> HRESULT,
+}
+
+/// Only the setters we use are typed
+#[repr(C)]
+struct IEventSubscriptionVtbl {
+    base: IDispatchVtbl,
+    get_SubscriptionID: usize,
+    put_SubscriptionID: Put,
+    get_SubscriptionName: usize,
+    put_SubscriptionName: Put,
+    get_PublisherID: usize,
+    put_PublisherID: usize,
+    get_EventClassID: usize,
+    put_EventClassID: Put,
+    get_MethodName: usize,
+    put_MethodName: usize,
+    get_SubscriberCLSID: usize,
+    put_SubscriberCLSID: usize,
+    get_SubscriberInterface: usize,
+    put_SubscriberInterface:
+        unsafe extern "system" fn(this: LPVOID, value: LPVOID) -> HRESULT,
+}
+
+type Notify = unsafe extern "system" fn(this: LPVOID, user: BSTR) -> HRESULT;
+type Notify2 = unsafe extern "system" fn(
+    this: LPVOID,
+    user: BSTR,
+    session_id: DWORD,
+) -> HRESULT;
+
+#[repr(C)]
+struct ISensLogonVtbl {
+    base: IDispatchVtbl,
+    Logon: Notify,
+    Logoff: Notify,
+    StartShell: Notify,
+    DisplayLock: Notify,
+    DisplayUnlock: Notify,
+    StartScreenSaver: Notify,
+    StopScreenSaver: Notify,
+}
+
+#[repr(C)]
+struct ISensLogon2Vtbl {
+    base: IDispatchVtbl,
+    Logon: Notify2,
+    Logoff: Notify2,
+    SessionDisconnect: Notify2,
+    SessionReconnect: Notify2,
+    PostShell: Notify2,
+}
+
+/// Our COM object, handed to the event system as the subscriber
+#[repr(C)]
+struct Sink {
+    vtbl: *const c_void,
+    iid: GUID,
+    refs: AtomicU32,
+    inbox: Inbox,
+    /// Ours, ISensLogon doesn't say which session it is about
+    session_id: u32,
+    all_sessions: bool,
+}
+
+const DISPATCH: IDispatchVtbl = IDispatchVtbl {
+    base: IUnknownVtbl {
+        QueryInterface: query_interface,
+        AddRef: add_ref,
+        Release: release,
+    },
+    GetTypeInfoCount: get_type_info_count,
+    GetTypeInfo: get_type_info,
+    GetIDsOfNames: get_ids_of_names,
+    Invoke: invoke,
+};
+
+static SENS_LOGON: ISensLogonVtbl = ISensLogonVtbl {
+    base: DISPATCH,
+    Logon: ignore,
+    Logoff: ignore,
+    StartShell: ignore,
+    DisplayLock: display_lock,
+    DisplayUnlock: display_unlock,
+    StartScreenSaver: start_screen_saver,
+    StopScreenSaver: stop_screen_saver,
+};
+
+static SENS_LOGON2: ISensLogon2Vtbl = ISensLogon2Vtbl {
+    base: DISPATCH,
+    Logon: logon,
+    Logoff: logoff,
+    SessionDisconnect: ignore2,
+    SessionReconnect: ignore2,
+    PostShell: ignore2,
+};
+
+unsafe extern "system" fn query_interface(
+    this: LPVOID,
+    riid: *const GUID,
+    ppv: *mut LPVOID,
+) -> HRESULT {
+    let sink = &*(this as *const Sink);
+    let riid = &*riid;
+    if *riid == IID_IUNKNOWN || *riid == IID_IDISPATCH || *riid == sink.iid {
+        add_ref(this);
+        *ppv = this;
+        return S_OK;
+    }
+    *ppv = null_mut();
+    E_NOINTERFACE
+}
+
+unsafe extern "system" fn add_ref(this: LPVOID) -> u32 {
+    let sink = &*(this as *const Sink);
+    sink.refs.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+unsafe extern "system" fn release(this: LPVOID) -> u32 {
+    let refs = (*(this as *const Sink)).refs.fetch_sub(1, Ordering::AcqRel) - 1;
+    if refs == 0 {
+        drop(Box::from_raw(this as *mut Sink));
+    }
+    refs
+}
+
+unsafe extern "system" fn get_type_info_count(
+    _this: LPVOID,
+    count: *mut u32,
+) -> HRESULT {
+    *count = 0;
+    S_OK
+}
+
+unsafe extern "system" fn get_type_info(
+    _this: LPVOID,
+    _index: u32,
+    _lcid: u32,
+    _info: *mut LPVOID,
+) -> HRESULT {
+    E_NOTIMPL
+}
+
+unsafe extern "system" fn get_ids_of_names(
+    _this: LPVOID,
+    _riid: *const GUID,
+    _names: *mut LPWSTR,
+    _count: u32,
+    _lcid: u32,
+    _ids: *mut i32,
+) -> HRESULT {
+    E_NOTIMPL
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn invoke(
+    _this: LPVOID,
+    _id: i32,
+    _riid: *const GUID,
+    _lcid: u32,
+    _flags: u16,
+    _params: LPVOID,
+    _result: LPVOID,
+    _exception: LPVOID,
+    _arg_err: *mut u32,
+) -> HRESULT {
+    E_NOTIMPL
+}
+
+/// Queue `state` for `session_id` if the sink's scope covers it
+unsafe fn notify(this: LPVOID, state: WtsState, session_id: u32) -> HRESULT {
+    let sink = &*(this as *const Sink);
+    if sink.all_sessions || session_id == sink.session_id {
+        sink.inbox.push(SessionEvent::new(state, session_id));
+    }
+    S_OK
+}
+
+unsafe extern "system" fn ignore(_this: LPVOID, _user: BSTR) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn ignore2(
+    _this: LPVOID,
+    _user: BSTR,
+    _session_id: DWORD,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn display_lock(this: LPVOID, _user: BSTR) -> HRESULT {
+    notify(this, WtsState::Lock, (*(this as *const Sink)).session_id)
+}
+
+unsafe extern "system" fn display_unlock(this: LPVOID, _user: BSTR) -> HRESULT {
+    notify(this, WtsState::Unlock, (*(this as *const Sink)).session_id)
+}
+
+unsafe extern "system" fn start_screen_saver(
+    this: LPVOID,
+    _user: BSTR,
+) -> HRESULT {
+    let session_id = (*(this as *const Sink)).session_id;
+    notify(this, WtsState::ScreensaverStart, session_id)
+}
+
+unsafe extern "system" fn stop_screen_saver(
+    this: LPVOID,
+    _user: BSTR,
+) -> HRESULT {
+    let session_id = (*(this as *const Sink)).session_id;
+    notify(this, WtsState::ScreensaverStop, session_id)
+}
+
+unsafe extern "system" fn logon(
+    this: LPVOID,
+    _user: BSTR,
+    session_id: DWORD,
+) -> HRESULT {
+    notify(this, WtsState::Logon, session_id)
+}
+
+unsafe extern "system" fn logoff(
+    this: LPVOID,
+    _user: BSTR,
+    session_id: DWORD,
+) -> HRESULT {
+    notify(this, WtsState::Logoff, session_id)
+}
+
+/// Subscriptions to the SENS logon event classes, removed on drop. Lock and
+/// unlock come from ISensLogon, logon and logoff with their session from
+/// ISensLogon2
+pub(crate) struct SensSubscription {
+    system: LPVOID,
+    subscriptions: Vec<String>,
+    // Released after the subscriptions are removed
+    _com: ComApartment,
+}
+
+impl SensSubscription {
+    pub fn start(scope: SessionScope, inbox: Inbox) -> Result<Self, Error> {
+        let com = ComApartment::enter()?;
+        let system =
+            co_create_instance(&CLSID_CEVENTSYSTEM, &IID_IEVENTSYSTEM)?;
+        let mut subscription = Self {
+            system,
+            subscriptions: Vec::new(),
+            _com: com,
+        };
+        let session_id = current_session_id()?;
+        let all_sessions = scope == SessionScope::AllSessions;
+        for (class, iid, vtbl) in [
+            (
+                SENSGUID_EVENTCLASS_LOGON,
+                IID_ISENSLOGON,
+                &SENS_LOGON as *const _ as *const c_void,
+            ),
+            (
+                SENSGUID_EVENTCLASS_LOGON2,
+                IID_ISENSLOGON2,
+                &SENS_LOGON2 as *const _ as *const c_void,
+            ),
+        ] {
+            let sink = Box::into_raw(Box::new(Sink {
+                vtbl,
+                iid,
+                refs: AtomicU32::new(1),
+                inbox: inbox.clone(),
+                session_id,
+                all_sessions,
+            }));
+            // The event system takes its own reference if it keeps the sink
+            let res = unsafe { subscription.subscribe(&class, sink.cast()) };
+            unsafe { release(sink.cast()) };
+            subscription.subscriptions.push(res?);
+        }
+        Ok(subscription)
+    }
+
+    /// Store a subscription of `sink` to `class`, returns its ID
+    unsafe fn subscribe(
+        &self,
+        class: &GUID,
+        sink: LPVOID,
+    ) -> Result<String, Error> {
+        let sub = co_create_instance(
+            &CLSID_CEVENTSUBSCRIPTION,
+            &IID_IEVENTSUBSCRIPTION,
+        )?;
+        let vtbl = &**(sub as *const *const IEventSubscriptionVtbl);
+        let id = co_create_guid()?.to_braced_string();
+        let res = check_hresult(
+            "put_SubscriptionID",
+            (vtbl.put_SubscriptionID)(sub, Bstr::new(&id).as_raw()),
+        )
+        .and_then(|_| {
+            check_hresult(
+                "put_SubscriptionName",
+                (vtbl.put_SubscriptionName)(
+                    sub,
+                    Bstr::new("rusty-lock").as_raw(),
+                ),
+            )
+        })
+        .and_then(|_| {
+            check_hresult(
+                "put_EventClassID",
+                (vtbl.put_EventClassID)(
+                    sub,
+                    Bstr::new(&class.to_braced_string()).as_raw(),
+                ),
+            )
+        })
+        .and_then(|_| {
+            check_hresult(
+                "put_SubscriberInterface",
+                (vtbl.put_SubscriberInterface)(sub, sink),
+            )
+        })
+        .and_then(|_| {
+            let system = &**(self.system as *const *const IEventSystemVtbl);
+            let progid = Bstr::new(PROGID_EVENTSUBSCRIPTION);
+            check_hresult(
+                "IEventSystem::Store",
+                (system.Store)(self.system, progid.as_raw(), sub),
+            )
+        });
+        (vtbl.base.base.Release)(sub);
+        res?;
+        event!(Level::INFO, "SENS subscription {id}");
+        Ok(id)
+    }
+}
+
+impl Drop for SensSubscription {
+    fn drop(&mut self) {
+        let system =
+            unsafe { &**(self.system as *const *const IEventSystemVtbl) };
+        let progid = Bstr::new(PROGID_EVENTSUBSCRIPTION);
+        for id in &self.subscriptions {
+            let query = Bstr::new(&format!("SubscriptionID={id}"));
+            let mut index = 0;
+            let hr = unsafe {
+                (system.Remove)(
+                    self.system,
+                    progid.as_raw(),
+                    query.as_raw(),
+                    &mut index,
+                )
+            };
+            let _ = check_hresult("IEventSystem::Remove", hr);
+        }
+        unsafe { (system.base.base.Release)(self.system) };
+    }
+}
@@ -0,0 +1,59 @@
+//! The Remote Desktop Session Host that session queries run against
+use crate::wynapi::*;
+
+/// A handle to the local machine or a remote RDS host opened with
+/// WTSOpenServerW, closed on drop.
+///
+/// Session notifications are only delivered for the local machine, a remote
+/// server can only be queried
+#[derive(Debug)]
+pub struct WtsServer {
+    handle: HANDLE,
+    name: Option<String>,
+}
+
+// WTS server handles are not tied to the thread that opened them
+unsafe impl Send for WtsServer {}
+
+impl WtsServer {
+    /// The machine we are running on
+    pub fn local() -> Self {
+        Self {
+            handle: WTS_CURRENT_SERVER_HANDLE,
+            name: None,
+        }
+    }
+
+    /// Open the RDS host called `name`, eg. `"rds01"` or its NetBIOS name
+    pub fn open(name: &str) -> Result<Self, Error> {
+        let handle = wts_open_server_w(name)?;
+        Ok(Self {
+            handle,
+            name: Some(name.into()),
+        })
+    }
+
+    /// The remote host name, `None` for the local machine
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The raw server handle, for passing to other WTS functions
+    pub fn handle(&self) -> HANDLE {
+        self.handle
+    }
+}
+
+impl Default for WtsServer {
+    fn default() -> Self {
+        Self::local()
+    }
+}
+
+impl Drop for WtsServer {
+    fn drop(&mut self) {
+        if self.name.is_some() {
+            wts_close_server(self.handle);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Querying the sessions that exist on a machine, so consumers can establish
+//! the current state before any notification arrives
+use crate::wynapi::*;
+use crate::WtsServer;
+use std::net::IpAddr;
+
+/// One session as returned by [`enumerate_sessions`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Session {
+    pub id: u32,
+    pub state: ConnectState,
+    /// The WinStation name, eg. `Console`, `RDP-Tcp#3` or `Services`
+    pub station_name: String,
+}
+
+/// Every session on the local machine
+pub fn enumerate_sessions() -> Result<Vec<Session>, Error> {
+    WtsServer::local().enumerate_sessions()
+}
+
+impl WtsServer {
+    /// Every session on this server
+    pub fn enumerate_sessions(&self) -> Result<Vec<Session>, Error> {
+        wts_enumerate_sessions_w(self.handle())
+    }
+}
+
+/// How a session is connected, WTSClientProtocolType
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Protocol {
+    Console,
+    Ica,
+    Rdp,
+    /// A protocol this version does not know about yet
+    Unknown(u16),
+}
+
+impl From<u16> for Protocol {
+    fn from(protocol: u16) -> Self {
+        match protocol {
+            0 => Self::Console,
+            1 => Self::Ica,
+            2 => Self::Rdp,
+            _ => Self::Unknown(protocol),
+        }
+    }
+}
+
+/// Who a session belongs to and where it is connected from
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionInfo {
+    /// Empty when nobody is logged on
+    pub user_name: String,
+    pub domain: String,
+    /// The remote client's machine name, empty for the console
+    pub client_name: String,
+    /// The remote client's address, `None` for the console
+    pub client_address: Option<IpAddr>,
+    pub protocol: Protocol,
+}
+
+/// Query [`SessionInfo`] for a session on the local machine
+pub fn query_session_info(session_id: u32) -> Result<SessionInfo, Error> {
+    WtsServer::local().query_session_info(session_id)
+}
+
+impl WtsServer {
+    /// Query [`SessionInfo`] for a session on this server
+    pub fn query_session_info(
+        &self,
+        session_id: u32,
+    ) -> Result<SessionInfo, Error> {
+        let query = |class| {
+            wts_query_session_information_w(self.handle(), session_id, class)
+        };
+
+        Ok(SessionInfo {
+            user_name: wide_string(&query(WTS_USER_NAME)?),
+            domain: wide_string(&query(WTS_DOMAIN_NAME)?),
+            client_name: wide_string(&query(WTS_CLIENT_NAME)?),
+            client_address: client_address(&query(WTS_CLIENT_ADDRESS)?),
+            protocol: query(WTS_CLIENT_PROTOCOL_TYPE)?
+                .get(..2)
+                .map_or(0, |bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .into(),
+        })
+    }
+}
+
+/// The user a session belongs to, attached to every event in
+/// [`SessionScope::AllSessions`](crate::SessionScope::AllSessions) mode
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionUser {
+    pub user_name: String,
+    pub domain: String,
+}
+
+impl std::fmt::Display for SessionUser {
+    /// `DOMAIN\user`, or just the user without a domain
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.domain.is_empty() {
+            write!(f, "{}", self.user_name)
+        } else {
+            write!(f, "{}\\{}", self.domain, self.user_name)
+        }
+    }
+}
+
+/// Query the [`SessionUser`] of a session on the local machine, `None` if
+/// nobody is logged on to it or it can't be queried
+pub(crate) fn query_session_user(session_id: u32) -> Option<SessionUser> {
+    let query = |class| {
+        wts_query_session_information_w(
+            WTS_CURRENT_SERVER_HANDLE,
+            session_id,
+            class,
+        )
+        .ok()
+    };
+    let user_name = wide_string(&query(WTS_USER_NAME)?);
+    if user_name.is_empty() {
+        return None;
+    }
+    Some(SessionUser {
+        user_name,
+        domain: wide_string(&query(WTS_DOMAIN_NAME)?),
+    })
+}
+
+/// Who is shadowing a session, attached to RemoteControl events
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Shadow {
+    /// The session doing the shadowing
+    pub session_id: u32,
+    /// Its user and client, if it could still be queried
+    pub info: Option<SessionInfo>,
+}
+
+/// Find the session shadowing `session_id`, it is the one in the Shadow
+/// state. `None` once the shadowing has ended
+pub(crate) fn find_shadow(session_id: u32) -> Option<Shadow> {
+    let shadower = enumerate_sessions().ok()?.into_iter().find(|session| {
+        session.state == ConnectState::Shadow && session.id != session_id
+    })?;
+    Some(Shadow {
+        session_id: shadower.id,
+        info: query_session_info(shadower.id).ok(),
+    })
+}
+
+/// Decode a null terminated UTF-16 buffer
+fn wide_string(bytes: &[u8]) -> String {
+    let wide: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|&c| c != 0)
+        .collect();
+    String::from_utf16_lossy(&wide)
+}
+
+/// Decode a WTS_CLIENT_ADDRESS, a DWORD address family followed by the
+/// address bytes, which start at offset 2 of the address field
+fn client_address(bytes: &[u8]) -> Option<IpAddr> {
+    const AF_INET: u32 = 2;
+    const AF_INET6: u32 = 23;
+
+    let family = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?);
+    let address = bytes.get(4..)?;
+    match family {
+        AF_INET => {
+            let octets: [u8; 4] = address.get(2..6)?.try_into().ok()?;
+            Some(IpAddr::from(octets))
+        }
+        AF_INET6 => {
+            let octets: [u8; 16] = address.get(2..18)?.try_into().ok()?;
+            Some(IpAddr::from(octets))
+        }
+        _ => None,
+    }
+}
+
+/// Best effort probe of whether a session is locked right now, returning
+/// [`WtsState::Lock`] or [`WtsState::Unlock`] or `None` if it can't be told.
+///
+/// Asks WTS for the session flags first, for our own session it falls back to
+/// checking whether the input desktop can be switched to
+pub fn query_lock_state(session_id: u32) -> Option<WtsState> {
+    // WTSINFOEX_LEVEL1_W.SessionFlags, after the DWORD level and the 8 byte
+    // aligned SessionId and SessionState
+    const SESSION_FLAGS: usize = 16;
+    const WTS_SESSIONSTATE_LOCK: i32 = 0;
+    const WTS_SESSIONSTATE_UNLOCK: i32 = 1;
+
+    let info = wts_query_session_information_w(
+        WTS_CURRENT_SERVER_HANDLE,
+        session_id,
+        WTS_SESSION_INFO_EX,
+    );
+    let flags = info.ok().and_then(|info| {
+        let bytes = info.get(SESSION_FLAGS..SESSION_FLAGS + 4)?;
+        Some(i32::from_le_bytes(bytes.try_into().ok()?))
+    });
+    match flags {
+        Some(WTS_SESSIONSTATE_LOCK) => return Some(WtsState::Lock),
+        Some(WTS_SESSIONSTATE_UNLOCK) => return Some(WtsState::Unlock),
+        _ => {}
+    }
+
+    if current_session_id().ok()? != session_id {
+        return None;
+    }
+    if input_desktop_is_switchable() {
+        Some(WtsState::Unlock)
+    } else {
+        Some(WtsState::Lock)
+    }
+}
@@ -0,0 +1,80 @@
+//! Run a [`SessionMonitor`] on its own thread so the caller's thread is free
+//! to do other work
+use crate::{Error, SessionEvent, SessionMonitorBuilder, StopHandle};
+use std::sync::mpsc::{channel, sync_channel, Receiver};
+use std::thread::{self, JoinHandle};
+
+/// Controls a monitor thread started by [`spawn_monitor`]
+pub struct MonitorHandle {
+    thread: JoinHandle<()>,
+    stop: StopHandle,
+}
+
+impl MonitorHandle {
+    /// Ask the monitor thread to end its message loop
+    pub fn stop(&self) -> Result<(), Error> {
+        self.stop.stop()
+    }
+
+    /// A handle that can stop the monitor thread without owning it
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// Wait for the monitor thread to finish
+    pub fn join(self) -> thread::Result<()> {
+        self.thread.join()
+    }
+}
+
+/// Spawn a thread owning a new [`SessionMonitor`] and receive its events over
+/// a channel
+pub fn spawn_monitor() -> Result<(Receiver<SessionEvent>, MonitorHandle), Error>
+{
+    SessionMonitorBuilder::new().spawn()
+}
+
+impl SessionMonitorBuilder {
+    /// Like [`spawn_monitor`] but with the options from this builder
+    pub fn spawn(
+        self,
+    ) -> Result<(Receiver<SessionEvent>, MonitorHandle), Error> {
+        let (tx, rx) = channel();
+        let handle = spawn_pump(self, move |event| tx.send(event).is_ok())?;
+        Ok((rx, handle))
+    }
+}
+
+/// Start a thread with a monitor built from `builder` that hands every event
+/// to `forward` until it returns false or the message loop ends
+pub(crate) fn spawn_pump<F>(
+    builder: SessionMonitorBuilder,
+    mut forward: F,
+) -> Result<MonitorHandle, Error>
+where
+    F: FnMut(SessionEvent) -> bool + Send + 'static,
+{
+    // The window has to be created on the thread that pumps it, so wait for
+    // that thread to tell us if it worked
+    let (ready_tx, ready_rx) = sync_channel(1);
+
+    let thread = thread::spawn(move || {
+        let monitor = match builder.build() {
+            Ok(monitor) => monitor,
+            Err(err) => {
+                let _ = ready_tx.send(Err(err));
+                return;
+            }
+        };
+        let _ = ready_tx.send(Ok(monitor.stop_handle()));
+
+        for event in monitor.events() {
+            if !forward(event) {
+                break;
+            }
+        }
+    });
+
+    let stop = ready_rx.recv().expect("Monitor thread exited early")?;
+    Ok(MonitorHandle { thread, stop })
+}
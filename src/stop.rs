@@ -0,0 +1,86 @@
+//! Ending a monitor's message loop from another thread or the console
+use crate::wynapi::{
+    post_thread_message_a, set_console_ctrl_handler, BOOL, CTRL_CLOSE_EVENT,
+    CTRL_LOGOFF_EVENT, CTRL_SHUTDOWN_EVENT, DWORD, WM_QUIT,
+};
+use crate::Error;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+use tracing::{event, Level};
+
+/// Ends the message loop of a [`SessionMonitor`](crate::SessionMonitor) by
+/// posting WM_QUIT to the thread that created it. Can be cloned and sent to
+/// any thread
+#[derive(Debug, Clone)]
+pub struct StopHandle {
+    thread_id: DWORD,
+}
+
+impl StopHandle {
+    pub(crate) fn new(thread_id: DWORD) -> Self {
+        Self { thread_id }
+    }
+
+    /// Ask the monitor to stop, the event iterator ends and
+    /// [`SessionMonitor::run`](crate::SessionMonitor::run) returns once the
+    /// messages queued before this one are handled
+    pub fn stop(&self) -> Result<(), Error> {
+        post_thread_message_a(self.thread_id, WM_QUIT)
+    }
+}
+
+/// What the console control handler stops, and whether the process has
+/// finished cleaning up
+static CONSOLE: Mutex<Option<(StopHandle, bool)>> = Mutex::new(None);
+static CLEANED_UP: Condvar = Condvar::new();
+/// Windows ends the process 5 seconds after a close, logoff or shutdown event
+const CLEANUP_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Stops a monitor on Ctrl+C, Ctrl+Break or the console window closing so it
+/// unregisters and destroys its window instead of dying mid loop. Only one
+/// can be installed at a time, the handler is removed on drop.
+///
+/// Returning from the handler of a close lets Windows end the process, so it
+/// waits until this guard is dropped. Create it before the monitor so it is
+/// dropped after
+pub struct ConsoleCtrl {
+    _private: (),
+}
+
+impl ConsoleCtrl {
+    pub fn install(stop: StopHandle) -> Result<Self, Error> {
+        *CONSOLE.lock().unwrap() = Some((stop, false));
+        set_console_ctrl_handler(Some(handler), true)?;
+        Ok(Self { _private: () })
+    }
+}
+
+impl Drop for ConsoleCtrl {
+    fn drop(&mut self) {
+        let _ = set_console_ctrl_handler(Some(handler), false);
+        if let Some((_, cleaned_up)) = CONSOLE.lock().unwrap().as_mut() {
+            *cleaned_up = true;
+        }
+        CLEANED_UP.notify_all();
+    }
+}
+
+unsafe extern "system" fn handler(ctrl_type: DWORD) -> BOOL {
+    let console = CONSOLE.lock().unwrap();
+    let Some((stop, _)) = console.as_ref() else {
+        return 0;
+    };
+    event!(Level::INFO, "Console control event {ctrl_type}, stopping");
+    let _ = stop.stop();
+    if matches!(
+        ctrl_type,
+        CTRL_CLOSE_EVENT | CTRL_LOGOFF_EVENT | CTRL_SHUTDOWN_EVENT
+    ) {
+        let _ = CLEANED_UP.wait_timeout_while(
+            console,
+            CLEANUP_TIMEOUT,
+            |console| !console.as_ref().is_some_and(|(_, done)| *done),
+        );
+    }
+    1
+}
@@ -0,0 +1,53 @@
+//! Async [`Stream`] of session events, enabled with the `tokio` feature
+use crate::spawn::spawn_pump;
+use crate::{
+    Error, SessionEvent, SessionMonitor, SessionMonitorBuilder, StopHandle,
+};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_core::Stream;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// Events from a [`SessionMonitor`] whose message loop runs on a dedicated
+/// thread, so no runtime worker is blocked in GetMessageA
+pub struct SessionStream {
+    rx: UnboundedReceiver<SessionEvent>,
+    stop: StopHandle,
+}
+
+impl Drop for SessionStream {
+    fn drop(&mut self) {
+        let _ = self.stop.stop();
+    }
+}
+
+impl Stream for SessionStream {
+    type Item = SessionEvent;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl SessionMonitor {
+    /// Spawn a thread that owns a new monitor and forwards its events into
+    /// the returned stream. The thread exits once the stream is dropped
+    pub fn stream() -> Result<SessionStream, Error> {
+        SessionMonitorBuilder::new().stream()
+    }
+}
+
+impl SessionMonitorBuilder {
+    /// Like [`SessionMonitor::stream`] but with the options from this builder
+    pub fn stream(self) -> Result<SessionStream, Error> {
+        let (tx, rx) = unbounded_channel();
+        let handle = spawn_pump(self, move |event| tx.send(event).is_ok())?;
+        Ok(SessionStream {
+            rx,
+            stop: handle.stop_handle(),
+        })
+    }
+}
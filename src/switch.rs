@@ -0,0 +1,100 @@
+//! Tell Fast User Switching apart from other console disconnects by looking
+//! at which sessions are still around
+use crate::session::enumerate_sessions;
+use crate::wynapi::{ConnectState, WtsState};
+use std::collections::HashSet;
+
+/// A switch worked out from the raw console notifications
+pub(crate) struct Switch {
+    pub state: WtsState,
+    pub session_id: u32,
+    pub other_session_id: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SwitchTracker {
+    /// The session that last lost the console and has not been reported yet
+    disconnected: Option<u32>,
+    /// The session that last lost the console
+    last_console: Option<u32>,
+    /// Sessions we have reported SwitchedAway for
+    away: HashSet<u32>,
+}
+
+impl SwitchTracker {
+    /// Feed a raw notification, returns the switches it completes
+    pub fn on_state(
+        &mut self,
+        state: WtsState,
+        session_id: u32,
+    ) -> Vec<Switch> {
+        let mut switches = Vec::new();
+        match state {
+            WtsState::ConsoleDisconnect => {
+                self.last_console = Some(session_id);
+                // The new owner is often attached already, otherwise wait for
+                // its ConsoleConnect which needs all sessions to be seen
+                match console_owner(session_id) {
+                    Some(other) => switches.push(self.away(session_id, other)),
+                    None => self.disconnected = Some(session_id),
+                }
+            }
+            WtsState::ConsoleConnect => {
+                if let Some(previous) = self.disconnected.take() {
+                    if previous != session_id && still_exists(previous) {
+                        switches.push(self.away(previous, session_id));
+                    }
+                }
+                if self.away.remove(&session_id) {
+                    switches.push(Switch {
+                        state: WtsState::SwitchedBack,
+                        session_id,
+                        other_session_id: self
+                            .last_console
+                            .filter(|&other| other != session_id),
+                    });
+                }
+            }
+            WtsState::Logoff => {
+                self.away.remove(&session_id);
+            }
+            _ => {}
+        }
+        switches
+    }
+
+    fn away(&mut self, session_id: u32, other: u32) -> Switch {
+        self.away.insert(session_id);
+        Switch {
+            state: WtsState::SwitchedAway,
+            session_id,
+            other_session_id: Some(other),
+        }
+    }
+}
+
+/// Another session attached to the physical console
+fn console_owner(not: u32) -> Option<u32> {
+    enumerate_sessions()
+        .ok()?
+        .into_iter()
+        .find(|session| {
+            session.id != not
+                && session.station_name.eq_ignore_ascii_case("console")
+                && matches!(
+                    session.state,
+                    ConnectState::Active | ConnectState::Connected
+                )
+        })
+        .map(|session| session.id)
+}
+
+/// A disconnected session that was not logged off
+fn still_exists(session_id: u32) -> bool {
+    enumerate_sessions().is_ok_and(|sessions| {
+        sessions.iter().any(|session| {
+            session.id == session_id
+                && session.state == ConnectState::Disconnected
+        })
+    })
+}
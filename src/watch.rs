@@ -0,0 +1,177 @@
+//! Reloading the action config when its file changes, so edits take effect
+//! without restarting the monitor or re-registering its window
+use crate::actions::ActionRunner;
+use crate::wynapi::{DirectoryWatch, Error};
+use crate::{ConfigError, Schedule, SessionEvent};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use tracing::{event, Level};
+
+/// How long to let a save settle before reading the file, editors often
+/// write it in more than one go
+const SETTLE: Duration = Duration::from_millis(200);
+
+/// An [`ActionRunner`] loaded from a config file, or layered ones, swapped
+/// for a fresh one whenever a file changes with what changed logged. A
+/// config that no longer loads is logged and the one before kept.
+///
+/// Events already held back by [`ActionRunner::debounce`] survive a reload.
+/// The monitor can't be given new schedules, so actions at a schedule the
+/// monitor wasn't built with only run after a restart
+#[derive(Debug, Clone)]
+pub struct ConfigWatcher {
+    runner: Arc<RwLock<ActionRunner>>,
+}
+
+impl ConfigWatcher {
+    /// Watch the config at `path` that `runner` was loaded from, on a thread
+    /// of its own. `prepare` is applied to `runner` and each reload, for
+    /// what isn't in the file such as [`ActionRunner::dry_run`]
+    pub fn start(
+        path: impl Into<PathBuf>,
+        runner: ActionRunner,
+        prepare: impl Fn(ActionRunner) -> ActionRunner + Send + 'static,
+    ) -> Result<Self, Error> {
+        let path = path.into();
+        let paths = vec![path.clone()];
+        Self::watch(paths, runner, prepare, move || ActionRunner::load(&path))
+    }
+
+    /// Like [`ConfigWatcher::start`] for the layered configs `runner` was
+    /// loaded from with [`ActionRunner::load_layered`]. Those that don't
+    /// exist yet are picked up once created, if their directory exists
+    pub fn start_layered(
+        paths: Vec<PathBuf>,
+        runner: ActionRunner,
+        prepare: impl Fn(ActionRunner) -> ActionRunner + Send + 'static,
+    ) -> Result<Self, Error> {
+        let layers = paths.clone();
+        Self::watch(paths, runner, prepare, move || {
+            ActionRunner::load_layered(&layers)
+        })
+    }
+
+    fn watch(
+        paths: Vec<PathBuf>,
+        runner: ActionRunner,
+        prepare: impl Fn(ActionRunner) -> ActionRunner + Send + 'static,
+        load: impl Fn() -> Result<ActionRunner, ConfigError> + Send + 'static,
+    ) -> Result<Self, Error> {
+        // A thread per directory tells the one reloading when a file changed
+        let (changed, changes) = mpsc::channel();
+        for (dir, names) in directories(&paths) {
+            // Layers can live in directories nobody made
+            if paths.len() > 1 && !dir.is_dir() {
+                continue;
+            }
+            let watch = DirectoryWatch::open(&dir)?;
+            let changed = changed.clone();
+            thread::spawn(move || {
+                // Stops if the directory goes away
+                while let Ok(files) = watch.wait() {
+                    // No names when too much changed to say which
+                    let ours = files.is_empty()
+                        || files.iter().any(|file| {
+                            names
+                                .iter()
+                                .any(|name| file.eq_ignore_ascii_case(name))
+                        });
+                    if ours && changed.send(()).is_err() {
+                        return;
+                    }
+                }
+                event!(Level::ERROR, "Stopped watching {}", dir.display());
+            });
+        }
+
+        let runner = prepare(runner);
+        // What the monitor was given, see ActionRunner::schedules
+        let monitored: Vec<Schedule> = runner.schedules().cloned().collect();
+        let runner = Arc::new(RwLock::new(runner));
+        let shared = runner.clone();
+        let shown = paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" and ");
+        thread::spawn(move || {
+            while changes.recv().is_ok() {
+                thread::sleep(SETTLE);
+                // One reload for every change of a save
+                while changes.try_recv().is_ok() {}
+                reload(&shown, &shared, &load, &prepare, &monitored);
+            }
+        });
+        Ok(Self { runner })
+    }
+
+    /// Start the actions for `event` with the config as it is now, for
+    /// [`SessionMonitor::on`](crate::SessionMonitor::on) with
+    /// [`StateSet::ALL`](crate::StateSet::ALL) as a reload can add states
+    pub fn run(&self, event: &SessionEvent) {
+        self.runner.read().unwrap().run(event);
+    }
+
+    /// A copy of the runner in use
+    pub fn runner(&self) -> ActionRunner {
+        self.runner.read().unwrap().clone()
+    }
+}
+
+/// The directories of `paths`, each with the names of the files in it
+fn directories(paths: &[PathBuf]) -> Vec<(PathBuf, Vec<String>)> {
+    let mut directories: Vec<(PathBuf, Vec<String>)> = Vec::new();
+    for path in paths {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = path.file_name().unwrap_or_default();
+        let name = name.to_string_lossy().into_owned();
+        match directories.iter_mut().find(|(d, _)| d == dir) {
+            Some((_, names)) => names.push(name),
+            None => directories.push((dir.to_path_buf(), vec![name])),
+        }
+    }
+    directories
+}
+
+/// Load the config, `shown` in the logs, again and swap it in if anything
+/// changed
+fn reload(
+    shown: &str,
+    runner: &RwLock<ActionRunner>,
+    load: &impl Fn() -> Result<ActionRunner, ConfigError>,
+    prepare: &impl Fn(ActionRunner) -> ActionRunner,
+    monitored: &[Schedule],
+) {
+    let new = match load() {
+        Ok(new) => prepare(new),
+        Err(err) => {
+            event!(Level::ERROR, "Keeping the config we had, {shown} {err}");
+            return;
+        }
+    };
+    let mut runner = runner.write().unwrap();
+    let changes = runner.changes(&new);
+    if changes.is_empty() {
+        event!(Level::DEBUG, "{shown} changed but not its actions");
+        return;
+    }
+    event!(Level::INFO, "Reloaded {shown}");
+    for change in changes {
+        event!(Level::INFO, "{change}");
+    }
+    for schedule in new.schedules() {
+        let added = !runner.schedules().any(|old| old == schedule);
+        if added && !monitored.contains(schedule) {
+            event!(
+                Level::WARN,
+                "schedule {schedule} only runs after a restart"
+            );
+        }
+    }
+    runner.replace(new);
+}
@@ -0,0 +1,385 @@
+//! A photo from the first webcam, read through a Media Foundation source
+//! reader that converts it to 32 bit RGB
+#![allow(non_snake_case)]
+use crate::wynapi::*;
+use core::ptr::null_mut;
+use tracing::{event, Level};
+
+const MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE: GUID = GUID {
+    Data1: 0xc60ac5fe,
+    Data2: 0x252a,
+    Data3: 0x478f,
+    Data4: [0xa0, 0xef, 0xbc, 0x8f, 0xa5, 0xf7, 0xca, 0xd3],
+};
+const MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_GUID: GUID = GUID {
+    Data1: 0x8ac3587a,
+    Data2: 0x4ae7,
+    Data3: 0x42d8,
+    Data4: [0x99, 0xe0, 0x0a, 0x60, 0x13, 0xee, 0xf9, 0x0f],
+};
+const MF_SOURCE_READER_ENABLE_VIDEO_PROCESSING: GUID = GUID {
+    Data1: 0xfb394f3d,
+    Data2: 0xccf1,
+    Data3: 0x42ee,
+    Data4: [0xbb, 0xb3, 0xf9, 0xb8, 0x45, 0xd5, 0x68, 0x1d],
+};
+const MF_MT_MAJOR_TYPE: GUID = GUID {
+    Data1: 0x48eba18e,
+    Data2: 0xf8c9,
+    Data3: 0x4687,
+    Data4: [0xbf, 0x11, 0x0a, 0x74, 0xc9, 0xf9, 0x6a, 0x8f],
+};
+const MF_MT_SUBTYPE: GUID = GUID {
+    Data1: 0xf7e34c9a,
+    Data2: 0x42e8,
+    Data3: 0x4714,
+    Data4: [0xb7, 0x4b, 0xcb, 0x29, 0xd7, 0x2c, 0x35, 0xe5],
+};
+const MF_MT_FRAME_SIZE: GUID = GUID {
+    Data1: 0x1652c33d,
+    Data2: 0xd6b2,
+    Data3: 0x4012,
+    Data4: [0xb8, 0x34, 0x72, 0x03, 0x08, 0x49, 0xa3, 0x7d],
+};
+const MF_MT_DEFAULT_STRIDE: GUID = GUID {
+    Data1: 0x644b4e48,
+    Data2: 0x1e02,
+    Data3: 0x4516,
+    Data4: [0xb0, 0xeb, 0xc0, 0x1c, 0xa9, 0xd4, 0x9a, 0xc6],
+};
+const MFMEDIATYPE_VIDEO: GUID = GUID {
+    Data1: 0x73646976,
+    Data2: 0x0000,
+    Data3: 0x0010,
+    Data4: [0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71],
+};
+const MFVIDEOFORMAT_RGB32: GUID = GUID {
+    Data1: 0x00000016,
+    Data2: 0x0000,
+    Data3: 0x0010,
+    Data4: [0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71],
+};
+const IID_IMFMEDIASOURCE: GUID = GUID {
+    Data1: 0x279a808d,
+    Data2: 0xaec7,
+    Data3: 0x40c8,
+    Data4: [0x9c, 0x6b, 0xa6, 0xb4, 0x92, 0xc7, 0x8a, 0x66],
+};
+const MF_SOURCE_READER_FIRST_VIDEO_STREAM: u32 = 0xFFFF_FFFC;
+const MF_SOURCE_READERF_ERROR: u32 = 0x1;
+const MF_SOURCE_READERF_ENDOFSTREAM: u32 = 0x2;
+/// Frames thrown away while the camera settles its exposure, the first
+/// ones are often black
+const WARM_UP_FRAMES: usize = 10;
+/// Reads given up after, cameras send empty samples while starting
+const MAX_READS: usize = 100;
+
+/// All of IMFAttributes, which the other interfaces here start with
+#[repr(C)]
+struct IMFAttributesVtbl {
+    base: IUnknownVtbl,
+    _before_get: [usize; 4],
+    GetUINT32: unsafe extern "system" fn(
+        this: LPVOID,
+        guidKey: *const GUID,
+        punValue: *mut u32,
+    ) -> HRESULT,
+    GetUINT64: unsafe extern "system" fn(
+        this: LPVOID,
+        guidKey: *const GUID,
+        punValue: *mut u64,
+    ) -> HRESULT,
+    _before_set: [usize; 12],
+    SetUINT32: unsafe extern "system" fn(
+        this: LPVOID,
+        guidKey: *const GUID,
+        unValue: u32,
+    ) -> HRESULT,
+    SetUINT64: usize,
+    SetDouble: usize,
+    SetGUID: unsafe extern "system" fn(
+        this: LPVOID,
+        guidKey: *const GUID,
+        guidValue: *const GUID,
+    ) -> HRESULT,
+    _after: [usize; 8],
+}
+
+#[repr(C)]
+struct IMFActivateVtbl {
+    attributes: IMFAttributesVtbl,
+    ActivateObject: unsafe extern "system" fn(
+        this: LPVOID,
+        riid: *const GUID,
+        ppv: *mut LPVOID,
+    ) -> HRESULT,
+    ShutdownObject: unsafe extern "system" fn(this: LPVOID) -> HRESULT,
+    DetachObject: usize,
+}
+
+#[repr(C)]
+struct IMFSourceReaderVtbl {
+    base: IUnknownVtbl,
+    _before: [usize; 3],
+    GetCurrentMediaType: unsafe extern "system" fn(
+        this: LPVOID,
+        dwStreamIndex: u32,
+        ppMediaType: *mut LPVOID,
+    ) -> HRESULT,
+    SetCurrentMediaType: unsafe extern "system" fn(
+        this: LPVOID,
+        dwStreamIndex: u32,
+        pdwReserved: *mut u32,
+        pMediaType: LPVOID,
+    ) -> HRESULT,
+    SetCurrentPosition: usize,
+    ReadSample: unsafe extern "system" fn(
+        this: LPVOID,
+        dwStreamIndex: u32,
+        dwControlFlags: u32,
+        pdwActualStreamIndex: *mut u32,
+        pdwStreamFlags: *mut u32,
+        pllTimestamp: *mut i64,
+        ppSample: *mut LPVOID,
+    ) -> HRESULT,
+}
+
+/// Only ConvertToContiguousBuffer is typed after IMFAttributes
+#[repr(C)]
+struct IMFSampleVtbl {
+    attributes: IMFAttributesVtbl,
+    _before: [usize; 8],
+    ConvertToContiguousBuffer: unsafe extern "system" fn(
+        this: LPVOID,
+        ppBuffer: *mut LPVOID,
+    ) -> HRESULT,
+}
+
+#[repr(C)]
+struct IMFMediaBufferVtbl {
+    base: IUnknownVtbl,
+    Lock: unsafe extern "system" fn(
+        this: LPVOID,
+        ppbBuffer: *mut *mut u8,
+        pcbMaxLength: *mut u32,
+        pcbCurrentLength: *mut u32,
+    ) -> HRESULT,
+    Unlock: unsafe extern "system" fn(this: LPVOID) -> HRESULT,
+}
+
+/// An interface pointer we own, released on drop
+struct Interface(LPVOID);
+
+impl Drop for Interface {
+    fn drop(&mut self) {
+        unsafe { release(self.0) };
+    }
+}
+
+unsafe fn attributes<'a>(object: &Interface) -> &'a IMFAttributesVtbl {
+    vtbl::<IMFAttributesVtbl>(object.0)
+}
+
+/// One picture, rows of blue, green, red and an unused byte per pixel
+pub(crate) struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// The bottom row comes first, as in a bitmap
+    pub bottom_up: bool,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    /// The frame as the contents of a .bmp file
+    pub fn to_bitmap(&self) -> Vec<u8> {
+        const HEADERS: u32 = 14 + 40;
+        let size = (self.width * self.height * 4) as usize;
+        let height = if self.bottom_up {
+            self.height as i32
+        } else {
+            -(self.height as i32)
+        };
+        let mut bitmap = Vec::with_capacity(HEADERS as usize + size);
+        // BITMAPFILEHEADER
+        bitmap.extend_from_slice(b"BM");
+        bitmap.extend_from_slice(&(HEADERS + size as u32).to_le_bytes());
+        bitmap.extend_from_slice(&0u32.to_le_bytes());
+        bitmap.extend_from_slice(&HEADERS.to_le_bytes());
+        // BITMAPINFOHEADER, 32 bits per pixel without compression
+        bitmap.extend_from_slice(&40u32.to_le_bytes());
+        bitmap.extend_from_slice(&(self.width as i32).to_le_bytes());
+        bitmap.extend_from_slice(&height.to_le_bytes());
+        bitmap.extend_from_slice(&1u16.to_le_bytes());
+        bitmap.extend_from_slice(&32u16.to_le_bytes());
+        bitmap.extend_from_slice(&0u32.to_le_bytes());
+        bitmap.extend_from_slice(&(size as u32).to_le_bytes());
+        bitmap.extend_from_slice(&[0; 16]);
+        bitmap.extend_from_slice(&self.pixels[..size.min(self.pixels.len())]);
+        bitmap.resize(HEADERS as usize + size, 0);
+        bitmap
+    }
+}
+
+/// Take a photo with the first webcam, after letting it warm up
+pub(crate) fn capture() -> Result<Frame, Error> {
+    // Dropped last, after every interface
+    let _com = ComApartment::enter()?;
+    let _media_foundation = MediaFoundation::startup()?;
+    unsafe {
+        let filter = Interface(mf_create_attributes(1)?);
+        check_hresult(
+            "IMFAttributes::SetGUID",
+            (attributes(&filter).SetGUID)(
+                filter.0,
+                &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE,
+                &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_GUID,
+            ),
+        )?;
+        let devices: Vec<Interface> = mf_enum_device_sources(filter.0)?
+            .into_iter()
+            .map(Interface)
+            .collect();
+        let Some(device) = devices.first() else {
+            event!(Level::ERROR, "No webcam found");
+            return Err(Error::ERROR_DEVICE_NOT_CONNECTED);
+        };
+
+        let activate = vtbl::<IMFActivateVtbl>(device.0);
+        let mut source = null_mut();
+        check_hresult(
+            "IMFActivate::ActivateObject",
+            (activate.ActivateObject)(
+                device.0,
+                &IID_IMFMEDIASOURCE,
+                &mut source,
+            ),
+        )?;
+        let frame = read_frame(&Interface(source));
+        // Turns the camera and its light off again
+        (activate.ShutdownObject)(device.0);
+        frame
+    }
+}
+
+/// Read frames from `source` until it has warmed up
+unsafe fn read_frame(source: &Interface) -> Result<Frame, Error> {
+    let settings = Interface(mf_create_attributes(1)?);
+    check_hresult(
+        "IMFAttributes::SetUINT32",
+        (attributes(&settings).SetUINT32)(
+            settings.0,
+            &MF_SOURCE_READER_ENABLE_VIDEO_PROCESSING,
+            1,
+        ),
+    )?;
+    let reader = Interface(mf_create_source_reader(source.0, settings.0)?);
+    let reader_vtbl = vtbl::<IMFSourceReaderVtbl>(reader.0);
+
+    let rgb32 = Interface(mf_create_media_type()?);
+    for (key, value) in [
+        (&MF_MT_MAJOR_TYPE, &MFMEDIATYPE_VIDEO),
+        (&MF_MT_SUBTYPE, &MFVIDEOFORMAT_RGB32),
+    ] {
+        check_hresult(
+            "IMFMediaType::SetGUID",
+            (attributes(&rgb32).SetGUID)(rgb32.0, key, value),
+        )?;
+    }
+    check_hresult(
+        "IMFSourceReader::SetCurrentMediaType",
+        (reader_vtbl.SetCurrentMediaType)(
+            reader.0,
+            MF_SOURCE_READER_FIRST_VIDEO_STREAM,
+            null_mut(),
+            rgb32.0,
+        ),
+    )?;
+    let mut current = null_mut();
+    check_hresult(
+        "IMFSourceReader::GetCurrentMediaType",
+        (reader_vtbl.GetCurrentMediaType)(
+            reader.0,
+            MF_SOURCE_READER_FIRST_VIDEO_STREAM,
+            &mut current,
+        ),
+    )?;
+    let current = Interface(current);
+    let mut frame_size = 0u64;
+    check_hresult(
+        "IMFMediaType::GetUINT64",
+        (attributes(&current).GetUINT64)(
+            current.0,
+            &MF_MT_FRAME_SIZE,
+            &mut frame_size,
+        ),
+    )?;
+    // RGB is bottom up unless the stride says otherwise
+    let mut stride = 0u32;
+    let bottom_up = (attributes(&current).GetUINT32)(
+        current.0,
+        &MF_MT_DEFAULT_STRIDE,
+        &mut stride,
+    ) < 0
+        || (stride as i32) < 0;
+
+    let mut sample = None;
+    let mut frames = 0;
+    for _ in 0..MAX_READS {
+        let (mut index, mut flags, mut timestamp) = (0, 0, 0);
+        let mut raw = null_mut();
+        check_hresult(
+            "IMFSourceReader::ReadSample",
+            (reader_vtbl.ReadSample)(
+                reader.0,
+                MF_SOURCE_READER_FIRST_VIDEO_STREAM,
+                0,
+                &mut index,
+                &mut flags,
+                &mut timestamp,
+                &mut raw,
+            ),
+        )?;
+        if flags & (MF_SOURCE_READERF_ERROR | MF_SOURCE_READERF_ENDOFSTREAM)
+            != 0
+        {
+            break;
+        }
+        if raw.is_null() {
+            continue;
+        }
+        sample = Some(Interface(raw));
+        frames += 1;
+        if frames == WARM_UP_FRAMES {
+            break;
+        }
+    }
+    let Some(sample) = sample else {
+        event!(Level::ERROR, "The webcam sent no frames");
+        return Err(Error::ERROR_DEVICE_NOT_CONNECTED);
+    };
+
+    let mut buffer = null_mut();
+    check_hresult(
+        "IMFSample::ConvertToContiguousBuffer",
+        (vtbl::<IMFSampleVtbl>(sample.0).ConvertToContiguousBuffer)(
+            sample.0,
+            &mut buffer,
+        ),
+    )?;
+    let buffer = Interface(buffer);
+    let buffer_vtbl = vtbl::<IMFMediaBufferVtbl>(buffer.0);
+    let (mut data, mut max_length, mut length) = (null_mut(), 0, 0);
+    check_hresult(
+        "IMFMediaBuffer::Lock",
+        (buffer_vtbl.Lock)(buffer.0, &mut data, &mut max_length, &mut length),
+    )?;
+    let pixels = core::slice::from_raw_parts(data, length as usize).to_vec();
+    (buffer_vtbl.Unlock)(buffer.0);
+
+    Ok(Frame {
+        width: (frame_size >> 32) as u32,
+        height: frame_size as u32,
+        bottom_up,
+        pixels,
+    })
+}
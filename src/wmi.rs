@@ -0,0 +1,261 @@
+//! WMI as a source of events, through a notification query for
+//! Win32_SessionChangeEvent read on a thread of its own
+#![allow(non_snake_case)]
+use crate::backend::Inbox;
+use crate::wynapi::*;
+use crate::SessionEvent;
+use core::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use tracing::{event, Level};
+
+const CLSID_WBEMLOCATOR: GUID = GUID {
+    Data1: 0x4590f811,
+    Data2: 0x1d3a,
+    Data3: 0x11d0,
+    Data4: [0x89, 0x1f, 0x00, 0xaa, 0x00, 0x4b, 0x2e, 0x24],
+};
+const IID_IWBEMLOCATOR: GUID = GUID {
+    Data1: 0xdc12a687,
+    Data2: 0x737f,
+    Data3: 0x11cf,
+    Data4: [0x88, 0x4d, 0x00, 0xaa, 0x00, 0x4b, 0x2e, 0x24],
+};
+const NAMESPACE: &str = "ROOT\\CIMV2";
+const QUERY: &str = "SELECT * FROM Win32_SessionChangeEvent";
+const WBEM_FLAG_RETURN_IMMEDIATELY: i32 = 0x10;
+const WBEM_FLAG_FORWARD_ONLY: i32 = 0x20;
+/// How long a wait for the next event blocks before checking for stop
+const NEXT_TIMEOUT_MS: i32 = 500;
+
+#[repr(C)]
+struct IWbemLocatorVtbl {
+    base: IUnknownVtbl,
+    ConnectServer: unsafe extern "system" fn(
+        this: LPVOID,
+        strNetworkResource: BSTR,
+        strUser: BSTR,
+        strPassword: BSTR,
+        strLocale: BSTR,
+        lSecurityFlags: i32,
+        strAuthority: BSTR,
+        pCtx: LPVOID,
+        ppNamespace: *mut LPVOID,
+    ) -> HRESULT,
+}
+
+/// Only ExecNotificationQuery is typed, the methods before it are skipped
+#[repr(C)]
+struct IWbemServicesVtbl {
+    base: IUnknownVtbl,
+    _before: [usize; 19],
+    ExecNotificationQuery: unsafe extern "system" fn(
+        this: LPVOID,
+        strQueryLanguage: BSTR,
+        strQuery: BSTR,
+        lFlags: i32,
+        pCtx: LPVOID,
+        ppEnum: *mut LPVOID,
+    ) -> HRESULT,
+}
+
+#[repr(C)]
+struct IEnumWbemClassObjectVtbl {
+    base: IUnknownVtbl,
+    Reset: usize,
+    Next: unsafe extern "system" fn(
+        this: LPVOID,
+        lTimeout: i32,
+        uCount: u32,
+        apObjects: *mut LPVOID,
+        puReturned: *mut u32,
+    ) -> HRESULT,
+}
+
+#[repr(C)]
+struct IWbemClassObjectVtbl {
+    base: IUnknownVtbl,
+    GetQualifierSet: usize,
+    Get: unsafe extern "system" fn(
+        this: LPVOID,
+        wszName: *const WCHAR,
+        lFlags: i32,
+        pVal: *mut VARIANT,
+        pType: *mut i32,
+        plFlavor: *mut i32,
+    ) -> HRESULT,
+}
+
+/// A connection to ROOT\CIMV2 with the notification query running. COM
+/// objects can't leave the thread that made them so this stays on the
+/// subscription's thread
+struct Query {
+    enumerator: LPVOID,
+    services: LPVOID,
+    locator: LPVOID,
+    // Released after the interfaces
+    _com: ComApartment,
+}
+
+impl Query {
+    fn open() -> Result<Self, Error> {
+        let com = ComApartment::enter()?;
+        let locator =
+            co_create_instance(&CLSID_WBEMLOCATOR, &IID_IWBEMLOCATOR)?;
+        let mut query = Self {
+            enumerator: null_mut(),
+            services: null_mut(),
+            locator,
+            _com: com,
+        };
+        unsafe {
+            let namespace = Bstr::new(NAMESPACE);
+            check_hresult(
+                "IWbemLocator::ConnectServer",
+                (vtbl::<IWbemLocatorVtbl>(locator).ConnectServer)(
+                    locator,
+                    namespace.as_raw(),
+                    null_mut(),
+                    null_mut(),
+                    null_mut(),
+                    0,
+                    null_mut(),
+                    null_mut(),
+                    &mut query.services,
+                ),
+            )?;
+            co_set_proxy_blanket(query.services)?;
+
+            let language = Bstr::new("WQL");
+            let wql = Bstr::new(QUERY);
+            check_hresult(
+                "IWbemServices::ExecNotificationQuery",
+                (vtbl::<IWbemServicesVtbl>(query.services)
+                    .ExecNotificationQuery)(
+                    query.services,
+                    language.as_raw(),
+                    wql.as_raw(),
+                    WBEM_FLAG_RETURN_IMMEDIATELY | WBEM_FLAG_FORWARD_ONLY,
+                    null_mut(),
+                    &mut query.enumerator,
+                ),
+            )?;
+        }
+        event!(Level::INFO, "WMI query {QUERY}");
+        Ok(query)
+    }
+
+    /// Wait a little for the next event, None if there wasn't one or it
+    /// was missing its properties
+    fn next(&self) -> Result<Option<(WtsState, u32)>, Error> {
+        let mut object = null_mut();
+        let mut returned = 0;
+        unsafe {
+            check_hresult(
+                "IEnumWbemClassObject::Next",
+                (vtbl::<IEnumWbemClassObjectVtbl>(self.enumerator).Next)(
+                    self.enumerator,
+                    NEXT_TIMEOUT_MS,
+                    1,
+                    &mut object,
+                    &mut returned,
+                ),
+            )?;
+            if returned == 0 {
+                return Ok(None);
+            }
+            let event_type = property(object, "EventType");
+            let session_id = property(object, "SessionId");
+            release(object);
+            Ok(event_type
+                .zip(session_id)
+                .map(|(code, id)| (WtsState::from(code as usize), id)))
+        }
+    }
+}
+
+impl Drop for Query {
+    fn drop(&mut self) {
+        unsafe {
+            release(self.enumerator);
+            release(self.services);
+            release(self.locator);
+        }
+    }
+}
+
+/// A 32 bit integer property of a WMI object
+unsafe fn property(object: LPVOID, name: &str) -> Option<u32> {
+    let mut value = Variant::default();
+    let hr = (vtbl::<IWbemClassObjectVtbl>(object).Get)(
+        object,
+        to_wide(name).as_ptr(),
+        0,
+        value.as_mut_ptr(),
+        null_mut(),
+        null_mut(),
+    );
+    check_hresult("IWbemClassObject::Get", hr).ok()?;
+    value.as_u32()
+}
+
+/// A thread reading Win32_SessionChangeEvent, stopped and joined on drop
+pub(crate) struct WmiSubscription {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WmiSubscription {
+    pub fn start(scope: SessionScope, inbox: Inbox) -> Result<Self, Error> {
+        let session_id = match scope {
+            SessionScope::ThisSession => Some(current_session_id()?),
+            SessionScope::AllSessions => None,
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        // Connecting happens on the thread, wait for it to say if it worked
+        let (ready_tx, ready_rx) = sync_channel(1);
+
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let query = match Query::open() {
+                    Ok(query) => query,
+                    Err(err) => {
+                        let _ = ready_tx.send(Err(err));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+
+                while !stop.load(Ordering::Relaxed) {
+                    match query.next() {
+                        Ok(Some((state, id)))
+                            if session_id.is_none_or(|ours| ours == id) =>
+                        {
+                            inbox.push(SessionEvent::new(state, id))
+                        }
+                        Ok(_) => {}
+                        Err(_) => break,
+                    }
+                }
+            }
+        });
+
+        ready_rx.recv().expect("WMI thread exited early")?;
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for WmiSubscription {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
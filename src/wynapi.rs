@@ -1,19 +1,20 @@
 //! This is our user abstraction that turns windows API into
 //! more of a rust friendly interface
-#![allow(non_camel_case_types)]
+#![allow(non_camel_case_types, non_snake_case, clippy::upper_case_acronyms)]
 
-use core::ffi::{c_char, c_int, c_void};
+use crate::session::Session;
+use core::ffi::{c_char, c_int, c_void, CStr};
 use core::mem::MaybeUninit;
 use core::ptr::{null, null_mut};
 use tracing::{event, Level};
 
-type HANDLE = *mut c_void;
-type LPVOID = *mut c_void;
-type HWND = HANDLE;
+pub(crate) type HANDLE = *mut c_void;
+pub(crate) type LPVOID = *mut c_void;
+pub(crate) type HWND = HANDLE;
 type HMENU = HANDLE;
 type HINSTANCE = HANDLE;
 type HMODULE = HANDLE;
-type DWORD = i32;
+pub(crate) type DWORD = u32;
 type CHAR = c_char;
 type LPCSTR = *const CHAR;
 type INT = c_int;
@@ -28,6 +29,45 @@ type WPARAM = UINT_PTR;
 type LONG_PTR = isize;
 type LPARAM = LONG_PTR;
 type LONG = i32;
+pub(crate) type BOOL = i32;
+pub(crate) type WCHAR = u16;
+pub(crate) type LPWSTR = *mut WCHAR;
+type LPCWSTR = *const WCHAR;
+pub(crate) type HRESULT = i32;
+type NTSTATUS = i32;
+pub(crate) type BSTR = *mut WCHAR;
+type HDESK = HANDLE;
+type HDC = HANDLE;
+type HBITMAP = HANDLE;
+type HGDIOBJ = HANDLE;
+type ACCESS_MASK = DWORD;
+pub(crate) type HPOWERNOTIFY = HANDLE;
+pub(crate) type HDEVNOTIFY = HANDLE;
+pub(crate) type EVT_HANDLE = HANDLE;
+/// Called on a thread pool thread for each event matching a subscription
+pub(crate) type EVT_SUBSCRIBE_CALLBACK = Option<
+    unsafe extern "system" fn(
+        Action: c_int,
+        UserContext: LPVOID,
+        Event: EVT_HANDLE,
+    ) -> DWORD,
+>;
+/// Called for each top level window by EnumWindows, which stops at FALSE
+type WNDENUMPROC =
+    Option<unsafe extern "system" fn(hWnd: HWND, lParam: LPARAM) -> BOOL>;
+/// Called on a new thread for console control events
+pub(crate) type PHANDLER_ROUTINE =
+    Option<unsafe extern "system" fn(CtrlType: DWORD) -> BOOL>;
+/// Called on a thread pool thread when an IP interface changes
+pub(crate) type PIPINTERFACE_CHANGE_CALLBACK = Option<
+    unsafe extern "system" fn(
+        CallerContext: LPVOID,
+        Row: *const MIB_IPINTERFACE_ROW,
+        NotificationType: c_int,
+    ),
+>;
+type WTS_CONNECTSTATE_CLASS = c_int;
+pub(crate) type WTS_INFO_CLASS = c_int;
 
 type WNDPROC = Option<
     unsafe extern "system" fn(
@@ -41,8 +81,257 @@ type WNDPROC = Option<
 const NOTIFY_FOR_THIS_SESSION: DWORD = 0;
 const NOTIFY_FOR_ALL_SESSIONS: DWORD = 1;
 const HWND_MESSAGE: HWND = -3isize as HWND;
+const GWLP_USERDATA: INT = -21;
+pub(crate) const WM_QUIT: UINT = 0x0012;
+const WM_QUERYENDSESSION: UINT = 0x0011;
+const WM_ENDSESSION: UINT = 0x0016;
+const WM_TIMER: UINT = 0x0113;
+const WM_DISPLAYCHANGE: UINT = 0x007E;
+const WM_HOTKEY: UINT = 0x0312;
+const MOD_NOREPEAT: UINT = 0x4000;
+const SM_CXSCREEN: INT = 0;
+const SM_CYSCREEN: INT = 1;
+const SM_CMONITORS: INT = 80;
+const SM_XVIRTUALSCREEN: INT = 76;
+const SM_YVIRTUALSCREEN: INT = 77;
+const SM_CXVIRTUALSCREEN: INT = 78;
+const SM_CYVIRTUALSCREEN: INT = 79;
+const SRCCOPY: DWORD = 0x00CC_0020;
+const CAPTUREBLT: DWORD = 0x4000_0000;
+const BI_RGB: DWORD = 0;
+const DIB_RGB_COLORS: UINT = 0;
+/// Posted by the other [`Backend`](crate::Backend)s when events are waiting
+pub(crate) const WM_BACKEND: UINT = 0x8000 + 1;
+pub(crate) const EVT_SUBSCRIBE_ACTION_DELIVER: c_int = 1;
+pub(crate) const CTRL_CLOSE_EVENT: DWORD = 2;
+pub(crate) const CTRL_LOGOFF_EVENT: DWORD = 5;
+pub(crate) const CTRL_SHUTDOWN_EVENT: DWORD = 6;
+const EVT_SUBSCRIBE_TO_FUTURE_EVENTS: DWORD = 1;
+const EVT_QUERY_CHANNEL_PATH: DWORD = 0x1;
+const EVT_QUERY_REVERSE_DIRECTION: DWORD = 0x200;
+const EVT_RENDER_CONTEXT_VALUES: DWORD = 0;
+const EVT_RENDER_EVENT_VALUES: DWORD = 0;
+const EVT_VAR_TYPE_NULL: DWORD = 0;
+const SPI_GETSCREENSAVERRUNNING: UINT = 0x0072;
+const WM_DEVICECHANGE: UINT = 0x0219;
+const DBT_DEVICEARRIVAL: WPARAM = 0x8000;
+const DBT_DEVICEREMOVECOMPLETE: WPARAM = 0x8004;
+const DBT_DEVTYP_DEVICEINTERFACE: DWORD = 0x5;
+const GUID_DEVINTERFACE_USB_DEVICE: GUID = GUID {
+    Data1: 0xa5dcbf10,
+    Data2: 0x6530,
+    Data3: 0x11d2,
+    Data4: [0x90, 0x1f, 0x00, 0xc0, 0x4f, 0xb9, 0x51, 0xed],
+};
+const PROCESS_QUERY_LIMITED_INFORMATION: DWORD = 0x1000;
+const PROCESS_TERMINATE: DWORD = 0x0001;
+const SYNCHRONIZE: DWORD = 0x0010_0000;
+const TH32CS_SNAPPROCESS: DWORD = 0x2;
+const INVALID_HANDLE_VALUE: HANDLE = -1isize as HANDLE;
+const WM_CLOSE: UINT = 0x0010;
+const WM_SYSCOMMAND: UINT = 0x0112;
+const SC_MONITORPOWER: WPARAM = 0xF170;
+/// The lParam of SC_MONITORPOWER that powers the displays off
+const MONITOR_POWER_OFF: LPARAM = 2;
+const HWND_BROADCAST: HWND = 0xFFFF as HWND;
+const CF_UNICODETEXT: UINT = 13;
+const ERROR_BUFFER_TOO_SMALL: DWORD = 603;
+const RESOURCETYPE_DISK: DWORD = 0x1;
+const GUID_DEVINTERFACE_DISK: GUID = GUID {
+    Data1: 0x53f56307,
+    Data2: 0xb6bf,
+    Data3: 0x11d0,
+    Data4: [0x94, 0xf2, 0x00, 0xa0, 0xc9, 0x1e, 0xfb, 0x8b],
+};
+const DIGCF_PRESENT: DWORD = 0x2;
+const DIGCF_DEVICEINTERFACE: DWORD = 0x10;
+const SPDRP_FRIENDLYNAME: DWORD = 0xC;
+const SPDRP_REMOVAL_POLICY: DWORD = 0x1F;
+const CM_REMOVAL_POLICY_EXPECT_NO_REMOVAL: DWORD = 1;
+const CR_SUCCESS: u32 = 0;
+/// CONFIGRET when a program or driver refused an eject
+const CR_REMOVE_VETOED: u32 = 0x17;
+const SC_MANAGER_CONNECT: DWORD = 0x1;
+const SERVICE_QUERY_STATUS: DWORD = 0x4;
+const SERVICE_START: DWORD = 0x10;
+const SERVICE_STOP: DWORD = 0x20;
+const SERVICE_CONTROL_STOP: DWORD = 0x1;
+pub(crate) const SERVICE_STOPPED: DWORD = 0x1;
+pub(crate) const SERVICE_RUNNING: DWORD = 0x4;
+const QUNS_BUSY: c_int = 2;
+const QUNS_RUNNING_D3D_FULL_SCREEN: c_int = 3;
+const QUNS_PRESENTATION_MODE: c_int = 4;
+const ENDSESSION_LOGOFF: LPARAM = 0x80000000u32 as LPARAM;
+const WM_WTSSESSION_CHANGE: UINT = 0x02B1;
+pub(crate) const WTS_CURRENT_SERVER_HANDLE: HANDLE = null_mut();
+pub(crate) const WTS_USER_NAME: WTS_INFO_CLASS = 5;
+pub(crate) const WTS_DOMAIN_NAME: WTS_INFO_CLASS = 7;
+pub(crate) const WTS_CLIENT_NAME: WTS_INFO_CLASS = 10;
+pub(crate) const WTS_CLIENT_ADDRESS: WTS_INFO_CLASS = 14;
+pub(crate) const WTS_CLIENT_PROTOCOL_TYPE: WTS_INFO_CLASS = 16;
+pub(crate) const WTS_SESSION_INFO_EX: WTS_INFO_CLASS = 25;
+const DESKTOP_SWITCHDESKTOP: ACCESS_MASK = 0x0100;
+const WM_POWERBROADCAST: UINT = 0x0218;
+const PBT_APMSUSPEND: WPARAM = 0x4;
+const PBT_APMRESUMEAUTOMATIC: WPARAM = 0x12;
+const PBT_POWERSETTINGCHANGE: WPARAM = 0x8013;
+pub(crate) const GUID_CONSOLE_DISPLAY_STATE: GUID = GUID {
+    Data1: 0x6fe69556,
+    Data2: 0x704a,
+    Data3: 0x47a0,
+    Data4: [0x8f, 0x24, 0xc2, 0x8d, 0x93, 0x6f, 0xda, 0x47],
+};
+pub(crate) const GUID_LIDSWITCH_STATE_CHANGE: GUID = GUID {
+    Data1: 0xba3e0f4d,
+    Data2: 0xb817,
+    Data3: 0x4094,
+    Data4: [0xa2, 0xd1, 0xd5, 0x63, 0x79, 0xe6, 0xa0, 0xf3],
+};
+pub(crate) const GUID_ACDC_POWER_SOURCE: GUID = GUID {
+    Data1: 0x5d3e9a59,
+    Data2: 0xe9d5,
+    Data3: 0x4b00,
+    Data4: [0xa6, 0xbd, 0xff, 0x34, 0xff, 0x51, 0x65, 0x48],
+};
+const DEVICE_NOTIFY_WINDOW_HANDLE: DWORD = 0x0;
+const AF_UNSPEC: u16 = 0;
+const NO_ERROR: DWORD = 0;
+const IF_TYPE_SOFTWARE_LOOPBACK: u32 = 24;
+pub(crate) const IF_TYPE_IEEE80211: u32 = 71;
+const IF_OPER_STATUS_UP: u32 = 1;
+const MEDIA_CONNECT_STATE_CONNECTED: u32 = 1;
+/// The FilterInterface bit of InterfaceAndOperStatusFlags
+const FILTER_INTERFACE: u8 = 0x2;
+const WLAN_API_VERSION_2_0: DWORD = 2;
+const WLAN_INTF_OPCODE_CURRENT_CONNECTION: c_int = 7;
+/// WLAN_CONNECTION_ATTRIBUTES.wlanAssociationAttributes.dot11Ssid, after
+/// the isState and wlanConnectionMode DWORDs and the 256 WCHAR profile name
+const WLAN_CONNECTION_SSID: usize = 520;
+const LAST_WAKE_TIME: c_int = 14;
+const LAST_SLEEP_TIME: c_int = 15;
+const VER_SUITENAME: DWORD = 0x40;
+const VER_AND: u8 = 6;
+const VER_SUITE_TERMINAL: u16 = 0x10;
+const VER_SUITE_SINGLEUSERTS: u16 = 0x100;
+pub(crate) const MB_OK: DWORD = 0x0;
+pub(crate) const MB_OKCANCEL: DWORD = 0x1;
+pub(crate) const MB_YESNO: DWORD = 0x4;
+pub(crate) const MB_ICONINFORMATION: DWORD = 0x40;
+const CREATE_UNICODE_ENVIRONMENT: DWORD = 0x400;
+const CREATE_NO_WINDOW: DWORD = 0x0800_0000;
+const CREATE_SUSPENDED: DWORD = 0x4;
+const INFINITE: DWORD = 0xFFFF_FFFF;
+const WAIT_OBJECT_0: DWORD = 0;
+const WAIT_TIMEOUT: DWORD = 0x102;
+const STARTF_USESTDHANDLES: DWORD = 0x100;
+const WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY: DWORD = 4;
+const WINHTTP_FLAG_SECURE: DWORD = 0x0080_0000;
+const WINHTTP_QUERY_STATUS_CODE: DWORD = 19;
+const WINHTTP_QUERY_FLAG_NUMBER: DWORD = 0x2000_0000;
+const HANDLE_FLAG_INHERIT: DWORD = 0x1;
+const FILE_LIST_DIRECTORY: DWORD = 0x1;
+const FILE_SHARE_READ: DWORD = 0x1;
+const FILE_SHARE_WRITE: DWORD = 0x2;
+const FILE_SHARE_DELETE: DWORD = 0x4;
+const OPEN_EXISTING: DWORD = 3;
+const FILE_FLAG_BACKUP_SEMANTICS: DWORD = 0x0200_0000;
+const FILE_NOTIFY_CHANGE_FILE_NAME: DWORD = 0x1;
+const FILE_NOTIFY_CHANGE_LAST_WRITE: DWORD = 0x10;
+const GENERIC_READ: DWORD = 0x8000_0000;
+const CRED_TYPE_GENERIC: DWORD = 1;
+const CRYPTPROTECT_UI_FORBIDDEN: DWORD = 0x1;
+const CRYPTPROTECT_LOCAL_MACHINE: DWORD = 0x4;
+const CRYPT_STRING_BASE64: DWORD = 0x1;
+const CRYPT_STRING_NOCRLF: DWORD = 0x4000_0000;
+const GENERIC_WRITE: DWORD = 0x4000_0000;
+const PIPE_ACCESS_DUPLEX: DWORD = 0x3;
+const FILE_FLAG_FIRST_PIPE_INSTANCE: DWORD = 0x0008_0000;
+const PIPE_TYPE_MESSAGE: DWORD = 0x4;
+const PIPE_READMODE_MESSAGE: DWORD = 0x2;
+const PIPE_REJECT_REMOTE_CLIENTS: DWORD = 0x8;
+const ERROR_PIPE_CONNECTED: DWORD = 535;
+const ERROR_BROKEN_PIPE: DWORD = 109;
+/// The longest request or response sent over a [`NamedPipe`]
+const PIPE_BUFFER: usize = 4096;
 
-#[derive(Debug)]
+/// Which sessions a window receives notifications for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionScope {
+    /// Only the session the window was created in
+    #[default]
+    ThisSession,
+    /// Every session on the machine
+    AllSessions,
+}
+
+impl SessionScope {
+    /// [`SessionScope::AllSessions`] if `all_sessions` is set
+    pub fn from_all_sessions(all_sessions: bool) -> Self {
+        if all_sessions {
+            Self::AllSessions
+        } else {
+            Self::ThisSession
+        }
+    }
+
+    /// [`SessionScope::AllSessions`] on a Remote Desktop Session Host, where
+    /// many users are logged on at once, otherwise
+    /// [`SessionScope::ThisSession`]
+    pub fn detect() -> Self {
+        Self::from_all_sessions(is_terminal_server())
+    }
+
+    fn flags(self) -> DWORD {
+        match self {
+            Self::ThisSession => NOTIFY_FOR_THIS_SESSION,
+            Self::AllSessions => NOTIFY_FOR_ALL_SESSIONS,
+        }
+    }
+}
+
+/// The connection state of a session, WTS_CONNECTSTATE_CLASS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum ConnectState {
+    Active,
+    Connected,
+    ConnectQuery,
+    Shadow,
+    Disconnected,
+    Idle,
+    Listen,
+    Reset,
+    Down,
+    Init,
+    /// A state this version does not know about yet
+    Unknown(i32),
+}
+
+impl From<WTS_CONNECTSTATE_CLASS> for ConnectState {
+    fn from(state: WTS_CONNECTSTATE_CLASS) -> Self {
+        match state {
+            0 => Self::Active,
+            1 => Self::Connected,
+            2 => Self::ConnectQuery,
+            3 => Self::Shadow,
+            4 => Self::Disconnected,
+            5 => Self::Idle,
+            6 => Self::Listen,
+            7 => Self::Reset,
+            8 => Self::Down,
+            9 => Self::Init,
+            _ => Self::Unknown(state),
+        }
+    }
+}
+
+/// The kind of a [`SessionEvent`](crate::SessionEvent). Converts to and from
+/// the WTS status codes, notifications that don't come from WTS use our own
+/// codes from 0x100 up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum WtsState {
     ConsoleConnect,
     ConsoleDisconnect,
@@ -53,30 +342,511 @@ pub enum WtsState {
     Lock,
     Unlock,
     RemoteControl,
+    /// The machine is about to sleep or hibernate. Windows only waits about
+    /// two seconds for this to be handled
+    Suspend,
+    /// The machine woke up from sleep or hibernation
+    Resume,
+    /// The display turned off. The current display state is also sent as
+    /// soon as the monitor is created
+    DisplayOff,
+    DisplayOn,
+    DisplayDimmed,
+    /// The laptop lid was shut. Like the display and power source states
+    /// the current lid state is sent as soon as the monitor is created
+    LidClosed,
+    LidOpened,
+    /// Switched to mains power
+    AcPower,
+    /// Switched to battery power
+    BatteryPower,
+    /// Switched to a short term source such as a UPS
+    UpsPower,
+    /// Windows is asking whether it may shut down, handlers get to run
+    /// before the session ends
+    ShutdownPending,
+    /// Our session is about to be logged off
+    LogoffPending,
+    /// The screensaver started, seen by polling so up to a poll interval late
+    ScreensaverStart,
+    ScreensaverStop,
+    /// Presence changed, see [`Presence`](crate::Presence). Only for our own
+    /// session and needs [`SessionMonitorBuilder::away_after`](crate::SessionMonitorBuilder::away_after)
+    Present,
+    Away,
+    Locked,
+    /// The input desktop became one we can't switch to, such as a UAC prompt
+    /// or Winlogon. Seen by polling so up to a poll interval late
+    SecureDesktopEnter,
+    SecureDesktopLeave,
+    /// Fast User Switching moved the console from this session to
+    /// [`SessionEvent::other_session_id`](crate::SessionEvent::other_session_id)
+    SwitchedAway,
+    /// The console came back to a session it was switched away from
+    SwitchedBack,
+    /// A USB device was plugged in, see
+    /// [`SessionEvent::device`](crate::SessionEvent::device)
+    DeviceArrived,
+    DeviceRemoved,
+    /// The monitor re-registered for session notifications after a resume or
+    /// after missing a lock state change
+    Reregistered,
+    /// The session is ending after a LogoffPending or ShutdownPending went
+    /// ahead, from WM_ENDSESSION. Its handlers run before the window procedure
+    /// returns as the process can be ended any time after
+    SessionEnding,
+    /// A monitor was plugged in, see
+    /// [`SessionEvent::display`](crate::SessionEvent::display). Needs a top
+    /// level window so it is only seen when asked for
+    MonitorAdded,
+    MonitorRemoved,
+    /// The resolution of the primary monitor changed
+    ResolutionChanged,
+    /// A hotkey registered with
+    /// [`SessionMonitorBuilder::hotkey`](crate::SessionMonitorBuilder::hotkey)
+    /// was pressed, for hotkeys that don't stand in for another state
+    Hotkey,
+    /// A network interface came up with media connected, see
+    /// [`SessionEvent::network`](crate::SessionEvent::network)
+    NetworkConnected,
+    NetworkDisconnected,
+    /// A minute a schedule given to
+    /// [`SessionMonitorBuilder::schedule`](crate::SessionMonitorBuilder::schedule)
+    /// matches began, see [`SessionEvent::schedule`](crate::SessionEvent::schedule)
+    Scheduled,
+    /// A status code this version does not know about yet
+    Unknown(usize),
+}
+
+impl From<usize> for WtsState {
+    fn from(wparam: usize) -> Self {
+        match wparam {
+            0x1 => Self::ConsoleConnect,
+            0x2 => Self::ConsoleDisconnect,
+            0x3 => Self::RemoteConnect,
+            0x4 => Self::RemoteDisconnnect,
+            0x5 => Self::Logon,
+            0x6 => Self::Logoff,
+            0x7 => Self::Lock,
+            0x8 => Self::Unlock,
+            0x9 => Self::RemoteControl,
+            0x100 => Self::Suspend,
+            0x101 => Self::Resume,
+            0x102 => Self::DisplayOff,
+            0x103 => Self::DisplayOn,
+            0x104 => Self::DisplayDimmed,
+            0x105 => Self::LidClosed,
+            0x106 => Self::LidOpened,
+            0x107 => Self::AcPower,
+            0x108 => Self::BatteryPower,
+            0x109 => Self::UpsPower,
+            0x10A => Self::ShutdownPending,
+            0x10B => Self::LogoffPending,
+            0x10C => Self::ScreensaverStart,
+            0x10D => Self::ScreensaverStop,
+            0x10E => Self::Present,
+            0x10F => Self::Away,
+            0x110 => Self::Locked,
+            0x111 => Self::SecureDesktopEnter,
+            0x112 => Self::SecureDesktopLeave,
+            0x113 => Self::SwitchedAway,
+            0x114 => Self::SwitchedBack,
+            0x115 => Self::DeviceArrived,
+            0x116 => Self::DeviceRemoved,
+            0x117 => Self::Reregistered,
+            0x118 => Self::SessionEnding,
+            0x119 => Self::NetworkConnected,
+            0x11A => Self::NetworkDisconnected,
+            0x11B => Self::MonitorAdded,
+            0x11C => Self::MonitorRemoved,
+            0x11D => Self::ResolutionChanged,
+            0x11E => Self::Hotkey,
+            0x11F => Self::Scheduled,
+            _ => {
+                event!(Level::WARN, "{wparam} is not a known WtsState");
+                Self::Unknown(wparam)
+            }
+        }
+    }
+}
+
+impl From<WtsState> for usize {
+    fn from(state: WtsState) -> Self {
+        match state {
+            WtsState::ConsoleConnect => 0x1,
+            WtsState::ConsoleDisconnect => 0x2,
+            WtsState::RemoteConnect => 0x3,
+            WtsState::RemoteDisconnnect => 0x4,
+            WtsState::Logon => 0x5,
+            WtsState::Logoff => 0x6,
+            WtsState::Lock => 0x7,
+            WtsState::Unlock => 0x8,
+            WtsState::RemoteControl => 0x9,
+            WtsState::Suspend => 0x100,
+            WtsState::Resume => 0x101,
+            WtsState::DisplayOff => 0x102,
+            WtsState::DisplayOn => 0x103,
+            WtsState::DisplayDimmed => 0x104,
+            WtsState::LidClosed => 0x105,
+            WtsState::LidOpened => 0x106,
+            WtsState::AcPower => 0x107,
+            WtsState::BatteryPower => 0x108,
+            WtsState::UpsPower => 0x109,
+            WtsState::ShutdownPending => 0x10A,
+            WtsState::LogoffPending => 0x10B,
+            WtsState::ScreensaverStart => 0x10C,
+            WtsState::ScreensaverStop => 0x10D,
+            WtsState::Present => 0x10E,
+            WtsState::Away => 0x10F,
+            WtsState::Locked => 0x110,
+            WtsState::SecureDesktopEnter => 0x111,
+            WtsState::SecureDesktopLeave => 0x112,
+            WtsState::SwitchedAway => 0x113,
+            WtsState::SwitchedBack => 0x114,
+            WtsState::DeviceArrived => 0x115,
+            WtsState::DeviceRemoved => 0x116,
+            WtsState::Reregistered => 0x117,
+            WtsState::SessionEnding => 0x118,
+            WtsState::NetworkConnected => 0x119,
+            WtsState::NetworkDisconnected => 0x11A,
+            WtsState::MonitorAdded => 0x11B,
+            WtsState::MonitorRemoved => 0x11C,
+            WtsState::ResolutionChanged => 0x11D,
+            WtsState::Hotkey => 0x11E,
+            WtsState::Scheduled => 0x11F,
+            WtsState::Unknown(wparam) => wparam,
+        }
+    }
 }
 
-impl TryFrom<usize> for WtsState {
-    type Error = ();
-
-    fn try_from(lparam: usize) -> Result<Self, Self::Error> {
-        match lparam {
-            0x1 => Ok(Self::ConsoleConnect),
-            0x2 => Ok(Self::ConsoleDisconnect),
-            0x3 => Ok(Self::RemoteConnect),
-            0x4 => Ok(Self::RemoteDisconnnect),
-            0x5 => Ok(Self::Logon),
-            0x6 => Ok(Self::Logoff),
-            0x7 => Ok(Self::Lock),
-            0x8 => Ok(Self::Unlock),
-            0x9 => Ok(Self::RemoteControl),
+impl WtsState {
+    /// The snake case name used for this state by the bindings and config,
+    /// eg. `remote_disconnect`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ConsoleConnect => "console_connect",
+            Self::ConsoleDisconnect => "console_disconnect",
+            Self::RemoteConnect => "remote_connect",
+            Self::RemoteDisconnnect => "remote_disconnect",
+            Self::Logon => "logon",
+            Self::Logoff => "logoff",
+            Self::Lock => "lock",
+            Self::Unlock => "unlock",
+            Self::RemoteControl => "remote_control",
+            Self::Suspend => "suspend",
+            Self::Resume => "resume",
+            Self::DisplayOff => "display_off",
+            Self::DisplayOn => "display_on",
+            Self::DisplayDimmed => "display_dimmed",
+            Self::LidClosed => "lid_closed",
+            Self::LidOpened => "lid_opened",
+            Self::AcPower => "ac_power",
+            Self::BatteryPower => "battery_power",
+            Self::UpsPower => "ups_power",
+            Self::ShutdownPending => "shutdown_pending",
+            Self::LogoffPending => "logoff_pending",
+            Self::ScreensaverStart => "screensaver_start",
+            Self::ScreensaverStop => "screensaver_stop",
+            Self::Present => "present",
+            Self::Away => "away",
+            Self::Locked => "locked",
+            Self::SecureDesktopEnter => "secure_desktop_enter",
+            Self::SecureDesktopLeave => "secure_desktop_leave",
+            Self::SwitchedAway => "switched_away",
+            Self::SwitchedBack => "switched_back",
+            Self::DeviceArrived => "device_arrived",
+            Self::DeviceRemoved => "device_removed",
+            Self::Reregistered => "reregistered",
+            Self::SessionEnding => "session_ending",
+            Self::NetworkConnected => "network_connected",
+            Self::NetworkDisconnected => "network_disconnected",
+            Self::MonitorAdded => "monitor_added",
+            Self::MonitorRemoved => "monitor_removed",
+            Self::ResolutionChanged => "resolution_changed",
+            Self::Hotkey => "hotkey",
+            Self::Scheduled => "scheduled",
+            Self::Unknown(_) => "unknown",
+        }
+    }
+}
+
+impl core::str::FromStr for WtsState {
+    type Err = ();
+
+    /// Parse a name returned by [`WtsState::name`], `unknown` is rejected
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "console_connect" => Ok(Self::ConsoleConnect),
+            "console_disconnect" => Ok(Self::ConsoleDisconnect),
+            "remote_connect" => Ok(Self::RemoteConnect),
+            "remote_disconnect" => Ok(Self::RemoteDisconnnect),
+            "logon" => Ok(Self::Logon),
+            "logoff" => Ok(Self::Logoff),
+            "lock" => Ok(Self::Lock),
+            "unlock" => Ok(Self::Unlock),
+            "remote_control" => Ok(Self::RemoteControl),
+            "suspend" => Ok(Self::Suspend),
+            "resume" => Ok(Self::Resume),
+            "display_off" => Ok(Self::DisplayOff),
+            "display_on" => Ok(Self::DisplayOn),
+            "display_dimmed" => Ok(Self::DisplayDimmed),
+            "lid_closed" => Ok(Self::LidClosed),
+            "lid_opened" => Ok(Self::LidOpened),
+            "ac_power" => Ok(Self::AcPower),
+            "battery_power" => Ok(Self::BatteryPower),
+            "ups_power" => Ok(Self::UpsPower),
+            "shutdown_pending" => Ok(Self::ShutdownPending),
+            "logoff_pending" => Ok(Self::LogoffPending),
+            "screensaver_start" => Ok(Self::ScreensaverStart),
+            "screensaver_stop" => Ok(Self::ScreensaverStop),
+            "present" => Ok(Self::Present),
+            "away" => Ok(Self::Away),
+            "locked" => Ok(Self::Locked),
+            "secure_desktop_enter" => Ok(Self::SecureDesktopEnter),
+            "secure_desktop_leave" => Ok(Self::SecureDesktopLeave),
+            "switched_away" => Ok(Self::SwitchedAway),
+            "switched_back" => Ok(Self::SwitchedBack),
+            "device_arrived" => Ok(Self::DeviceArrived),
+            "device_removed" => Ok(Self::DeviceRemoved),
+            "reregistered" => Ok(Self::Reregistered),
+            "session_ending" => Ok(Self::SessionEnding),
+            "network_connected" => Ok(Self::NetworkConnected),
+            "network_disconnected" => Ok(Self::NetworkDisconnected),
+            "monitor_added" => Ok(Self::MonitorAdded),
+            "monitor_removed" => Ok(Self::MonitorRemoved),
+            "resolution_changed" => Ok(Self::ResolutionChanged),
+            "hotkey" => Ok(Self::Hotkey),
+            "scheduled" => Ok(Self::Scheduled),
             _ => {
-                event!(Level::ERROR, "{lparam} is not a valid WtsState");
+                event!(Level::ERROR, "{name} is not a valid WtsState");
                 Err(())
             }
         }
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct SYSTEMTIME {
+    pub wYear: u16,
+    pub wMonth: u16,
+    /// Sunday is 0
+    pub wDayOfWeek: u16,
+    pub wDay: u16,
+    pub wHour: u16,
+    pub wMinute: u16,
+    pub wSecond: u16,
+    pub wMilliseconds: u16,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct BITMAPINFOHEADER {
+    biSize: DWORD,
+    biWidth: LONG,
+    biHeight: LONG,
+    biPlanes: u16,
+    biBitCount: u16,
+    biCompression: DWORD,
+    biSizeImage: DWORD,
+    biXPelsPerMeter: LONG,
+    biYPelsPerMeter: LONG,
+    biClrUsed: DWORD,
+    biClrImportant: DWORD,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct BITMAPINFO {
+    bmiHeader: BITMAPINFOHEADER,
+    bmiColors: [DWORD; 1],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct GUID {
+    pub Data1: u32,
+    pub Data2: u16,
+    pub Data3: u16,
+    pub Data4: [u8; 8],
+}
+
+impl GUID {
+    /// The registry format, eg. `{6FE69556-704A-47A0-8F24-C28D936FDA47}`
+    pub fn to_braced_string(self) -> String {
+        let mut buf = [0u16; 39];
+        let len = unsafe {
+            StringFromGUID2(&self, buf.as_mut_ptr(), buf.len() as c_int)
+        };
+        // The length includes the null terminator
+        String::from_utf16_lossy(&buf[..(len.max(1) - 1) as usize])
+    }
+}
+
+#[repr(C)]
+struct LASTINPUTINFO {
+    cbSize: UINT,
+    dwTime: DWORD,
+}
+
+#[repr(C)]
+struct STARTUPINFOW {
+    cb: DWORD,
+    lpReserved: LPWSTR,
+    lpDesktop: LPWSTR,
+    lpTitle: LPWSTR,
+    dwX: DWORD,
+    dwY: DWORD,
+    dwXSize: DWORD,
+    dwYSize: DWORD,
+    dwXCountChars: DWORD,
+    dwYCountChars: DWORD,
+    dwFillAttribute: DWORD,
+    dwFlags: DWORD,
+    wShowWindow: u16,
+    cbReserved2: u16,
+    lpReserved2: *mut u8,
+    hStdInput: HANDLE,
+    hStdOutput: HANDLE,
+    hStdError: HANDLE,
+}
+
+#[repr(C)]
+struct SP_DEVINFO_DATA {
+    cbSize: DWORD,
+    ClassGuid: GUID,
+    DevInst: DWORD,
+    Reserved: UINT_PTR,
+}
+
+#[repr(C)]
+struct NETRESOURCEW {
+    dwScope: DWORD,
+    dwType: DWORD,
+    dwDisplayType: DWORD,
+    dwUsage: DWORD,
+    lpLocalName: LPWSTR,
+    lpRemoteName: LPWSTR,
+    lpComment: LPWSTR,
+    lpProvider: LPWSTR,
+}
+
+#[repr(C)]
+struct DATA_BLOB {
+    cbData: DWORD,
+    pbData: *mut u8,
+}
+
+#[repr(C)]
+struct CREDENTIALW {
+    Flags: DWORD,
+    Type: DWORD,
+    TargetName: LPWSTR,
+    Comment: LPWSTR,
+    /// A FILETIME
+    LastWritten: [DWORD; 2],
+    CredentialBlobSize: DWORD,
+    CredentialBlob: *mut u8,
+    Persist: DWORD,
+    AttributeCount: DWORD,
+    Attributes: LPVOID,
+    TargetAlias: LPWSTR,
+    UserName: LPWSTR,
+}
+
+/// The Windows 7 layout, sized in dwSize
+#[repr(C)]
+struct RASCONNW {
+    dwSize: DWORD,
+    hrasconn: HANDLE,
+    szEntryName: [WCHAR; 257],
+    szDeviceType: [WCHAR; 17],
+    szDeviceName: [WCHAR; 129],
+    szPhonebook: [WCHAR; 260],
+    dwSubEntry: DWORD,
+    guidEntry: GUID,
+    dwFlags: DWORD,
+    luid: LUID,
+    guidCorrelationId: GUID,
+}
+
+#[repr(C)]
+struct SERVICE_STATUS {
+    dwServiceType: DWORD,
+    dwCurrentState: DWORD,
+    dwControlsAccepted: DWORD,
+    dwWin32ExitCode: DWORD,
+    dwServiceSpecificExitCode: DWORD,
+    dwCheckPoint: DWORD,
+    dwWaitHint: DWORD,
+}
+
+#[repr(C)]
+struct PROCESSENTRY32W {
+    dwSize: DWORD,
+    cntUsage: DWORD,
+    th32ProcessID: DWORD,
+    th32DefaultHeapID: UINT_PTR,
+    th32ModuleID: DWORD,
+    cntThreads: DWORD,
+    th32ParentProcessID: DWORD,
+    pcPriClassBase: LONG,
+    dwFlags: DWORD,
+    szExeFile: [WCHAR; 260],
+}
+
+#[repr(C)]
+struct PROCESS_INFORMATION {
+    hProcess: HANDLE,
+    hThread: HANDLE,
+    dwProcessId: DWORD,
+    dwThreadId: DWORD,
+}
+
+/// Only the size and wSuiteMask are filled in, for VerifyVersionInfoW
+#[repr(C)]
+struct OSVERSIONINFOEXW {
+    dwOSVersionInfoSize: DWORD,
+    dwMajorVersion: DWORD,
+    dwMinorVersion: DWORD,
+    dwBuildNumber: DWORD,
+    dwPlatformId: DWORD,
+    szCSDVersion: [WCHAR; 128],
+    wServicePackMajor: u16,
+    wServicePackMinor: u16,
+    wSuiteMask: u16,
+    wProductType: u8,
+    wReserved: u8,
+}
+
+/// A rendered event value, the union is read as the integer types we ask for
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct EVT_VARIANT {
+    Value: u64,
+    Count: DWORD,
+    Type: DWORD,
+}
+
+/// What lParam points to for WM_DEVICECHANGE, the name is null terminated
+/// and runs past the end of the struct
+#[repr(C)]
+struct DEV_BROADCAST_DEVICEINTERFACE_W {
+    dbcc_size: DWORD,
+    dbcc_devicetype: DWORD,
+    dbcc_reserved: DWORD,
+    dbcc_classguid: GUID,
+    dbcc_name: [WCHAR; 1],
+}
+
+/// The header of the data lParam points to for PBT_POWERSETTINGCHANGE
+#[repr(C)]
+struct POWERBROADCAST_SETTING {
+    PowerSetting: GUID,
+    DataLength: DWORD,
+    Data: [u8; 1],
+}
+
 #[repr(C)]
 #[allow(non_snake_case)]
 struct WNDCLASSEXA {
@@ -107,6 +877,14 @@ struct MSG {
     lPrivate: DWORD,
 }
 
+#[repr(C)]
+#[allow(non_snake_case)]
+struct WTS_SESSION_INFOW {
+    SessionId: DWORD,
+    pWinStationName: LPWSTR,
+    State: WTS_CONNECTSTATE_CLASS,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 struct POINT {
@@ -124,6 +902,17 @@ pub enum Error {
     INVALID_HANDLE,
     ERROR_CANNOT_FIND_WND_CLASS,
     ERROR_WINDOW_OF_OTHER_THREAD,
+    ERROR_CLASS_ALREADY_EXISTS,
+    ERROR_CLASS_DOES_NOT_EXIST,
+    ERROR_CLASS_HAS_WINDOWS,
+    ERROR_HOTKEY_ALREADY_REGISTERED,
+    ERROR_DEVICE_NOT_CONNECTED,
+    ERROR_SERVICE_ALREADY_RUNNING,
+    ERROR_SERVICE_DOES_NOT_EXIST,
+    ERROR_SERVICE_NOT_ACTIVE,
+    RPC_S_SERVER_UNAVAILABLE,
+    /// A GetLastError code without a variant of its own yet
+    Other(DWORD),
 }
 
 impl core::fmt::Display for Error {
@@ -137,7 +926,20 @@ impl std::error::Error for self::Error {}
 impl Error {
     /// Rust wrapper around GetLastError()
     pub fn get_last() -> Self {
-        let err = unsafe { GetLastError() };
+        Self::from_code(unsafe { GetLastError() })
+    }
+
+    /// A failed HRESULT, the Win32 code if it wraps one
+    pub(crate) fn from_hresult(hr: HRESULT) -> Self {
+        match hr as u32 {
+            hr if hr & 0xFFFF_0000 == 0x8007_0000 => {
+                Self::from_code(hr & 0xFFFF)
+            }
+            hr => Self::Other(hr),
+        }
+    }
+
+    fn from_code(err: DWORD) -> Self {
         match err {
             5 => Self::NOT_SUPPORTED,
             6 => Self::INVALID_HANDLE,
@@ -146,25 +948,240 @@ impl Error {
             998 => Self::NOACCESS,
             1407 => Self::ERROR_CANNOT_FIND_WND_CLASS,
             1408 => Self::ERROR_WINDOW_OF_OTHER_THREAD,
-            _ => unimplemented!("GetLastError code: {err} not yet handled"),
+            1056 => Self::ERROR_SERVICE_ALREADY_RUNNING,
+            1060 => Self::ERROR_SERVICE_DOES_NOT_EXIST,
+            1062 => Self::ERROR_SERVICE_NOT_ACTIVE,
+            1167 => Self::ERROR_DEVICE_NOT_CONNECTED,
+            1409 => Self::ERROR_HOTKEY_ALREADY_REGISTERED,
+            1410 => Self::ERROR_CLASS_ALREADY_EXISTS,
+            1411 => Self::ERROR_CLASS_DOES_NOT_EXIST,
+            1412 => Self::ERROR_CLASS_HAS_WINDOWS,
+            1722 => Self::RPC_S_SERVER_UNAVAILABLE,
+            _ => Self::Other(err),
         }
     }
 }
 
 #[link(name = "Wtsapi32")]
 extern "system" {
-    fn WTSRegisterSessionNotification(hWnd: HWND, dwFlags: DWORD) -> bool;
-    fn WTSUnRegisterSessionNotification(hWnd: HWND);
+    fn WTSRegisterSessionNotification(hWnd: HWND, dwFlags: DWORD) -> BOOL;
+    fn WTSUnRegisterSessionNotification(hWnd: HWND) -> BOOL;
+    fn WTSOpenServerW(pServerName: LPWSTR) -> HANDLE;
+    fn WTSCloseServer(hServer: HANDLE);
+    fn WTSEnumerateSessionsW(
+        hServer: HANDLE,
+        Reserved: DWORD,
+        Version: DWORD,
+        ppSessionInfo: *mut *mut WTS_SESSION_INFOW,
+        pCount: *mut DWORD,
+    ) -> BOOL;
+    fn WTSFreeMemory(pMemory: LPVOID);
+    fn WTSDisconnectSession(
+        hServer: HANDLE,
+        SessionId: DWORD,
+        bWait: BOOL,
+    ) -> BOOL;
+    fn WTSLogoffSession(hServer: HANDLE, SessionId: DWORD, bWait: BOOL)
+        -> BOOL;
+    fn WTSSendMessageW(
+        hServer: HANDLE,
+        SessionId: DWORD,
+        pTitle: LPWSTR,
+        TitleLength: DWORD,
+        pMessage: LPWSTR,
+        MessageLength: DWORD,
+        Style: DWORD,
+        Timeout: DWORD,
+        pResponse: *mut DWORD,
+        bWait: BOOL,
+    ) -> BOOL;
+    fn WTSQueryUserToken(SessionId: DWORD, phToken: *mut HANDLE) -> BOOL;
+    fn WTSQuerySessionInformationW(
+        hServer: HANDLE,
+        SessionId: DWORD,
+        WTSInfoClass: WTS_INFO_CLASS,
+        ppBuffer: *mut LPWSTR,
+        pBytesReturned: *mut DWORD,
+    ) -> BOOL;
 }
 
 #[link(name = "Kernel32")]
 extern "system" {
+    fn GetTickCount() -> DWORD;
+    fn GetLocalTime(lpSystemTime: *mut SYSTEMTIME);
     fn GetLastError() -> DWORD;
     fn GetModuleHandleA(lpModuleName: LPCSTR) -> HMODULE;
+    fn LoadLibraryW(lpLibFileName: LPCWSTR) -> HMODULE;
+    fn GetProcAddress(hModule: HMODULE, lpProcName: LPCSTR) -> LPVOID;
+    fn FreeLibrary(hLibModule: HMODULE) -> BOOL;
+    fn GetCurrentThreadId() -> DWORD;
+    fn GetCurrentProcessId() -> DWORD;
+    fn ProcessIdToSessionId(dwProcessId: DWORD, pSessionId: *mut DWORD)
+        -> BOOL;
+    fn OpenProcess(
+        dwDesiredAccess: DWORD,
+        bInheritHandle: BOOL,
+        dwProcessId: DWORD,
+    ) -> HANDLE;
+    fn QueryFullProcessImageNameW(
+        hProcess: HANDLE,
+        dwFlags: DWORD,
+        lpExeName: LPWSTR,
+        lpdwSize: *mut DWORD,
+    ) -> BOOL;
+    fn CloseHandle(hObject: HANDLE) -> BOOL;
+    fn CreatePipe(
+        hReadPipe: *mut HANDLE,
+        hWritePipe: *mut HANDLE,
+        lpPipeAttributes: LPVOID,
+        nSize: DWORD,
+    ) -> BOOL;
+    fn SetHandleInformation(
+        hObject: HANDLE,
+        dwMask: DWORD,
+        dwFlags: DWORD,
+    ) -> BOOL;
+    fn ReadFile(
+        hFile: HANDLE,
+        lpBuffer: LPVOID,
+        nNumberOfBytesToRead: DWORD,
+        lpNumberOfBytesRead: *mut DWORD,
+        lpOverlapped: LPVOID,
+    ) -> BOOL;
+    fn CreateFileW(
+        lpFileName: LPCWSTR,
+        dwDesiredAccess: DWORD,
+        dwShareMode: DWORD,
+        lpSecurityAttributes: LPVOID,
+        dwCreationDisposition: DWORD,
+        dwFlagsAndAttributes: DWORD,
+        hTemplateFile: HANDLE,
+    ) -> HANDLE;
+    fn ReadDirectoryChangesW(
+        hDirectory: HANDLE,
+        lpBuffer: LPVOID,
+        nBufferLength: DWORD,
+        bWatchSubtree: BOOL,
+        dwNotifyFilter: DWORD,
+        lpBytesReturned: *mut DWORD,
+        lpOverlapped: LPVOID,
+        lpCompletionRoutine: LPVOID,
+    ) -> BOOL;
+    fn WriteFile(
+        hFile: HANDLE,
+        lpBuffer: *const c_void,
+        nNumberOfBytesToWrite: DWORD,
+        lpNumberOfBytesWritten: *mut DWORD,
+        lpOverlapped: LPVOID,
+    ) -> BOOL;
+    fn FlushFileBuffers(hFile: HANDLE) -> BOOL;
+    fn CreateNamedPipeW(
+        lpName: LPCWSTR,
+        dwOpenMode: DWORD,
+        dwPipeMode: DWORD,
+        nMaxInstances: DWORD,
+        nOutBufferSize: DWORD,
+        nInBufferSize: DWORD,
+        nDefaultTimeOut: DWORD,
+        lpSecurityAttributes: LPVOID,
+    ) -> HANDLE;
+    fn ConnectNamedPipe(hNamedPipe: HANDLE, lpOverlapped: LPVOID) -> BOOL;
+    fn DisconnectNamedPipe(hNamedPipe: HANDLE) -> BOOL;
+    fn LocalFree(hMem: LPVOID) -> LPVOID;
+    fn CreateJobObjectW(lpJobAttributes: LPVOID, lpName: LPCWSTR) -> HANDLE;
+    fn AssignProcessToJobObject(hJob: HANDLE, hProcess: HANDLE) -> BOOL;
+    fn TerminateJobObject(hJob: HANDLE, uExitCode: UINT) -> BOOL;
+    fn CreateProcessW(
+        lpApplicationName: LPCWSTR,
+        lpCommandLine: LPWSTR,
+        lpProcessAttributes: LPVOID,
+        lpThreadAttributes: LPVOID,
+        bInheritHandles: BOOL,
+        dwCreationFlags: DWORD,
+        lpEnvironment: LPVOID,
+        lpCurrentDirectory: LPCWSTR,
+        lpStartupInfo: *const STARTUPINFOW,
+        lpProcessInformation: *mut PROCESS_INFORMATION,
+    ) -> BOOL;
+    fn ResumeThread(hThread: HANDLE) -> DWORD;
+    fn WaitForSingleObject(hHandle: HANDLE, dwMilliseconds: DWORD) -> DWORD;
+    fn GetExitCodeProcess(hProcess: HANDLE, lpExitCode: *mut DWORD) -> BOOL;
+    fn TerminateProcess(hProcess: HANDLE, uExitCode: UINT) -> BOOL;
+    fn GlobalLock(hMem: HANDLE) -> LPVOID;
+    fn GlobalUnlock(hMem: HANDLE) -> BOOL;
+    fn CreateToolhelp32Snapshot(dwFlags: DWORD, th32ProcessID: DWORD)
+        -> HANDLE;
+    fn Process32FirstW(hSnapshot: HANDLE, lppe: *mut PROCESSENTRY32W) -> BOOL;
+    fn Process32NextW(hSnapshot: HANDLE, lppe: *mut PROCESSENTRY32W) -> BOOL;
+    fn SetConsoleCtrlHandler(
+        HandlerRoutine: PHANDLER_ROUTINE,
+        Add: BOOL,
+    ) -> BOOL;
+    fn VerifyVersionInfoW(
+        lpVersionInformation: *mut OSVERSIONINFOEXW,
+        dwTypeMask: DWORD,
+        dwlConditionMask: u64,
+    ) -> BOOL;
+    fn VerSetConditionMask(
+        ConditionMask: u64,
+        TypeMask: DWORD,
+        Condition: u8,
+    ) -> u64;
+}
+
+#[link(name = "Shell32")]
+extern "system" {
+    fn SHQueryUserNotificationState(pquns: *mut c_int) -> HRESULT;
 }
 
 #[link(name = "User32")]
 extern "system" {
+    fn RegisterSuspendResumeNotification(
+        hRecipient: HANDLE,
+        Flags: DWORD,
+    ) -> HPOWERNOTIFY;
+    fn UnregisterSuspendResumeNotification(Handle: HPOWERNOTIFY) -> BOOL;
+    fn RegisterPowerSettingNotification(
+        hRecipient: HANDLE,
+        PowerSettingGuid: *const GUID,
+        Flags: DWORD,
+    ) -> HPOWERNOTIFY;
+    fn UnregisterPowerSettingNotification(Handle: HPOWERNOTIFY) -> BOOL;
+    fn ShutdownBlockReasonCreate(hWnd: HWND, pwszReason: LPCWSTR) -> BOOL;
+    fn ShutdownBlockReasonDestroy(hWnd: HWND) -> BOOL;
+    fn SetTimer(
+        hWnd: HWND,
+        nIDEvent: UINT_PTR,
+        uElapse: UINT,
+        lpTimerFunc: LPVOID,
+    ) -> UINT_PTR;
+    fn KillTimer(hWnd: HWND, uIDEvent: UINT_PTR) -> BOOL;
+    fn GetLastInputInfo(plii: *mut LASTINPUTINFO) -> BOOL;
+    fn GetForegroundWindow() -> HWND;
+    fn EnumWindows(lpEnumFunc: WNDENUMPROC, lParam: LPARAM) -> BOOL;
+    fn OpenClipboard(hWndNewOwner: HWND) -> BOOL;
+    fn CloseClipboard() -> BOOL;
+    fn EmptyClipboard() -> BOOL;
+    fn IsClipboardFormatAvailable(format: UINT) -> BOOL;
+    fn GetClipboardData(uFormat: UINT) -> HANDLE;
+    fn GetSystemMetrics(nIndex: INT) -> INT;
+    fn RegisterHotKey(hWnd: HWND, id: INT, fsModifiers: UINT, vk: UINT)
+        -> BOOL;
+    fn UnregisterHotKey(hWnd: HWND, id: INT) -> BOOL;
+    fn RegisterDeviceNotificationW(
+        hRecipient: HANDLE,
+        NotificationFilter: LPVOID,
+        Flags: DWORD,
+    ) -> HDEVNOTIFY;
+    fn UnregisterDeviceNotification(Handle: HDEVNOTIFY) -> BOOL;
+    fn GetWindowThreadProcessId(hWnd: HWND, lpdwProcessId: *mut DWORD)
+        -> DWORD;
+    fn SystemParametersInfoW(
+        uiAction: UINT,
+        uiParam: UINT,
+        pvParam: LPVOID,
+        fWinIni: UINT,
+    ) -> BOOL;
     fn RegisterClassExA(unnamedParam1: WNDCLASSEXA) -> ATOM;
     fn DefWindowProcA(
         hWnd: HWND,
@@ -191,107 +1208,3581 @@ extern "system" {
         hWnd: HWND,
         wMsgFilterMin: UINT,
         wMsgFilterMax: UINT,
-    ) -> bool;
+    ) -> BOOL;
+    fn DispatchMessageA(lpMsg: *const MSG) -> LRESULT;
+    fn DestroyWindow(hWnd: HWND) -> BOOL;
+    fn OpenInputDesktop(
+        dwFlags: DWORD,
+        fInherit: BOOL,
+        dwDesiredAccess: ACCESS_MASK,
+    ) -> HDESK;
+    fn SwitchDesktop(hDesktop: HDESK) -> BOOL;
+    fn LockWorkStation() -> BOOL;
+    fn CloseDesktop(hDesktop: HDESK) -> BOOL;
+    fn UnregisterClassA(lpClassName: LPCSTR, hInstance: HINSTANCE) -> BOOL;
+    #[cfg_attr(target_pointer_width = "32", link_name = "SetWindowLongW")]
+    fn SetWindowLongPtrW(
+        hWnd: HWND,
+        nIndex: INT,
+        dwNewLong: LONG_PTR,
+    ) -> LONG_PTR;
+    #[cfg_attr(target_pointer_width = "32", link_name = "GetWindowLongW")]
+    fn GetWindowLongPtrW(hWnd: HWND, nIndex: INT) -> LONG_PTR;
+    fn PostThreadMessageA(
+        idThread: DWORD,
+        Msg: UINT,
+        wParam: WPARAM,
+        lParam: LPARAM,
+    ) -> BOOL;
+    fn PostMessageA(
+        hWnd: HWND,
+        Msg: UINT,
+        wParam: WPARAM,
+        lParam: LPARAM,
+    ) -> BOOL;
+    fn GetDC(hWnd: HWND) -> HDC;
+    fn ReleaseDC(hWnd: HWND, hDC: HDC) -> INT;
 }
 
-// Rust wrapper for GetModuleHandleA
-pub fn get_module_handle_a() -> HANDLE {
-    unsafe { GetModuleHandleA(null()) }
+#[link(name = "Gdi32")]
+extern "system" {
+    fn CreateCompatibleDC(hdc: HDC) -> HDC;
+    fn CreateCompatibleBitmap(hdc: HDC, cx: INT, cy: INT) -> HBITMAP;
+    fn SelectObject(hdc: HDC, h: HGDIOBJ) -> HGDIOBJ;
+    fn BitBlt(
+        hdc: HDC,
+        x: INT,
+        y: INT,
+        cx: INT,
+        cy: INT,
+        hdcSrc: HDC,
+        x1: INT,
+        y1: INT,
+        rop: DWORD,
+    ) -> BOOL;
+    fn GetDIBits(
+        hdc: HDC,
+        hbm: HBITMAP,
+        start: UINT,
+        cLines: UINT,
+        lpvBits: LPVOID,
+        lpbmi: *mut BITMAPINFO,
+        usage: UINT,
+    ) -> INT;
+    fn DeleteObject(ho: HGDIOBJ) -> BOOL;
+    fn DeleteDC(hdc: HDC) -> BOOL;
 }
 
-// Rust wrapper for RegisterClassExA
-fn register_class_ex_a(window_class: WNDCLASSEXA) -> Option<ATOM> {
-    let res = unsafe { RegisterClassExA(window_class) };
+#[link(name = "Ole32")]
+extern "system" {
+    fn CoInitializeEx(pvReserved: LPVOID, dwCoInit: DWORD) -> HRESULT;
+    fn CoUninitialize();
+    fn CoCreateInstance(
+        rclsid: *const GUID,
+        pUnkOuter: LPVOID,
+        dwClsContext: DWORD,
+        riid: *const GUID,
+        ppv: *mut LPVOID,
+    ) -> HRESULT;
+    fn CoCreateGuid(pguid: *mut GUID) -> HRESULT;
+    fn CoTaskMemFree(pv: LPVOID);
+    fn StringFromGUID2(
+        rguid: *const GUID,
+        lpsz: LPWSTR,
+        cchMax: c_int,
+    ) -> c_int;
+    fn CoSetProxyBlanket(
+        pProxy: LPVOID,
+        dwAuthnSvc: DWORD,
+        dwAuthzSvc: DWORD,
+        pServerPrincName: LPWSTR,
+        dwAuthnLevel: DWORD,
+        dwImpLevel: DWORD,
+        pAuthInfo: LPVOID,
+        dwCapabilities: DWORD,
+    ) -> HRESULT;
+}
+
+#[link(name = "OleAut32")]
+extern "system" {
+    fn SysAllocString(psz: LPCWSTR) -> BSTR;
+    fn SysFreeString(bstrString: BSTR);
+    fn VariantClear(pvarg: *mut VARIANT) -> HRESULT;
+}
+
+#[link(name = "Wevtapi")]
+extern "system" {
+    fn EvtSubscribe(
+        Session: EVT_HANDLE,
+        SignalEvent: HANDLE,
+        ChannelPath: LPCWSTR,
+        Query: LPCWSTR,
+        Bookmark: EVT_HANDLE,
+        Context: LPVOID,
+        Callback: EVT_SUBSCRIBE_CALLBACK,
+        Flags: DWORD,
+    ) -> EVT_HANDLE;
+    fn EvtCreateRenderContext(
+        ValuePathsCount: DWORD,
+        ValuePaths: *const LPCWSTR,
+        Flags: DWORD,
+    ) -> EVT_HANDLE;
+    fn EvtRender(
+        Context: EVT_HANDLE,
+        Fragment: EVT_HANDLE,
+        Flags: DWORD,
+        BufferSize: DWORD,
+        Buffer: LPVOID,
+        BufferUsed: *mut DWORD,
+        PropertyCount: *mut DWORD,
+    ) -> BOOL;
+    fn EvtClose(Object: EVT_HANDLE) -> BOOL;
+    fn EvtQuery(
+        Session: EVT_HANDLE,
+        Path: LPCWSTR,
+        Query: LPCWSTR,
+        Flags: DWORD,
+    ) -> EVT_HANDLE;
+    fn EvtNext(
+        ResultSet: EVT_HANDLE,
+        EventsSize: DWORD,
+        Events: *mut EVT_HANDLE,
+        Timeout: DWORD,
+        Flags: DWORD,
+        Returned: *mut DWORD,
+    ) -> BOOL;
+}
+
+#[link(name = "Iphlpapi")]
+extern "system" {
+    fn NotifyIpInterfaceChange(
+        Family: u16,
+        Callback: PIPINTERFACE_CHANGE_CALLBACK,
+        CallerContext: LPVOID,
+        InitialNotification: u8,
+        NotificationHandle: *mut HANDLE,
+    ) -> DWORD;
+    fn CancelMibChangeNotify2(NotificationHandle: HANDLE) -> DWORD;
+    fn GetIfEntry2(Row: *mut MIB_IF_ROW2) -> DWORD;
+    fn GetIfTable2(Table: *mut *mut MIB_IF_TABLE2) -> DWORD;
+    fn FreeMibTable(Memory: LPVOID);
+}
+
+#[link(name = "Wlanapi")]
+extern "system" {
+    fn WlanOpenHandle(
+        dwClientVersion: DWORD,
+        pReserved: LPVOID,
+        pdwNegotiatedVersion: *mut DWORD,
+        phClientHandle: *mut HANDLE,
+    ) -> DWORD;
+    fn WlanQueryInterface(
+        hClientHandle: HANDLE,
+        pInterfaceGuid: *const GUID,
+        OpCode: c_int,
+        pReserved: LPVOID,
+        pdwDataSize: *mut DWORD,
+        ppData: *mut LPVOID,
+        pWlanOpcodeValueType: *mut c_int,
+    ) -> DWORD;
+    fn WlanFreeMemory(pMemory: LPVOID);
+    fn WlanCloseHandle(hClientHandle: HANDLE, pReserved: LPVOID) -> DWORD;
+}
+
+#[link(name = "PowrProf")]
+extern "system" {
+    fn CallNtPowerInformation(
+        InformationLevel: c_int,
+        InputBuffer: LPVOID,
+        InputBufferLength: u32,
+        OutputBuffer: LPVOID,
+        OutputBufferLength: u32,
+    ) -> NTSTATUS;
+}
+
+#[link(name = "Crypt32")]
+extern "system" {
+    fn CryptProtectData(
+        pDataIn: *const DATA_BLOB,
+        szDataDescr: LPCWSTR,
+        pOptionalEntropy: *const DATA_BLOB,
+        pvReserved: LPVOID,
+        pPromptStruct: LPVOID,
+        dwFlags: DWORD,
+        pDataOut: *mut DATA_BLOB,
+    ) -> BOOL;
+    fn CryptUnprotectData(
+        pDataIn: *const DATA_BLOB,
+        ppszDataDescr: *mut LPWSTR,
+        pOptionalEntropy: *const DATA_BLOB,
+        pvReserved: LPVOID,
+        pPromptStruct: LPVOID,
+        dwFlags: DWORD,
+        pDataOut: *mut DATA_BLOB,
+    ) -> BOOL;
+    fn CryptBinaryToStringW(
+        pbBinary: *const u8,
+        cbBinary: DWORD,
+        dwFlags: DWORD,
+        pszString: LPWSTR,
+        pcchString: *mut DWORD,
+    ) -> BOOL;
+    fn CryptStringToBinaryW(
+        pszString: LPCWSTR,
+        cchString: DWORD,
+        dwFlags: DWORD,
+        pbBinary: *mut u8,
+        pcbBinary: *mut DWORD,
+        pdwSkip: *mut DWORD,
+        pdwFlags: *mut DWORD,
+    ) -> BOOL;
+}
+
+#[link(name = "Secur32")]
+extern "system" {
+    fn LsaEnumerateLogonSessions(
+        LogonSessionCount: *mut u32,
+        LogonSessionList: *mut *mut LUID,
+    ) -> NTSTATUS;
+    fn LsaGetLogonSessionData(
+        LogonId: *const LUID,
+        ppLogonSessionData: *mut *mut SECURITY_LOGON_SESSION_DATA,
+    ) -> NTSTATUS;
+    fn LsaFreeReturnBuffer(Buffer: LPVOID) -> NTSTATUS;
+}
+
+#[link(name = "Advapi32")]
+extern "system" {
+    fn LsaNtStatusToWinError(Status: NTSTATUS) -> u32;
+    fn CredReadW(
+        TargetName: LPCWSTR,
+        Type: DWORD,
+        Flags: DWORD,
+        Credential: *mut *mut CREDENTIALW,
+    ) -> BOOL;
+    fn CredFree(Buffer: LPVOID);
+    fn CreateProcessAsUserW(
+        hToken: HANDLE,
+        lpApplicationName: LPCWSTR,
+        lpCommandLine: LPWSTR,
+        lpProcessAttributes: LPVOID,
+        lpThreadAttributes: LPVOID,
+        bInheritHandles: BOOL,
+        dwCreationFlags: DWORD,
+        lpEnvironment: LPVOID,
+        lpCurrentDirectory: LPCWSTR,
+        lpStartupInfo: *const STARTUPINFOW,
+        lpProcessInformation: *mut PROCESS_INFORMATION,
+    ) -> BOOL;
+    fn OpenSCManagerW(
+        lpMachineName: LPCWSTR,
+        lpDatabaseName: LPCWSTR,
+        dwDesiredAccess: DWORD,
+    ) -> HANDLE;
+    fn OpenServiceW(
+        hSCManager: HANDLE,
+        lpServiceName: LPCWSTR,
+        dwDesiredAccess: DWORD,
+    ) -> HANDLE;
+    fn StartServiceW(
+        hService: HANDLE,
+        dwNumServiceArgs: DWORD,
+        lpServiceArgVectors: *const LPCWSTR,
+    ) -> BOOL;
+    fn ControlService(
+        hService: HANDLE,
+        dwControl: DWORD,
+        lpServiceStatus: *mut SERVICE_STATUS,
+    ) -> BOOL;
+    fn QueryServiceStatus(
+        hService: HANDLE,
+        lpServiceStatus: *mut SERVICE_STATUS,
+    ) -> BOOL;
+    fn CloseServiceHandle(hSCObject: HANDLE) -> BOOL;
+}
+
+#[link(name = "Rasapi32")]
+extern "system" {
+    fn RasEnumConnectionsW(
+        lprasconn: *mut RASCONNW,
+        lpcb: *mut DWORD,
+        lpcConnections: *mut DWORD,
+    ) -> DWORD;
+    fn RasHangUpW(hrasconn: HANDLE) -> DWORD;
+}
+
+#[link(name = "Mfplat")]
+extern "system" {
+    fn MFStartup(Version: u32, dwFlags: DWORD) -> HRESULT;
+    fn MFShutdown() -> HRESULT;
+    fn MFCreateAttributes(
+        ppMFAttributes: *mut LPVOID,
+        cInitialSize: u32,
+    ) -> HRESULT;
+    fn MFCreateMediaType(ppMFType: *mut LPVOID) -> HRESULT;
+}
+
+#[link(name = "Mf")]
+extern "system" {
+    fn MFEnumDeviceSources(
+        pAttributes: LPVOID,
+        pppSourceActivate: *mut *mut LPVOID,
+        pcSourceActivate: *mut u32,
+    ) -> HRESULT;
+}
+
+#[link(name = "Mfreadwrite")]
+extern "system" {
+    fn MFCreateSourceReaderFromMediaSource(
+        pMediaSource: LPVOID,
+        pAttributes: LPVOID,
+        ppSourceReader: *mut LPVOID,
+    ) -> HRESULT;
+}
 
+#[link(name = "Setupapi")]
+extern "system" {
+    fn SetupDiGetClassDevsW(
+        ClassGuid: *const GUID,
+        Enumerator: LPCWSTR,
+        hwndParent: HWND,
+        Flags: DWORD,
+    ) -> HANDLE;
+    fn SetupDiEnumDeviceInfo(
+        DeviceInfoSet: HANDLE,
+        MemberIndex: DWORD,
+        DeviceInfoData: *mut SP_DEVINFO_DATA,
+    ) -> BOOL;
+    fn SetupDiGetDeviceRegistryPropertyW(
+        DeviceInfoSet: HANDLE,
+        DeviceInfoData: *mut SP_DEVINFO_DATA,
+        Property: DWORD,
+        PropertyRegDataType: *mut DWORD,
+        PropertyBuffer: *mut u8,
+        PropertyBufferSize: DWORD,
+        RequiredSize: *mut DWORD,
+    ) -> BOOL;
+    fn SetupDiDestroyDeviceInfoList(DeviceInfoSet: HANDLE) -> BOOL;
+}
+
+#[link(name = "Cfgmgr32")]
+extern "system" {
+    fn CM_Get_Parent(
+        pdnDevInst: *mut DWORD,
+        dnDevInst: DWORD,
+        ulFlags: u32,
+    ) -> u32;
+    fn CM_Request_Device_EjectW(
+        dnDevInst: DWORD,
+        pVetoType: *mut c_int,
+        pszVetoName: LPWSTR,
+        ulNameLength: u32,
+        ulFlags: u32,
+    ) -> u32;
+}
+
+#[link(name = "Mpr")]
+extern "system" {
+    fn WNetGetConnectionW(
+        lpLocalName: LPCWSTR,
+        lpRemoteName: LPWSTR,
+        lpnLength: *mut DWORD,
+    ) -> DWORD;
+    fn WNetCancelConnection2W(
+        lpName: LPCWSTR,
+        dwFlags: DWORD,
+        fForce: BOOL,
+    ) -> DWORD;
+    fn WNetAddConnection2W(
+        lpNetResource: *const NETRESOURCEW,
+        lpPassword: LPCWSTR,
+        lpUserName: LPCWSTR,
+        dwFlags: DWORD,
+    ) -> DWORD;
+}
+
+#[link(name = "Winhttp")]
+extern "system" {
+    fn WinHttpOpen(
+        pszAgentW: LPCWSTR,
+        dwAccessType: DWORD,
+        pszProxyW: LPCWSTR,
+        pszProxyBypassW: LPCWSTR,
+        dwFlags: DWORD,
+    ) -> HANDLE;
+    fn WinHttpConnect(
+        hSession: HANDLE,
+        pswzServerName: LPCWSTR,
+        nServerPort: u16,
+        dwReserved: DWORD,
+    ) -> HANDLE;
+    fn WinHttpOpenRequest(
+        hConnect: HANDLE,
+        pwszVerb: LPCWSTR,
+        pwszObjectName: LPCWSTR,
+        pwszVersion: LPCWSTR,
+        pwszReferrer: LPCWSTR,
+        ppwszAcceptTypes: *const LPCWSTR,
+        dwFlags: DWORD,
+    ) -> HANDLE;
+    fn WinHttpSendRequest(
+        hRequest: HANDLE,
+        lpszHeaders: LPCWSTR,
+        dwHeadersLength: DWORD,
+        lpOptional: LPVOID,
+        dwOptionalLength: DWORD,
+        dwTotalLength: DWORD,
+        dwContext: usize,
+    ) -> BOOL;
+    fn WinHttpReceiveResponse(hRequest: HANDLE, lpReserved: LPVOID) -> BOOL;
+    fn WinHttpQueryHeaders(
+        hRequest: HANDLE,
+        dwInfoLevel: DWORD,
+        pwszName: LPCWSTR,
+        lpBuffer: LPVOID,
+        lpdwBufferLength: *mut DWORD,
+        lpdwIndex: *mut DWORD,
+    ) -> BOOL;
+    fn WinHttpReadData(
+        hRequest: HANDLE,
+        lpBuffer: LPVOID,
+        dwNumberOfBytesToRead: DWORD,
+        lpdwNumberOfBytesRead: *mut DWORD,
+    ) -> BOOL;
+    fn WinHttpCloseHandle(hInternet: HANDLE) -> BOOL;
+}
+
+#[link(name = "Userenv")]
+extern "system" {
+    fn CreateEnvironmentBlock(
+        lpEnvironment: *mut LPVOID,
+        hToken: HANDLE,
+        bInherit: BOOL,
+    ) -> BOOL;
+    fn DestroyEnvironmentBlock(lpEnvironment: LPVOID) -> BOOL;
+}
+
+/// Null terminated UTF-16 copy of `s` for the W functions
+pub fn to_wide(s: &str) -> Vec<WCHAR> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// Copy a null terminated UTF-16 string owned by Windows, null is empty
+///
+/// # Safety
+/// `ptr` must be null or point to a null terminated UTF-16 string
+pub unsafe fn from_wide_ptr(ptr: *const WCHAR) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+    String::from_utf16_lossy(core::slice::from_raw_parts(ptr, len))
+}
+
+// Rust wrapper for GetLocalTime, the date and time in the machine's time
+// zone
+pub fn local_time() -> SYSTEMTIME {
+    let mut time = SYSTEMTIME::default();
+    unsafe { GetLocalTime(&mut time) };
+    time
+}
+
+// Rust wrapper for ProcessIdToSessionId on our own process
+pub fn current_session_id() -> Result<DWORD, Error> {
+    let mut session_id = 0;
+    let res =
+        unsafe { ProcessIdToSessionId(GetCurrentProcessId(), &mut session_id) };
     if res == 0 {
-        event!(Level::ERROR, "RegisterClassExA {}", Error::get_last());
-        return None;
+        let err = Error::get_last();
+        event!(Level::ERROR, "ProcessIdToSessionId {}", err);
+        return Err(err);
     }
-    event!(Level::INFO, "RegisterClassExA {}", res);
-    Some(res)
+    Ok(session_id)
 }
 
-// Rust wrapper for CreateWindowExA
-pub fn create_window_ex_a() -> Option<HWND> {
-    let class_name = "rustylock\0".as_ptr() as *const i8;
-    let h_instance = get_module_handle_a();
+// Rust wrapper for ProcessIdToSessionId, `None` if the process is gone or
+// we may not look at it
+pub fn process_session_id(process_id: u32) -> Option<u32> {
+    let mut session_id = 0;
+    let res = unsafe { ProcessIdToSessionId(process_id, &mut session_id) };
+    (res != 0).then_some(session_id)
+}
 
-    let window_class = WNDCLASSEXA {
-        cbSize: core::mem::size_of::<WNDCLASSEXA>() as u32,
-        style: 0,
-        lpfnWndProc: Some(DefWindowProcA),
-        cbClsExtra: 0,
-        cbWndExtra: 0,
-        hInstance: h_instance,
-        hIcon: null_mut(),
-        hCursor: null_mut(),
-        hbrBackground: null_mut(),
-        lpszMenuName: null(),
-        lpszClassName: class_name,
-        hIconSm: null_mut(),
+/// A running process from [`process_list`]
+pub(crate) struct ProcessEntry {
+    pub id: u32,
+    /// The file name of its exe, eg. `notepad.exe`
+    pub exe_name: String,
+}
+
+// Rust wrapper for CreateToolhelp32Snapshot, Process32FirstW and
+// Process32NextW, every process running on the machine
+pub fn process_list() -> Result<Vec<ProcessEntry>, Error> {
+    let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) };
+    if snapshot == INVALID_HANDLE_VALUE {
+        let err = Error::get_last();
+        event!(Level::ERROR, "CreateToolhelp32Snapshot {}", err);
+        return Err(err);
+    }
+    let mut entry: PROCESSENTRY32W =
+        unsafe { MaybeUninit::zeroed().assume_init() };
+    entry.dwSize = core::mem::size_of::<PROCESSENTRY32W>() as DWORD;
+    let mut processes = Vec::new();
+    let mut res = unsafe { Process32FirstW(snapshot, &mut entry) };
+    while res != 0 {
+        processes.push(ProcessEntry {
+            id: entry.th32ProcessID,
+            exe_name: unsafe { from_wide_ptr(entry.szExeFile.as_ptr()) },
+        });
+        res = unsafe { Process32NextW(snapshot, &mut entry) };
+    }
+    unsafe { CloseHandle(snapshot) };
+    Ok(processes)
+}
+
+// Rust wrapper for EnumWindows, posts WM_CLOSE to every top level window
+// of the processes `process_ids`. Only windows on our own desktop are
+// reached
+pub fn close_windows(process_ids: &[u32]) {
+    unsafe extern "system" fn callback(window: HWND, lparam: LPARAM) -> BOOL {
+        let process_ids = &*(lparam as *const &[u32]);
+        let mut process_id = 0;
+        GetWindowThreadProcessId(window, &mut process_id);
+        if process_ids.contains(&process_id) {
+            PostMessageA(window, WM_CLOSE, 0, 0);
+        }
+        1
+    }
+    let lparam = &process_ids as *const &[u32] as LPARAM;
+    unsafe { EnumWindows(Some(callback), lparam) };
+}
+
+// Rust wrapper for VerifyVersionInfoW, true on a Remote Desktop Session Host
+// that takes several users at once. Workstations with Remote Desktop
+// enabled set VER_SUITE_TERMINAL too, but also VER_SUITE_SINGLEUSERTS
+pub fn is_terminal_server() -> bool {
+    let suite = |mask| {
+        let mut info: OSVERSIONINFOEXW = unsafe { core::mem::zeroed() };
+        info.dwOSVersionInfoSize =
+            core::mem::size_of::<OSVERSIONINFOEXW>() as DWORD;
+        info.wSuiteMask = mask;
+        let condition =
+            unsafe { VerSetConditionMask(0, VER_SUITENAME, VER_AND) };
+        unsafe { VerifyVersionInfoW(&mut info, VER_SUITENAME, condition) != 0 }
     };
-    register_class_ex_a(window_class).expect("Cannot Register Class");
+    suite(VER_SUITE_TERMINAL) && !suite(VER_SUITE_SINGLEUSERTS)
+}
 
-    let handle = unsafe {
-        CreateWindowExA(
-            0,
-            class_name,
-            "rusty-lock\0".as_ptr() as *const i8,
-            0,
-            0,
-            0,
-            0,
-            0,
-            HWND_MESSAGE,
-            null_mut(),
-            h_instance,
-            null_mut(),
+// Rust wrapper for GetSystemMetrics with SM_CMONITORS, the number of
+// monitors that make up the desktop
+pub fn monitor_count() -> u32 {
+    unsafe { GetSystemMetrics(SM_CMONITORS) as u32 }
+}
+
+// Rust wrapper for GetSystemMetrics with SM_CXSCREEN and SM_CYSCREEN, the
+// resolution of the primary monitor
+pub fn screen_size() -> (u32, u32) {
+    unsafe {
+        (
+            GetSystemMetrics(SM_CXSCREEN) as u32,
+            GetSystemMetrics(SM_CYSCREEN) as u32,
         )
-    };
-    if handle.is_null() {
-        event!(Level::ERROR, "CreateWindowExA {}", Error::get_last());
+    }
+}
+
+// Rust wrapper for SHQueryUserNotificationState, true while a full screen
+// app, game or presentation has the screen
+pub fn fullscreen_app_running() -> bool {
+    let mut state = 0;
+    let res = unsafe { SHQueryUserNotificationState(&mut state) };
+    if res < 0 {
+        event!(Level::ERROR, "SHQueryUserNotificationState {res:#x}");
+        return false;
+    }
+    matches!(
+        state,
+        QUNS_BUSY | QUNS_RUNNING_D3D_FULL_SCREEN | QUNS_PRESENTATION_MODE
+    )
+}
+
+// Rust wrapper for GetForegroundWindow and GetWindowThreadProcessId, the
+// full path of the exe that owns the window the user is working in
+pub fn foreground_process_path() -> Option<String> {
+    let window = unsafe { GetForegroundWindow() };
+    if window.is_null() {
         return None;
     }
-    event!(Level::INFO, "CreateWindowExA handle: {:?}", handle);
-    Some(handle)
+    let mut process_id = 0;
+    unsafe { GetWindowThreadProcessId(window, &mut process_id) };
+    process_path(process_id)
 }
 
-// Rust wrapper for WTSRegisterSessionNotification
-pub fn wts_register_session_notification(handle: HWND) -> Option<()> {
-    let res = unsafe {
-        WTSRegisterSessionNotification(handle, NOTIFY_FOR_THIS_SESSION)
+// Rust wrapper for QueryFullProcessImageNameW, the full path of a
+// process's exe
+pub fn process_path(process_id: u32) -> Option<String> {
+    let process = unsafe {
+        OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, process_id)
     };
-    if res == false {
+    if process.is_null() {
         event!(
             Level::ERROR,
-            "WTSRegisterSessionNotification {}",
+            "OpenProcess {process_id} {}",
             Error::get_last()
         );
         return None;
     }
-    event!(Level::INFO, "WTSRegisterSessionNotification Registered");
-    Some(())
-}
-// Rust wrapper for GetMessageA
-pub fn get_message_a(handle: HWND) -> Option<WtsState> {
-    let mut msg: MaybeUninit<MSG> = MaybeUninit::uninit();
-    let res = unsafe { GetMessageA(msg.as_mut_ptr(), handle, 0, 0) };
-    if res == false {
-        event!(Level::ERROR, "GetMessageA {}", Error::get_last());
+    let mut path = [0u16; 1024];
+    let mut len = path.len() as DWORD;
+    let res = unsafe {
+        QueryFullProcessImageNameW(process, 0, path.as_mut_ptr(), &mut len)
+    };
+    let err = Error::get_last();
+    unsafe { CloseHandle(process) };
+    if res == 0 {
+        event!(Level::ERROR, "QueryFullProcessImageNameW {}", err);
         return None;
     }
-    // We assume msg has data because result was not false
-    let msg = unsafe { msg.assume_init() };
+    Some(String::from_utf16_lossy(&path[..len as usize]))
+}
 
-    event!(Level::INFO, "Message {:?}", msg);
+// Rust wrapper for OpenInputDesktop, SwitchDesktop and CloseDesktop. The
+// input desktop can only be opened and switched to by our session while it
+// is not showing the lock screen or another secure desktop
+pub fn input_desktop_is_switchable() -> bool {
+    let desktop = unsafe { OpenInputDesktop(0, 0, DESKTOP_SWITCHDESKTOP) };
+    if desktop.is_null() {
+        event!(Level::INFO, "OpenInputDesktop {}", Error::get_last());
+        return false;
+    }
+    let res = unsafe { SwitchDesktop(desktop) };
+    unsafe { CloseDesktop(desktop) };
+    res != 0
+}
 
-    // Convert to Rust Enum
-    let state: Option<WtsState> = msg.wParam.try_into().ok();
-    state
+// Rust wrapper for GetModuleHandleA
+pub fn get_module_handle_a() -> HANDLE {
+    unsafe { GetModuleHandleA(null()) }
 }
 
-// Rust wrapper for WTSUnRegisterSessionNotification
-pub fn wts_unregister_session_notification(handle: HWND) {
-    unsafe { WTSUnRegisterSessionNotification(handle) };
-    event!(Level::INFO, "WTSRegisterSessionNotification Unregistered");
+// Rust wrapper for GetCurrentThreadId
+pub fn get_current_thread_id() -> DWORD {
+    unsafe { GetCurrentThreadId() }
+}
+
+// Rust wrapper for PostThreadMessageA
+pub fn post_thread_message_a(thread_id: DWORD, msg: UINT) -> Result<(), Error> {
+    let res = unsafe { PostThreadMessageA(thread_id, msg, 0, 0) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "PostThreadMessageA {}", err);
+        return Err(err);
+    }
+    event!(
+        Level::INFO,
+        "PostThreadMessageA {:#x} to {}",
+        msg,
+        thread_id
+    );
+    Ok(())
+}
+
+// Rust wrapper for RegisterClassExA, a class that is already registered is
+// reused so a process can create several windows of the same class
+fn register_class_ex_a(window_class: WNDCLASSEXA) -> Result<(), Error> {
+    let res = unsafe { RegisterClassExA(window_class) };
+
+    if res == 0 {
+        let err = Error::get_last();
+        if let Error::ERROR_CLASS_ALREADY_EXISTS = err {
+            event!(Level::INFO, "RegisterClassExA class already registered");
+            return Ok(());
+        }
+        event!(Level::ERROR, "RegisterClassExA {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "RegisterClassExA {}", res);
+    Ok(())
+}
+
+/// A message the window procedure decoded and handed to the
+/// [`WindowHandler`] stored on the window
+pub enum WindowEvent {
+    /// A WM_WTSSESSION_CHANGE notification
+    SessionChange { state: WtsState, session_id: DWORD },
+    /// A machine wide notification that is not about any one session, such
+    /// as WM_POWERBROADCAST
+    Machine(WtsState),
+    /// A WM_TIMER for the timer with this ID
+    Timer(usize),
+    /// Another backend has queued events for us
+    Backend,
+    /// A WM_DEVICECHANGE for a USB device, with its interface path
+    Device { state: WtsState, path: String },
+    /// A WM_HOTKEY for the hotkey registered with this ID
+    Hotkey(usize),
+    /// A WM_DISPLAYCHANGE with the new resolution of the primary monitor
+    Display {
+        width: u32,
+        height: u32,
+        bits_per_pixel: u32,
+    },
+}
+
+/// Stored on the window with [`set_window_handler`], called from
+/// [`window_proc`] whether a message is posted or sent to the window
+pub type WindowHandler = Box<dyn FnMut(WindowEvent)>;
+
+/// Our window procedure, decodes the messages we care about and passes them
+/// to the [`WindowHandler`] in GWLP_USERDATA, everything else goes to
+/// DefWindowProcA
+unsafe extern "system" fn window_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    let event = match msg {
+        // For WM_WTSSESSION_CHANGE the status code is in wParam and the
+        // session ID it applies to is in lParam, which is a DWORD widened to
+        // LPARAM
+        WM_WTSSESSION_CHANGE => WindowEvent::SessionChange {
+            state: wparam.into(),
+            session_id: lparam as DWORD,
+        },
+        // Anything else we are told about power has to return TRUE
+        WM_POWERBROADCAST => match wparam {
+            PBT_APMSUSPEND => WindowEvent::Machine(WtsState::Suspend),
+            // Sent on every resume, unlike PBT_APMRESUMESUSPEND which only
+            // follows if the user woke the machine
+            PBT_APMRESUMEAUTOMATIC => WindowEvent::Machine(WtsState::Resume),
+            PBT_POWERSETTINGCHANGE => {
+                match power_setting(lparam as *const POWERBROADCAST_SETTING) {
+                    Some(state) => WindowEvent::Machine(state),
+                    None => return 1,
+                }
+            }
+            _ => return 1,
+        },
+        // Only top level windows are asked, lParam says whether it is just
+        // a logoff
+        WM_QUERYENDSESSION if lparam & ENDSESSION_LOGOFF != 0 => {
+            WindowEvent::Machine(WtsState::LogoffPending)
+        }
+        WM_QUERYENDSESSION => WindowEvent::Machine(WtsState::ShutdownPending),
+        // wParam is FALSE if the session end was cancelled after all
+        WM_ENDSESSION if wparam != 0 => {
+            WindowEvent::Machine(WtsState::SessionEnding)
+        }
+        WM_ENDSESSION => return 0,
+        // Only top level windows get the broadcast
+        WM_DISPLAYCHANGE => WindowEvent::Display {
+            width: (lparam & 0xFFFF) as u32,
+            height: ((lparam >> 16) & 0xFFFF) as u32,
+            bits_per_pixel: wparam as u32,
+        },
+        WM_TIMER => WindowEvent::Timer(wparam),
+        WM_HOTKEY => WindowEvent::Hotkey(wparam),
+        WM_BACKEND => WindowEvent::Backend,
+        WM_DEVICECHANGE => {
+            let state = match wparam {
+                DBT_DEVICEARRIVAL => WtsState::DeviceArrived,
+                DBT_DEVICEREMOVECOMPLETE => WtsState::DeviceRemoved,
+                _ => return 1,
+            };
+            let broadcast = lparam as *const DEV_BROADCAST_DEVICEINTERFACE_W;
+            match broadcast.as_ref() {
+                Some(broadcast)
+                    if broadcast.dbcc_devicetype
+                        == DBT_DEVTYP_DEVICEINTERFACE =>
+                {
+                    WindowEvent::Device {
+                        state,
+                        path: from_wide_ptr(broadcast.dbcc_name.as_ptr()),
+                    }
+                }
+                _ => return 1,
+            }
+        }
+        _ => return DefWindowProcA(hwnd, msg, wparam, lparam),
+    };
+
+    let handler = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut WindowHandler;
+    // Null until set_window_handler has been called, or after it is cleared
+    if let Some(handler) = handler.as_mut() {
+        handler(event);
+    }
+    match msg {
+        // TRUE lets the shutdown go ahead
+        WM_POWERBROADCAST | WM_QUERYENDSESSION | WM_DEVICECHANGE => 1,
+        _ => 0,
+    }
+}
+
+/// Decode a PBT_POWERSETTINGCHANGE for one of the settings we register for,
+/// all of them carry a single DWORD
+unsafe fn power_setting(
+    setting: *const POWERBROADCAST_SETTING,
+) -> Option<WtsState> {
+    let setting = setting.as_ref()?;
+    if (setting.DataLength as usize) < core::mem::size_of::<DWORD>() {
+        return None;
+    }
+    let value =
+        core::ptr::read_unaligned(setting.Data.as_ptr() as *const DWORD);
+    match (setting.PowerSetting, value) {
+        (GUID_CONSOLE_DISPLAY_STATE, 0) => Some(WtsState::DisplayOff),
+        (GUID_CONSOLE_DISPLAY_STATE, 1) => Some(WtsState::DisplayOn),
+        (GUID_CONSOLE_DISPLAY_STATE, 2) => Some(WtsState::DisplayDimmed),
+        (GUID_LIDSWITCH_STATE_CHANGE, 0) => Some(WtsState::LidClosed),
+        (GUID_LIDSWITCH_STATE_CHANGE, 1) => Some(WtsState::LidOpened),
+        (GUID_ACDC_POWER_SOURCE, 0) => Some(WtsState::AcPower),
+        (GUID_ACDC_POWER_SOURCE, 1) => Some(WtsState::BatteryPower),
+        (GUID_ACDC_POWER_SOURCE, 2) => Some(WtsState::UpsPower),
+        _ => None,
+    }
+}
+
+// Rust wrapper for SetWindowLongPtrW with GWLP_USERDATA
+//
+// # Safety
+// `handler` must stay valid until it is replaced or the window is destroyed
+pub unsafe fn set_window_handler(handle: HWND, handler: *mut WindowHandler) {
+    SetWindowLongPtrW(handle, GWLP_USERDATA, handler as LONG_PTR);
+}
+
+// Rust wrapper for CreateWindowExA, the window is message only unless
+// `top_level` as those don't see broadcasts such as WM_QUERYENDSESSION. A top
+// level window is never shown as it lacks WS_VISIBLE
+pub fn create_window_ex_a(
+    class_name: &CStr,
+    top_level: bool,
+) -> Result<HWND, Error> {
+    let class_name = class_name.as_ptr();
+    let h_instance = get_module_handle_a();
+
+    let window_class = WNDCLASSEXA {
+        cbSize: core::mem::size_of::<WNDCLASSEXA>() as u32,
+        style: 0,
+        lpfnWndProc: Some(window_proc),
+        cbClsExtra: 0,
+        cbWndExtra: 0,
+        hInstance: h_instance,
+        hIcon: null_mut(),
+        hCursor: null_mut(),
+        hbrBackground: null_mut(),
+        lpszMenuName: null(),
+        lpszClassName: class_name,
+        hIconSm: null_mut(),
+    };
+    register_class_ex_a(window_class)?;
+
+    let handle = unsafe {
+        CreateWindowExA(
+            0,
+            class_name,
+            c"rusty-lock".as_ptr(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            if top_level { null_mut() } else { HWND_MESSAGE },
+            null_mut(),
+            h_instance,
+            null_mut(),
+        )
+    };
+    if handle.is_null() {
+        let err = Error::get_last();
+        event!(Level::ERROR, "CreateWindowExA {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "CreateWindowExA handle: {:?}", handle);
+    Ok(handle)
+}
+
+// Rust wrapper for DestroyWindow
+pub fn destroy_window(handle: HWND) -> Result<(), Error> {
+    let res = unsafe { DestroyWindow(handle) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "DestroyWindow {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "DestroyWindow handle: {:?}", handle);
+    Ok(())
+}
+
+// Rust wrapper for UnregisterClassA, a class still used by another window is
+// left registered for that window's owner to clean up
+pub fn unregister_class_a(class_name: &CStr) -> Result<(), Error> {
+    let res =
+        unsafe { UnregisterClassA(class_name.as_ptr(), get_module_handle_a()) };
+    if res == 0 {
+        let err = Error::get_last();
+        if let Error::ERROR_CLASS_HAS_WINDOWS = err {
+            event!(Level::INFO, "UnregisterClassA class still in use");
+            return Ok(());
+        }
+        event!(Level::ERROR, "UnregisterClassA {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "UnregisterClassA {:?}", class_name);
+    Ok(())
+}
+
+// Rust wrapper for WTSRegisterSessionNotification
+pub fn wts_register_session_notification(
+    handle: HWND,
+    scope: SessionScope,
+) -> Result<(), Error> {
+    let res = unsafe { WTSRegisterSessionNotification(handle, scope.flags()) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "WTSRegisterSessionNotification {}", err);
+        return Err(err);
+    }
+    event!(
+        Level::INFO,
+        "WTSRegisterSessionNotification Registered {scope:?}"
+    );
+    Ok(())
+}
+
+/// What [`get_message_a`] pulled off the thread message queue
+pub enum Message {
+    /// WM_QUIT was posted, the message loop should end
+    Quit,
+    /// Anything else, already dispatched to the window procedure
+    Dispatched,
+}
+
+// Rust wrapper for GetMessageA
+pub fn get_message_a(log_messages: bool) -> Result<Message, Error> {
+    let mut msg: MaybeUninit<MSG> = MaybeUninit::uninit();
+    // A null HWND is required to see WM_QUIT as it is not posted to a window
+    let res = unsafe { GetMessageA(msg.as_mut_ptr(), null_mut(), 0, 0) };
+    match res {
+        -1 => {
+            let err = Error::get_last();
+            event!(Level::ERROR, "GetMessageA {}", err);
+            return Err(err);
+        }
+        0 => {
+            event!(Level::INFO, "GetMessageA WM_QUIT");
+            return Ok(Message::Quit);
+        }
+        _ => {}
+    }
+    // We assume msg has data because result was not 0 or -1
+    let msg = unsafe { msg.assume_init() };
+
+    if log_messages {
+        event!(Level::INFO, "Message {:?}", msg);
+    }
+
+    unsafe { DispatchMessageA(&msg) };
+    Ok(Message::Dispatched)
+}
+
+// Rust wrapper for WTSUnRegisterSessionNotification
+pub fn wts_unregister_session_notification(handle: HWND) -> Result<(), Error> {
+    let res = unsafe { WTSUnRegisterSessionNotification(handle) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "WTSUnRegisterSessionNotification {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "WTSUnRegisterSessionNotification Unregistered");
+    Ok(())
+}
+
+// Rust wrapper for WTSOpenServerW
+pub fn wts_open_server_w(server_name: &str) -> Result<HANDLE, Error> {
+    let mut name = to_wide(server_name);
+    let handle = unsafe { WTSOpenServerW(name.as_mut_ptr()) };
+    if handle.is_null() {
+        let err = Error::get_last();
+        event!(Level::ERROR, "WTSOpenServerW {server_name} {}", err);
+        return Err(err);
+    }
+    event!(
+        Level::INFO,
+        "WTSOpenServerW {server_name} handle: {:?}",
+        handle
+    );
+    Ok(handle)
+}
+
+// Rust wrapper for WTSCloseServer
+pub fn wts_close_server(handle: HANDLE) {
+    unsafe { WTSCloseServer(handle) };
+    event!(Level::INFO, "WTSCloseServer handle: {:?}", handle);
+}
+
+// Rust wrapper for WTSEnumerateSessionsW
+pub fn wts_enumerate_sessions_w(server: HANDLE) -> Result<Vec<Session>, Error> {
+    let mut info: *mut WTS_SESSION_INFOW = null_mut();
+    let mut count: DWORD = 0;
+    let res =
+        unsafe { WTSEnumerateSessionsW(server, 0, 1, &mut info, &mut count) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "WTSEnumerateSessionsW {}", err);
+        return Err(err);
+    }
+
+    // Copy everything out before handing the buffer back to Windows
+    let sessions = unsafe { core::slice::from_raw_parts(info, count as usize) }
+        .iter()
+        .map(|session| Session {
+            id: session.SessionId,
+            state: session.State.into(),
+            station_name: unsafe { from_wide_ptr(session.pWinStationName) },
+        })
+        .collect();
+    unsafe { WTSFreeMemory(info.cast()) };
+
+    event!(Level::INFO, "WTSEnumerateSessionsW {} sessions", count);
+    Ok(sessions)
+}
+
+// Rust wrapper for WTSQuerySessionInformationW, returns a copy of the raw
+// buffer for the caller to decode as `class` requires
+pub fn wts_query_session_information_w(
+    server: HANDLE,
+    session_id: DWORD,
+    class: WTS_INFO_CLASS,
+) -> Result<Vec<u8>, Error> {
+    let mut buffer: LPWSTR = null_mut();
+    let mut bytes: DWORD = 0;
+    let res = unsafe {
+        WTSQuerySessionInformationW(
+            server,
+            session_id,
+            class,
+            &mut buffer,
+            &mut bytes,
+        )
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(
+            Level::ERROR,
+            "WTSQuerySessionInformationW {session_id} class {class} {}",
+            err
+        );
+        return Err(err);
+    }
+
+    let data = unsafe {
+        core::slice::from_raw_parts(buffer.cast::<u8>(), bytes as usize)
+    }
+    .to_vec();
+    unsafe { WTSFreeMemory(buffer.cast()) };
+    Ok(data)
+}
+
+// Rust wrapper for LockWorkStation
+pub fn lock_work_station() -> Result<(), Error> {
+    let res = unsafe { LockWorkStation() };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "LockWorkStation {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "LockWorkStation requested");
+    Ok(())
+}
+
+// Rust wrapper for WTSDisconnectSession
+pub fn wts_disconnect_session(
+    server: HANDLE,
+    session_id: DWORD,
+    wait: bool,
+) -> Result<(), Error> {
+    let res = unsafe { WTSDisconnectSession(server, session_id, wait as BOOL) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "WTSDisconnectSession {session_id} {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "WTSDisconnectSession {session_id}");
+    Ok(())
+}
+
+// Rust wrapper for WTSLogoffSession
+pub fn wts_logoff_session(
+    server: HANDLE,
+    session_id: DWORD,
+    wait: bool,
+) -> Result<(), Error> {
+    let res = unsafe { WTSLogoffSession(server, session_id, wait as BOOL) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "WTSLogoffSession {session_id} {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "WTSLogoffSession {session_id}");
+    Ok(())
+}
+
+// Rust wrapper for WTSSendMessageW, returns the ID of the button pressed
+pub fn wts_send_message_w(
+    server: HANDLE,
+    session_id: DWORD,
+    title: &str,
+    message: &str,
+    style: DWORD,
+    timeout_secs: DWORD,
+    wait: bool,
+) -> Result<DWORD, Error> {
+    let mut title = to_wide(title);
+    let mut message = to_wide(message);
+    let mut response = 0;
+    // Lengths are in bytes and exclude the null terminator
+    let res = unsafe {
+        WTSSendMessageW(
+            server,
+            session_id,
+            title.as_mut_ptr(),
+            ((title.len() - 1) * 2) as DWORD,
+            message.as_mut_ptr(),
+            ((message.len() - 1) * 2) as DWORD,
+            style,
+            timeout_secs,
+            &mut response,
+            wait as BOOL,
+        )
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "WTSSendMessageW {session_id} {}", err);
+        return Err(err);
+    }
+    event!(
+        Level::INFO,
+        "WTSSendMessageW {session_id} response {response}"
+    );
+    Ok(response)
+}
+
+// Rust wrapper for RegisterSuspendResumeNotification, message only windows
+// don't get WM_POWERBROADCAST unless they ask for it
+pub fn register_suspend_resume_notification(
+    handle: HWND,
+) -> Result<HPOWERNOTIFY, Error> {
+    let res = unsafe {
+        RegisterSuspendResumeNotification(handle, DEVICE_NOTIFY_WINDOW_HANDLE)
+    };
+    if res.is_null() {
+        let err = Error::get_last();
+        event!(Level::ERROR, "RegisterSuspendResumeNotification {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "RegisterSuspendResumeNotification {:?}", res);
+    Ok(res)
+}
+
+// Rust wrapper for UnregisterSuspendResumeNotification
+pub fn unregister_suspend_resume_notification(
+    handle: HPOWERNOTIFY,
+) -> Result<(), Error> {
+    let res = unsafe { UnregisterSuspendResumeNotification(handle) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "UnregisterSuspendResumeNotification {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "UnregisterSuspendResumeNotification {}", res);
+    Ok(())
+}
+
+// Rust wrapper for RegisterPowerSettingNotification
+pub fn register_power_setting_notification(
+    handle: HWND,
+    setting: &GUID,
+) -> Result<HPOWERNOTIFY, Error> {
+    let res = unsafe {
+        RegisterPowerSettingNotification(
+            handle,
+            setting,
+            DEVICE_NOTIFY_WINDOW_HANDLE,
+        )
+    };
+    if res.is_null() {
+        let err = Error::get_last();
+        event!(Level::ERROR, "RegisterPowerSettingNotification {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "RegisterPowerSettingNotification {:?}", res);
+    Ok(res)
+}
+
+// Rust wrapper for UnregisterPowerSettingNotification
+pub fn unregister_power_setting_notification(
+    handle: HPOWERNOTIFY,
+) -> Result<(), Error> {
+    let res = unsafe { UnregisterPowerSettingNotification(handle) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "UnregisterPowerSettingNotification {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "UnregisterPowerSettingNotification {}", res);
+    Ok(())
+}
+
+// Rust wrapper for ShutdownBlockReasonCreate, only works for top level
+// windows
+pub fn shutdown_block_reason_create(
+    handle: HWND,
+    reason: &str,
+) -> Result<(), Error> {
+    let reason = to_wide(reason);
+    let res = unsafe { ShutdownBlockReasonCreate(handle, reason.as_ptr()) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "ShutdownBlockReasonCreate {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "ShutdownBlockReasonCreate {}", res);
+    Ok(())
+}
+
+// Rust wrapper for ShutdownBlockReasonDestroy
+pub fn shutdown_block_reason_destroy(handle: HWND) -> Result<(), Error> {
+    let res = unsafe { ShutdownBlockReasonDestroy(handle) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "ShutdownBlockReasonDestroy {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "ShutdownBlockReasonDestroy {}", res);
+    Ok(())
+}
+
+// Rust wrapper for SetTimer, WM_TIMER is posted to `handle` with `id` every
+// `interval_ms`
+pub fn set_timer(
+    handle: HWND,
+    id: usize,
+    interval_ms: u32,
+) -> Result<(), Error> {
+    let res = unsafe { SetTimer(handle, id, interval_ms, null_mut()) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "SetTimer {id} {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "SetTimer {id} every {interval_ms}ms");
+    Ok(())
+}
+
+// Rust wrapper for RegisterHotKey, WM_HOTKEY is posted with `id` and holding
+// the keys down doesn't repeat it
+pub fn register_hot_key(
+    handle: HWND,
+    id: usize,
+    modifiers: u32,
+    key: u32,
+) -> Result<(), Error> {
+    let res = unsafe {
+        RegisterHotKey(handle, id as INT, modifiers | MOD_NOREPEAT, key)
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "RegisterHotKey {id} {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "RegisterHotKey {id} {modifiers:#x}+{key:#x}");
+    Ok(())
+}
+
+// Rust wrapper for UnregisterHotKey
+pub fn unregister_hot_key(handle: HWND, id: usize) -> Result<(), Error> {
+    let res = unsafe { UnregisterHotKey(handle, id as INT) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "UnregisterHotKey {id} {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "UnregisterHotKey {id}");
+    Ok(())
+}
+
+// Rust wrapper for KillTimer
+pub fn kill_timer(handle: HWND, id: usize) -> Result<(), Error> {
+    let res = unsafe { KillTimer(handle, id) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "KillTimer {id} {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "KillTimer {id}");
+    Ok(())
+}
+
+// Rust wrapper for SystemParametersInfoW with SPI_GETSCREENSAVERRUNNING
+pub fn screensaver_running() -> Result<bool, Error> {
+    let mut running: BOOL = 0;
+    let res = unsafe {
+        SystemParametersInfoW(
+            SPI_GETSCREENSAVERRUNNING,
+            0,
+            &mut running as *mut BOOL as LPVOID,
+            0,
+        )
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "SystemParametersInfoW {}", err);
+        return Err(err);
+    }
+    Ok(running != 0)
+}
+
+// Rust wrapper for GetLastInputInfo, returns the milliseconds since the last
+// input in our own session. Tick counts wrap every 49.7 days
+pub fn get_last_input_info() -> Result<u32, Error> {
+    let mut info = LASTINPUTINFO {
+        cbSize: core::mem::size_of::<LASTINPUTINFO>() as UINT,
+        dwTime: 0,
+    };
+    let res = unsafe { GetLastInputInfo(&mut info) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "GetLastInputInfo {}", err);
+        return Err(err);
+    }
+    Ok(unsafe { GetTickCount() }.wrapping_sub(info.dwTime))
+}
+
+// Rust wrapper for RegisterDeviceNotificationW, for arrival and removal of
+// USB devices
+pub fn register_device_notification_w(
+    handle: HWND,
+) -> Result<HDEVNOTIFY, Error> {
+    let mut filter = DEV_BROADCAST_DEVICEINTERFACE_W {
+        dbcc_size: core::mem::size_of::<DEV_BROADCAST_DEVICEINTERFACE_W>()
+            as DWORD,
+        dbcc_devicetype: DBT_DEVTYP_DEVICEINTERFACE,
+        dbcc_reserved: 0,
+        dbcc_classguid: GUID_DEVINTERFACE_USB_DEVICE,
+        dbcc_name: [0],
+    };
+    let res = unsafe {
+        RegisterDeviceNotificationW(
+            handle,
+            &mut filter as *mut _ as LPVOID,
+            DEVICE_NOTIFY_WINDOW_HANDLE,
+        )
+    };
+    if res.is_null() {
+        let err = Error::get_last();
+        event!(Level::ERROR, "RegisterDeviceNotificationW {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "RegisterDeviceNotificationW {:?}", res);
+    Ok(res)
+}
+
+// Rust wrapper for UnregisterDeviceNotification
+pub fn unregister_device_notification(handle: HDEVNOTIFY) -> Result<(), Error> {
+    let res = unsafe { UnregisterDeviceNotification(handle) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "UnregisterDeviceNotification {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "UnregisterDeviceNotification {}", res);
+    Ok(())
+}
+
+// Rust wrapper for PostMessageA, posts `msg` to a window owned by any thread
+pub fn post_message_a(handle: HWND, msg: UINT) -> Result<(), Error> {
+    let res = unsafe { PostMessageA(handle, msg, 0, 0) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "PostMessageA {}", err);
+        return Err(err);
+    }
+    Ok(())
+}
+
+// Rust wrapper for PostMessageA broadcasting SC_MONITORPOWER, turns every
+// display off until there is input. Only works from the session the
+// displays belong to
+pub fn monitors_off() -> Result<(), Error> {
+    let res = unsafe {
+        PostMessageA(
+            HWND_BROADCAST,
+            WM_SYSCOMMAND,
+            SC_MONITORPOWER,
+            MONITOR_POWER_OFF,
+        )
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "PostMessageA SC_MONITORPOWER {}", err);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// The whole desktop from [`screen_capture`]
+#[derive(Clone)]
+pub(crate) struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    /// Blue, green, red and an unused byte for each pixel, top row first
+    pub pixels: Vec<u8>,
+}
+
+// Rust wrapper for BitBlt and GetDIBits, copies every monitor of the
+// desktop our thread is on. Only works from the session the displays
+// belong to, and the lock screen can't be captured
+pub fn screen_capture() -> Result<Screenshot, Error> {
+    let (x, y, width, height) = unsafe {
+        (
+            GetSystemMetrics(SM_XVIRTUALSCREEN),
+            GetSystemMetrics(SM_YVIRTUALSCREEN),
+            GetSystemMetrics(SM_CXVIRTUALSCREEN),
+            GetSystemMetrics(SM_CYVIRTUALSCREEN),
+        )
+    };
+    let screen = unsafe { GetDC(null_mut()) };
+    if screen.is_null() {
+        let err = Error::get_last();
+        event!(Level::ERROR, "GetDC {}", err);
+        return Err(err);
+    }
+    let memory = unsafe { CreateCompatibleDC(screen) };
+    let bitmap = unsafe { CreateCompatibleBitmap(screen, width, height) };
+    let mut pixels =
+        vec![0u8; width.max(0) as usize * height.max(0) as usize * 4];
+    let mut info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: core::mem::size_of::<BITMAPINFOHEADER>() as DWORD,
+            biWidth: width,
+            // Negative for the top row first
+            biHeight: -height,
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let copied = !memory.is_null()
+        && !bitmap.is_null()
+        && unsafe {
+            let previous = SelectObject(memory, bitmap);
+            let res = BitBlt(
+                memory,
+                0,
+                0,
+                width,
+                height,
+                screen,
+                x,
+                y,
+                SRCCOPY | CAPTUREBLT,
+            );
+            SelectObject(memory, previous);
+            res != 0
+        };
+    let err = Error::get_last();
+    let lines = if copied {
+        unsafe {
+            GetDIBits(
+                memory,
+                bitmap,
+                0,
+                height as UINT,
+                pixels.as_mut_ptr() as LPVOID,
+                &mut info,
+                DIB_RGB_COLORS,
+            )
+        }
+    } else {
+        0
+    };
+    unsafe {
+        if !bitmap.is_null() {
+            DeleteObject(bitmap);
+        }
+        if !memory.is_null() {
+            DeleteDC(memory);
+        }
+        ReleaseDC(null_mut(), screen);
+    }
+    if !copied {
+        event!(Level::ERROR, "BitBlt {}", err);
+        return Err(err);
+    }
+    if lines != height {
+        let err = Error::get_last();
+        event!(Level::ERROR, "GetDIBits {}", err);
+        return Err(err);
+    }
+    Ok(Screenshot {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+// Rust wrapper for SetConsoleCtrlHandler, adds or removes `handler`
+pub fn set_console_ctrl_handler(
+    handler: PHANDLER_ROUTINE,
+    add: bool,
+) -> Result<(), Error> {
+    let res = unsafe { SetConsoleCtrlHandler(handler, add as BOOL) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "SetConsoleCtrlHandler {}", err);
+        return Err(err);
+    }
+    Ok(())
+}
+
+// Rust wrapper for EvtSubscribe to future events, `callback` is handed
+// `context` for every event until the subscription is closed
+pub fn evt_subscribe(
+    channel: &str,
+    query: &str,
+    context: LPVOID,
+    callback: EVT_SUBSCRIBE_CALLBACK,
+) -> Result<EVT_HANDLE, Error> {
+    let channel = to_wide(channel);
+    let query = to_wide(query);
+    let res = unsafe {
+        EvtSubscribe(
+            null_mut(),
+            null_mut(),
+            channel.as_ptr(),
+            query.as_ptr(),
+            null_mut(),
+            context,
+            callback,
+            EVT_SUBSCRIBE_TO_FUTURE_EVENTS,
+        )
+    };
+    if res.is_null() {
+        let err = Error::get_last();
+        event!(Level::ERROR, "EvtSubscribe {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "EvtSubscribe {:?}", res);
+    Ok(res)
+}
+
+// Rust wrapper for EvtCreateRenderContext selecting `paths`
+pub fn evt_create_render_context(paths: &[&str]) -> Result<EVT_HANDLE, Error> {
+    let paths: Vec<Vec<u16>> = paths.iter().map(|path| to_wide(path)).collect();
+    let ptrs: Vec<LPCWSTR> = paths.iter().map(|path| path.as_ptr()).collect();
+    let res = unsafe {
+        EvtCreateRenderContext(
+            ptrs.len() as DWORD,
+            ptrs.as_ptr(),
+            EVT_RENDER_CONTEXT_VALUES,
+        )
+    };
+    if res.is_null() {
+        let err = Error::get_last();
+        event!(Level::ERROR, "EvtCreateRenderContext {}", err);
+        return Err(err);
+    }
+    Ok(res)
+}
+
+// Rust wrapper for EvtRender with EvtRenderEventValues, up to 8 integer
+// values in the order of the context's paths, `None` where a path had none
+pub fn evt_render_values(
+    context: EVT_HANDLE,
+    event: EVT_HANDLE,
+) -> Result<Vec<Option<u64>>, Error> {
+    let mut values = [EVT_VARIANT {
+        Value: 0,
+        Count: 0,
+        Type: EVT_VAR_TYPE_NULL,
+    }; 8];
+    let mut used = 0;
+    let mut count = 0;
+    let res = unsafe {
+        EvtRender(
+            context,
+            event,
+            EVT_RENDER_EVENT_VALUES,
+            core::mem::size_of_val(&values) as DWORD,
+            values.as_mut_ptr() as LPVOID,
+            &mut used,
+            &mut count,
+        )
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "EvtRender {}", err);
+        return Err(err);
+    }
+    Ok(values[..(count as usize).min(values.len())]
+        .iter()
+        .map(|value| (value.Type != EVT_VAR_TYPE_NULL).then_some(value.Value))
+        .collect())
+}
+
+// Rust wrapper for EvtQuery and EvtNext, the values at `paths` of the newest
+// event in `channel` matching `query`, `None` if there is none
+pub fn evt_query_latest(
+    channel: &str,
+    query: &str,
+    paths: &[&str],
+) -> Result<Option<Vec<Option<u64>>>, Error> {
+    let channel = to_wide(channel);
+    let query = to_wide(query);
+    let results = unsafe {
+        EvtQuery(
+            null_mut(),
+            channel.as_ptr(),
+            query.as_ptr(),
+            EVT_QUERY_CHANNEL_PATH | EVT_QUERY_REVERSE_DIRECTION,
+        )
+    };
+    if results.is_null() {
+        let err = Error::get_last();
+        event!(Level::ERROR, "EvtQuery {}", err);
+        return Err(err);
+    }
+
+    let mut event = null_mut();
+    let mut returned = 0;
+    let res = unsafe { EvtNext(results, 1, &mut event, 0, 0, &mut returned) };
+    if res == 0 || returned == 0 {
+        // ERROR_NO_MORE_ITEMS when nothing matched
+        let _ = evt_close(results);
+        return Ok(None);
+    }
+    let values = evt_create_render_context(paths).and_then(|render| {
+        let values = evt_render_values(render, event);
+        let _ = evt_close(render);
+        values
+    });
+    let _ = evt_close(event);
+    let _ = evt_close(results);
+    values.map(Some)
+}
+
+// Rust wrapper for CallNtPowerInformation with LastSleepTime and
+// LastWakeTime, the interrupt time in 100ns units when the machine last went
+// to sleep and when it woke up again
+pub fn last_sleep_and_wake() -> Result<(u64, u64), Error> {
+    let query = |level| {
+        let mut time = 0u64;
+        let status = unsafe {
+            CallNtPowerInformation(
+                level,
+                null_mut(),
+                0,
+                &mut time as *mut u64 as LPVOID,
+                core::mem::size_of::<u64>() as u32,
+            )
+        };
+        if status != 0 {
+            let err =
+                Error::from_code(unsafe { LsaNtStatusToWinError(status) });
+            event!(Level::ERROR, "CallNtPowerInformation {}", err);
+            return Err(err);
+        }
+        Ok(time)
+    };
+    Ok((query(LAST_SLEEP_TIME)?, query(LAST_WAKE_TIME)?))
+}
+
+#[repr(C)]
+struct LUID {
+    LowPart: DWORD,
+    HighPart: LONG,
+}
+
+#[repr(C)]
+struct LSA_UNICODE_STRING {
+    /// In bytes, not including a terminator there may not be
+    Length: u16,
+    MaximumLength: u16,
+    Buffer: LPWSTR,
+}
+
+/// Only the fields up to Session are declared, the rest are never read
+#[repr(C)]
+struct SECURITY_LOGON_SESSION_DATA {
+    Size: u32,
+    LogonId: LUID,
+    UserName: LSA_UNICODE_STRING,
+    LogonDomain: LSA_UNICODE_STRING,
+    AuthenticationPackage: LSA_UNICODE_STRING,
+    LogonType: u32,
+    Session: u32,
+}
+
+/// What LsaGetLogonSessionData says about one logon session
+#[derive(Debug, Clone)]
+pub(crate) struct LogonSessionData {
+    pub user_name: String,
+    pub domain: String,
+    /// A SECURITY_LOGON_TYPE
+    pub logon_type: u32,
+    pub session_id: u32,
+}
+
+unsafe fn lsa_string(string: &LSA_UNICODE_STRING) -> String {
+    if string.Buffer.is_null() {
+        return String::new();
+    }
+    let len = string.Length as usize / core::mem::size_of::<WCHAR>();
+    String::from_utf16_lossy(core::slice::from_raw_parts(string.Buffer, len))
+}
+
+// Rust wrapper for LsaEnumerateLogonSessions and LsaGetLogonSessionData,
+// logon sessions that can't be read are skipped
+pub fn lsa_logon_sessions() -> Result<Vec<LogonSessionData>, Error> {
+    let mut count = 0;
+    let mut list = null_mut();
+    let status = unsafe { LsaEnumerateLogonSessions(&mut count, &mut list) };
+    if status != 0 {
+        let err = Error::from_code(unsafe { LsaNtStatusToWinError(status) });
+        event!(Level::ERROR, "LsaEnumerateLogonSessions {}", err);
+        return Err(err);
+    }
+
+    let mut sessions = Vec::with_capacity(count as usize);
+    for i in 0..count as usize {
+        let mut data = null_mut();
+        let status = unsafe { LsaGetLogonSessionData(list.add(i), &mut data) };
+        if status != 0 || data.is_null() {
+            continue;
+        }
+        unsafe {
+            let session = &*data;
+            sessions.push(LogonSessionData {
+                user_name: lsa_string(&session.UserName),
+                domain: lsa_string(&session.LogonDomain),
+                logon_type: session.LogonType,
+                session_id: session.Session,
+            });
+            LsaFreeReturnBuffer(data.cast());
+        }
+    }
+    unsafe { LsaFreeReturnBuffer(list.cast()) };
+    Ok(sessions)
+}
+
+// Rust wrapper for EvtClose, closing a subscription waits for its callbacks
+pub fn evt_close(handle: EVT_HANDLE) -> Result<(), Error> {
+    let res = unsafe { EvtClose(handle) };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "EvtClose {}", err);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// An IUnknown vtable, every COM interface starts with one
+#[repr(C)]
+pub(crate) struct IUnknownVtbl {
+    pub QueryInterface: unsafe extern "system" fn(
+        this: LPVOID,
+        riid: *const GUID,
+        ppv: *mut LPVOID,
+    ) -> HRESULT,
+    pub AddRef: unsafe extern "system" fn(this: LPVOID) -> u32,
+    pub Release: unsafe extern "system" fn(this: LPVOID) -> u32,
+}
+
+/// The vtable of a COM interface pointer
+///
+/// # Safety
+/// `object` must be a live interface pointer whose vtable starts like `T`
+pub(crate) unsafe fn vtbl<'a, T>(object: LPVOID) -> &'a T {
+    &**(object as *const *const T)
+}
+
+/// Release a COM interface pointer, null is ignored
+///
+/// # Safety
+/// `object` must be null or a live interface pointer we hold a reference to
+pub(crate) unsafe fn release(object: LPVOID) {
+    if !object.is_null() {
+        (vtbl::<IUnknownVtbl>(object).Release)(object);
+    }
+}
+
+/// The IDispatch methods after IUnknown, none of which we call
+#[repr(C)]
+pub(crate) struct IDispatchVtbl {
+    pub base: IUnknownVtbl,
+    pub GetTypeInfoCount:
+        unsafe extern "system" fn(this: LPVOID, pctinfo: *mut UINT) -> HRESULT,
+    pub GetTypeInfo: unsafe extern "system" fn(
+        this: LPVOID,
+        iTInfo: UINT,
+        lcid: DWORD,
+        ppTInfo: *mut LPVOID,
+    ) -> HRESULT,
+    pub GetIDsOfNames: unsafe extern "system" fn(
+        this: LPVOID,
+        riid: *const GUID,
+        rgszNames: *mut LPWSTR,
+        cNames: UINT,
+        lcid: DWORD,
+        rgDispId: *mut i32,
+    ) -> HRESULT,
+    pub Invoke: unsafe extern "system" fn(
+        this: LPVOID,
+        dispIdMember: i32,
+        riid: *const GUID,
+        lcid: DWORD,
+        wFlags: u16,
+        pDispParams: LPVOID,
+        pVarResult: LPVOID,
+        pExcepInfo: LPVOID,
+        puArgErr: *mut UINT,
+    ) -> HRESULT,
+}
+
+pub(crate) const S_OK: HRESULT = 0;
+pub(crate) const E_NOINTERFACE: HRESULT = 0x8000_4002u32 as HRESULT;
+pub(crate) const E_NOTIMPL: HRESULT = 0x8000_4001u32 as HRESULT;
+pub(crate) const IID_IUNKNOWN: GUID = GUID {
+    Data1: 0x00000000,
+    Data2: 0x0000,
+    Data3: 0x0000,
+    Data4: [0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+};
+pub(crate) const IID_IDISPATCH: GUID = GUID {
+    Data1: 0x00020400,
+    Data2: 0x0000,
+    Data3: 0x0000,
+    Data4: [0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+};
+const COINIT_APARTMENTTHREADED: DWORD = 0x2;
+const RPC_E_CHANGED_MODE: HRESULT = 0x8001_0106u32 as HRESULT;
+pub(crate) const CLSCTX_SERVER: DWORD = 0x15;
+
+/// COM initialised on this thread, uninitialised on drop if we did it
+pub(crate) struct ComApartment {
+    initialised: bool,
+}
+
+impl ComApartment {
+    // Rust wrapper for CoInitializeEx, an apartment of another kind that is
+    // already there is used as it is
+    pub fn enter() -> Result<Self, Error> {
+        let hr =
+            unsafe { CoInitializeEx(null_mut(), COINIT_APARTMENTTHREADED) };
+        if hr == RPC_E_CHANGED_MODE {
+            return Ok(Self { initialised: false });
+        }
+        if hr < 0 {
+            event!(Level::ERROR, "CoInitializeEx {hr:#x}");
+            return Err(Error::from_hresult(hr));
+        }
+        Ok(Self { initialised: true })
+    }
+}
+
+impl Drop for ComApartment {
+    fn drop(&mut self) {
+        if self.initialised {
+            unsafe { CoUninitialize() };
+        }
+    }
+}
+
+// Rust wrapper for CoCreateInstance, the returned interface pointer is owned
+// by the caller
+pub fn co_create_instance(clsid: &GUID, iid: &GUID) -> Result<LPVOID, Error> {
+    let mut object = null_mut();
+    let hr = unsafe {
+        CoCreateInstance(clsid, null_mut(), CLSCTX_SERVER, iid, &mut object)
+    };
+    if hr < 0 {
+        event!(Level::ERROR, "CoCreateInstance {hr:#x}");
+        return Err(Error::from_hresult(hr));
+    }
+    Ok(object)
+}
+
+/// Media Foundation started, shut down on drop
+pub(crate) struct MediaFoundation(());
+
+impl MediaFoundation {
+    // Rust wrapper for MFStartup
+    pub fn startup() -> Result<Self, Error> {
+        const MF_VERSION: u32 = 0x0002_0070;
+        check_hresult("MFStartup", unsafe { MFStartup(MF_VERSION, 0) })?;
+        Ok(Self(()))
+    }
+}
+
+impl Drop for MediaFoundation {
+    fn drop(&mut self) {
+        unsafe { MFShutdown() };
+    }
+}
+
+// Rust wrapper for MFCreateAttributes, an empty IMFAttributes owned by the
+// caller
+pub fn mf_create_attributes(size: u32) -> Result<LPVOID, Error> {
+    let mut attributes = null_mut();
+    let hr = unsafe { MFCreateAttributes(&mut attributes, size) };
+    check_hresult("MFCreateAttributes", hr)?;
+    Ok(attributes)
+}
+
+// Rust wrapper for MFCreateMediaType, an empty IMFMediaType owned by the
+// caller
+pub fn mf_create_media_type() -> Result<LPVOID, Error> {
+    let mut media_type = null_mut();
+    let hr = unsafe { MFCreateMediaType(&mut media_type) };
+    check_hresult("MFCreateMediaType", hr)?;
+    Ok(media_type)
+}
+
+// Rust wrapper for MFEnumDeviceSources, an IMFActivate owned by the caller
+// for each device matching `attributes`
+pub fn mf_enum_device_sources(
+    attributes: LPVOID,
+) -> Result<Vec<LPVOID>, Error> {
+    let mut array = null_mut();
+    let mut count = 0;
+    let hr = unsafe { MFEnumDeviceSources(attributes, &mut array, &mut count) };
+    check_hresult("MFEnumDeviceSources", hr)?;
+    if array.is_null() {
+        return Ok(Vec::new());
+    }
+    let devices =
+        unsafe { core::slice::from_raw_parts(array, count as usize) }.to_vec();
+    unsafe { CoTaskMemFree(array.cast()) };
+    Ok(devices)
+}
+
+// Rust wrapper for MFCreateSourceReaderFromMediaSource, an IMFSourceReader
+// owned by the caller
+pub fn mf_create_source_reader(
+    source: LPVOID,
+    attributes: LPVOID,
+) -> Result<LPVOID, Error> {
+    let mut reader = null_mut();
+    let hr = unsafe {
+        MFCreateSourceReaderFromMediaSource(source, attributes, &mut reader)
+    };
+    check_hresult("MFCreateSourceReaderFromMediaSource", hr)?;
+    Ok(reader)
+}
+
+// Rust wrapper for CoCreateGuid
+pub fn co_create_guid() -> Result<GUID, Error> {
+    let mut guid = MaybeUninit::<GUID>::zeroed();
+    let hr = unsafe { CoCreateGuid(guid.as_mut_ptr()) };
+    if hr < 0 {
+        event!(Level::ERROR, "CoCreateGuid {hr:#x}");
+        return Err(Error::from_hresult(hr));
+    }
+    Ok(unsafe { guid.assume_init() })
+}
+
+const RPC_C_AUTHN_WINNT: DWORD = 10;
+const RPC_C_AUTHZ_NONE: DWORD = 0;
+const RPC_C_AUTHN_LEVEL_CALL: DWORD = 3;
+const RPC_C_IMP_LEVEL_IMPERSONATE: DWORD = 3;
+const EOAC_NONE: DWORD = 0;
+
+// Rust wrapper for CoSetProxyBlanket, lets calls through `proxy` impersonate
+// us, which WMI needs
+pub fn co_set_proxy_blanket(proxy: LPVOID) -> Result<(), Error> {
+    let hr = unsafe {
+        CoSetProxyBlanket(
+            proxy,
+            RPC_C_AUTHN_WINNT,
+            RPC_C_AUTHZ_NONE,
+            null_mut(),
+            RPC_C_AUTHN_LEVEL_CALL,
+            RPC_C_IMP_LEVEL_IMPERSONATE,
+            null_mut(),
+            EOAC_NONE,
+        )
+    };
+    check_hresult("CoSetProxyBlanket", hr)
+}
+
+const VT_I4: u16 = 3;
+const VT_UI4: u16 = 19;
+
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct VARIANT {
+    vt: u16,
+    wReserved: [u16; 3],
+    data: [usize; 2],
+}
+
+/// A VARIANT filled in by a COM call, cleared on drop
+#[derive(Default)]
+pub(crate) struct Variant(VARIANT);
+
+impl Variant {
+    pub fn as_mut_ptr(&mut self) -> *mut VARIANT {
+        &mut self.0
+    }
+
+    /// The value if it is a 32 bit integer
+    pub fn as_u32(&self) -> Option<u32> {
+        match self.0.vt {
+            VT_I4 | VT_UI4 => Some(self.0.data[0] as u32),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Variant {
+    fn drop(&mut self) {
+        unsafe { VariantClear(&mut self.0) };
+    }
+}
+
+/// A job object holding a process and everything it starts, so the whole
+/// tree can be killed. Closed on drop, which leaves the processes running
+pub(crate) struct Job(HANDLE);
+
+// Job handles are not tied to the thread that created them
+unsafe impl Send for Job {}
+
+impl Job {
+    // Rust wrapper for CreateJobObjectW, an empty job. Hand it to
+    // [`create_process`] or [`create_process_as_user`] to start a process in
+    // it, along with every process that one starts
+    pub fn new() -> Result<Self, Error> {
+        let job = unsafe { CreateJobObjectW(null_mut(), null()) };
+        if job.is_null() {
+            let err = Error::get_last();
+            event!(Level::ERROR, "CreateJobObjectW {}", err);
+            return Err(err);
+        }
+        Ok(Self(job))
+    }
+
+    // Rust wrapper for TerminateJobObject, kills every process in the job
+    pub fn terminate(&self, exit_code: u32) -> Result<(), Error> {
+        let res = unsafe { TerminateJobObject(self.0, exit_code) };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "TerminateJobObject {}", err);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// A process we can wait for and kill, such as one started in another
+/// session by [`create_process_as_user`]. The handle is closed on drop
+pub(crate) struct ProcessHandle {
+    handle: HANDLE,
+    id: DWORD,
+}
+
+// Process handles are not tied to the thread that created them
+unsafe impl Send for ProcessHandle {}
+
+impl ProcessHandle {
+    // Rust wrapper for OpenProcess
+    pub fn open(process_id: u32) -> Result<Self, Error> {
+        let handle = unsafe {
+            OpenProcess(PROCESS_TERMINATE | SYNCHRONIZE, 0, process_id)
+        };
+        if handle.is_null() {
+            let err = Error::get_last();
+            event!(Level::ERROR, "OpenProcess {process_id} {}", err);
+            return Err(err);
+        }
+        Ok(Self {
+            handle,
+            id: process_id,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Rust wrapper for WaitForSingleObject and GetExitCodeProcess, the exit
+    // code or `None` if still running after `timeout_ms`
+    pub fn wait(&self, timeout_ms: Option<u32>) -> Result<Option<u32>, Error> {
+        match unsafe {
+            WaitForSingleObject(self.handle, timeout_ms.unwrap_or(INFINITE))
+        } {
+            WAIT_OBJECT_0 => {}
+            WAIT_TIMEOUT => return Ok(None),
+            _ => {
+                let err = Error::get_last();
+                event!(Level::ERROR, "WaitForSingleObject {}", err);
+                return Err(err);
+            }
+        }
+        let mut exit_code = 0;
+        let res = unsafe { GetExitCodeProcess(self.handle, &mut exit_code) };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "GetExitCodeProcess {}", err);
+            return Err(err);
+        }
+        Ok(Some(exit_code))
+    }
+
+    // Rust wrapper for TerminateProcess
+    pub fn kill(&self) -> Result<(), Error> {
+        let res = unsafe { TerminateProcess(self.handle, 1) };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "TerminateProcess {}", err);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ProcessHandle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.handle) };
+    }
+}
+
+/// A loaded DLL, freed on drop
+pub(crate) struct Library(HMODULE);
+
+// Modules are loaded for the whole process, not a thread
+unsafe impl Send for Library {}
+
+impl Library {
+    // Rust wrapper for LoadLibraryW, which also runs the DLL's DllMain
+    pub fn load(path: &std::path::Path) -> Result<Self, Error> {
+        let wide = to_wide(&path.to_string_lossy());
+        let module = unsafe { LoadLibraryW(wide.as_ptr()) };
+        if module.is_null() {
+            let err = Error::get_last();
+            event!(Level::ERROR, "LoadLibraryW {} {}", path.display(), err);
+            return Err(err);
+        }
+        Ok(Self(module))
+    }
+
+    // Rust wrapper for GetProcAddress, the address of an export by name
+    pub fn symbol(&self, name: &CStr) -> Option<LPVOID> {
+        let address = unsafe { GetProcAddress(self.0, name.as_ptr()) };
+        (!address.is_null()).then_some(address)
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        unsafe { FreeLibrary(self.0) };
+    }
+}
+
+/// A directory whose files are watched for being written, created, renamed
+/// or deleted. The handle is closed on drop
+pub(crate) struct DirectoryWatch(HANDLE);
+
+// Directory handles are not tied to the thread that opened them
+unsafe impl Send for DirectoryWatch {}
+
+impl DirectoryWatch {
+    // Rust wrapper for CreateFileW, opens `dir` for listing without stopping
+    // anyone else using, renaming or deleting it
+    pub fn open(dir: &std::path::Path) -> Result<Self, Error> {
+        let wide = to_wide(&dir.to_string_lossy());
+        let handle = unsafe {
+            CreateFileW(
+                wide.as_ptr(),
+                FILE_LIST_DIRECTORY,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                null_mut(),
+                OPEN_EXISTING,
+                FILE_FLAG_BACKUP_SEMANTICS,
+                null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let err = Error::get_last();
+            event!(Level::ERROR, "CreateFileW {} {}", dir.display(), err);
+            return Err(err);
+        }
+        Ok(Self(handle))
+    }
+
+    // Rust wrapper for ReadDirectoryChangesW, blocks until something in the
+    // directory changes and returns the names of the files that did. Empty
+    // if so much changed that Windows lost track
+    pub fn wait(&self) -> Result<Vec<String>, Error> {
+        // FILE_NOTIFY_INFORMATION records have to be DWORD aligned
+        let mut buffer = [0u32; 1024];
+        let mut returned = 0;
+        let res = unsafe {
+            ReadDirectoryChangesW(
+                self.0,
+                buffer.as_mut_ptr().cast(),
+                core::mem::size_of_val(&buffer) as DWORD,
+                0,
+                FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_LAST_WRITE,
+                &mut returned,
+                null_mut(),
+                null_mut(),
+            )
+        };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "ReadDirectoryChangesW {}", err);
+            return Err(err);
+        }
+        // Each record is NextEntryOffset, Action, FileNameLength in bytes and
+        // the name, which isn't NUL terminated
+        let mut names = Vec::new();
+        if returned == 0 {
+            return Ok(names);
+        }
+        let mut offset = 0;
+        loop {
+            let record = &buffer[offset..];
+            let len = record[2] as usize / 2;
+            let name = unsafe {
+                core::slice::from_raw_parts(
+                    record[3..].as_ptr().cast::<WCHAR>(),
+                    len,
+                )
+            };
+            names.push(String::from_utf16_lossy(name));
+            if record[0] == 0 {
+                break;
+            }
+            offset += record[0] as usize / 4;
+        }
+        Ok(names)
+    }
+}
+
+impl Drop for DirectoryWatch {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// A message mode pipe that only takes clients on this machine, either the
+/// server end waiting for them or a client's. The handle is closed on drop
+pub(crate) struct NamedPipe(HANDLE);
+
+// Pipe handles are not tied to the thread that created them
+unsafe impl Send for NamedPipe {}
+
+impl NamedPipe {
+    // Rust wrapper for CreateNamedPipeW, fails if another process already
+    // serves `name`. The default security lets only us, LocalSystem and
+    // administrators write to it
+    pub fn create(name: &str) -> Result<Self, Error> {
+        let wide = to_wide(name);
+        let handle = unsafe {
+            CreateNamedPipeW(
+                wide.as_ptr(),
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+                PIPE_TYPE_MESSAGE
+                    | PIPE_READMODE_MESSAGE
+                    | PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                PIPE_BUFFER as DWORD,
+                PIPE_BUFFER as DWORD,
+                0,
+                null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let err = Error::get_last();
+            event!(Level::ERROR, "CreateNamedPipeW {name} {}", err);
+            return Err(err);
+        }
+        Ok(Self(handle))
+    }
+
+    // Rust wrapper for CreateFileW, connects to the server of `name`
+    pub fn open(name: &str) -> Result<Self, Error> {
+        let wide = to_wide(name);
+        let handle = unsafe {
+            CreateFileW(
+                wide.as_ptr(),
+                GENERIC_READ | GENERIC_WRITE,
+                0,
+                null_mut(),
+                OPEN_EXISTING,
+                0,
+                null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let err = Error::get_last();
+            event!(Level::ERROR, "CreateFileW {name} {}", err);
+            return Err(err);
+        }
+        Ok(Self(handle))
+    }
+
+    // Rust wrapper for ConnectNamedPipe, blocks until a client connects
+    pub fn connect(&self) -> Result<(), Error> {
+        let res = unsafe { ConnectNamedPipe(self.0, null_mut()) };
+        // Fails like this when the client beat us to it
+        if res == 0 && unsafe { GetLastError() } != ERROR_PIPE_CONNECTED {
+            let err = Error::get_last();
+            event!(Level::ERROR, "ConnectNamedPipe {}", err);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Rust wrapper for FlushFileBuffers and DisconnectNamedPipe, waits for
+    // the client to read what we wrote and lets the next one connect
+    pub fn disconnect(&self) {
+        unsafe {
+            FlushFileBuffers(self.0);
+            DisconnectNamedPipe(self.0);
+        }
+    }
+
+    // Rust wrapper for ReadFile, one message
+    pub fn read(&self) -> Result<Vec<u8>, Error> {
+        let mut buffer = vec![0u8; PIPE_BUFFER];
+        let mut read = 0;
+        let res = unsafe {
+            ReadFile(
+                self.0,
+                buffer.as_mut_ptr().cast(),
+                buffer.len() as DWORD,
+                &mut read,
+                null_mut(),
+            )
+        };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "ReadFile {}", err);
+            return Err(err);
+        }
+        buffer.truncate(read as usize);
+        Ok(buffer)
+    }
+
+    // Rust wrapper for WriteFile, one message
+    pub fn write(&self, message: &[u8]) -> Result<(), Error> {
+        let mut written = 0;
+        let res = unsafe {
+            WriteFile(
+                self.0,
+                message.as_ptr().cast(),
+                message.len() as DWORD,
+                &mut written,
+                null_mut(),
+            )
+        };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "WriteFile {}", err);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for NamedPipe {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// An anonymous pipe for collecting what a process prints, whose write end
+/// is handed to it as stdout or stderr. Both ends are closed on drop
+pub(crate) struct Pipe {
+    read: HANDLE,
+    write: HANDLE,
+}
+
+// Pipe handles are not tied to the thread that created them
+unsafe impl Send for Pipe {}
+
+impl Pipe {
+    // Rust wrapper for CreatePipe, only the write end is inherited
+    pub fn new() -> Result<Self, Error> {
+        let mut read = null_mut();
+        let mut write = null_mut();
+        if unsafe { CreatePipe(&mut read, &mut write, null_mut(), 0) } == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "CreatePipe {}", err);
+            return Err(err);
+        }
+        let pipe = Self { read, write };
+        let res = unsafe {
+            SetHandleInformation(
+                write,
+                HANDLE_FLAG_INHERIT,
+                HANDLE_FLAG_INHERIT,
+            )
+        };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "SetHandleInformation {}", err);
+            return Err(err);
+        }
+        Ok(pipe)
+    }
+
+    /// The read end alone, which reaches the end once the processes holding
+    /// the write end close it
+    pub fn into_reader(mut self) -> Self {
+        unsafe { CloseHandle(self.write) };
+        self.write = null_mut();
+        self
+    }
+}
+
+impl std::io::Read for Pipe {
+    // Rust wrapper for ReadFile
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let mut read = 0;
+        let res = unsafe {
+            ReadFile(
+                self.read,
+                buffer.as_mut_ptr().cast(),
+                buffer.len().min(DWORD::MAX as usize) as DWORD,
+                &mut read,
+                null_mut(),
+            )
+        };
+        if res == 0 {
+            let err = std::io::Error::last_os_error();
+            // Once every writer has gone
+            if err.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) {
+                return Ok(0);
+            }
+            return Err(err);
+        }
+        Ok(read as usize)
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.read) };
+        if !self.write.is_null() {
+            unsafe { CloseHandle(self.write) };
+        }
+    }
+}
+
+// Rust wrapper for CreateProcessW, starts `command_line` in our session
+// with our environment if `inherit_env`, otherwise as
+// [`create_process_as_user`] does
+pub fn create_process(
+    command_line: &str,
+    cwd: Option<&std::path::Path>,
+    env: &[(String, String)],
+    inherit_env: bool,
+    output: Option<(&Pipe, &Pipe)>,
+    job: Option<&Job>,
+) -> Result<ProcessHandle, Error> {
+    let mut variables = Vec::new();
+    if inherit_env {
+        variables.extend(std::env::vars_os().map(|(name, value)| {
+            format!("{}={}", name.to_string_lossy(), value.to_string_lossy())
+        }));
+    }
+    start_process(None, variables, command_line, cwd, env, output, job)
+}
+
+// Rust wrapper for WTSQueryUserToken and CreateProcessAsUserW, starts
+// `command_line` as the user logged on to `session_id`, on their desktop
+// and with their profile's environment if `inherit_env`. `env` is added on
+// top and `output` takes what it prints to stdout and stderr. With a `job`
+// it starts in it, before it can start anything that would escape it. Only
+// LocalSystem may query another user's token
+pub fn create_process_as_user(
+    session_id: u32,
+    command_line: &str,
+    cwd: Option<&std::path::Path>,
+    env: &[(String, String)],
+    inherit_env: bool,
+    output: Option<(&Pipe, &Pipe)>,
+    job: Option<&Job>,
+) -> Result<ProcessHandle, Error> {
+    let mut token = null_mut();
+    if unsafe { WTSQueryUserToken(session_id, &mut token) } == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "WTSQueryUserToken {session_id} {}", err);
+        return Err(err);
+    }
+
+    let mut variables = Vec::new();
+    if inherit_env {
+        let mut block = null_mut();
+        if unsafe { CreateEnvironmentBlock(&mut block, token, 0) } == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "CreateEnvironmentBlock {}", err);
+            unsafe { CloseHandle(token) };
+            return Err(err);
+        }
+        // NUL separated `name=value` strings ending with an empty one
+        let mut ptr = block as *const WCHAR;
+        loop {
+            let variable = unsafe { from_wide_ptr(ptr) };
+            if variable.is_empty() {
+                break;
+            }
+            ptr = unsafe { ptr.add(variable.encode_utf16().count() + 1) };
+            variables.push(variable);
+        }
+        unsafe { DestroyEnvironmentBlock(block) };
+    }
+    let process = start_process(
+        Some(token),
+        variables,
+        command_line,
+        cwd,
+        env,
+        output,
+        job,
+    );
+    unsafe { CloseHandle(token) };
+    process
+}
+
+/// [`create_process`] or, with a `token`, [`create_process_as_user`] with
+/// the environment `variables` as `name=value` before `env` is added.
+/// With a `job` the process is started suspended, put in the job and only
+/// then resumed
+fn start_process(
+    token: Option<HANDLE>,
+    mut variables: Vec<String>,
+    command_line: &str,
+    cwd: Option<&std::path::Path>,
+    env: &[(String, String)],
+    output: Option<(&Pipe, &Pipe)>,
+    job: Option<&Job>,
+) -> Result<ProcessHandle, Error> {
+    // Hidden variables such as `=C:` start with an =, so the name ends at
+    // the first one after that
+    let name_of = |variable: &str| {
+        variable
+            .char_indices()
+            .skip(1)
+            .find(|&(_, c)| c == '=')
+            .map_or(variable.len(), |(end, _)| end)
+    };
+    for (name, value) in env {
+        // Names are case insensitive
+        variables.retain(|variable: &String| {
+            !variable[..name_of(variable)].eq_ignore_ascii_case(name)
+        });
+        variables.push(format!("{name}={value}"));
+    }
+    let mut block: Vec<WCHAR> = variables
+        .iter()
+        .flat_map(|variable| to_wide(variable))
+        .collect();
+    block.push(0);
+
+    let mut desktop = to_wide("winsta0\\default");
+    let mut command_line = to_wide(command_line);
+    let cwd = cwd.map(|cwd| to_wide(&cwd.to_string_lossy()));
+    let mut startup_info = STARTUPINFOW {
+        cb: core::mem::size_of::<STARTUPINFOW>() as DWORD,
+        ..unsafe { MaybeUninit::zeroed().assume_init() }
+    };
+    if token.is_some() {
+        startup_info.lpDesktop = desktop.as_mut_ptr();
+    }
+    if let Some((stdout, stderr)) = output {
+        startup_info.dwFlags = STARTF_USESTDHANDLES;
+        startup_info.hStdOutput = stdout.write;
+        startup_info.hStdError = stderr.write;
+    }
+    let mut flags = CREATE_UNICODE_ENVIRONMENT | CREATE_NO_WINDOW;
+    if job.is_some() {
+        flags |= CREATE_SUSPENDED;
+    }
+    let mut process_info = MaybeUninit::<PROCESS_INFORMATION>::zeroed();
+    let (res, function) = match token {
+        Some(token) => (
+            unsafe {
+                CreateProcessAsUserW(
+                    token,
+                    null(),
+                    command_line.as_mut_ptr(),
+                    null_mut(),
+                    null_mut(),
+                    output.is_some() as BOOL,
+                    flags,
+                    block.as_mut_ptr().cast(),
+                    cwd.as_ref().map_or(null(), |cwd| cwd.as_ptr()),
+                    &startup_info,
+                    process_info.as_mut_ptr(),
+                )
+            },
+            "CreateProcessAsUserW",
+        ),
+        None => (
+            unsafe {
+                CreateProcessW(
+                    null(),
+                    command_line.as_mut_ptr(),
+                    null_mut(),
+                    null_mut(),
+                    output.is_some() as BOOL,
+                    flags,
+                    block.as_mut_ptr().cast(),
+                    cwd.as_ref().map_or(null(), |cwd| cwd.as_ptr()),
+                    &startup_info,
+                    process_info.as_mut_ptr(),
+                )
+            },
+            "CreateProcessW",
+        ),
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "{function} {}", err);
+        return Err(err);
+    }
+    let process_info = unsafe { process_info.assume_init() };
+    let process = ProcessHandle {
+        handle: process_info.hProcess,
+        id: process_info.dwProcessId,
+    };
+    if let Some(job) = job {
+        let res = unsafe { AssignProcessToJobObject(job.0, process.handle) };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "AssignProcessToJobObject {}", err);
+            let _ = process.kill();
+            unsafe { CloseHandle(process_info.hThread) };
+            return Err(err);
+        }
+        if unsafe { ResumeThread(process_info.hThread) } == DWORD::MAX {
+            let err = Error::get_last();
+            event!(Level::ERROR, "ResumeThread {}", err);
+            let _ = process.kill();
+            unsafe { CloseHandle(process_info.hThread) };
+            return Err(err);
+        }
+    }
+    unsafe { CloseHandle(process_info.hThread) };
+    Ok(process)
+}
+
+/// A service opened to start, stop and query, closed on drop
+pub(crate) struct Service {
+    manager: HANDLE,
+    service: HANDLE,
+}
+
+impl Service {
+    // Rust wrapper for OpenSCManagerW and OpenServiceW, `name` is the
+    // service's short name such as `Spooler`, not its display name
+    pub fn open(name: &str) -> Result<Self, Error> {
+        let manager =
+            unsafe { OpenSCManagerW(null(), null(), SC_MANAGER_CONNECT) };
+        if manager.is_null() {
+            let err = Error::get_last();
+            event!(Level::ERROR, "OpenSCManagerW {}", err);
+            return Err(err);
+        }
+        let service = unsafe {
+            OpenServiceW(
+                manager,
+                to_wide(name).as_ptr(),
+                SERVICE_START | SERVICE_STOP | SERVICE_QUERY_STATUS,
+            )
+        };
+        if service.is_null() {
+            let err = Error::get_last();
+            event!(Level::ERROR, "OpenServiceW {name} {}", err);
+            unsafe { CloseServiceHandle(manager) };
+            return Err(err);
+        }
+        Ok(Self { manager, service })
+    }
+
+    // Rust wrapper for StartServiceW
+    pub fn start(&self) -> Result<(), Error> {
+        let res = unsafe { StartServiceW(self.service, 0, null()) };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "StartServiceW {}", err);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Rust wrapper for ControlService with SERVICE_CONTROL_STOP
+    pub fn stop(&self) -> Result<(), Error> {
+        let mut status = MaybeUninit::<SERVICE_STATUS>::zeroed();
+        let res = unsafe {
+            ControlService(
+                self.service,
+                SERVICE_CONTROL_STOP,
+                status.as_mut_ptr(),
+            )
+        };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "ControlService {}", err);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Rust wrapper for QueryServiceStatus, the dwCurrentState such as
+    // SERVICE_RUNNING
+    pub fn state(&self) -> Result<DWORD, Error> {
+        let mut status = MaybeUninit::<SERVICE_STATUS>::zeroed();
+        let res =
+            unsafe { QueryServiceStatus(self.service, status.as_mut_ptr()) };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "QueryServiceStatus {}", err);
+            return Err(err);
+        }
+        Ok(unsafe { status.assume_init() }.dwCurrentState)
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        unsafe {
+            CloseServiceHandle(self.service);
+            CloseServiceHandle(self.manager);
+        }
+    }
+}
+
+/// The clipboard while we have it open, closed on drop
+pub(crate) struct Clipboard(());
+
+impl Clipboard {
+    // Rust wrapper for OpenClipboard, trying a few times as other programs
+    // hold it open briefly while they copy
+    pub fn open() -> Result<Self, Error> {
+        for _ in 0..10 {
+            if unsafe { OpenClipboard(null_mut()) } != 0 {
+                return Ok(Self(()));
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        let err = Error::get_last();
+        event!(Level::ERROR, "OpenClipboard {}", err);
+        Err(err)
+    }
+
+    // Rust wrapper for GetClipboardData, the text on the clipboard if there
+    // is any
+    pub fn text(&self) -> Option<String> {
+        if unsafe { IsClipboardFormatAvailable(CF_UNICODETEXT) } == 0 {
+            return None;
+        }
+        let data = unsafe { GetClipboardData(CF_UNICODETEXT) };
+        if data.is_null() {
+            event!(Level::ERROR, "GetClipboardData {}", Error::get_last());
+            return None;
+        }
+        let text = unsafe { GlobalLock(data) };
+        if text.is_null() {
+            event!(Level::ERROR, "GlobalLock {}", Error::get_last());
+            return None;
+        }
+        let string = unsafe { from_wide_ptr(text as *const WCHAR) };
+        unsafe { GlobalUnlock(data) };
+        Some(string)
+    }
+
+    // Rust wrapper for EmptyClipboard
+    pub fn empty(&self) -> Result<(), Error> {
+        if unsafe { EmptyClipboard() } == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "EmptyClipboard {}", err);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Clipboard {
+    fn drop(&mut self) {
+        unsafe { CloseClipboard() };
+    }
+}
+
+/// A dial up or VPN connection from [`ras_connections`]
+pub(crate) struct RasConnection {
+    handle: HANDLE,
+    /// The phonebook entry it was dialled from, eg. `Corp VPN`
+    pub name: String,
+}
+
+// Rust wrapper for RasEnumConnectionsW, every active dial up and VPN
+// connection
+pub fn ras_connections() -> Result<Vec<RasConnection>, Error> {
+    let size = core::mem::size_of::<RASCONNW>() as DWORD;
+    let mut connections: Vec<RASCONNW> = Vec::new();
+    let mut count = 0;
+    loop {
+        let mut buffer_size = size * connections.len() as DWORD;
+        if let Some(first) = connections.first_mut() {
+            first.dwSize = size;
+        }
+        // Null with a size of 0 asks for the size needed
+        let ptr = if connections.is_empty() {
+            null_mut()
+        } else {
+            connections.as_mut_ptr()
+        };
+        let res =
+            unsafe { RasEnumConnectionsW(ptr, &mut buffer_size, &mut count) };
+        match res {
+            NO_ERROR => break,
+            ERROR_BUFFER_TOO_SMALL => {
+                let needed = (buffer_size / size) as usize;
+                connections.resize_with(needed.max(1), || unsafe {
+                    MaybeUninit::zeroed().assume_init()
+                });
+            }
+            err => {
+                let err = Error::from_code(err);
+                event!(Level::ERROR, "RasEnumConnectionsW {}", err);
+                return Err(err);
+            }
+        }
+    }
+    connections.truncate(count as usize);
+    Ok(connections
+        .iter()
+        .map(|connection| RasConnection {
+            handle: connection.hrasconn,
+            name: unsafe { from_wide_ptr(connection.szEntryName.as_ptr()) },
+        })
+        .collect())
+}
+
+// Rust wrapper for RasHangUpW
+pub fn ras_hang_up(connection: &RasConnection) -> Result<(), Error> {
+    let res = unsafe { RasHangUpW(connection.handle) };
+    if res != NO_ERROR {
+        let err = Error::from_code(res);
+        event!(Level::ERROR, "RasHangUpW {} {}", connection.name, err);
+        return Err(err);
+    }
+    Ok(())
+}
+
+// Rust wrapper for WNetGetConnectionW, the share a drive such as `S:` is
+// mapped to, eg. `\\server\share`
+pub fn wnet_get_connection(drive: &str) -> Result<String, Error> {
+    let mut remote = [0u16; 1024];
+    let mut len = remote.len() as DWORD;
+    let res = unsafe {
+        WNetGetConnectionW(
+            to_wide(drive).as_ptr(),
+            remote.as_mut_ptr(),
+            &mut len,
+        )
+    };
+    if res != NO_ERROR {
+        let err = Error::from_code(res);
+        event!(Level::ERROR, "WNetGetConnectionW {drive} {}", err);
+        return Err(err);
+    }
+    Ok(unsafe { from_wide_ptr(remote.as_ptr()) })
+}
+
+// Rust wrapper for WNetCancelConnection2W, unmaps the drive even with files
+// open on it. The mapping stays in the profile for the next logon
+pub fn wnet_cancel_connection(drive: &str) -> Result<(), Error> {
+    let res = unsafe { WNetCancelConnection2W(to_wide(drive).as_ptr(), 0, 1) };
+    if res != NO_ERROR {
+        let err = Error::from_code(res);
+        event!(Level::ERROR, "WNetCancelConnection2W {drive} {}", err);
+        return Err(err);
+    }
+    Ok(())
+}
+
+// Rust wrapper for WNetAddConnection2W, maps the drive to `remote` with the
+// credentials saved for it, or the logged on user's
+pub fn wnet_add_connection(drive: &str, remote: &str) -> Result<(), Error> {
+    let mut local = to_wide(drive);
+    let mut remote_wide = to_wide(remote);
+    let resource = NETRESOURCEW {
+        dwScope: 0,
+        dwType: RESOURCETYPE_DISK,
+        dwDisplayType: 0,
+        dwUsage: 0,
+        lpLocalName: local.as_mut_ptr(),
+        lpRemoteName: remote_wide.as_mut_ptr(),
+        lpComment: null_mut(),
+        lpProvider: null_mut(),
+    };
+    let res = unsafe { WNetAddConnection2W(&resource, null(), null(), 0) };
+    if res != NO_ERROR {
+        let err = Error::from_code(res);
+        event!(Level::ERROR, "WNetAddConnection2W {drive} {remote} {}", err);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// A disk from [`removable_disks`]
+pub(crate) struct RemovableDisk {
+    dev_inst: DWORD,
+    /// The name Device Manager shows, eg. `SanDisk Cruzer USB Device`
+    pub name: String,
+}
+
+// Rust wrapper for SetupDiGetClassDevsW and SetupDiEnumDeviceInfo, every
+// present disk whose removal policy says it can be unplugged, such as USB
+// sticks and drives
+pub fn removable_disks() -> Result<Vec<RemovableDisk>, Error> {
+    let set = unsafe {
+        SetupDiGetClassDevsW(
+            &GUID_DEVINTERFACE_DISK,
+            null(),
+            null_mut(),
+            DIGCF_PRESENT | DIGCF_DEVICEINTERFACE,
+        )
+    };
+    if set == INVALID_HANDLE_VALUE {
+        let err = Error::get_last();
+        event!(Level::ERROR, "SetupDiGetClassDevsW {}", err);
+        return Err(err);
+    }
+    // Rust wrapper for SetupDiGetDeviceRegistryPropertyW
+    let property = |data: &mut SP_DEVINFO_DATA, property, buffer: &mut [u8]| {
+        let res = unsafe {
+            SetupDiGetDeviceRegistryPropertyW(
+                set,
+                data,
+                property,
+                null_mut(),
+                buffer.as_mut_ptr(),
+                buffer.len() as DWORD,
+                null_mut(),
+            )
+        };
+        res != 0
+    };
+
+    let mut disks = Vec::new();
+    for index in 0.. {
+        let mut data: SP_DEVINFO_DATA =
+            unsafe { MaybeUninit::zeroed().assume_init() };
+        data.cbSize = core::mem::size_of::<SP_DEVINFO_DATA>() as DWORD;
+        if unsafe { SetupDiEnumDeviceInfo(set, index, &mut data) } == 0 {
+            break;
+        }
+        let mut policy = [0u8; 4];
+        if !property(&mut data, SPDRP_REMOVAL_POLICY, &mut policy)
+            || u32::from_ne_bytes(policy) == CM_REMOVAL_POLICY_EXPECT_NO_REMOVAL
+        {
+            continue;
+        }
+        let mut name = [0u16; 256];
+        let name = if property(&mut data, SPDRP_FRIENDLYNAME, unsafe {
+            core::slice::from_raw_parts_mut(name.as_mut_ptr().cast(), 512)
+        }) {
+            unsafe { from_wide_ptr(name.as_ptr()) }
+        } else {
+            format!("disk {}", data.DevInst)
+        };
+        disks.push(RemovableDisk {
+            dev_inst: data.DevInst,
+            name,
+        });
+    }
+    unsafe { SetupDiDestroyDeviceInfoList(set) };
+    Ok(disks)
+}
+
+// Rust wrapper for CM_Get_Parent and CM_Request_Device_EjectW, ejects the
+// device the disk is on like Safely Remove Hardware does
+pub fn eject_device(disk: &RemovableDisk) -> Result<(), Error> {
+    let mut parent = 0;
+    let res = unsafe { CM_Get_Parent(&mut parent, disk.dev_inst, 0) };
+    if res != CR_SUCCESS {
+        event!(Level::ERROR, "CM_Get_Parent {} {res:#x}", disk.name);
+        return Err(Error::Other(res));
+    }
+    let mut veto_type = 0;
+    let mut veto_name = [0u16; 260];
+    let res = unsafe {
+        CM_Request_Device_EjectW(
+            parent,
+            &mut veto_type,
+            veto_name.as_mut_ptr(),
+            veto_name.len() as u32,
+            0,
+        )
+    };
+    if res != CR_SUCCESS || veto_type != 0 {
+        event!(
+            Level::ERROR,
+            "CM_Request_Device_EjectW {} vetoed, type {veto_type} by {}",
+            disk.name,
+            unsafe { from_wide_ptr(veto_name.as_ptr()) }
+        );
+        return Err(Error::Other(CR_REMOVE_VETOED));
+    }
+    Ok(())
+}
+
+/// A BSTR we allocated, freed on drop
+pub(crate) struct Bstr(BSTR);
+
+impl Bstr {
+    pub fn new(s: &str) -> Self {
+        Self(unsafe { SysAllocString(to_wide(s).as_ptr()) })
+    }
+
+    pub fn as_raw(&self) -> BSTR {
+        self.0
+    }
+}
+
+impl Drop for Bstr {
+    fn drop(&mut self) {
+        unsafe { SysFreeString(self.0) };
+    }
+}
+
+/// Check the HRESULT of a COM method call
+pub(crate) fn check_hresult(method: &str, hr: HRESULT) -> Result<(), Error> {
+    if hr < 0 {
+        event!(Level::ERROR, "{method} {hr:#x}");
+        return Err(Error::from_hresult(hr));
+    }
+    Ok(())
+}
+
+/// Only the fields up to InterfaceIndex are declared, the rest are never read
+#[repr(C)]
+pub(crate) struct MIB_IPINTERFACE_ROW {
+    Family: u16,
+    InterfaceLuid: u64,
+    pub InterfaceIndex: DWORD,
+}
+
+#[repr(C)]
+struct MIB_IF_ROW2 {
+    InterfaceLuid: u64,
+    InterfaceIndex: DWORD,
+    InterfaceGuid: GUID,
+    Alias: [WCHAR; 257],
+    Description: [WCHAR; 257],
+    PhysicalAddressLength: DWORD,
+    PhysicalAddress: [u8; 32],
+    PermanentPhysicalAddress: [u8; 32],
+    Mtu: DWORD,
+    Type: DWORD,
+    TunnelType: DWORD,
+    MediaType: DWORD,
+    PhysicalMediumType: DWORD,
+    AccessType: DWORD,
+    DirectionType: DWORD,
+    InterfaceAndOperStatusFlags: u8,
+    OperStatus: DWORD,
+    AdminStatus: DWORD,
+    MediaConnectState: DWORD,
+    NetworkGuid: GUID,
+    ConnectionType: DWORD,
+    /// The link speeds and traffic counters
+    Counters: [u64; 20],
+}
+
+#[repr(C)]
+struct MIB_IF_TABLE2 {
+    NumEntries: u32,
+    Table: [MIB_IF_ROW2; 1],
+}
+
+/// What GetIfEntry2 says about one network interface
+#[derive(Clone)]
+pub(crate) struct IfEntry {
+    pub index: u32,
+    pub guid: GUID,
+    /// The name shown in Network Connections, eg. `Wi-Fi`
+    pub alias: String,
+    pub description: String,
+    /// An IFTYPE such as [`IF_TYPE_IEEE80211`]
+    pub if_type: u32,
+    /// Up and with media connected
+    pub connected: bool,
+}
+
+impl IfEntry {
+    fn from_row(row: &MIB_IF_ROW2) -> Self {
+        Self {
+            index: row.InterfaceIndex,
+            guid: row.InterfaceGuid,
+            alias: unsafe { from_wide_ptr(row.Alias.as_ptr()) },
+            description: unsafe { from_wide_ptr(row.Description.as_ptr()) },
+            if_type: row.Type,
+            connected: row.OperStatus == IF_OPER_STATUS_UP
+                && row.MediaConnectState == MEDIA_CONNECT_STATE_CONNECTED,
+        }
+    }
+
+    /// Loopback and the filter drivers stacked on real adapters never say
+    /// anything about connectivity
+    fn is_relevant(row: &MIB_IF_ROW2) -> bool {
+        row.Type != IF_TYPE_SOFTWARE_LOOPBACK
+            && row.InterfaceAndOperStatusFlags & FILTER_INTERFACE == 0
+    }
+}
+
+// Rust wrapper for NotifyIpInterfaceChange on IPv4 and IPv6, `callback` is
+// called on a thread pool thread with `context`
+pub fn notify_ip_interface_change(
+    context: LPVOID,
+    callback: PIPINTERFACE_CHANGE_CALLBACK,
+) -> Result<HANDLE, Error> {
+    let mut handle = null_mut();
+    let res = unsafe {
+        NotifyIpInterfaceChange(AF_UNSPEC, callback, context, 0, &mut handle)
+    };
+    if res != NO_ERROR {
+        let err = Error::from_code(res);
+        event!(Level::ERROR, "NotifyIpInterfaceChange {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "NotifyIpInterfaceChange {:?}", handle);
+    Ok(handle)
+}
+
+// Rust wrapper for CancelMibChangeNotify2, waits for running callbacks
+pub fn cancel_mib_change_notify2(handle: HANDLE) -> Result<(), Error> {
+    let res = unsafe { CancelMibChangeNotify2(handle) };
+    if res != NO_ERROR {
+        let err = Error::from_code(res);
+        event!(Level::ERROR, "CancelMibChangeNotify2 {}", err);
+        return Err(err);
+    }
+    event!(Level::INFO, "CancelMibChangeNotify2 {:?}", handle);
+    Ok(())
+}
+
+// Rust wrapper for GetIfEntry2, `None` for interfaces that don't matter for
+// connectivity
+pub fn get_if_entry2(index: u32) -> Result<Option<IfEntry>, Error> {
+    let mut row: MIB_IF_ROW2 = unsafe { core::mem::zeroed() };
+    row.InterfaceIndex = index;
+    let res = unsafe { GetIfEntry2(&mut row) };
+    if res != NO_ERROR {
+        let err = Error::from_code(res);
+        event!(Level::ERROR, "GetIfEntry2 {}", err);
+        return Err(err);
+    }
+    Ok(IfEntry::is_relevant(&row).then(|| IfEntry::from_row(&row)))
+}
+
+// Rust wrapper for GetIfTable2, every interface that matters for
+// connectivity
+pub fn get_if_table2() -> Result<Vec<IfEntry>, Error> {
+    let mut table = null_mut();
+    let res = unsafe { GetIfTable2(&mut table) };
+    if res != NO_ERROR {
+        let err = Error::from_code(res);
+        event!(Level::ERROR, "GetIfTable2 {}", err);
+        return Err(err);
+    }
+    let entries = unsafe {
+        let count = (*table).NumEntries as usize;
+        core::slice::from_raw_parts((*table).Table.as_ptr(), count)
+            .iter()
+            .filter(|row| IfEntry::is_relevant(row))
+            .map(IfEntry::from_row)
+            .collect()
+    };
+    unsafe { FreeMibTable(table.cast()) };
+    Ok(entries)
+}
+
+// Rust wrapper for WlanQueryInterface, the SSID the wireless interface
+// `guid` is connected to
+pub fn wlan_current_ssid(guid: &GUID) -> Option<String> {
+    let mut version = 0;
+    let mut client = null_mut();
+    let res = unsafe {
+        WlanOpenHandle(
+            WLAN_API_VERSION_2_0,
+            null_mut(),
+            &mut version,
+            &mut client,
+        )
+    };
+    if res != NO_ERROR {
+        event!(Level::ERROR, "WlanOpenHandle {}", Error::from_code(res));
+        return None;
+    }
+
+    let mut size = 0;
+    let mut data = null_mut();
+    let res = unsafe {
+        WlanQueryInterface(
+            client,
+            guid,
+            WLAN_INTF_OPCODE_CURRENT_CONNECTION,
+            null_mut(),
+            &mut size,
+            &mut data,
+            null_mut(),
+        )
+    };
+    let ssid = (res == NO_ERROR).then(|| {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(data as *const u8, size as _)
+        };
+        let ssid =
+            bytes.get(WLAN_CONNECTION_SSID..WLAN_CONNECTION_SSID + 36)?;
+        let len = u32::from_le_bytes(ssid[..4].try_into().ok()?) as usize;
+        let name = ssid.get(4..4 + len.min(32))?;
+        Some(String::from_utf8_lossy(name).into_owned())
+    });
+    if res == NO_ERROR {
+        unsafe { WlanFreeMemory(data) };
+    } else {
+        event!(Level::ERROR, "WlanQueryInterface {}", Error::from_code(res));
+    }
+    unsafe { WlanCloseHandle(client, null_mut()) };
+    ssid.flatten()
+}
+
+/// A WinHTTP session, connection or request, closed on drop
+struct Internet(HANDLE);
+
+impl Drop for Internet {
+    fn drop(&mut self) {
+        unsafe { WinHttpCloseHandle(self.0) };
+    }
+}
+
+// Rust wrapper for the WinHTTP calls making one request, such as a `POST` of
+// `body` to an http or https `url` with `headers` such as `Authorization`.
+// Returns the response's status code and body. Only the scheme and host of
+// `url` are logged
+pub fn http_request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<(u32, Vec<u8>), Error> {
+    let (secure, rest) = match url.split_once("://") {
+        Some(("https", rest)) => (true, rest),
+        Some(("http", rest)) => (false, rest),
+        _ => return Err(Error::INVALID_PARAMETER),
+    };
+    let (authority, path) = rest
+        .find('/')
+        .map_or((rest, "/"), |slash| (&rest[..slash], &rest[slash..]));
+    // The path can hold a key, such as a Hue bridge's user
+    let shown = &url[..url.len() - rest.len() + authority.len()];
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => {
+            (host, port.parse().map_err(|_| Error::INVALID_PARAMETER)?)
+        }
+        None if secure => (authority, 443),
+        None => (authority, 80),
+    };
+
+    let call = |name: &str, handle: HANDLE| {
+        if handle.is_null() {
+            let err = Error::get_last();
+            event!(Level::ERROR, "{name} {shown} {}", err);
+            return Err(err);
+        }
+        Ok(Internet(handle))
+    };
+    let agent = to_wide("rusty-lock");
+    let session = call("WinHttpOpen", unsafe {
+        WinHttpOpen(
+            agent.as_ptr(),
+            WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY,
+            null(),
+            null(),
+            0,
+        )
+    })?;
+    let host = to_wide(host);
+    let connection = call("WinHttpConnect", unsafe {
+        WinHttpConnect(session.0, host.as_ptr(), port, 0)
+    })?;
+    let method = to_wide(method);
+    let path = to_wide(path);
+    let flags = if secure { WINHTTP_FLAG_SECURE } else { 0 };
+    let request = call("WinHttpOpenRequest", unsafe {
+        WinHttpOpenRequest(
+            connection.0,
+            method.as_ptr(),
+            path.as_ptr(),
+            null(),
+            null(),
+            null(),
+            flags,
+        )
+    })?;
+
+    let headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}\r\n"))
+        .collect();
+    let headers: Vec<WCHAR> = headers.encode_utf16().collect();
+    let res = unsafe {
+        WinHttpSendRequest(
+            request.0,
+            headers.as_ptr(),
+            headers.len() as DWORD,
+            body.as_ptr() as LPVOID,
+            body.len() as DWORD,
+            body.len() as DWORD,
+            0,
+        )
+    };
+    if res == 0 || unsafe { WinHttpReceiveResponse(request.0, null_mut()) } == 0
+    {
+        let err = Error::get_last();
+        event!(Level::ERROR, "WinHttpSendRequest {shown} {}", err);
+        return Err(err);
+    }
+    let mut status: DWORD = 0;
+    let mut size = core::mem::size_of::<DWORD>() as DWORD;
+    let res = unsafe {
+        WinHttpQueryHeaders(
+            request.0,
+            WINHTTP_QUERY_STATUS_CODE | WINHTTP_QUERY_FLAG_NUMBER,
+            null(),
+            (&mut status as *mut DWORD).cast(),
+            &mut size,
+            null_mut(),
+        )
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "WinHttpQueryHeaders {shown} {}", err);
+        return Err(err);
+    }
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let mut read = 0;
+        let res = unsafe {
+            WinHttpReadData(
+                request.0,
+                buffer.as_mut_ptr().cast(),
+                buffer.len() as DWORD,
+                &mut read,
+            )
+        };
+        if res == 0 {
+            let err = Error::get_last();
+            event!(Level::ERROR, "WinHttpReadData {shown} {}", err);
+            return Err(err);
+        }
+        // Nothing read once the response has all been read
+        if read == 0 {
+            break;
+        }
+        response.extend_from_slice(&buffer[..read as usize]);
+    }
+    event!(Level::INFO, "{shown} responded {status}");
+    Ok((status, response))
+}
+
+// Rust wrapper for CredReadW, the secret of the generic credential `target`
+// in the Credential Manager of the user we run as. Secrets can be UTF-16, as
+// cmdkey and the control panel store them, or UTF-8
+pub fn read_credential(target: &str) -> Result<String, Error> {
+    let wide = to_wide(target);
+    let mut credential = null_mut();
+    let res = unsafe {
+        CredReadW(wide.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential)
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "CredReadW {target} {}", err);
+        return Err(err);
+    }
+    let blob = unsafe {
+        let credential = &*credential;
+        if credential.CredentialBlob.is_null() {
+            &[][..]
+        } else {
+            core::slice::from_raw_parts(
+                credential.CredentialBlob,
+                credential.CredentialBlobSize as usize,
+            )
+        }
+    };
+    // UTF-16 has NULs in it for anything ASCII, UTF-8 never does
+    let secret = match core::str::from_utf8(blob) {
+        Ok(secret) if !blob.contains(&0) => secret.to_string(),
+        _ => {
+            let units: Vec<u16> = blob
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+    };
+    unsafe { CredFree(credential.cast()) };
+    Ok(secret)
+}
+
+// Rust wrapper for CryptProtectData and CryptBinaryToStringW, `secret`
+// encrypted so only the user we run as can decrypt it, or anyone on this
+// machine with `machine`, as base64
+pub fn protect_data(secret: &str, machine: bool) -> Result<String, Error> {
+    let input = DATA_BLOB {
+        cbData: secret.len() as DWORD,
+        pbData: secret.as_ptr().cast_mut(),
+    };
+    let mut flags = CRYPTPROTECT_UI_FORBIDDEN;
+    if machine {
+        flags |= CRYPTPROTECT_LOCAL_MACHINE;
+    }
+    let mut output = DATA_BLOB {
+        cbData: 0,
+        pbData: null_mut(),
+    };
+    let res = unsafe {
+        CryptProtectData(
+            &input,
+            null(),
+            null(),
+            null_mut(),
+            null_mut(),
+            flags,
+            &mut output,
+        )
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "CryptProtectData {}", err);
+        return Err(err);
+    }
+    let encoding = CRYPT_STRING_BASE64 | CRYPT_STRING_NOCRLF;
+    // Called once for the length, including the NUL, then to fill it in
+    let mut len = 0;
+    let mut base64 = Vec::new();
+    let mut res = unsafe {
+        CryptBinaryToStringW(
+            output.pbData,
+            output.cbData,
+            encoding,
+            null_mut(),
+            &mut len,
+        )
+    };
+    if res != 0 {
+        base64.resize(len as usize, 0);
+        res = unsafe {
+            CryptBinaryToStringW(
+                output.pbData,
+                output.cbData,
+                encoding,
+                base64.as_mut_ptr(),
+                &mut len,
+            )
+        };
+    }
+    let err = (res == 0).then(Error::get_last);
+    unsafe { LocalFree(output.pbData.cast()) };
+    if let Some(err) = err {
+        event!(Level::ERROR, "CryptBinaryToStringW {}", err);
+        return Err(err);
+    }
+    Ok(String::from_utf16_lossy(&base64[..len as usize]))
+}
+
+// Rust wrapper for CryptStringToBinaryW and CryptUnprotectData, the secret
+// `protect_data` made `base64` from
+pub fn unprotect_data(base64: &str) -> Result<String, Error> {
+    let wide = to_wide(base64);
+    let len = wide.len() as DWORD - 1;
+    let mut size = 0;
+    let mut blob = Vec::new();
+    let mut res = unsafe {
+        CryptStringToBinaryW(
+            wide.as_ptr(),
+            len,
+            CRYPT_STRING_BASE64,
+            null_mut(),
+            &mut size,
+            null_mut(),
+            null_mut(),
+        )
+    };
+    if res != 0 {
+        blob.resize(size as usize, 0);
+        res = unsafe {
+            CryptStringToBinaryW(
+                wide.as_ptr(),
+                len,
+                CRYPT_STRING_BASE64,
+                blob.as_mut_ptr(),
+                &mut size,
+                null_mut(),
+                null_mut(),
+            )
+        };
+    }
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "CryptStringToBinaryW {}", err);
+        return Err(err);
+    }
+    let input = DATA_BLOB {
+        cbData: size,
+        pbData: blob.as_mut_ptr(),
+    };
+    let mut output = DATA_BLOB {
+        cbData: 0,
+        pbData: null_mut(),
+    };
+    let res = unsafe {
+        CryptUnprotectData(
+            &input,
+            null_mut(),
+            null(),
+            null_mut(),
+            null_mut(),
+            CRYPTPROTECT_UI_FORBIDDEN,
+            &mut output,
+        )
+    };
+    if res == 0 {
+        let err = Error::get_last();
+        event!(Level::ERROR, "CryptUnprotectData {}", err);
+        return Err(err);
+    }
+    let secret = unsafe {
+        core::slice::from_raw_parts(output.pbData, output.cbData as usize)
+    };
+    let secret = String::from_utf8_lossy(secret).into_owned();
+    unsafe { LocalFree(output.pbData.cast()) };
+    Ok(secret)
 }